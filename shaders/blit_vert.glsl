@@ -0,0 +1,19 @@
+#version 450
+
+// Set to undo a flipped viewport, so a target that was drawn
+// through the same flip is copied the right way up
+layout (constant_id = 0) const bool FLIP_Y = false;
+
+layout (location = 0) out vec2 uv;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    // One oversized triangle covers the whole screen,
+    // so no vertex buffer is needed
+    uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+    if (FLIP_Y) gl_Position.y = -gl_Position.y;
+}
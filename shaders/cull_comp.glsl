@@ -0,0 +1,34 @@
+#version 450
+
+layout (local_size_x = 1) in;
+
+layout (push_constant) uniform PushConsts {
+    float mouse_x;
+    float mouse_y;
+} push;
+
+// Matches VkDrawIndexedIndirectCommand
+layout (std430, binding = 0) buffer DrawCommand {
+    uint index_count;
+    uint instance_count;
+    uint first_index;
+    int vertex_offset;
+    uint first_instance;
+} draw;
+
+void main() {
+    // Same placement as the scene's vertex shader,
+    // where the quad spans half a unit either side
+    vec2 offset = vec2(push.mouse_x, push.mouse_y) * 2.0 - 0.5;
+    vec2 lower = offset - 0.5;
+    vec2 upper = offset + 0.5;
+
+    // Nothing is drawn once the quad is entirely off screen
+    bool visible = all(lessThan(lower, vec2(1.0))) && all(greaterThan(upper, vec2(-1.0)));
+
+    draw.index_count = visible ? 6 : 0;
+    draw.instance_count = 1;
+    draw.first_index = 0;
+    draw.vertex_offset = 0;
+    draw.first_instance = 0;
+}
@@ -0,0 +1,5 @@
+#version 450
+
+// Nothing to write, only depth is kept and the color mask is empty
+void main() {
+}
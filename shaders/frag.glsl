@@ -1,10 +1,18 @@
 #version 450
 
+layout (push_constant) uniform PushConsts {
+    // Must match the fragment range in the pipeline layout
+    layout (offset = 16) vec4 tint;
+    // In pixels, so gl_FragCoord.xy / resolution
+    // gives normalized coordinates
+    vec2 resolution;
+} push;
+
 layout (location = 1) in vec3 frag_color;
 
 // Locations are required for SPIRV compilation
 layout (location = 0) out vec4 color;
 
 void main() {
-    color = vec4(1.0);
+    color = push.tint;
 }
\ No newline at end of file
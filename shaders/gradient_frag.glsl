@@ -0,0 +1,20 @@
+#version 450
+
+// Written each frame, see FrameUniforms
+layout (set = 0, binding = 0) uniform Frame {
+    // The whole target's size in pixels
+    vec2 resolution;
+    // Seconds of simulated time
+    float time;
+} frame;
+
+layout (location = 0) out vec4 color;
+
+void main() {
+    // Zero at the top of the target, one at the bottom
+    float t = gl_FragCoord.y / frame.resolution.y;
+    // The top drifts slowly so it's clear the shader is live
+    vec3 top = vec3(0.35, 0.55, 0.85) + 0.05 * sin(frame.time * vec3(0.5, 0.7, 0.9));
+    vec3 bottom = vec3(0.08, 0.06, 0.15);
+    color = vec4(mix(top, bottom, t), 1.0);
+}
@@ -0,0 +1,17 @@
+#version 450
+
+// The material's base color texture, white without one
+layout (set = 0, binding = 0) uniform sampler2D base_color_texture;
+
+layout (push_constant) uniform PushConsts {
+    // Must match the fragment range in the pipeline layout
+    layout (offset = 64) vec4 base_color;
+} push;
+
+layout (location = 0) in vec2 uv;
+
+layout (location = 0) out vec4 color;
+
+void main() {
+    color = push.base_color * texture(base_color_texture, uv);
+}
@@ -0,0 +1,27 @@
+#version 450
+
+layout (push_constant) uniform PushConsts {
+    // Fits the model to the view, then projects it
+    mat4 mvp;
+} push;
+
+// Set when clip space Y is flipped here rather than by the viewport
+layout (constant_id = 0) const bool FLIP_Y = false;
+
+layout (location = 0) in vec3 position;
+// Read with the rest of the vertex, though nothing lights it yet
+layout (location = 1) in vec3 normal;
+layout (location = 2) in vec2 uv;
+
+layout (location = 0) out vec2 frag_uv;
+
+// Drawn again after a depth pre-pass, which has to land on the same depths
+out gl_PerVertex {
+    invariant vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = push.mvp * vec4(position, 1.0);
+    frag_uv = uv;
+    if (FLIP_Y) gl_Position.y = -gl_Position.y;
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout (push_constant) uniform PushConsts {
+    // Must match the fragment range in the pipeline layout
+    layout (offset = 64) vec4 tint;
+} push;
+
+layout (location = 0) out vec4 color;
+
+void main() {
+    color = push.tint;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout (push_constant) uniform PushConsts {
+    // Must match the fragment range in the pipeline layout
+    layout (offset = 64) uint id;
+} push;
+
+// Integer targets take the value as is, without blending
+layout (location = 0) out uint id;
+
+void main() {
+    id = push.id;
+}
@@ -0,0 +1,24 @@
+#version 450
+
+layout (push_constant) uniform PushConsts {
+    // Shared by every node in the batch
+    mat4 projection;
+} push;
+
+// Set when clip space Y is flipped here rather than by the viewport
+layout (constant_id = 0) const bool FLIP_Y = false;
+
+// Per vertex, from the shared quad
+layout (location = 0) in vec2 position;
+
+// Per instance, one location for each column
+layout (location = 1) in mat4 world;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = push.projection * world * vec4(position, 0.0, 1.0);
+    if (FLIP_Y) gl_Position.y = -gl_Position.y;
+}
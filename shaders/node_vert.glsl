@@ -0,0 +1,20 @@
+#version 450
+
+// Set when clip space Y is flipped here rather than by the viewport
+layout (constant_id = 0) const bool FLIP_Y = false;
+
+layout (push_constant) uniform PushConsts {
+    // Model, view and projection combined
+    mat4 mvp;
+} push;
+
+layout (location = 0) in vec2 position;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = push.mvp * vec4(position, 0.0, 1.0);
+    if (FLIP_Y) gl_Position.y = -gl_Position.y;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout (push_constant) uniform PushConsts {
+    // Must match the fragment range in the pipeline layout
+    layout (offset = 64) vec4 tint;
+} push;
+
+layout (location = 0) out vec4 color;
+
+// The opposite of the fill, so edges stand out whatever the tint
+void main() {
+    color = vec4(1.0 - push.tint.rgb, 1.0);
+}
@@ -0,0 +1,9 @@
+#version 450
+
+layout (location = 0) out vec4 color;
+
+// Added to whatever is already there, so each layer
+// of fragments makes the pixel a little brighter
+void main() {
+    color = vec4(0.1, 0.03, 0.01, 1.0);
+}
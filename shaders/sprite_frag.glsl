@@ -0,0 +1,13 @@
+#version 450
+
+// Shared by every sprite, white unless an image was loaded
+layout (set = 0, binding = 0) uniform sampler2D sprite_texture;
+
+layout (location = 0) in vec4 sprite_color;
+layout (location = 1) in vec2 uv;
+
+layout (location = 0) out vec4 color;
+
+void main() {
+    color = sprite_color * texture(sprite_texture, uv);
+}
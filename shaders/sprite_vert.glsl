@@ -0,0 +1,36 @@
+#version 450
+
+// Set when clip space Y is flipped here rather than by the viewport
+layout (constant_id = 0) const bool FLIP_Y = false;
+
+layout (push_constant) uniform PushConsts {
+    // Maps pixel coordinates to clip space
+    vec2 scale;
+    vec2 offset;
+} push;
+
+// Per vertex, from the shared quad
+layout (location = 0) in vec2 corner;
+
+// Per instance, from the sprite batch
+layout (location = 1) in vec2 sprite_pos;
+layout (location = 2) in vec2 sprite_size;
+layout (location = 3) in vec4 sprite_color;
+layout (location = 4) in vec4 sprite_uv_rect;
+
+layout (location = 0) out vec4 color;
+layout (location = 1) out vec2 uv;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    // Quad corners are centered on the origin
+    vec2 unit = corner + 0.5;
+    vec2 pixel = sprite_pos + unit * sprite_size;
+    gl_Position = vec4(pixel * push.scale + push.offset, 0.0, 1.0);
+    color = sprite_color;
+    uv = sprite_uv_rect.xy + unit * sprite_uv_rect.zw;
+    if (FLIP_Y) gl_Position.y = -gl_Position.y;
+}
@@ -0,0 +1,20 @@
+#version 450
+
+// Set when clip space Y is flipped here rather than by the viewport
+layout (constant_id = 0) const bool FLIP_Y = false;
+
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec4 vertex_color;
+
+// Interpolated across the face on the way to the fragment shader
+layout (location = 0) out vec4 color;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    color = vertex_color;
+    if (FLIP_Y) gl_Position.y = -gl_Position.y;
+}
@@ -5,6 +5,9 @@ layout (push_constant) uniform PushConsts {
     float mouse_y;
 } push;
 
+// Set when clip space Y is flipped here rather than by the viewport
+layout (constant_id = 0) const bool FLIP_Y = false;
+
 layout (location = 0) in vec2 position;
 
 // Equivalent to OpenGL gl_Position
@@ -15,4 +18,5 @@ layout (location = 0) out gl_PerVertex {
 void main() {
     vec2 offset = vec2(push.mouse_x, push.mouse_y) * 2.0 - 0.5;
     gl_Position = vec4(position + offset, 0.0, 1.0);
+    if (FLIP_Y) gl_Position.y = -gl_Position.y;
 }
\ No newline at end of file
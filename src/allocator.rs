@@ -0,0 +1,243 @@
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::{Adapter, MemoryProperties, PhysicalDevice},
+    device::Device,
+    memory::{Properties, Requirements},
+    Backend, MemoryTypeId,
+};
+use std::{cell::RefCell, fmt};
+
+// Where a block of memory came from. Kept alongside the memory
+// so that freeing it can be taken off the right heap's count.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Allocation {
+    pub heap: usize,
+    pub size: u64,
+    pub properties: Properties,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct HeapStats {
+    // Bytes allocated over the whole run, including those since freed
+    pub allocated: u64,
+    pub in_use: u64,
+    // Most bytes in use at any one time
+    pub peak: u64,
+    // Allocations not yet freed
+    pub live: usize,
+}
+
+// Running totals for every allocation made through this module,
+// for spotting leaks and growth. Bytes are what was asked of the
+// driver, which may round them up further.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AllocatorStats {
+    // Indexed by heap, only as long as the last heap allocated from
+    pub heaps: Vec<HeapStats>,
+}
+
+impl AllocatorStats {
+    pub fn in_use(&self) -> u64 {
+        self.heaps.iter().map(|heap| heap.in_use).sum()
+    }
+
+    pub fn live(&self) -> usize {
+        self.heaps.iter().map(|heap| heap.live).sum()
+    }
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const KIB: u64 = 1024;
+        for (i, heap) in self.heaps.iter().enumerate() {
+            writeln!(
+                f,
+                "Heap {}: {} KiB in use in {} allocations, {} KiB peak, {} KiB allocated in total",
+                i,
+                heap.in_use / KIB,
+                heap.live,
+                heap.peak / KIB,
+                heap.allocated / KIB
+            )?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // All allocation happens on the render thread
+    static STATS: RefCell<AllocatorStats> = RefCell::new(AllocatorStats::default());
+}
+
+pub fn allocator_stats() -> AllocatorStats {
+    STATS.with(|stats| stats.borrow().clone())
+}
+
+// Picks the first memory type allowed by the type mask that has all
+// the required properties, favoring those that also have the preferred ones
+pub fn find_memory_type(
+    props: &MemoryProperties,
+    type_mask: u64,
+    required: Properties,
+    preferred: Properties,
+) -> Option<MemoryTypeId> {
+    let candidates = || {
+        props
+            .memory_types
+            .iter()
+            .enumerate()
+            .filter(move |&(id, memory_type)| {
+                type_mask & (1 << id) != 0 && memory_type.properties.contains(required)
+            })
+    };
+
+    candidates()
+        .find(|(_, memory_type)| memory_type.properties.contains(preferred))
+        .or_else(|| candidates().next())
+        .map(|(id, _)| MemoryTypeId(id))
+}
+
+// Allocates memory to satisfy a buffer or image's requirements,
+// along with where it came from. It must be released with free
+// rather than directly, to keep the stats right.
+pub fn allocate(
+    device: &back::Device,
+    adapter: &Adapter<back::Backend>,
+    requirements: &Requirements,
+    required: Properties,
+    preferred: Properties,
+) -> Result<(<back::Backend as Backend>::Memory, Allocation), &'static str> {
+    let props = adapter.physical_device.memory_properties();
+    let memory_type_id = find_memory_type(&props, requirements.type_mask, required, preferred)
+        .ok_or("Failed to find a suitable memory type")?;
+
+    let memory = unsafe { device.allocate_memory(memory_type_id, requirements.size) }
+        .map_err(|_| "Failed to allocate memory")?;
+
+    let memory_type = &props.memory_types[memory_type_id.0];
+    let allocation = Allocation {
+        heap: memory_type.heap_index,
+        size: requirements.size,
+        properties: memory_type.properties,
+    };
+    record_allocation(&allocation);
+    Ok((memory, allocation))
+}
+
+pub fn free(
+    device: &back::Device,
+    memory: <back::Backend as Backend>::Memory,
+    allocation: &Allocation,
+) {
+    unsafe { device.free_memory(memory) }
+    record_free(allocation);
+}
+
+fn record_allocation(allocation: &Allocation) {
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        if stats.heaps.len() <= allocation.heap {
+            stats
+                .heaps
+                .resize(allocation.heap + 1, HeapStats::default());
+        }
+        let heap = &mut stats.heaps[allocation.heap];
+        heap.allocated += allocation.size;
+        heap.in_use += allocation.size;
+        heap.peak = heap.peak.max(heap.in_use);
+        heap.live += 1;
+    });
+}
+
+fn record_free(allocation: &Allocation) {
+    STATS.with(|stats| {
+        if let Some(heap) = stats.borrow_mut().heaps.get_mut(allocation.heap) {
+            heap.in_use -= allocation.size;
+            heap.live -= 1;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gfx_hal::adapter::MemoryType;
+
+    fn props() -> MemoryProperties {
+        let memory_type = |properties, heap_index| MemoryType {
+            properties,
+            heap_index,
+        };
+        MemoryProperties {
+            memory_types: vec![
+                memory_type(Properties::DEVICE_LOCAL, 0),
+                memory_type(Properties::CPU_VISIBLE, 1),
+                memory_type(Properties::CPU_VISIBLE | Properties::COHERENT, 1),
+                memory_type(Properties::DEVICE_LOCAL | Properties::CPU_VISIBLE, 0),
+            ],
+            memory_heaps: vec![1 << 30, 1 << 30],
+        }
+    }
+
+    #[test]
+    fn prefers_types_with_the_preferred_properties() {
+        let id = find_memory_type(&props(), !0, Properties::CPU_VISIBLE, Properties::COHERENT);
+        assert_eq!(id, Some(MemoryTypeId(2)));
+    }
+
+    #[test]
+    fn falls_back_to_the_first_with_the_required_properties() {
+        let id = find_memory_type(
+            &props(),
+            !0,
+            Properties::CPU_VISIBLE,
+            Properties::LAZILY_ALLOCATED,
+        );
+        assert_eq!(id, Some(MemoryTypeId(1)));
+    }
+
+    #[test]
+    fn only_considers_types_in_the_mask() {
+        let id = find_memory_type(
+            &props(),
+            0b1001,
+            Properties::CPU_VISIBLE,
+            Properties::COHERENT,
+        );
+        assert_eq!(id, Some(MemoryTypeId(3)));
+
+        let id = find_memory_type(
+            &props(),
+            0b0001,
+            Properties::CPU_VISIBLE,
+            Properties::empty(),
+        );
+        assert_eq!(id, None);
+    }
+
+    // Each test runs on its own thread, so starts from empty stats
+    #[test]
+    fn in_use_returns_to_zero() {
+        let allocation = |heap, size| Allocation {
+            heap,
+            size,
+            properties: Properties::DEVICE_LOCAL,
+        };
+        let a = allocation(0, 256);
+        let b = allocation(1, 1024);
+        record_allocation(&a);
+        record_allocation(&b);
+        let stats = allocator_stats();
+        assert_eq!(stats.in_use(), 1280);
+        assert_eq!(stats.live(), 2);
+
+        record_free(&a);
+        record_free(&b);
+        let stats = allocator_stats();
+        assert_eq!(stats.in_use(), 0);
+        assert_eq!(stats.live(), 0);
+        // The totals are kept
+        assert_eq!(stats.heaps[0].peak, 256);
+        assert_eq!(stats.heaps[1].allocated, 1024);
+    }
+}
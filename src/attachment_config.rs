@@ -0,0 +1,177 @@
+use gfx_hal::{
+    format::Format,
+    image::NumSamples,
+    pass::{
+        Attachment, AttachmentLayout, AttachmentLoadOp, AttachmentOps, AttachmentRef,
+        AttachmentStoreOp,
+    },
+};
+
+// Scene passes share the one depth format. Every device supports
+// it or D24 for depth attachments, and nearly all support this one.
+pub const DEPTH_FORMAT: Format = Format::D32Sfloat;
+
+// Describes what happens to an attachment's contents
+// at the start and end of a render pass
+#[derive(Copy, Clone, Debug)]
+pub struct AttachmentConfig {
+    pub load_op: AttachmentLoadOp,
+    pub store_op: AttachmentStoreOp,
+}
+
+impl AttachmentConfig {
+    // Clear the render target to the clear color and preserve the result
+    pub const CLEAR: Self = Self {
+        load_op: AttachmentLoadOp::Clear,
+        store_op: AttachmentStoreOp::Store,
+    };
+
+    // Draw over whatever the previous pass left behind,
+    // useful for accumulation or UI over a scene
+    pub const LOAD: Self = Self {
+        load_op: AttachmentLoadOp::Load,
+        store_op: AttachmentStoreOp::Store,
+    };
+
+    // Loading needs the previous contents to be in a known layout,
+    // otherwise the image can begin uninitialized
+    pub fn initial_layout(&self, final_layout: AttachmentLayout) -> AttachmentLayout {
+        match self.load_op {
+            AttachmentLoadOp::Load => final_layout,
+            AttachmentLoadOp::Clear | AttachmentLoadOp::DontCare => AttachmentLayout::Undefined,
+        }
+    }
+
+    // Images start out undefined, so the first pass into them has
+    // nothing to load. Render passes that only differ in this
+    // are compatible, so the same framebuffers and pipelines work.
+    pub fn first_use(&self) -> Self {
+        match self.load_op {
+            AttachmentLoadOp::Load => Self {
+                load_op: AttachmentLoadOp::DontCare,
+                ..*self
+            },
+            AttachmentLoadOp::Clear | AttachmentLoadOp::DontCare => *self,
+        }
+    }
+
+    // Whether the first use needs a render pass of its own
+    pub fn loads(&self) -> bool {
+        self.load_op == AttachmentLoadOp::Load
+    }
+
+    // Only stores the resolved samples, the
+    // multisampled source is left undefined
+    pub const RESOLVE: Self = Self {
+        load_op: AttachmentLoadOp::DontCare,
+        store_op: AttachmentStoreOp::Store,
+    };
+
+    pub fn attachment(
+        &self,
+        format: Format,
+        samples: NumSamples,
+        final_layout: AttachmentLayout,
+    ) -> Attachment {
+        Attachment {
+            format: Some(format),
+            samples,
+            ops: AttachmentOps::new(self.load_op, self.store_op),
+            stencil_ops: AttachmentOps::DONT_CARE,
+            layouts: self.initial_layout(final_layout)..final_layout,
+        }
+    }
+
+    // The color attachment, followed by a single sample attachment
+    // to resolve into when there is more than one sample, then the
+    // depth buffer. Only the color that is kept ends up in the final layout.
+    pub fn attachments(
+        &self,
+        format: Format,
+        samples: NumSamples,
+        final_layout: AttachmentLayout,
+    ) -> Vec<Attachment> {
+        let mut attachments = if samples > 1 {
+            vec![
+                self.attachment(format, samples, AttachmentLayout::ColorAttachmentOptimal),
+                Self::RESOLVE.attachment(format, 1, final_layout),
+            ]
+        } else {
+            vec![self.attachment(format, 1, final_layout)]
+        };
+        attachments.push(depth_attachment(samples));
+        attachments
+    }
+}
+
+// Cleared every frame and thrown away at the end,
+// since nothing reads depth after the pass
+fn depth_attachment(samples: NumSamples) -> Attachment {
+    Attachment {
+        format: Some(DEPTH_FORMAT),
+        samples,
+        ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::DontCare),
+        stencil_ops: AttachmentOps::DONT_CARE,
+        layouts: AttachmentLayout::Undefined..AttachmentLayout::DepthStencilAttachmentOptimal,
+    }
+}
+
+// How many attachments `AttachmentConfig::attachments` makes
+pub fn attachment_count(samples: NumSamples) -> usize {
+    if samples > 1 {
+        3
+    } else {
+        2
+    }
+}
+
+// Subpass depth reference to go with `AttachmentConfig::attachments`
+pub fn depth_ref(samples: NumSamples) -> AttachmentRef {
+    (
+        attachment_count(samples) - 1,
+        AttachmentLayout::DepthStencilAttachmentOptimal,
+    )
+}
+
+// Subpass resolve references to go with `AttachmentConfig::attachments`
+pub fn resolves(samples: NumSamples) -> &'static [AttachmentRef] {
+    if samples > 1 {
+        &[(1, AttachmentLayout::ColorAttachmentOptimal)]
+    } else {
+        &[]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESENT: AttachmentLayout = AttachmentLayout::Present;
+
+    #[test]
+    fn loading_keeps_the_layout() {
+        assert_eq!(AttachmentConfig::LOAD.initial_layout(PRESENT), PRESENT);
+    }
+
+    #[test]
+    fn clearing_starts_undefined() {
+        let dont_care = AttachmentConfig {
+            load_op: AttachmentLoadOp::DontCare,
+            ..AttachmentConfig::CLEAR
+        };
+        for config in &[AttachmentConfig::CLEAR, dont_care] {
+            assert_eq!(config.initial_layout(PRESENT), AttachmentLayout::Undefined);
+        }
+    }
+
+    #[test]
+    fn first_use_has_nothing_to_load() {
+        let first = AttachmentConfig::LOAD.first_use();
+        assert_eq!(first.load_op, AttachmentLoadOp::DontCare);
+        assert_eq!(first.store_op, AttachmentStoreOp::Store);
+        assert_eq!(first.initial_layout(PRESENT), AttachmentLayout::Undefined);
+        // Nothing to change when it doesn't load anyway
+        let clear = AttachmentConfig::CLEAR.first_use();
+        assert_eq!(clear.load_op, AttachmentLoadOp::Clear);
+    }
+}
@@ -1,17 +1,28 @@
+use crate::allocator::{self, Allocation};
 use gfx_backend_vulkan as back;
 use gfx_hal::{
-    adapter::{Adapter, PhysicalDevice},
-    buffer::Usage,
+    adapter::Adapter,
+    buffer::{self, Usage},
+    command::CommandBuffer,
     device::Device,
-    memory::{Properties, Requirements},
-    Backend, MemoryTypeId,
+    memory::{Barrier, Dependencies, Properties, Requirements},
+    pso::PipelineStage,
+    Backend,
+};
+use std::{
+    iter,
+    mem::{self, ManuallyDrop},
+    ptr, slice,
 };
-use std::{mem::ManuallyDrop, ptr};
 
 pub struct BufferInfo {
     pub buffer: ManuallyDrop<<back::Backend as Backend>::Buffer>,
     pub memory: ManuallyDrop<<back::Backend as Backend>::Memory>,
+    pub allocation: Allocation,
     pub requirements: Requirements,
+    pub coherent: bool,
+    // Set while the memory is persistently mapped
+    pub mapped: Option<*mut u8>,
 }
 
 impl BufferInfo {
@@ -21,30 +32,32 @@ impl BufferInfo {
         data: &[T],
         usage: Usage,
     ) -> Result<Self, &'static str> {
-        let mut buffer = unsafe { device.create_buffer(array_size(data) as u64, usage) }
+        Self::with_size(device, adapter, array_size(data) as u64, usage)
+    }
+
+    // For buffers whose contents aren't known up front
+    pub fn with_size(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        size: u64,
+        usage: Usage,
+    ) -> Result<Self, &'static str> {
+        let mut buffer = unsafe { device.create_buffer(size, usage) }
             .map_err(|_| "Failed to create a buffer for the vertices")?;
 
         // Creation of the buffer does not imply allocation.
         // We can now query it's prerequistes and allocate memory to match.
         let requirements = unsafe { device.get_buffer_requirements(&buffer) };
 
-        // Find id of CPU-visible memory for vertex buffer
-        let memory_type_id = adapter
-            .physical_device
-            .memory_properties()
-            .memory_types
-            .iter()
-            .enumerate()
-            .find(|&(id, memory_type)| {
-                requirements.type_mask & (1 << id) != 0
-                    && memory_type.properties.contains(Properties::CPU_VISIBLE)
-            })
-            .map(|(id, _)| MemoryTypeId(id))
-            .ok_or("Failed to find a memory type to support the vertex buffer")?;
-
-        // Allocate vertex buffer
-        let memory = unsafe { device.allocate_memory(memory_type_id, requirements.size) }
-            .map_err(|_| "Failed to allocate vertex buffer memory")?;
+        // CPU-visible memory for writing from the host,
+        // coherent if possible so writes don't need flushing
+        let (memory, allocation) = allocator::allocate(
+            device,
+            adapter,
+            &requirements,
+            Properties::CPU_VISIBLE,
+            Properties::COHERENT,
+        )?;
 
         // Make the buffer use the allocation
         unsafe { device.bind_buffer_memory(&memory, 0, &mut buffer) }
@@ -53,26 +66,138 @@ impl BufferInfo {
         Ok(Self {
             buffer: ManuallyDrop::new(buffer),
             memory: ManuallyDrop::new(memory),
+            allocation,
             requirements,
+            coherent: allocation.properties.contains(Properties::COHERENT),
+            mapped: None,
         })
     }
 
+    // Keeps the memory mapped until the buffer is freed,
+    // for buffers that are written often
+    pub fn map_persistent(&mut self, device: &back::Device) -> Result<(), &'static str> {
+        if self.mapped.is_none() {
+            let mapped_memory =
+                unsafe { device.map_memory(&self.memory, 0..self.requirements.size) }
+                    .map_err(|_| "Failed to memory map buffer")?;
+            self.mapped = Some(mapped_memory);
+        }
+        Ok(())
+    }
+
+    // Copies into persistently mapped memory at a byte offset
+    pub fn write<T>(
+        &self,
+        device: &back::Device,
+        offset: u64,
+        data: &[T],
+    ) -> Result<(), &'static str> {
+        let mapped_memory = self.mapped.ok_or("Buffer is not persistently mapped")?;
+        if !in_bounds(offset, array_size(data) as u64, self.requirements.size) {
+            return Err("Write is larger than the buffer");
+        }
+
+        unsafe {
+            ptr::copy(
+                data.as_ptr() as *const u8,
+                mapped_memory.add(offset as usize),
+                array_size(data),
+            );
+        }
+        self.flush(device)
+    }
+
+    // Reads a value the device wrote to persistently mapped memory.
+    // The writes have to be finished and made visible to the host.
+    pub fn read<T: Copy>(&self, device: &back::Device, offset: u64) -> Result<T, &'static str> {
+        let mapped_memory = self.mapped.ok_or("Buffer is not persistently mapped")?;
+        if !in_bounds(offset, mem::size_of::<T>() as u64, self.requirements.size) {
+            return Err("Read is larger than the buffer");
+        }
+
+        self.invalidate(device)?;
+        Ok(unsafe { ptr::read_unaligned(mapped_memory.add(offset as usize) as *const T) })
+    }
+
+    // Copies out the first len bytes, under the same conditions as read
+    pub fn read_bytes(&self, device: &back::Device, len: usize) -> Result<Vec<u8>, &'static str> {
+        let mapped_memory = self.mapped.ok_or("Buffer is not persistently mapped")?;
+        if !in_bounds(0, len as u64, self.requirements.size) {
+            return Err("Read is larger than the buffer");
+        }
+
+        self.invalidate(device)?;
+        Ok(unsafe { slice::from_raw_parts(mapped_memory, len) }.to_vec())
+    }
+
     pub fn load_data<T>(&self, device: &back::Device, data: &[T]) -> Result<(), &'static str> {
+        if self.mapped.is_some() {
+            return self.write(device, 0, data);
+        }
+
         let mapped_memory = unsafe { device.map_memory(&self.memory, 0..self.requirements.size) }
             .map_err(|_| "Failed to memory map buffer")?;
 
-        unsafe {
-            ptr::copy(data.as_ptr() as *const u8, mapped_memory, array_size(data));
-            device.unmap_memory(&self.memory)
+        unsafe { ptr::copy(data.as_ptr() as *const u8, mapped_memory, array_size(data)) }
+        let flushed = self.flush(device);
+        unsafe { device.unmap_memory(&self.memory) }
+
+        flushed
+    }
+
+    // Makes host writes visible to the device when the memory isn't coherent
+    fn flush(&self, device: &back::Device) -> Result<(), &'static str> {
+        if self.coherent {
+            return Ok(());
         }
 
-        Ok(())
+        unsafe { device.flush_mapped_memory_ranges(iter::once((&*self.memory, ..))) }
+            .map_err(|_| "Failed to flush buffer memory")
+    }
+
+    fn invalidate(&self, device: &back::Device) -> Result<(), &'static str> {
+        if self.coherent {
+            return Ok(());
+        }
+
+        unsafe { device.invalidate_mapped_memory_ranges(iter::once((&*self.memory, ..))) }
+            .map_err(|_| "Failed to invalidate buffer memory")
+    }
+
+    // Orders host writes before vertex input reads them. Only recorded
+    // for memory that isn't coherent, where the flush alone may not be
+    // enough on some drivers. Must be outside a render pass.
+    pub unsafe fn record_host_barrier(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        reads: buffer::Access,
+    ) {
+        if self.coherent {
+            return;
+        }
+
+        commands.pipeline_barrier(
+            PipelineStage::HOST..PipelineStage::VERTEX_INPUT,
+            Dependencies::empty(),
+            &[Barrier::Buffer {
+                states: buffer::Access::HOST_WRITE..reads,
+                target: &*self.buffer,
+                families: None,
+                range: None..None,
+            }],
+        );
     }
 
     pub fn free(&mut self, device: &back::Device) {
+        // Clearing the pointer stops writes after the memory is gone
+        if self.mapped.take().is_some() {
+            unsafe { device.unmap_memory(&self.memory) }
+        }
+
         unsafe {
             device.destroy_buffer(ManuallyDrop::into_inner(ptr::read(&self.buffer)));
-            device.free_memory(ManuallyDrop::into_inner(ptr::read(&self.memory)));
+            let memory = ManuallyDrop::into_inner(ptr::read(&self.memory));
+            allocator::free(device, memory, &self.allocation);
         }
     }
 }
@@ -80,3 +205,35 @@ impl BufferInfo {
 fn array_size<T>(array: &[T]) -> usize {
     array.len() * std::mem::size_of::<T>()
 }
+
+// Whether len bytes from offset stay within the buffer. An offset
+// near the top of the range would otherwise wrap past the check.
+fn in_bounds(offset: u64, len: u64, size: u64) -> bool {
+    offset.checked_add(len).map_or(false, |end| end <= size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_up_to_the_end() {
+        assert!(in_bounds(0, 64, 64));
+        assert!(in_bounds(60, 4, 64));
+        assert!(in_bounds(64, 0, 64));
+        assert!(!in_bounds(61, 4, 64));
+        assert!(!in_bounds(0, 65, 64));
+    }
+
+    #[test]
+    fn overflowing_offsets_are_rejected() {
+        assert!(!in_bounds(u64::max_value(), 1, 64));
+        assert!(!in_bounds(u64::max_value() - 2, 4, u64::max_value()));
+    }
+
+    #[test]
+    fn array_size_counts_bytes() {
+        assert_eq!(array_size(&[0u32; 3]), 12);
+        assert_eq!(array_size::<u64>(&[]), 0);
+    }
+}
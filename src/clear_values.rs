@@ -0,0 +1,104 @@
+use arrayvec::ArrayVec;
+use gfx_hal::command::{ClearColor, ClearDepthStencil, ClearValue};
+
+// Enough for several color targets with their resolves and a depth buffer
+pub const MAX_ATTACHMENTS: usize = 8;
+
+pub type ClearValues = ArrayVec<[ClearValue; MAX_ATTACHMENTS]>;
+
+// Collects a clear for each attachment in the order the render pass
+// declares them, so begin_render_pass gets each one in the right slot.
+// Built every frame, so it stays on the stack.
+#[derive(Default)]
+pub struct ClearValuesBuilder {
+    values: ClearValues,
+    // Set when more were pushed than fit, reported by build
+    overflowed: bool,
+}
+
+impl ClearValuesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // For float, UNORM and sRGB targets
+    pub fn color(self, color: [f32; 4]) -> Self {
+        self.push(ClearValue {
+            color: ClearColor { float32: color },
+        })
+    }
+
+    // For integer targets, like the picking IDs
+    pub fn color_uint(self, color: [u32; 4]) -> Self {
+        self.push(ClearValue {
+            color: ClearColor { uint32: color },
+        })
+    }
+
+    pub fn depth_stencil(self, depth: f32, stencil: u32) -> Self {
+        self.push(ClearValue {
+            depth_stencil: ClearDepthStencil { depth, stencil },
+        })
+    }
+
+    // Holds the place of an attachment that isn't cleared,
+    // such as a resolve target, since the values are by index
+    pub fn skip(self) -> Self {
+        self.color([0.0; 4])
+    }
+
+    // Fails unless there is exactly one clear per attachment
+    pub fn build(self, attachments: usize) -> Result<ClearValues, &'static str> {
+        if self.overflowed {
+            return Err("Too many clear values");
+        }
+        if self.values.len() != attachments {
+            return Err("Clear values don't match the render pass attachments");
+        }
+        Ok(self.values)
+    }
+
+    fn push(mut self, value: ClearValue) -> Self {
+        if self.values.try_push(value).is_err() {
+            self.overflowed = true;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_keep_the_order_pushed() {
+        let values = ClearValuesBuilder::new()
+            .color([0.25, 0.5, 0.75, 1.0])
+            .skip()
+            .depth_stencil(0.5, 7)
+            .build(3)
+            .unwrap();
+        unsafe {
+            assert_eq!(values[0].color.float32, [0.25, 0.5, 0.75, 1.0]);
+            assert_eq!(values[1].color.float32, [0.0; 4]);
+            assert_eq!(values[2].depth_stencil.depth, 0.5);
+            assert_eq!(values[2].depth_stencil.stencil, 7);
+        }
+    }
+
+    #[test]
+    fn count_must_match_the_attachments() {
+        let builder = || ClearValuesBuilder::new().color_uint([1, 0, 0, 0]);
+        assert!(builder().build(1).is_ok());
+        assert!(builder().build(2).is_err());
+    }
+
+    #[test]
+    fn overflow_is_reported() {
+        let mut builder = ClearValuesBuilder::new();
+        for _ in 0..=MAX_ATTACHMENTS {
+            builder = builder.skip();
+        }
+        assert!(builder.build(MAX_ATTACHMENTS).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{
+    descriptor::{DescriptorAllocation, DescriptorAllocator},
+    pipeline_info, PipelineOptions,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{device::Device, pso, Backend};
+use shaderc::{Compiler, ShaderKind};
+use std::{mem::ManuallyDrop, ops::Range};
+
+// The compute counterpart to PipelineInfo,
+// a single shader with no fixed function state
+pub struct ComputeInfo {
+    // Descriptors used by a set of the first layout
+    pub descriptor_ranges: Vec<pso::DescriptorRangeDesc>,
+    pub descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout>,
+    pub layout: ManuallyDrop<<back::Backend as Backend>::PipelineLayout>,
+    pub handle: ManuallyDrop<<back::Backend as Backend>::ComputePipeline>,
+}
+
+impl ComputeInfo {
+    pub fn new(
+        device: &back::Device,
+        shader: &str,
+        bindings: Vec<pso::DescriptorSetLayoutBinding>,
+        push_constants: Range<u32>,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        let module = {
+            let mut compiler = Compiler::new().ok_or("Failed to create shader compiler")?;
+            pipeline_info::compile_shader(
+                shader,
+                &mut compiler,
+                device,
+                ShaderKind::Compute,
+                options.dump_spirv,
+            )?
+        };
+
+        let descriptor_ranges = bindings
+            .iter()
+            .map(|binding| pso::DescriptorRangeDesc {
+                ty: binding.ty,
+                count: binding.count,
+            })
+            .collect();
+
+        let descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout> =
+            vec![unsafe {
+                device.create_descriptor_set_layout(
+                    bindings,
+                    Vec::<<back::Backend as Backend>::Sampler>::new(),
+                )
+            }
+            .map_err(|_| "Failed to create a descriptor set layout")?];
+
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &descriptor_set_layouts,
+                &[(pso::ShaderStageFlags::COMPUTE, push_constants)],
+            )
+        }
+        .map_err(|_| "Failed to create a pipeline layout")?;
+
+        let handle = unsafe {
+            device.create_compute_pipeline(
+                &pso::ComputePipelineDesc::new(
+                    pso::EntryPoint {
+                        entry: "main",
+                        module: &module,
+                        specialization: pso::Specialization::EMPTY,
+                    },
+                    &layout,
+                ),
+                None,
+            )
+        }
+        .map_err(|_| "Failed to create compute pipeline")?;
+
+        // Not needed after pipeline is built
+        unsafe { device.destroy_shader_module(module) }
+
+        Ok(Self {
+            descriptor_ranges,
+            descriptor_set_layouts,
+            layout: ManuallyDrop::new(layout),
+            handle: ManuallyDrop::new(handle),
+        })
+    }
+
+    pub fn allocate_set(
+        &self,
+        device: &back::Device,
+        descriptors: &mut DescriptorAllocator,
+    ) -> Result<DescriptorAllocation, &'static str> {
+        descriptors.allocate(
+            device,
+            &self.descriptor_set_layouts[0],
+            &self.descriptor_ranges,
+        )
+    }
+
+    pub fn free(&mut self, device: &back::Device) {
+        use std::ptr::read;
+
+        for layout in self.descriptor_set_layouts.drain(..) {
+            unsafe { device.destroy_descriptor_set_layout(layout) }
+        }
+
+        unsafe {
+            device.destroy_pipeline_layout(ManuallyDrop::into_inner(read(&self.layout)));
+            device.destroy_compute_pipeline(ManuallyDrop::into_inner(read(&self.handle)));
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::{fmt, time::Duration};
+
+// Where draw_frame spent its CPU time, separate from any GPU time.
+// Waiting on the frame's fence isn't included, see render_fence_wait.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CpuFrameTiming {
+    pub acquire: Duration,
+    pub record: Duration,
+    pub submit: Duration,
+    pub present: Duration,
+}
+
+impl CpuFrameTiming {
+    pub fn total(&self) -> Duration {
+        self.acquire + self.record + self.submit + self.present
+    }
+}
+
+impl fmt::Display for CpuFrameTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        writeln!(f, "Acquire: {:.3} ms", ms(self.acquire))?;
+        writeln!(f, "Record: {:.3} ms", ms(self.record))?;
+        writeln!(f, "Submit: {:.3} ms", ms(self.submit))?;
+        writeln!(f, "Present: {:.3} ms", ms(self.present))?;
+        writeln!(f, "CPU total: {:.3} ms", ms(self.total()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_adds_every_span() {
+        let timing = CpuFrameTiming {
+            acquire: Duration::from_micros(100),
+            record: Duration::from_micros(2000),
+            submit: Duration::from_micros(30),
+            present: Duration::from_micros(400),
+        };
+        assert_eq!(timing.total(), Duration::from_micros(2530));
+        assert_eq!(CpuFrameTiming::default().total(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn display_ends_with_the_total() {
+        let timing = CpuFrameTiming {
+            record: Duration::from_millis(2),
+            ..CpuFrameTiming::default()
+        };
+        let text = timing.to_string();
+        assert!(text.ends_with("CPU total: 2.000 ms\n"), "{}", text);
+    }
+}
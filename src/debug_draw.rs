@@ -0,0 +1,192 @@
+use crate::{
+    debug_names::set_debug_name,
+    utils::{Vec2, Vec4},
+    vertex::ColorVertex,
+    BufferInfo,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{adapter::Adapter, buffer::Usage, device::Device};
+use std::{f32::consts::PI, mem::size_of};
+
+// Thickness of every outline, in clip space
+pub const LINE_WIDTH: f32 = 0.005;
+
+// Straight segments making up each circle
+pub const CIRCLE_SEGMENTS: usize = 32;
+
+// Size of the outline around the cursor
+pub const CURSOR_RADIUS: f32 = 0.05;
+
+// Two triangles for each line segment
+pub const VERTICES_PER_LINE: usize = 6;
+
+// Immediate-mode outlines for visualizing bounds, in clip space.
+// Shapes are added each frame and cleared once it's drawn.
+// Lines are built from thin quads, so they don't need line
+// primitives or wide line support.
+#[derive(Default)]
+pub struct DebugDraw {
+    pub vertices: Vec<ColorVertex>,
+    // Created on first upload, with a region for each frame in flight
+    pub buffer: Option<BufferInfo>,
+    // Number of vertices each region can hold
+    pub capacity: usize,
+    // Number of regions in the buffer
+    pub frames: usize,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, a: Vec2, b: Vec2, color: Vec4) {
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let length = (dx * dx + dy * dy).sqrt();
+        // No direction to give it a width
+        if length == 0.0 {
+            return;
+        }
+        // Half the width, perpendicular to the line
+        let nx = -dy / length * LINE_WIDTH * 0.5;
+        let ny = dx / length * LINE_WIDTH * 0.5;
+        let corner = |p: Vec2, side: f32| ColorVertex {
+            position: Vec2 {
+                x: p.x + nx * side,
+                y: p.y + ny * side,
+            },
+            color,
+        };
+        let quad = [
+            corner(a, 1.0),
+            corner(a, -1.0),
+            corner(b, -1.0),
+            corner(b, -1.0),
+            corner(b, 1.0),
+            corner(a, 1.0),
+        ];
+        self.vertices.extend_from_slice(&quad);
+    }
+
+    // Outline of an axis-aligned box
+    pub fn rect(&mut self, min: Vec2, max: Vec2, color: Vec4) {
+        let corners = [
+            min,
+            Vec2 { x: max.x, y: min.y },
+            max,
+            Vec2 { x: min.x, y: max.y },
+        ];
+        for i in 0..corners.len() {
+            self.line(corners[i], corners[(i + 1) % corners.len()], color);
+        }
+    }
+
+    pub fn circle(&mut self, center: Vec2, radius: f32, color: Vec4) {
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+            Vec2 {
+                x: center.x + radius * angle.cos(),
+                y: center.y + radius * angle.sin(),
+            }
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    // Byte offset of a frame's region in the buffer
+    pub fn offset(&self, frame: usize) -> u64 {
+        (frame * self.capacity * size_of::<ColorVertex>()) as u64
+    }
+
+    pub fn upload(
+        &mut self,
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        frame: usize,
+        frames: usize,
+    ) -> Result<(), &'static str> {
+        if self.vertices.len() > self.capacity || frames != self.frames {
+            // Grows geometrically like the sprite batch,
+            // since the shape count changes from frame to frame
+            let capacity = self.vertices.len().next_power_of_two().max(self.capacity);
+
+            if let Some(mut old) = self.buffer.take() {
+                // Frames in flight may still be reading the old buffer
+                let _ = device.wait_idle();
+                old.free(device);
+            }
+
+            let mut buffer = BufferInfo::with_size(
+                device,
+                adapter,
+                (frames * capacity * size_of::<ColorVertex>()) as u64,
+                Usage::VERTEX,
+            )?;
+            // Rewritten every frame
+            buffer.map_persistent(device)?;
+            set_debug_name(device, &mut *buffer.buffer, "debug_vertices");
+            self.buffer = Some(buffer);
+            self.capacity = capacity;
+            self.frames = frames;
+        }
+
+        match &self.buffer {
+            Some(buffer) => buffer.write(device, self.offset(frame), &self.vertices),
+            None => Ok(()),
+        }
+    }
+
+    pub fn free(&mut self, device: &back::Device) {
+        if let Some(mut buffer) = self.buffer.take() {
+            buffer.free(device);
+        }
+        self.capacity = 0;
+        self.frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Vec4 = Vec4::new(1.0, 1.0, 1.0, 1.0);
+
+    #[test]
+    fn rect_is_four_lines() {
+        let mut debug_draw = DebugDraw::default();
+        debug_draw.rect(Vec2 { x: -0.5, y: -0.5 }, Vec2 { x: 0.5, y: 0.5 }, WHITE);
+        assert_eq!(debug_draw.len(), 4 * VERTICES_PER_LINE);
+    }
+
+    #[test]
+    fn circle_is_a_line_per_segment() {
+        let mut debug_draw = DebugDraw::default();
+        debug_draw.circle(Vec2 { x: 0.0, y: 0.0 }, 0.5, WHITE);
+        assert_eq!(debug_draw.len(), CIRCLE_SEGMENTS * VERTICES_PER_LINE);
+    }
+
+    #[test]
+    fn empty_line_is_skipped() {
+        let mut debug_draw = DebugDraw::default();
+        let point = Vec2 { x: 0.25, y: 0.25 };
+        debug_draw.line(point, point, WHITE);
+        assert!(debug_draw.is_empty());
+    }
+
+    #[test]
+    fn clear_empties() {
+        let mut debug_draw = DebugDraw::default();
+        debug_draw.line(Vec2 { x: 0.0, y: 0.0 }, Vec2 { x: 1.0, y: 0.0 }, WHITE);
+        debug_draw.clear();
+        assert!(debug_draw.is_empty());
+    }
+}
@@ -0,0 +1,38 @@
+use gfx_backend_vulkan as back;
+use gfx_hal::{device::Device, Backend};
+
+// Objects that can be labelled for captures and validation messages.
+// Names go through VK_EXT_debug_utils, which the backend skips
+// when the extension isn't available. gfx-hal has no entry point
+// for naming pipelines or the swapchain.
+pub trait DebugName {
+    unsafe fn name(&mut self, device: &back::Device, name: &str);
+}
+
+macro_rules! debug_name {
+    ($ty:ident, $set:ident) => {
+        impl DebugName for <back::Backend as Backend>::$ty {
+            unsafe fn name(&mut self, device: &back::Device, name: &str) {
+                device.$set(self, name)
+            }
+        }
+    };
+}
+
+debug_name!(Buffer, set_buffer_name);
+debug_name!(Image, set_image_name);
+debug_name!(RenderPass, set_render_pass_name);
+debug_name!(Framebuffer, set_framebuffer_name);
+debug_name!(CommandBuffer, set_command_buffer_name);
+debug_name!(Semaphore, set_semaphore_name);
+debug_name!(Fence, set_fence_name);
+debug_name!(DescriptorSetLayout, set_descriptor_set_layout_name);
+
+// Only does anything with the validation feature enabled
+#[cfg(feature = "validation")]
+pub fn set_debug_name(device: &back::Device, handle: &mut impl DebugName, name: &str) {
+    unsafe { handle.name(device, name) }
+}
+
+#[cfg(not(feature = "validation"))]
+pub fn set_debug_name(_device: &back::Device, _handle: &mut impl DebugName, _name: &str) {}
@@ -0,0 +1,203 @@
+use crate::{
+    drawing::DrawCommand,
+    utils::{self, Vec2},
+    vertex::{self, Vertex},
+    GfxConfig,
+};
+use std::f32::consts::PI;
+use winit::event::VirtualKeyCode;
+
+// Cells along each side of the grid demo
+pub const GRID_CELLS: usize = 4;
+
+// Triangles around the circle demo's center
+pub const CIRCLE_SEGMENTS: usize = 32;
+
+// Room left between grid cells, as a fraction of each
+const GRID_GAP: f32 = 0.1;
+
+const GRID_VERTICES: usize = GRID_CELLS * GRID_CELLS * 4;
+const GRID_INDICES: usize = GRID_CELLS * GRID_CELLS * 6;
+
+// What the number keys switch between. All but the triangle are
+// packed into the shared vertex and index buffers after the quad,
+// so switching never allocates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Demo {
+    Triangle,
+    Quad,
+    Grid,
+    Circle,
+}
+
+impl Demo {
+    pub const ALL: [Demo; 4] = [Demo::Triangle, Demo::Quad, Demo::Grid, Demo::Circle];
+
+    pub const GRID: DrawCommand = DrawCommand {
+        first_index: utils::QUAD_INDICES.len() as u32,
+        index_count: GRID_INDICES as u32,
+        base_vertex: utils::QUAD_DATA.len() as i32,
+        line_width: 1.0,
+    };
+
+    pub const CIRCLE: DrawCommand = DrawCommand {
+        first_index: (utils::QUAD_INDICES.len() + GRID_INDICES) as u32,
+        index_count: (CIRCLE_SEGMENTS * 3) as u32,
+        base_vertex: (utils::QUAD_DATA.len() + GRID_VERTICES) as i32,
+        line_width: 1.0,
+    };
+
+    pub fn from_key(key: VirtualKeyCode) -> Option<Self> {
+        match key {
+            VirtualKeyCode::Key1 => Some(Demo::Triangle),
+            VirtualKeyCode::Key2 => Some(Demo::Quad),
+            VirtualKeyCode::Key3 => Some(Demo::Grid),
+            VirtualKeyCode::Key4 => Some(Demo::Circle),
+            _ => None,
+        }
+    }
+
+    // The triangle has buffers of its own, and ignores this
+    pub fn draw(self) -> DrawCommand {
+        match self {
+            Demo::Triangle | Demo::Quad => DrawCommand::QUAD,
+            Demo::Grid => Demo::GRID,
+            Demo::Circle => Demo::CIRCLE,
+        }
+    }
+
+    // Whether its pipeline tests against the depth buffer. The
+    // triangle's pipeline has no depth state. The rest go through the
+    // scene pipeline, which tests at GfxConfig::flat_depth_comparison.
+    pub fn depth_tested(self) -> bool {
+        self != Demo::Triangle
+    }
+
+    pub fn apply(self, config: &mut GfxConfig) {
+        config.triangle_example = self == Demo::Triangle;
+    }
+
+    // The one the config starts out drawing
+    pub fn current(config: &GfxConfig) -> Self {
+        if config.triangle_example {
+            Demo::Triangle
+        } else {
+            Demo::Quad
+        }
+    }
+}
+
+// Everything in the shared vertex and index buffers, the quad first
+// so that DrawCommand::QUAD and the scene nodes still find it there
+pub struct DemoMeshes {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u16>,
+}
+
+impl DemoMeshes {
+    pub fn new() -> Self {
+        let mut vertices = utils::QUAD_DATA.to_vec();
+        let mut indices = utils::QUAD_INDICES.to_vec();
+
+        // Each cell is a smaller copy of the quad, and indices
+        // restart at each mesh since draws add the base vertex
+        let cell = 1.0 / GRID_CELLS as f32;
+        let half = cell * (1.0 - GRID_GAP) * 0.5;
+        for row in 0..GRID_CELLS {
+            for column in 0..GRID_CELLS {
+                let center = Vec2 {
+                    x: -0.5 + cell * (column as f32 + 0.5),
+                    y: -0.5 + cell * (row as f32 + 0.5),
+                };
+                let first = (vertices.len() - Demo::GRID.base_vertex as usize) as u16;
+                vertices.extend(utils::QUAD_DATA.iter().map(|corner| Vertex {
+                    position: Vec2 {
+                        x: center.x + corner.position.x * 2.0 * half,
+                        y: center.y + corner.position.y * 2.0 * half,
+                    },
+                }));
+                indices.extend(utils::QUAD_INDICES.iter().map(|&i| first + i));
+            }
+        }
+
+        // A fan around the center, wound the same way as the quad
+        vertices.push(Vertex {
+            position: Vec2 { x: 0.0, y: 0.0 },
+        });
+        for i in 0..CIRCLE_SEGMENTS {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+            vertices.push(Vertex {
+                position: Vec2 {
+                    x: 0.5 * angle.cos(),
+                    y: 0.5 * angle.sin(),
+                },
+            });
+            let rim = |i: usize| (1 + i % CIRCLE_SEGMENTS) as u16;
+            indices.extend_from_slice(&[0, rim(i + 1), rim(i)]);
+        }
+
+        Self { vertices, indices }
+    }
+
+    // Culling relies on every mesh winding the same way
+    pub fn check_winding(&self) -> Result<(), &'static str> {
+        for demo in Demo::ALL.iter() {
+            let draw = demo.draw();
+            draw.validate(&self.indices, self.vertices.len())?;
+            let positions: Vec<_> = draw
+                .deindex(&self.vertices, &self.indices)
+                .iter()
+                .map(|vertex| vertex.position)
+                .collect();
+            let indices: Vec<u16> = (0..positions.len() as u16).collect();
+            vertex::check_winding(&positions, &indices)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meshes_are_valid_and_wound_alike() {
+        let meshes = DemoMeshes::new();
+        assert!(meshes.check_winding().is_ok());
+    }
+
+    #[test]
+    fn meshes_fill_the_buffers_exactly() {
+        let meshes = DemoMeshes::new();
+        let last = Demo::CIRCLE;
+        assert_eq!(
+            meshes.indices.len(),
+            (last.first_index + last.index_count) as usize
+        );
+        assert_eq!(
+            meshes.vertices.len(),
+            last.base_vertex as usize + CIRCLE_SEGMENTS + 1
+        );
+    }
+
+    #[test]
+    fn number_keys_pick_demos() {
+        assert_eq!(Demo::from_key(VirtualKeyCode::Key1), Some(Demo::Triangle));
+        assert_eq!(Demo::from_key(VirtualKeyCode::Key4), Some(Demo::Circle));
+        assert_eq!(Demo::from_key(VirtualKeyCode::Key5), None);
+    }
+
+    #[test]
+    fn switching_follows_the_depth_state() {
+        let mut config = GfxConfig::default();
+        for &from in Demo::ALL.iter() {
+            for &to in Demo::ALL.iter() {
+                from.apply(&mut config);
+                to.apply(&mut config);
+                assert_eq!(Demo::current(&config).depth_tested(), to.depth_tested());
+                // Only the triangle leaves the scene pipeline
+                assert_eq!(config.triangle_example, !to.depth_tested());
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    device::Device,
+    pso::{self, DescriptorPool, DescriptorRangeDesc},
+    Backend,
+};
+use std::iter;
+
+// A set handed out by the allocator, remembering
+// where it came from so it can be returned
+pub struct DescriptorAllocation {
+    pub set: <back::Backend as Backend>::DescriptorSet,
+    pool: usize,
+    ranges: Vec<DescriptorRangeDesc>,
+}
+
+// What a pool has left, counted on our side
+// since Vulkan doesn't report it
+#[derive(Clone, Debug, PartialEq)]
+struct PoolCounts {
+    sets_left: usize,
+    // Remaining descriptors of each type
+    descriptors_left: Vec<DescriptorRangeDesc>,
+}
+
+impl PoolCounts {
+    fn new(sets: usize, ranges: &[DescriptorRangeDesc]) -> Self {
+        Self {
+            sets_left: sets,
+            descriptors_left: ranges.to_vec(),
+        }
+    }
+
+    fn fits(&self, ranges: &[DescriptorRangeDesc]) -> bool {
+        self.sets_left > 0
+            && ranges
+                .iter()
+                .all(|need| available(&self.descriptors_left, need))
+    }
+
+    fn take(&mut self, ranges: &[DescriptorRangeDesc]) {
+        self.sets_left -= 1;
+        for need in ranges {
+            if let Some(left) = self
+                .descriptors_left
+                .iter_mut()
+                .find(|left| left.ty == need.ty)
+            {
+                left.count -= need.count;
+            }
+        }
+    }
+
+    fn give_back(&mut self, ranges: &[DescriptorRangeDesc]) {
+        self.sets_left += 1;
+        for freed in ranges {
+            if let Some(left) = self
+                .descriptors_left
+                .iter_mut()
+                .find(|left| left.ty == freed.ty)
+            {
+                left.count += freed.count;
+            }
+        }
+    }
+}
+
+struct PoolInfo {
+    pool: <back::Backend as Backend>::DescriptorPool,
+    counts: PoolCounts,
+}
+
+// Creates descriptor pools as they are needed so that
+// nobody has to size a pool for every possible set up front
+pub struct DescriptorAllocator {
+    sets_per_pool: usize,
+    // Descriptors of each type given to every new pool
+    pool_ranges: Vec<DescriptorRangeDesc>,
+    pools: Vec<PoolInfo>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(sets_per_pool: usize, pool_ranges: Vec<DescriptorRangeDesc>) -> Self {
+        Self {
+            sets_per_pool,
+            pool_ranges,
+            pools: vec![],
+        }
+    }
+
+    // The ranges describe how many descriptors of each type the layout uses
+    pub fn allocate(
+        &mut self,
+        device: &back::Device,
+        layout: &<back::Backend as Backend>::DescriptorSetLayout,
+        ranges: &[DescriptorRangeDesc],
+    ) -> Result<DescriptorAllocation, &'static str> {
+        if !ranges.iter().all(|need| available(&self.pool_ranges, need)) {
+            return Err("Descriptor set is larger than a whole descriptor pool");
+        }
+
+        let existing = first_fit(self.pools.iter().map(|info| &info.counts), ranges);
+        let pool = match existing {
+            Some(pool) => pool,
+            None => self.add_pool(device)?,
+        };
+
+        match self.allocate_from(pool, layout, ranges) {
+            Ok(allocation) => Ok(allocation),
+            // Our counts are only an estimate of what the driver has left,
+            // so fall back to a fresh pool if it disagrees
+            Err(_) => {
+                let pool = self.add_pool(device)?;
+                self.allocate_from(pool, layout, ranges)
+            }
+        }
+    }
+
+    // Returns the set to its pool to be allocated again.
+    // Sets from pools already destroyed went with them.
+    pub fn free(&mut self, allocation: DescriptorAllocation) -> Result<(), &'static str> {
+        let info = self
+            .pools
+            .get_mut(allocation.pool)
+            .ok_or("Descriptor set freed after its pool")?;
+        unsafe { info.pool.free_sets(iter::once(allocation.set)) }
+        info.counts.give_back(&allocation.ranges);
+        Ok(())
+    }
+
+    // Destroys every pool, along with any sets still allocated from them
+    pub fn free_pools(&mut self, device: &back::Device) {
+        for info in self.pools.drain(..) {
+            unsafe { device.destroy_descriptor_pool(info.pool) }
+        }
+    }
+
+    fn allocate_from(
+        &mut self,
+        pool: usize,
+        layout: &<back::Backend as Backend>::DescriptorSetLayout,
+        ranges: &[DescriptorRangeDesc],
+    ) -> Result<DescriptorAllocation, &'static str> {
+        let set = unsafe { self.pools[pool].pool.allocate_set(layout) }
+            .map_err(|_| "Could not allocate descriptor set")?;
+        self.pools[pool].counts.take(ranges);
+        Ok(DescriptorAllocation {
+            set,
+            pool,
+            ranges: ranges.to_vec(),
+        })
+    }
+
+    fn add_pool(&mut self, device: &back::Device) -> Result<usize, &'static str> {
+        let pool = unsafe {
+            device.create_descriptor_pool(
+                self.sets_per_pool,
+                &self.pool_ranges,
+                // Needed to return individual sets
+                pso::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            )
+        }
+        .map_err(|_| "Could not create descriptor pool")?;
+
+        self.pools.push(PoolInfo {
+            pool,
+            counts: PoolCounts::new(self.sets_per_pool, &self.pool_ranges),
+        });
+        Ok(self.pools.len() - 1)
+    }
+}
+
+// The first pool with room for the set, if any
+fn first_fit<'a>(
+    mut pools: impl Iterator<Item = &'a PoolCounts>,
+    ranges: &[DescriptorRangeDesc],
+) -> Option<usize> {
+    pools.position(|counts| counts.fits(ranges))
+}
+
+fn available(ranges: &[DescriptorRangeDesc], need: &DescriptorRangeDesc) -> bool {
+    ranges
+        .iter()
+        .any(|range| range.ty == need.ty && range.count >= need.count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samplers(count: usize) -> Vec<DescriptorRangeDesc> {
+        vec![DescriptorRangeDesc {
+            ty: pso::DescriptorType::CombinedImageSampler,
+            count,
+        }]
+    }
+
+    // What the allocator does, without the pools themselves
+    fn allocate(pools: &mut Vec<PoolCounts>, ranges: &[DescriptorRangeDesc]) -> usize {
+        let pool = match first_fit(pools.iter(), ranges) {
+            Some(pool) => pool,
+            None => {
+                pools.push(PoolCounts::new(2, &samplers(4)));
+                pools.len() - 1
+            }
+        };
+        pools[pool].take(ranges);
+        pool
+    }
+
+    #[test]
+    fn a_new_pool_is_added_once_one_is_full() {
+        let mut pools = vec![];
+        let set = samplers(1);
+        let placed: Vec<usize> = (0..5).map(|_| allocate(&mut pools, &set)).collect();
+        // Two sets to a pool
+        assert_eq!(placed, vec![0, 0, 1, 1, 2]);
+        assert_eq!(pools.len(), 3);
+    }
+
+    #[test]
+    fn descriptors_run_out_before_sets() {
+        let mut pools = vec![];
+        assert_eq!(allocate(&mut pools, &samplers(3)), 0);
+        // A set left, but only one sampler
+        assert_eq!(allocate(&mut pools, &samplers(2)), 1);
+        assert_eq!(allocate(&mut pools, &samplers(1)), 0);
+    }
+
+    #[test]
+    fn freeing_makes_room_again() {
+        let mut pools = vec![];
+        let set = samplers(1);
+        allocate(&mut pools, &set);
+        allocate(&mut pools, &set);
+        pools[0].give_back(&set);
+        assert_eq!(allocate(&mut pools, &set), 0);
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0], PoolCounts::new(0, &samplers(2)));
+    }
+}
@@ -0,0 +1,101 @@
+use gfx_hal::Features;
+
+// gfx-hal has no way to name device extensions when opening the
+// device. The Vulkan backend always enables VK_KHR_swapchain, and
+// enables the others it knows about only when a feature that needs
+// one is asked for. So an extension can only be requested through
+// its feature. The ones listed without a feature are known to be
+// asked for but have no way in, so they're reported as such rather
+// than as missing from the device.
+const EXTENSION_FEATURES: &[(&str, Option<Features>)] = &[
+    // Always on, since presenting needs it
+    ("VK_KHR_swapchain", Some(Features::empty())),
+    // Negative viewport heights
+    ("VK_KHR_maintenance1", Some(Features::NDC_Y_UP)),
+    // Core in Vulkan 1.2, but the backend only targets 1.0
+    ("VK_KHR_timeline_semaphore", None),
+    // Heap budgets, see MemoryBudget for the estimate used instead
+    ("VK_EXT_memory_budget", None),
+    ("VK_KHR_draw_indirect_count", None),
+];
+
+// What became of each extension asked for
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Negotiated<'a> {
+    // The device was opened with these
+    pub enabled: Vec<&'a str>,
+    // Reachable, but the adapter lacks the feature behind them
+    pub unsupported: Vec<&'a str>,
+    // Nothing in gfx-hal turns these on, or they aren't known here
+    pub unreachable: Vec<&'a str>,
+}
+
+// The features that stand in for the extensions, to open the device with
+pub fn features(extensions: &[&str]) -> Features {
+    extensions
+        .iter()
+        .filter_map(|&name| feature(name))
+        .fold(Features::empty(), |all, feature| all | feature)
+}
+
+// Sorts the extensions by whether the device was opened with them,
+// given the features it got. The features were already intersected
+// with the adapter's, which is how an extension the adapter lacks
+// ends up unsupported.
+pub fn negotiate<'a>(extensions: &[&'a str], enabled: Features) -> Negotiated<'a> {
+    let mut negotiated = Negotiated::default();
+    for &name in extensions {
+        match feature(name) {
+            Some(feature) if enabled.contains(feature) => negotiated.enabled.push(name),
+            Some(_) => negotiated.unsupported.push(name),
+            None => negotiated.unreachable.push(name),
+        }
+    }
+    negotiated
+}
+
+fn feature(extension: &str) -> Option<Features> {
+    EXTENSION_FEATURES
+        .iter()
+        .find(|(name, _)| *name == extension)
+        .and_then(|&(_, feature)| feature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASKED: &[&str] = &[
+        "VK_KHR_swapchain",
+        "VK_KHR_maintenance1",
+        "VK_KHR_timeline_semaphore",
+        "VK_made_up",
+    ];
+
+    #[test]
+    fn only_reachable_extensions_add_features() {
+        assert_eq!(features(ASKED), Features::NDC_Y_UP);
+    }
+
+    #[test]
+    fn extensions_follow_the_enabled_features() {
+        let negotiated = negotiate(ASKED, Features::NDC_Y_UP);
+        assert_eq!(
+            negotiated.enabled,
+            vec!["VK_KHR_swapchain", "VK_KHR_maintenance1"]
+        );
+        assert!(negotiated.unsupported.is_empty());
+        assert_eq!(
+            negotiated.unreachable,
+            vec!["VK_KHR_timeline_semaphore", "VK_made_up"]
+        );
+    }
+
+    #[test]
+    fn adapter_without_the_feature_leaves_it_unsupported() {
+        let negotiated = negotiate(ASKED, Features::empty());
+        // Presenting doesn't depend on any feature
+        assert_eq!(negotiated.enabled, vec!["VK_KHR_swapchain"]);
+        assert_eq!(negotiated.unsupported, vec!["VK_KHR_maintenance1"]);
+    }
+}
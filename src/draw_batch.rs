@@ -0,0 +1,187 @@
+use crate::{
+    debug_names::set_debug_name, drawing::DrawCommand, transform::Mat4, BufferInfo, PipelineInfo,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{adapter::Adapter, buffer::Usage, device::Device};
+use std::{mem::size_of, ops::Range, ptr};
+
+// A mesh to draw once, placed by its own transform. Generic over
+// the pipeline only so batching can be tested without a device.
+pub struct MeshDraw<'a, P = PipelineInfo> {
+    pub pipeline: &'a P,
+    pub mesh: DrawCommand,
+    pub transform: Mat4,
+}
+
+// Derived impls would want the pipeline to be Copy as well
+impl<P> Copy for MeshDraw<'_, P> {}
+
+impl<P> Clone for MeshDraw<'_, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+// A run of draws sharing a pipeline and mesh, drawn with one
+// instanced call. Instances index the batched transforms.
+pub struct Batch<'a, P = PipelineInfo> {
+    pub pipeline: &'a P,
+    pub mesh: DrawCommand,
+    pub instances: Range<u32>,
+}
+
+impl<P> Clone for Batch<'_, P> {
+    fn clone(&self) -> Self {
+        Self {
+            pipeline: self.pipeline,
+            mesh: self.mesh,
+            instances: self.instances.clone(),
+        }
+    }
+}
+
+// Merges consecutive draws of the same mesh with the same pipeline.
+// Only neighbours are merged, so the draw order is kept, and a
+// draw that differs starts a new batch. The transforms come back
+// in instance order, ready to upload.
+pub fn batch<'a, P>(draws: &[MeshDraw<'a, P>]) -> (Vec<Batch<'a, P>>, Vec<Mat4>) {
+    let mut batches: Vec<Batch<'a, P>> = vec![];
+    let mut transforms = Vec::with_capacity(draws.len());
+    for draw in draws {
+        let instance = transforms.len() as u32;
+        transforms.push(draw.transform);
+        match batches.last_mut() {
+            // Pipelines are compared by address, since
+            // two built the same way are still separate
+            Some(last) if ptr::eq(last.pipeline, draw.pipeline) && last.mesh == draw.mesh => {
+                last.instances.end = instance + 1;
+            }
+            _ => batches.push(Batch {
+                pipeline: draw.pipeline,
+                mesh: draw.mesh,
+                instances: instance..instance + 1,
+            }),
+        }
+    }
+    (batches, transforms)
+}
+
+// Per-instance transforms for batched draws, rewritten every frame
+#[derive(Default)]
+pub struct InstanceTransforms {
+    // A buffer per frame in flight, created on first upload. Each
+    // only grows once its own frame's fence has signalled, so nothing
+    // still in flight can be reading it and the device never idles.
+    frames: Vec<Option<FrameTransforms>>,
+}
+
+struct FrameTransforms {
+    buffer: BufferInfo,
+    // Number of transforms it can hold
+    capacity: usize,
+}
+
+impl InstanceTransforms {
+    // Only after the frame's upload
+    pub fn buffer(&self, frame: usize) -> Option<&BufferInfo> {
+        self.frames
+            .get(frame)
+            .and_then(Option::as_ref)
+            .map(|transforms| &transforms.buffer)
+    }
+
+    // Once the frame's fence shows it's done with its buffer. The
+    // number of frames only changes with the device idle.
+    pub fn upload(
+        &mut self,
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        frame: usize,
+        frames: usize,
+        transforms: &[Mat4],
+    ) -> Result<(), &'static str> {
+        if frames != self.frames.len() {
+            self.free(device);
+            self.frames.resize_with(frames, || None);
+        }
+
+        let slot = &mut self.frames[frame];
+        let capacity = slot.as_ref().map_or(0, |transforms| transforms.capacity);
+        if transforms.len() > capacity || slot.is_none() {
+            // Grows geometrically like the sprite batch
+            let capacity = transforms.len().next_power_of_two().max(capacity);
+            if let Some(mut old) = slot.take() {
+                old.buffer.free(device);
+            }
+
+            let mut buffer = BufferInfo::with_size(
+                device,
+                adapter,
+                (capacity * size_of::<Mat4>()) as u64,
+                Usage::VERTEX,
+            )?;
+            if let Err(e) = buffer.map_persistent(device) {
+                buffer.free(device);
+                return Err(e);
+            }
+            set_debug_name(device, &mut *buffer.buffer, "instance_transforms");
+            *slot = Some(FrameTransforms { buffer, capacity });
+        }
+
+        match slot {
+            Some(slot) => slot.buffer.write(device, 0, transforms),
+            None => Ok(()),
+        }
+    }
+
+    pub fn free(&mut self, device: &back::Device) {
+        for slot in self.frames.drain(..) {
+            if let Some(mut transforms) = slot {
+                transforms.buffer.free(device);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stand-ins for pipelines, which are only compared by address
+    fn draw(pipeline: &u8) -> MeshDraw<u8> {
+        MeshDraw {
+            pipeline,
+            mesh: DrawCommand::QUAD,
+            transform: Mat4::IDENTITY,
+        }
+    }
+
+    #[test]
+    fn identical_draws_make_one_batch() {
+        let pipeline = 0;
+        let draws = vec![draw(&pipeline); 10];
+        let (batches, transforms) = batch(&draws);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].instances, 0..10);
+        assert_eq!(transforms.len(), 10);
+    }
+
+    #[test]
+    fn another_pipeline_starts_a_batch() {
+        let (first, second) = (0, 0);
+        let draws = [draw(&first), draw(&first), draw(&second), draw(&first)];
+        let (batches, _) = batch(&draws);
+        let instances: Vec<_> = batches.iter().map(|b| b.instances.clone()).collect();
+        assert_eq!(instances, vec![0..2, 2..3, 3..4]);
+        assert!(ptr::eq(batches[1].pipeline, &second));
+    }
+
+    #[test]
+    fn another_mesh_starts_a_batch() {
+        let pipeline = 0;
+        let mut other = draw(&pipeline);
+        other.mesh.first_index += 6;
+        let (batches, _) = batch(&[draw(&pipeline), other]);
+        assert_eq!(batches.len(), 2);
+    }
+}
@@ -1,113 +1,1388 @@
-use crate::{utils, GfxState};
+use crate::{
+    attachment_config, draw_batch,
+    frame_graph::{self, AttachmentUse, FrameGraph, Pass},
+    gfx_state,
+    indirect_draw::DrawIndexedIndirectCommand,
+    picking, pipeline_info,
+    scene::Scene,
+    swapchain,
+    transform::Mat4,
+    utils, Batch, BufferInfo, ClearValues, ClearValuesBuilder, CpuFrameTiming, DebugDraw,
+    GfxConfig, GfxError, GfxState, GpuModel, IndirectDraw, InstanceTransforms, MeshDraw, Offscreen,
+    Picking, PipelineInfo, PixelBlit, SpriteBatch, StagedBuffer, StaticScene,
+};
 use arrayvec::ArrayVec;
+use gfx_backend_vulkan as back;
 use gfx_hal::{
-    buffer::IndexBufferView,
+    adapter::PhysicalDevice,
+    buffer::{self, IndexBufferView},
     command::{self, CommandBuffer},
     device::Device,
-    pso,
+    format::{ChannelType, Format},
+    image, pso,
     queue::{CommandQueue, Submission},
-    window::Swapchain,
-    IndexType,
+    window::Swapchain as _,
+    Backend, IndexType,
 };
-use std::mem;
+use std::{mem, ops::Range, ptr, time::Instant};
+
+// A range of the shared index buffer to draw,
+// for sub-meshes packed into combined buffers
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DrawCommand {
+    pub first_index: u32,
+    pub index_count: u32,
+    // Added to each index before reading the vertex buffer
+    pub base_vertex: i32,
+    // Width in pixels where the pipeline draws lines, already within
+    // the device's range, see gfx_state::line_width. Set with each
+    // draw so edges can be emphasized one at a time.
+    pub line_width: f32,
+}
+
+impl DrawCommand {
+    // Both triangles of the quad
+    pub const QUAD: Self = Self {
+        first_index: 0,
+        index_count: utils::QUAD_INDICES.len() as u32,
+        base_vertex: 0,
+        line_width: 1.0,
+    };
+
+    fn indices(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
+
+    // Out of range reads are undefined behaviour on the GPU,
+    // so check against the data that was uploaded
+    pub fn validate(&self, indices: &[u16], vertex_count: usize) -> Result<(), &'static str> {
+        let range = self.indices();
+        let range = range.start as usize..range.end as usize;
+        let used = indices
+            .get(range)
+            .ok_or("Draw command reads past the end of the index buffer")?;
+
+        let in_bounds = used.iter().all(|&index| {
+            let vertex = index as i64 + self.base_vertex as i64;
+            vertex >= 0 && (vertex as usize) < vertex_count
+        });
+        if in_bounds {
+            Ok(())
+        } else {
+            Err("Draw command reads past the end of the vertex buffer")
+        }
+    }
+
+    // The vertices the command reads, in order, for drawing the same
+    // triangles without an index buffer. Must already be validated.
+    pub fn deindex<V: Copy>(&self, vertices: &[V], indices: &[u16]) -> Vec<V> {
+        let range = self.indices();
+        indices[range.start as usize..range.end as usize]
+            .iter()
+            .map(|&index| vertices[(index as i64 + self.base_vertex as i64) as usize])
+            .collect()
+    }
+}
 
 pub fn draw_frame(
     state: &mut GfxState,
-    color: [f32; 4],
+    color: utils::Vec4,
     mouse: utils::Vec2,
-) -> Result<(), &'static str> {
-    let image_available = &state.image_available_semaphores[state.current_frame];
-    let render_finished = &state.render_finished_semaphores[state.current_frame];
-    state.current_frame = (state.current_frame + 1) % utils::FRAMES_IN_FLIGHT;
+    tint: utils::Vec4,
+    // Seconds, for anything animated on the GPU
+    time: f32,
+    draw: DrawCommand,
+    scene: &Scene,
+    // Part of the target to draw the scene into, or all of it
+    area: Option<pso::Rect>,
+) -> Result<(), GfxError> {
+    draw.validate(&state.meshes.indices, state.meshes.vertices.len())?;
+    // Render passes still cover the whole target, since the clear
+    // skips anything outside them. Only the viewport and scissor
+    // follow the area.
+    let full = state.swapchain.content_size;
+    let area = area.unwrap_or(full);
+    check_area(area, full)?;
+    // The heatmap adds up from black
+    let color = if state.config.overdraw {
+        utils::Vec4::new(0.0, 0.0, 0.0, 1.0)
+    } else {
+        color
+    };
+    // Before any waits, since replacing it idles the device
+    if !state.indexed {
+        state.prepare_flat_vertices(draw)?;
+    }
 
-    let (image_i, _suboptimal) = unsafe {
+    let frame_i = state.current_frame;
+    let frame = &state.frames[frame_i];
+    state.current_frame = (state.current_frame + 1) % state.frames.len();
+
+    // Only blocks once the GPU falls a full flight behind,
+    // so recording overlaps with the other frames' GPU work
+    let wait_start = Instant::now();
+    unsafe {
         state
-            .swapchain
-            .acquire_image(core::u64::MAX, Some(image_available), None)
+            .device
+            .wait_for_fence(&frame.in_flight, core::u64::MAX)
     }
-    .map_err(|_| "Failed to acquire an image from the swapchain")?;
-    let image_i = image_i as usize;
+    .map_err(GfxError::from)?;
 
-    let flight_fence = &state.in_flight_fences[image_i];
-    unsafe { state.device.wait_for_fence(flight_fence, core::u64::MAX) }
-        .map_err(|_| "Failed to wait on the fence")?;
-    unsafe { state.device.reset_fence(flight_fence) }.map_err(|_| "Failed to reset the fence")?;
+    // Its present waits on the render finishing, so once its fence
+    // signals no other rendering is queued ahead of this one. Its
+    // present may still be, which no fence or semaphore here covers.
+    if state.config.single_present {
+        let previous = (frame_i + state.frames.len() - 1) % state.frames.len();
+        let fence = &state.frames[previous].in_flight;
+        unsafe { state.device.wait_for_fence(fence, core::u64::MAX) }.map_err(GfxError::from)?;
+    }
 
-    state.vertices.load_data(&state.device, &utils::QUAD_DATA)?;
+    // Anything that can fail goes before the acquire. Returning after
+    // it would leave the semaphore signalled with nothing to wait on it,
+    // and the next acquire with the same semaphore isn't allowed.
+    if let Some(pixels) = &state.pixels {
+        pixels.write(&state.device, frame_i)?;
+    }
+    state
+        .debug_draw
+        .upload(&state.device, &state.adapter, frame_i, state.frames.len())?;
+    let frames = state.frames.len();
     state
-        .indices
-        .load_data(&state.device, &utils::QUAD_INDICES)?;
+        .sprites
+        .upload(&state.device, &state.adapter, frame_i, frames)?;
+    state
+        .particle_sprites
+        .upload(&state.device, &state.adapter, frame_i, frames)?;
+    // Every node shares the quad, so they usually
+    // collapse into a single instanced draw
+    let node_draws: Vec<MeshDraw> = scene
+        .world_matrices()
+        .into_iter()
+        .map(|world| MeshDraw {
+            pipeline: &state.node_pipeline,
+            mesh: DrawCommand::QUAD,
+            transform: world,
+        })
+        .collect();
+    let (mut node_batches, transforms) = draw_batch::batch(&node_draws);
+    // The same batches again with edges only, after all of the fill.
+    // Both read the same vertices, indices and instance transforms.
+    if let (true, Some(wireframe)) = (state.config.wireframe, &state.wireframe_pipeline) {
+        let line_width = gfx_state::line_width(
+            &state.config,
+            state.features,
+            state.adapter.physical_device.limits().line_width_range,
+        );
+        let edges: Vec<Batch> = node_batches
+            .iter()
+            .map(|batch| Batch {
+                pipeline: wireframe,
+                mesh: DrawCommand {
+                    line_width,
+                    ..batch.mesh
+                },
+                ..batch.clone()
+            })
+            .collect();
+        node_batches.extend(edges);
+    }
+    state.node_instances.upload(
+        &state.device,
+        &state.adapter,
+        frame_i,
+        state.frames.len(),
+        &transforms,
+    )?;
+
+    // Resolved before recording, so a stale handle can't
+    // leave a command buffer half recorded
+    let vertices = state
+        .resources
+        .buffer(state.vertices)
+        .ok_or("Vertex buffer handle is stale")?;
+    let indices = state
+        .resources
+        .buffer(state.indices)
+        .ok_or("Index buffer handle is stale")?;
+    // The pass the scene is drawn in, and its part of the target.
+    // Projections stay in window pixels, only the viewport follows the scale.
+    let (scene_pass, viewport) = match &state.offscreen {
+        Some(offscreen) => (
+            &*offscreen.render_pass,
+            scale_area(area, full, offscreen.content_size),
+        ),
+        None => (&*state.render_pass, area),
+    };
+    if let Some(static_scene) = &mut state.static_scene {
+        static_scene.update(
+            &state.device,
+            &state.adapter,
+            frame_i,
+            state.frames.len(),
+            scene_pass,
+            state.pass_generation,
+            &node_batches,
+            &transforms,
+            vertices,
+            indices,
+            tint,
+            viewport,
+            state.config.depth_range(),
+            area,
+        )?;
+    }
+
+    // Follows the scene's target, so it stays current across resizes
+    let target = match &state.offscreen {
+        Some(offscreen) => offscreen.content_size,
+        None => full,
+    };
+    // Frames still in flight keep reading their own
+    if let Some(uniforms) = &state.gradient_uniforms {
+        uniforms.write(&state.device, frame_i, gradient_uniforms(target, time))?;
+    }
+
+    let offscreen_clear = clear_values(
+        color,
+        swapchain::FORMAT,
+        state.config.offscreen_samples(),
+        state.config.clear_depth,
+    )?;
+    let swapchain_clear = clear_values(
+        color,
+        state.swapchain.format,
+        state.config.swapchain_samples(),
+        state.config.clear_depth,
+    )?;
+
+    let passes = frame_graph(state.offscreen.as_ref()).compile()?;
+
+    let acquire_start = Instant::now();
+    let swapchain = state
+        .swapchain
+        .handle
+        .as_mut()
+        .ok_or("The swapchain could not be recreated")?;
+    let (image_i, _suboptimal) =
+        unsafe { swapchain.acquire_image(core::u64::MAX, Some(&frame.image_available), None) }
+            .map_err(GfxError::from)?;
+    // Plain fields rather than anything that allocates per span
+    let mut timing = CpuFrameTiming {
+        acquire: acquire_start.elapsed(),
+        ..CpuFrameTiming::default()
+    };
+    state.acquire_latency.push(timing.acquire);
+    let image_i = image_i as usize;
+    // Nothing has been drawn into it since the swapchain was made,
+    // or it comes back from the present family with nothing to load
+    let first_use = state.images_in_flight[image_i].is_none() || state.present_handoff.is_some();
+
+    // The image's command buffer may still be in use
+    // by a different frame that drew to the same image
+    if let Some(previous) = state.images_in_flight[image_i] {
+        if previous != frame_i {
+            let fence = &state.frames[previous].in_flight;
+            unsafe { state.device.wait_for_fence(fence, core::u64::MAX) }
+                .map_err(GfxError::from)?;
+        }
+    }
+    state.images_in_flight[image_i] = Some(frame_i);
+    state.render_fence_wait = wait_start.elapsed();
+
+    // The fence also covers this frame's last statistics query
+    if let Some(stats) = &mut state.stats {
+        stats.read(&state.device, frame_i);
+    }
+    // Likewise for the copy of the ID under the cursor
+    if let Some(picking) = &mut state.picking {
+        picking.read(&state.device, frame_i);
+    }
+
+    let resolution = utils::Vec2 {
+        x: target.w as f32,
+        y: target.h as f32,
+    };
+    // Picking still wants it in window space
+    let scene_mouse = scene_mouse(mouse, state.config.flip_y);
+
+    // The swapchain pass to begin, which has nothing to load the first time
+    let swapchain_pass = match (&state.first_pass, first_use) {
+        (Some(first_pass), true) => first_pass,
+        _ => &*state.render_pass,
+    };
+    // Compatible with scene_pass, which pipelines
+    // and secondary buffers are built against
+    let begin_pass = match &state.offscreen {
+        Some(offscreen) => offscreen.begin_pass(),
+        None => swapchain_pass,
+    };
+
+    let record_start = Instant::now();
 
     {
-        let commands = &mut state.command_buffers[image_i];
-        let buffers: ArrayVec<[_; 1]> = [(&*state.vertices.buffer, 0)].into();
+        let commands = match state.frame_pools.get_mut(frame_i) {
+            // The fence wait above covers everything recorded from it
+            Some(pool) => {
+                unsafe { pool.reset() };
+                &mut pool.commands
+            }
+            None => &mut state.command_buffers[image_i],
+        };
         unsafe {
             // A primary command buffer may optionally call into
             // secondary command buffers, which are usually prerecorded
             // steps the primary buffer can reuse or switch between
             commands.begin_primary(command::CommandBufferFlags::EMPTY);
-            commands.bind_graphics_pipeline(&state.pipeline.handle);
-            commands.bind_vertex_buffers(0, buffers);
+
+            // Queries have to be reset before they're written again
+            if let Some(stats) = &state.stats {
+                stats.reset(commands, frame_i);
+            }
+
+            // Compute can't be dispatched inside a render pass
+            if let Some(indirect) = &state.indirect {
+                indirect.record(commands, scene_mouse);
+            }
+            if let Some(pixels) = &state.pixels {
+                pixels.record_upload(commands, frame_i);
+            }
+            // Nothing after this can fail before the submit,
+            // which the copy is only recorded into once
+            for stream in &mut state.triangle {
+                stream.record_upload(commands);
+            }
+
+            // Written from the host, which may need more than a flush
+            vertices.record_host_barrier(commands, buffer::Access::VERTEX_BUFFER_READ);
+            indices.record_host_barrier(commands, buffer::Access::INDEX_BUFFER_READ);
+            if let Some((_, flat)) = &state.flat_vertices {
+                flat.record_host_barrier(commands, buffer::Access::VERTEX_BUFFER_READ);
+            }
+            for batch in &[&state.sprites, &state.particle_sprites] {
+                if let Some(instances) = &batch.instances {
+                    instances.record_host_barrier(commands, buffer::Access::VERTEX_BUFFER_READ);
+                }
+            }
+            if let Some(shapes) = &state.debug_draw.buffer {
+                shapes.record_host_barrier(commands, buffer::Access::VERTEX_BUFFER_READ);
+            }
+            if let Some(transforms) = state.node_instances.buffer(frame_i) {
+                transforms.record_host_barrier(commands, buffer::Access::VERTEX_BUFFER_READ);
+            }
+
+            // Spans every render pass, so it has to start outside them.
+            // Secondary buffers can't run inside it, see GfxConfig::static_scene.
+            let counted = state.static_scene.is_none();
+            if let (Some(stats), true) = (&mut state.stats, counted) {
+                stats.begin(commands, frame_i);
+            }
+
+            let draws = SceneDraws {
+                config: &state.config,
+                render_pass: scene_pass,
+                begin_pass,
+                pipeline: &state.pipeline,
+                gradient_pipeline: &state.gradient_pipeline,
+                gradient_set: state
+                    .gradient_uniforms
+                    .as_ref()
+                    .and_then(|uniforms| uniforms.set(frame_i)),
+                triangle_pipeline: &state.triangle_pipeline,
+                sprite_pipeline: &state.sprite_pipeline,
+                debug_pipeline: &state.debug_pipeline,
+                model_pipeline: state.model_pipeline.as_ref(),
+                model_depth_pipeline: state.model_depth_pipeline.as_ref(),
+                model: state.model.as_ref(),
+                vertices,
+                indices,
+                flat_vertices: state.flat_vertices.as_ref().map(|(_, flat)| flat),
+                indirect: state.indirect.as_ref(),
+                triangle: &state.triangle,
+                nodes: &node_batches,
+                node_instances: &state.node_instances,
+                sprites: &state.sprites,
+                particle_sprites: &state.particle_sprites,
+                sprite_set: state
+                    .sprite_texture
+                    .as_ref()
+                    .map(|texture| &texture.descriptor_set.set),
+                debug_draw: &state.debug_draw,
+                // Otherwise it goes over the offscreen copy
+                pixels: if state.offscreen.is_none() {
+                    state.pixels.as_ref()
+                } else {
+                    None
+                },
+                frame: frame_i,
+                draw,
+                mouse: scene_mouse,
+                tint,
+                resolution,
+                target,
+                viewport,
+                area,
+            };
+            // Indexed like the graph's attachments
+            let images: Vec<_> = state
+                .offscreen
+                .iter()
+                .map(|offscreen| &*offscreen.target.image)
+                .collect();
+            for compiled in &passes {
+                frame_graph::record_transitions(commands, &compiled.transitions, &images);
+                match (compiled.pass, &state.offscreen) {
+                    // Draw the scene into the offscreen target...
+                    (SCENE_PASS, Some(offscreen)) => record_scene_pass(
+                        commands,
+                        &draws,
+                        state.static_scene.as_mut(),
+                        &offscreen.framebuffer,
+                        offscreen.content_size,
+                        &offscreen_clear,
+                    ),
+                    // ...then sample it onto the swapchain image
+                    (_, Some(offscreen)) => {
+                        commands.begin_render_pass(
+                            swapchain_pass,
+                            &state.swapchain.framebuffers[image_i],
+                            full,
+                            swapchain_clear.iter(),
+                            command::SubpassContents::Inline,
+                        );
+                        set_area(commands, full, FULL_DEPTH);
+                        commands.bind_graphics_pipeline(&offscreen.pipeline.handle);
+                        offscreen
+                            .pipeline
+                            .bind_set(commands, 0, &offscreen.descriptor_set.set);
+                        // Fullscreen triangle
+                        commands.draw(0..3, 0..1);
+                        if let Some(pixels) = &state.pixels {
+                            pixels.record_blit(commands);
+                        }
+                        commands.end_render_pass();
+                    }
+                    // A renderpass is a bunch of work done with a
+                    // particular set of attachments.
+                    (_, None) => record_scene_pass(
+                        commands,
+                        &draws,
+                        state.static_scene.as_mut(),
+                        &state.swapchain.framebuffers[image_i],
+                        full,
+                        &swapchain_clear,
+                    ),
+                }
+            }
+
+            if let (Some(stats), true) = (&state.stats, counted) {
+                stats.end(commands, frame_i);
+            }
+
+            // After the query, so the extra pass isn't counted in the
+            // statistics. The readback needs to be outside a render pass.
+            let picking = state.picking.as_mut().filter(|picking| picking.hovered);
+            if let (Some(picking), Some(pixel)) = (picking, picking::cursor_pixel(full, mouse)) {
+                record_picking(
+                    commands, picking, vertices, indices, scene, full, area, frame_i, pixel,
+                );
+            }
+            // Before the image is handed over for presenting
+            if let Some(screenshot) = &state.screenshot {
+                if screenshot.awaits_frame() {
+                    screenshot.record_frame_copy(commands, &state.swapchain.images[image_i]);
+                }
+            }
+            // Last, once nothing else here uses the image
+            if let Some(handoff) = &state.present_handoff {
+                handoff.record_release(commands, &state.swapchain.images[image_i]);
+            }
+            commands.finish();
+        }
+    }
+    timing.record = record_start.elapsed();
+
+    // Reset only once a submission is certain to follow,
+    // otherwise the next wait on this frame would never return
+    let submit_start = Instant::now();
+    unsafe { state.device.reset_fence(&frame.in_flight) }
+        .map_err(|_| "Failed to reset the fence")?;
+
+    let commands = match state.frame_pools.get(frame_i) {
+        Some(pool) => &pool.commands,
+        None => &state.command_buffers[image_i],
+    };
+    let submission = Submission {
+        command_buffers: Some(commands),
+        wait_semaphores: frame.submit_waits(),
+        signal_semaphores: frame.render_signals(),
+    };
+
+    unsafe { state.queue_group.queues[0].submit(submission, Some(&frame.in_flight)) };
+    if let Some(screenshot) = &mut state.screenshot {
+        if screenshot.awaits_frame() {
+            screenshot.submit_after(&mut state.queue_group.queues[0]);
+        }
+    }
+    if let Some(offscreen) = &mut state.offscreen {
+        offscreen.drawn = true;
+    }
+    timing.submit = submit_start.elapsed();
+
+    // Presentation waits on the render finished semaphore. When the
+    // queues differ, the present queue acquires the image after it,
+    // and presentation waits on that instead.
+    let (present_queue, present_wait) =
+        match (&mut state.present_queue_group, &state.present_handoff) {
+            (Some(group), Some(handoff)) => {
+                let queue = &mut group.queues[0];
+                handoff.submit_acquire(queue, image_i, &frame.render_finished, &frame.handed_off);
+                (queue, &frame.handed_off)
+            }
+            _ => (&mut state.queue_group.queues[0], &frame.render_finished),
+        };
+    let present_start = Instant::now();
+    let presented = match &state.swapchain.handle {
+        Some(swapchain) => unsafe {
+            swapchain.present(present_queue, image_i as u32, Some(present_wait))
+        },
+        None => unreachable!("The swapchain was acquired from this frame"),
+    }
+    // Discard suboptimal warning
+    .map(|_| ())
+    .map_err(GfxError::from);
+    timing.present = present_start.elapsed();
+
+    state.cpu_timing = timing;
+    presented
+}
+
+// Everything drawn into the scene's pass, borrowed from the state
+// apart from its command buffers. The same recording goes inline,
+// or into secondary buffers around a static scene's nodes.
+struct SceneDraws<'a> {
+    config: &'a GfxConfig,
+    render_pass: &'a <back::Backend as Backend>::RenderPass,
+    // A compatible pass begun in its place, see AttachmentConfig::first_use
+    begin_pass: &'a <back::Backend as Backend>::RenderPass,
+    pipeline: &'a PipelineInfo,
+    gradient_pipeline: &'a PipelineInfo,
+    // This frame's uniforms, with GfxConfig::gradient_background
+    gradient_set: Option<&'a <back::Backend as Backend>::DescriptorSet>,
+    triangle_pipeline: &'a PipelineInfo,
+    sprite_pipeline: &'a PipelineInfo,
+    debug_pipeline: &'a PipelineInfo,
+    // Both or neither, see GfxConfig::model
+    model_pipeline: Option<&'a PipelineInfo>,
+    // With GfxConfig::depth_prepass, drawn just before the model
+    model_depth_pipeline: Option<&'a PipelineInfo>,
+    model: Option<&'a GpuModel>,
+    vertices: &'a BufferInfo,
+    indices: &'a BufferInfo,
+    flat_vertices: Option<&'a BufferInfo>,
+    indirect: Option<&'a IndirectDraw>,
+    triangle: &'a [StagedBuffer],
+    nodes: &'a [Batch<'a>],
+    node_instances: &'a InstanceTransforms,
+    sprites: &'a SpriteBatch,
+    particle_sprites: &'a SpriteBatch,
+    // Empty only after freeing
+    sprite_set: Option<&'a <back::Backend as Backend>::DescriptorSet>,
+    debug_draw: &'a DebugDraw,
+    pixels: Option<&'a PixelBlit>,
+    frame: usize,
+    draw: DrawCommand,
+    mouse: utils::Vec2,
+    tint: utils::Vec4,
+    resolution: utils::Vec2,
+    // The whole target, the scene's part of it,
+    // and that part in window pixels
+    target: pso::Rect,
+    viewport: pso::Rect,
+    area: pso::Rect,
+}
+
+impl SceneDraws<'_> {
+    // Everything under the nodes
+    unsafe fn record_start(&self, commands: &mut <back::Backend as Backend>::CommandBuffer) {
+        if let (true, Some(set)) = (self.config.gradient_background, self.gradient_set) {
+            set_area(commands, self.target, FULL_DEPTH);
+            record_gradient(commands, self.gradient_pipeline, set);
+        }
+        set_area(commands, self.viewport, self.config.depth_range());
+        if self.config.triangle_example {
+            record_triangle(commands, self.triangle_pipeline, self.triangle);
+        } else {
+            record_scene(
+                commands,
+                self.pipeline,
+                self.vertices,
+                self.indices,
+                self.flat_vertices,
+                self.indirect,
+                self.draw,
+                self.mouse,
+                self.tint,
+                self.resolution,
+            );
+        }
+    }
+
+    // Everything over them
+    unsafe fn record_end(&self, commands: &mut <back::Backend as Backend>::CommandBuffer) {
+        // Secondary buffers start without a viewport
+        set_area(commands, self.viewport, self.config.depth_range());
+        if let Some(model) = self.model {
+            // The same projection both times, so the depths match
+            for pipeline in self.model_depth_pipeline.iter().chain(&self.model_pipeline) {
+                record_model(
+                    commands,
+                    pipeline,
+                    model,
+                    self.config.depth_comparison,
+                    self.area,
+                );
+            }
+        }
+        for sprites in &[self.sprites, self.particle_sprites] {
+            record_sprites(
+                commands,
+                self.sprite_pipeline,
+                self.vertices,
+                self.indices,
+                sprites,
+                self.sprite_set,
+                self.frame,
+                self.area,
+            );
+        }
+        record_debug(commands, self.debug_pipeline, self.debug_draw, self.frame);
+        if let Some(pixels) = self.pixels {
+            set_area(commands, self.target, FULL_DEPTH);
+            pixels.record_blit(commands);
+        }
+    }
+}
+
+// Begins the scene's pass and draws everything in it. With a static
+// scene the pass runs secondary buffers instead, this frame's own
+// around the reused nodes, since a subpass can't mix the two.
+unsafe fn record_scene_pass(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    draws: &SceneDraws,
+    static_scene: Option<&mut StaticScene>,
+    framebuffer: &<back::Backend as Backend>::Framebuffer,
+    render_area: pso::Rect,
+    clear: &ClearValues,
+) {
+    let contents = match static_scene {
+        Some(_) => command::SubpassContents::SecondaryBuffers,
+        None => command::SubpassContents::Inline,
+    };
+    commands.begin_render_pass(
+        draws.begin_pass,
+        framebuffer,
+        render_area,
+        clear.iter(),
+        contents,
+    );
+    match static_scene {
+        Some(static_scene) => {
+            let (before, after) = static_scene.begin_frame(draws.frame, draws.render_pass);
+            draws.record_start(before);
+            draws.record_end(after);
+            static_scene.execute(commands, draws.frame);
+        }
+        None => {
+            draws.record_start(commands);
+            record_nodes(
+                commands,
+                draws.nodes,
+                draws.vertices,
+                draws.indices,
+                draws.node_instances,
+                draws.frame,
+                draws.tint,
+                draws.area,
+            );
+            draws.record_end(commands);
+        }
+    }
+    commands.end_render_pass();
+}
+
+// Added first, so the scene's pass keeps the first index
+const SCENE_PASS: usize = 0;
+
+// The scene's pass, then with an offscreen target the swapchain's
+// pass that samples it. The graph orders them and moves the target
+// between being drawn and sampled, which its render pass leaves to it.
+fn frame_graph(offscreen: Option<&Offscreen>) -> FrameGraph {
+    let mut graph = FrameGraph::default();
+    match offscreen {
+        Some(offscreen) => {
+            // Where the last frame left it, or nothing worth keeping
+            let target = if offscreen.drawn {
+                graph.import(AttachmentUse::Sampled)
+            } else {
+                graph.add_attachment()
+            };
+            graph.add_pass(Pass {
+                reads: vec![],
+                writes: vec![target],
+            });
+            graph.add_pass(Pass {
+                reads: vec![target],
+                writes: vec![],
+            });
+        }
+        None => {
+            graph.add_pass(Pass::default());
+        }
+    }
+    graph
+}
+
+fn gradient_uniforms(target: pso::Rect, time: f32) -> pipeline_info::GradientUniforms {
+    pipeline_info::GradientUniforms {
+        resolution: [target.w as f32, target.h as f32],
+        time,
+    }
+}
+
+// UNORM targets store colors as given, so the linear clear color is
+// encoded by hand to look the same as it does with sRGB. Colors
+// from the shaders aren't, so the scene itself looks darker there.
+pub fn clear_values(
+    color: utils::Vec4,
+    format: Format,
+    samples: image::NumSamples,
+    depth: f32,
+) -> Result<ClearValues, &'static str> {
+    let color = match format.base_format().1 {
+        ChannelType::Unorm => color.linear_to_srgb(),
+        _ => color,
+    };
+    let mut clear = ClearValuesBuilder::new().color(color.into());
+    if samples > 1 {
+        // The resolve target is written, not cleared
+        clear = clear.skip();
+    }
+    clear
+        .depth_stencil(depth, 0)
+        .build(attachment_config::attachment_count(samples))
+}
+
+fn check_area(area: pso::Rect, target: pso::Rect) -> Result<(), &'static str> {
+    let within = area.x >= target.x
+        && area.y >= target.y
+        && area.x + area.w <= target.x + target.w
+        && area.y + area.h <= target.y + target.h;
+    if area.w <= 0 || area.h <= 0 || !within {
+        return Err("Render area must be a non-empty part of the framebuffer");
+    }
+    Ok(())
+}
+
+// The same part of a target with a different size
+fn scale_area(area: pso::Rect, from: pso::Rect, to: pso::Rect) -> pso::Rect {
+    let scale = |value: i16, to: i16, from: i16| (value as i32 * to as i32 / from as i32) as i16;
+    pso::Rect {
+        x: scale(area.x, to.w, from.w),
+        y: scale(area.y, to.h, from.h),
+        w: scale(area.w, to.w, from.w).max(1),
+        h: scale(area.h, to.h, from.h).max(1),
+    }
+}
+
+// Either flip points clip space Y up, so the cursor has to be
+// measured from the bottom to land under it
+fn scene_mouse(mouse: utils::Vec2, flip_y: bool) -> utils::Vec2 {
+    if flip_y {
+        utils::Vec2 {
+            x: mouse.x,
+            y: 1.0 - mouse.y,
+        }
+    } else {
+        mouse
+    }
+}
+
+// For fullscreen passes, which don't use depth
+const FULL_DEPTH: Range<f32> = 0.0..1.0;
+
+// Limits drawing to part of the target, for every pipeline bound after.
+// The depth range can differ between draws, since
+// the viewport isn't baked into the pipelines. With NDC_Y_UP
+// enabled, the backend negates the height itself for YFlip::Viewport.
+pub unsafe fn set_area(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    area: pso::Rect,
+    depth: Range<f32>,
+) {
+    commands.set_viewports(0, &[pso::Viewport { rect: area, depth }]);
+    commands.set_scissors(0, &[area]);
+}
+
+// Records the quad into whichever render pass has been begun
+unsafe fn record_scene(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    pipeline: &PipelineInfo,
+    vertices: &BufferInfo,
+    indices: &BufferInfo,
+    // Drawn in place of the indexed quad when set
+    flat_vertices: Option<&BufferInfo>,
+    indirect: Option<&IndirectDraw>,
+    draw: DrawCommand,
+    mouse: utils::Vec2,
+    tint: utils::Vec4,
+    resolution: utils::Vec2,
+) {
+    commands.bind_graphics_pipeline(&pipeline.handle);
+    match flat_vertices {
+        Some(flat) => commands.bind_vertex_buffers(0, Some((&*flat.buffer, 0))),
+        None => {
+            commands.bind_vertex_buffers(0, Some((&*vertices.buffer, 0)));
             commands.bind_index_buffer(IndexBufferView {
-                buffer: &state.indices.buffer,
+                buffer: &indices.buffer,
                 offset: 0,
                 index_type: IndexType::U16,
             });
+        }
+    }
+    commands.push_graphics_constants(
+        &pipeline.layout,
+        pso::ShaderStageFlags::VERTEX,
+        pipeline_info::VERTEX_PUSH_CONSTANTS.start,
+        &[
+            mem::transmute::<f32, u32>(mouse.x),
+            mem::transmute::<f32, u32>(mouse.y),
+        ],
+    );
+    commands.push_graphics_constants(
+        &pipeline.layout,
+        pso::ShaderStageFlags::FRAGMENT,
+        pipeline_info::FRAGMENT_PUSH_CONSTANTS.start,
+        &tint.to_bits(),
+    );
+    commands.push_graphics_constants(
+        &pipeline.layout,
+        pso::ShaderStageFlags::FRAGMENT,
+        pipeline_info::RESOLUTION_OFFSET,
+        &[resolution.x.to_bits(), resolution.y.to_bits()],
+    );
+    // Subpasses may change attachment behaviour,
+    // for example changing intermediate buffers
+    // from write to read in the case of
+    // deferred rendering. Subpasses are also likely
+    // to be faster, and their use is preferrable where
+    // limitations don't restrict their use. Each pixel of output
+    // can only read its corresponding pixel of input,
+    // so things like blur are not possible within subpasses.
+    match (flat_vertices, indirect) {
+        // Already in draw order, one vertex per index
+        (Some(_), _) => commands.draw(0..draw.index_count, 0..1),
+        // The index count comes from the culling pass
+        (None, Some(indirect)) => commands.draw_indexed_indirect(
+            &indirect.commands.buffer,
+            0,
+            1,
+            mem::size_of::<DrawIndexedIndirectCommand>() as u32,
+        ),
+        (None, None) => commands.draw_indexed(draw.indices(), draw.base_vertex, 0..1),
+    }
+}
+
+// Covers the whole viewport, so set it to the full target first.
+// The set is the frame's uniforms, written with gradient_uniforms.
+unsafe fn record_gradient(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    pipeline: &PipelineInfo,
+    set: &<back::Backend as Backend>::DescriptorSet,
+) {
+    commands.bind_graphics_pipeline(&pipeline.handle);
+    pipeline.bind_set(commands, 0, set);
+    // Fullscreen triangle
+    commands.draw(0..3, 0..1);
+}
+
+// Draws the colored triangle, three vertices and no indices
+unsafe fn record_triangle(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    pipeline: &PipelineInfo,
+    triangle: &[StagedBuffer],
+) {
+    commands.bind_graphics_pipeline(&pipeline.handle);
+    // One buffer per stream, starting from binding zero
+    commands.bind_vertex_buffers(0, triangle.iter().map(|stream| (&*stream.buffer, 0)));
+    commands.draw(0..utils::TRIANGLE_DATA.len() as u32, 0..1);
+}
+
+// Draws each batch of scene nodes with one instanced call,
+// their world transforms read from this frame's instance buffer
+pub unsafe fn record_nodes(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    batches: &[Batch],
+    vertices: &BufferInfo,
+    indices: &BufferInfo,
+    instances: &InstanceTransforms,
+    frame: usize,
+    tint: utils::Vec4,
+    area: pso::Rect,
+) {
+    let transforms = match instances.buffer(frame) {
+        Some(transforms) if !batches.is_empty() => transforms,
+        _ => return,
+    };
+
+    let buffers: ArrayVec<[_; 2]> = [(&*vertices.buffer, 0), (&*transforms.buffer, 0)].into();
+    commands.bind_vertex_buffers(0, buffers);
+    commands.bind_index_buffer(IndexBufferView {
+        buffer: &indices.buffer,
+        offset: 0,
+        index_type: IndexType::U16,
+    });
+
+    let projection = node_projection(area);
+    let mut bound: Option<&PipelineInfo> = None;
+    for batch in batches {
+        // Only rebound where neighbouring batches differ by pipeline
+        if !bound.map_or(false, |bound| ptr::eq(bound, batch.pipeline)) {
+            let pipeline = batch.pipeline;
+            commands.bind_graphics_pipeline(&pipeline.handle);
             commands.push_graphics_constants(
-                &state.pipeline.layout,
+                &pipeline.layout,
                 pso::ShaderStageFlags::VERTEX,
-                0,
-                &[
-                    mem::transmute::<f32, u32>(mouse.x),
-                    mem::transmute::<f32, u32>(mouse.y),
-                ],
+                pipeline_info::NODE_VERTEX_PUSH_CONSTANTS.start,
+                &projection.to_bits(),
             );
-            // A renderpass is a bunch of work done with a
-            // particular set of attachments. 
-            commands.begin_render_pass(
-                &state.render_pass,
-                &state.framebuffers[image_i],
-                state.content_size,
-                [command::ClearValue {
-                    color: command::ClearColor { float32: color },
-                }]
-                .iter(),
-                command::SubpassContents::Inline,
+            commands.push_graphics_constants(
+                &pipeline.layout,
+                pso::ShaderStageFlags::FRAGMENT,
+                pipeline_info::NODE_FRAGMENT_PUSH_CONSTANTS.start,
+                &tint.to_bits(),
             );
-            // Subpasses may change attachment behaviour,
-            // for example changing intermediate buffers 
-            // from write to read in the case of 
-            // deferred rendering. Subpasses are also likely
-            // to be faster, and their use is preferrable where
-            // limitations don't restrict their use. Each pixel of output
-            // can only read its corresponding pixel of input, 
-            // so things like blur are not possible within subpasses.
-            commands.draw_indexed(0..6, 0, 0..1);
-            commands.end_render_pass();
-            commands.finish();
+            bound = Some(pipeline);
+        }
+        if batch.pipeline.dynamic_line_width {
+            commands.set_line_width(batch.mesh.line_width);
         }
+        commands.draw_indexed(
+            batch.mesh.indices(),
+            batch.mesh.base_vertex,
+            batch.instances.clone(),
+        );
     }
+}
 
-    let submission = {
-        let wait_semaphores: ArrayVec<[_; 1]> =
-            [(image_available, pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT)].into();
-        let signal_semaphores: ArrayVec<[_; 1]> = [render_finished].into();
-        Submission {
-            command_buffers: &state.command_buffers.get(image_i),
-            wait_semaphores,
-            signal_semaphores,
-        }
+// Keeps squares square whatever the window shape
+fn node_projection(area: pso::Rect) -> Mat4 {
+    Mat4::scale(utils::Vec2 {
+        x: area.h as f32 / area.w as f32,
+        y: 1.0,
+    })
+}
+
+// Draws the loaded model, fit to the view and tinted by its material
+unsafe fn record_model(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    pipeline: &PipelineInfo,
+    model: &GpuModel,
+    depth_comparison: pso::Comparison,
+    area: pso::Rect,
+) {
+    commands.bind_graphics_pipeline(&pipeline.handle);
+    pipeline.bind_set(commands, 0, &model.descriptor_set.set);
+    commands.bind_vertex_buffers(0, Some((&*model.vertices.buffer, 0)));
+    // Files can have more vertices than u16 reaches
+    commands.bind_index_buffer(IndexBufferView {
+        buffer: &model.indices.buffer,
+        offset: 0,
+        index_type: IndexType::U32,
+    });
+    let mvp = model_projection(area, depth_comparison) * model.fit;
+    commands.push_graphics_constants(
+        &pipeline.layout,
+        pso::ShaderStageFlags::VERTEX,
+        pipeline_info::MODEL_VERTEX_PUSH_CONSTANTS.start,
+        &mvp.to_bits(),
+    );
+    commands.push_graphics_constants(
+        &pipeline.layout,
+        pso::ShaderStageFlags::FRAGMENT,
+        pipeline_info::MODEL_FRAGMENT_PUSH_CONSTANTS.start,
+        &model.base_color.to_bits(),
+    );
+    commands.draw_indexed(0..model.index_count, 0, 0..1);
+}
+
+// Squares stay square as with the nodes. glTF points Y up and
+// faces the viewer down -Z, so Y is turned over and the fitted
+// unit sphere's depth is squeezed into 0 to 1, nearer lower
+// unless reverse-Z has the nearer fragments win by being greater.
+fn model_projection(area: pso::Rect, depth_comparison: pso::Comparison) -> Mat4 {
+    let depth = match depth_comparison {
+        pso::Comparison::Greater | pso::Comparison::GreaterEqual => 0.5,
+        _ => -0.5,
+    };
+    Mat4 {
+        columns: [
+            utils::Vec4::new(area.h as f32 / area.w as f32, 0.0, 0.0, 0.0),
+            utils::Vec4::new(0.0, -1.0, 0.0, 0.0),
+            utils::Vec4::new(0.0, 0.0, depth, 0.0),
+            utils::Vec4::new(0.0, 0.0, 0.5, 1.0),
+        ],
+    }
+}
+
+// Draws each node's ID where its color would go, then
+// copies back the one under the cursor. Placed the same
+// way as record_nodes, so the IDs line up with the colors.
+unsafe fn record_picking(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    picking: &mut Picking,
+    vertices: &BufferInfo,
+    indices: &BufferInfo,
+    scene: &Scene,
+    full: pso::Rect,
+    area: pso::Rect,
+    frame: usize,
+    pixel: (u32, u32),
+) {
+    commands.begin_render_pass(
+        &picking.render_pass,
+        &picking.framebuffer,
+        full,
+        picking.clear.iter(),
+        command::SubpassContents::Inline,
+    );
+    set_area(commands, area, FULL_DEPTH);
+
+    let pipeline = &picking.pipeline;
+    commands.bind_graphics_pipeline(&pipeline.handle);
+    commands.bind_vertex_buffers(0, Some((&*vertices.buffer, 0)));
+    commands.bind_index_buffer(IndexBufferView {
+        buffer: &indices.buffer,
+        offset: 0,
+        index_type: IndexType::U16,
+    });
+    let projection = node_projection(area);
+    for (i, world) in scene.world_matrices().into_iter().enumerate() {
+        commands.push_graphics_constants(
+            &pipeline.layout,
+            pso::ShaderStageFlags::VERTEX,
+            pipeline_info::NODE_VERTEX_PUSH_CONSTANTS.start,
+            &(projection * world).to_bits(),
+        );
+        // Zero is left for where nothing was drawn
+        commands.push_graphics_constants(
+            &pipeline.layout,
+            pso::ShaderStageFlags::FRAGMENT,
+            pipeline_info::NODE_ID_PUSH_CONSTANTS.start,
+            &[i as u32 + 1],
+        );
+        commands.draw_indexed(DrawCommand::QUAD.indices(), 0, 0..1);
+    }
+    commands.end_render_pass();
+
+    picking.record_readback(commands, frame, pixel);
+}
+
+// Draws the frame's debug shapes, already in clip space
+unsafe fn record_debug(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    pipeline: &PipelineInfo,
+    debug_draw: &DebugDraw,
+    frame: usize,
+) {
+    let buffer = match &debug_draw.buffer {
+        Some(buffer) if !debug_draw.is_empty() => buffer,
+        _ => return,
     };
+    commands.bind_graphics_pipeline(&pipeline.handle);
+    commands.bind_vertex_buffers(0, Some((&*buffer.buffer, debug_draw.offset(frame))));
+    commands.draw(0..debug_draw.len() as u32, 0..1);
+}
 
-    let present_wait_semaphores: ArrayVec<[_; 1]> = [render_finished].into();
-    let command_queue = &mut state.queue_group.queues[0];
-    unsafe {
-        command_queue.submit(submission, Some(flight_fence));
-        state
-            .swapchain
-            .present(command_queue, image_i as u32, present_wait_semaphores)
+// Draws every sprite in the batch with one instanced call
+unsafe fn record_sprites(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    pipeline: &PipelineInfo,
+    vertices: &BufferInfo,
+    indices: &BufferInfo,
+    sprites: &SpriteBatch,
+    set: Option<&<back::Backend as Backend>::DescriptorSet>,
+    frame: usize,
+    area: pso::Rect,
+) {
+    let (instances, set) = match (&sprites.instances, set) {
+        (Some(instances), Some(set)) if !sprites.is_empty() => (instances, set),
+        _ => return,
+    };
+
+    let buffers: ArrayVec<[_; 2]> = [
+        (&*vertices.buffer, 0),
+        (&*instances.buffer, sprites.offset(frame)),
+    ]
+    .into();
+    commands.bind_graphics_pipeline(&pipeline.handle);
+    pipeline.bind_set(commands, 0, set);
+    commands.bind_vertex_buffers(0, buffers);
+    commands.bind_index_buffer(IndexBufferView {
+        buffer: &indices.buffer,
+        offset: 0,
+        index_type: IndexType::U16,
+    });
+
+    // Pixel coordinates, origin at the top left
+    let projection = utils::Vec4::new(2.0 / area.w as f32, 2.0 / area.h as f32, -1.0, -1.0);
+    commands.push_graphics_constants(
+        &pipeline.layout,
+        pso::ShaderStageFlags::VERTEX,
+        pipeline_info::SPRITE_PUSH_CONSTANTS.start,
+        &projection.to_bits(),
+    );
+    commands.draw_indexed(0..6, 0, 0..sprites.len() as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        descriptor::DescriptorAllocator,
+        headless::{self, Headless},
+        vertex::MeshVertex,
+        FrameUniforms, Material, Model, PipelineOptions,
+    };
+    use gfx_hal::{pass, pool::CommandPoolCreateFlags, Features};
+
+    // Two triangles over four vertices
+    const INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
+
+    fn command(first_index: u32, index_count: u32, base_vertex: i32) -> DrawCommand {
+        DrawCommand {
+            first_index,
+            index_count,
+            base_vertex,
+            line_width: 1.0,
+        }
+    }
+
+    #[test]
+    fn flipped_cursor_lands_on_the_same_corner() {
+        let mouse = utils::Vec2 { x: 0.25, y: 0.125 };
+        let window_y = mouse.y * 2.0 - 1.0;
+        // The quad's top left corner, as vert.glsl places it
+        let corner_y = |mouse: utils::Vec2| -0.5 + mouse.y * 2.0 - 0.5;
+        assert_eq!(corner_y(scene_mouse(mouse, false)), window_y);
+        // Either flip negates clip space Y on its way to the screen
+        let flipped = scene_mouse(mouse, true);
+        assert_eq!(-corner_y(flipped), window_y);
+        assert_eq!(flipped.x, mouse.x);
+    }
+
+    #[test]
+    fn model_depth_follows_the_comparison() {
+        let area = pso::Rect {
+            x: 0,
+            y: 0,
+            w: 200,
+            h: 100,
+        };
+        // The front of the fitted sphere, towards the viewer
+        let near = utils::Vec4::new(0.0, 1.0, 1.0, 1.0);
+        let standard = model_projection(area, pso::Comparison::Less).transform(near);
+        assert_eq!(standard.z, 0.0);
+        // Up in the file is up on screen
+        assert_eq!(standard.y, -1.0);
+        let reverse = model_projection(area, pso::Comparison::Greater).transform(near);
+        assert_eq!(reverse.z, 1.0);
+        let side = model_projection(area, pso::Comparison::Less)
+            .transform(utils::Vec4::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(side.x, 0.5);
+    }
+
+    #[test]
+    fn quad_is_valid() {
+        assert!(DrawCommand::QUAD.validate(&INDICES, 4).is_ok());
+        assert!(command(3, 3, 0).validate(&INDICES, 4).is_ok());
+    }
+
+    #[test]
+    fn rejects_reading_past_the_indices() {
+        assert!(command(3, 4, 0).validate(&INDICES, 4).is_err());
+    }
+
+    #[test]
+    fn rejects_reading_past_the_vertices() {
+        assert!(command(0, 6, 1).validate(&INDICES, 4).is_err());
+        assert!(command(0, 6, -1).validate(&INDICES, 4).is_err());
+        // Shifted into a bigger buffer instead
+        assert!(command(0, 6, 4).validate(&INDICES, 8).is_ok());
+    }
+
+    #[test]
+    fn deindexes_in_index_order() {
+        let vertices = [10, 11, 12, 13, 14];
+        assert_eq!(
+            command(3, 3, 1).deindex(&vertices, &INDICES),
+            vec![13, 14, 11]
+        );
+    }
+
+    #[test]
+    #[ignore] // Needs a Vulkan device
+    fn gradient_spans_the_target() {
+        let mut headless = Headless::new(16, 32, Features::empty());
+        let mut pipeline = PipelineInfo::gradient(
+            &headless.device,
+            pass::Subpass {
+                index: 0,
+                main_pass: headless.render_pass(),
+            },
+            1,
+            PipelineOptions::default(),
+        )
+        .unwrap();
+        let mut descriptors = DescriptorAllocator::new(
+            1,
+            vec![pso::DescriptorRangeDesc {
+                ty: pso::DescriptorType::UniformBuffer,
+                count: 1,
+            }],
+        );
+        let uniforms = FrameUniforms::new(
+            &headless.device,
+            &headless.adapter,
+            &pipeline,
+            0,
+            &mut descriptors,
+            1,
+        )
+        .unwrap();
+        let area = headless.area();
+        uniforms
+            .write(&headless.device, 0, gradient_uniforms(area, 0.0))
+            .unwrap();
+        let set = uniforms.set(0).unwrap();
+        let pixels = headless.draw(utils::Vec4::new(0.0, 0.0, 0.0, 1.0), |commands| unsafe {
+            record_gradient(commands, &pipeline, set)
+        });
+        // Light blue at the top, fading to nearly black by the
+        // bottom row of the target rather than of the window
+        let top = headless::pixel(&pixels, 16, 8, 0);
+        let bottom = headless::pixel(&pixels, 16, 8, 31);
+        assert!(top[2] > 200, "{:?}", top);
+        assert!(u32::from(bottom[2]) * 2 < u32::from(top[2]), "{:?}", bottom);
+
+        uniforms.free(&headless.device, &mut descriptors);
+        descriptors.free_pools(&headless.device);
+        pipeline.free(&headless.device);
+        headless.free();
+    }
+
+    // A red square facing the viewer, fit to fill the middle
+    fn square() -> Model {
+        let vertex = |x, y| MeshVertex {
+            position: [x, y, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            ..MeshVertex::default()
+        };
+        Model {
+            vertices: vec![
+                vertex(-1.0, -1.0),
+                vertex(1.0, -1.0),
+                vertex(1.0, 1.0),
+                vertex(-1.0, 1.0),
+            ],
+            indices: vec![0, 1, 2, 2, 3, 0],
+            material: Material {
+                base_color: utils::Vec4::new(1.0, 0.0, 0.0, 1.0),
+                base_color_texture: None,
+            },
+        }
+    }
+
+    #[test]
+    #[ignore] // Needs a Vulkan device
+    fn prepass_fills_depth_for_the_model() {
+        let mut headless = Headless::new(16, 16, Features::empty());
+        let subpass = || pass::Subpass {
+            index: 0,
+            main_pass: headless.render_pass(),
+        };
+        let model_pipeline = |fun| {
+            PipelineInfo::model(
+                &headless.device,
+                subpass(),
+                1,
+                None,
+                None,
+                pso::Rasterizer::FILL,
+                pso::DepthTest { fun, write: false },
+                PipelineOptions::default(),
+            )
+            .unwrap()
+        };
+        let mut equal = model_pipeline(pso::Comparison::LessEqual);
+        let mut strict = model_pipeline(pso::Comparison::Less);
+        let mut prepass = PipelineInfo::model_depth(
+            &headless.device,
+            subpass(),
+            1,
+            pso::Rasterizer::FILL,
+            pso::Comparison::Less,
+            PipelineOptions::default(),
+        )
+        .unwrap();
+
+        let mut descriptors = DescriptorAllocator::new(
+            1,
+            vec![pso::DescriptorRangeDesc {
+                ty: pso::DescriptorType::CombinedImageSampler,
+                count: 1,
+            }],
+        );
+        let mut pool = unsafe {
+            headless.device.create_command_pool(
+                headless.queue_group.family,
+                CommandPoolCreateFlags::TRANSIENT,
+            )
+        }
+        .unwrap();
+        let model = square()
+            .upload(
+                &headless.device,
+                &headless.adapter,
+                &mut pool,
+                &mut headless.queue_group.queues[0],
+                &equal,
+                &mut descriptors,
+                1.0,
+            )
+            .unwrap();
+
+        let area = headless.area();
+        let black = utils::Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let mut center = |pipelines: &[&PipelineInfo]| {
+            let pixels = headless.draw(black, |commands| unsafe {
+                for pipeline in pipelines {
+                    record_model(commands, pipeline, &model, pso::Comparison::Less, area);
+                }
+            });
+            headless::pixel(&pixels, 16, 8, 8)
+        };
+        // Without a pre-pass the square passes against the clear
+        assert_eq!(center(&[&strict]), [255, 0, 0, 255]);
+        // With one, only what lands on the depth it left passes
+        assert_eq!(center(&[&prepass, &equal]), [255, 0, 0, 255]);
+        assert_eq!(center(&[&prepass, &strict]), [0, 0, 0, 255]);
+
+        model.free(&headless.device, &mut descriptors);
+        descriptors.free_pools(&headless.device);
+        unsafe { headless.device.destroy_command_pool(pool) };
+        for pipeline in &mut [&mut equal, &mut strict, &mut prepass] {
+            pipeline.free(&headless.device);
+        }
+        headless.free();
     }
-    // Discard suboptimal warning
-    .map(|_| ())
-    .map_err(|_| "Failed to present into the swapchain")
 }
@@ -0,0 +1,77 @@
+#[cfg(feature = "renderdoc")]
+use renderdoc::{RenderDoc, V110};
+
+// Captures a chosen frame in RenderDoc. Build with
+// `--features renderdoc`, launch the app from RenderDoc,
+// then press C to capture the next frame drawn.
+// Does nothing when RenderDoc isn't attached.
+pub struct FrameCapture {
+    // Only loads when the app was launched from RenderDoc
+    #[cfg(feature = "renderdoc")]
+    api: Option<RenderDoc<V110>>,
+    // Capture the next frame drawn
+    triggered: bool,
+    capturing: bool,
+}
+
+impl FrameCapture {
+    // Finds RenderDoc if the app was launched from it
+    pub fn attach() -> Self {
+        Self {
+            #[cfg(feature = "renderdoc")]
+            api: RenderDoc::new().ok(),
+            triggered: false,
+            capturing: false,
+        }
+    }
+
+    #[cfg(feature = "renderdoc")]
+    pub fn is_available(&self) -> bool {
+        self.api.is_some()
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    pub fn is_available(&self) -> bool {
+        false
+    }
+
+    pub fn trigger(&mut self) -> Result<(), &'static str> {
+        if !self.is_available() {
+            return Err("RenderDoc isn't attached");
+        }
+        self.triggered = true;
+        Ok(())
+    }
+
+    // Before anything for the frame is recorded
+    pub fn start_frame(&mut self) {
+        if !self.triggered {
+            return;
+        }
+        self.triggered = false;
+
+        // Null handles capture whichever device and window are active
+        #[cfg(feature = "renderdoc")]
+        {
+            if let Some(api) = &mut self.api {
+                api.start_frame_capture(std::ptr::null(), std::ptr::null());
+                self.capturing = true;
+            }
+        }
+    }
+
+    // After the frame is presented, or fails
+    pub fn end_frame(&mut self) {
+        if !self.capturing {
+            return;
+        }
+        self.capturing = false;
+
+        #[cfg(feature = "renderdoc")]
+        {
+            if let Some(api) = &mut self.api {
+                api.end_frame_capture(std::ptr::null(), std::ptr::null());
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    command::CommandBuffer,
+    format,
+    image::{self, Layout},
+    memory::{Barrier, Dependencies},
+    pso::PipelineStage,
+    Backend,
+};
+use std::ops::Range;
+
+// How a pass uses one of its attachments
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AttachmentUse {
+    // Drawn into as a color attachment
+    Color,
+    // Sampled from the fragment shader
+    Sampled,
+}
+
+impl AttachmentUse {
+    pub fn state(self) -> AttachmentState {
+        match self {
+            AttachmentUse::Color => AttachmentState {
+                layout: Layout::ColorAttachmentOptimal,
+                access: image::Access::COLOR_ATTACHMENT_READ
+                    | image::Access::COLOR_ATTACHMENT_WRITE,
+                stage: PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+            },
+            AttachmentUse::Sampled => AttachmentState {
+                layout: Layout::ShaderReadOnlyOptimal,
+                access: image::Access::SHADER_READ,
+                stage: PipelineStage::FRAGMENT_SHADER,
+            },
+        }
+    }
+}
+
+// Where an attachment is between passes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AttachmentState {
+    pub layout: Layout,
+    pub access: image::Access,
+    pub stage: PipelineStage,
+}
+
+impl AttachmentState {
+    // Contents are discarded on the way out of this
+    pub const UNDEFINED: Self = Self {
+        layout: Layout::Undefined,
+        access: image::Access::empty(),
+        stage: PipelineStage::TOP_OF_PIPE,
+    };
+}
+
+// Attachments are referred to by the index they were added at
+#[derive(Clone, Debug, Default)]
+pub struct Pass {
+    pub reads: Vec<usize>,
+    pub writes: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub attachment: usize,
+    pub states: Range<AttachmentState>,
+}
+
+// A pass along with the barriers to record before it
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledPass {
+    pub pass: usize,
+    pub transitions: Vec<Transition>,
+}
+
+// Passes declare the attachments they read and write, and the graph
+// works out their order and the layout transitions between them.
+// Only handles chains for now, where each pass depends on at most
+// one other and has at most one pass depending on it. The render
+// passes should start in the layouts the graph leaves them in.
+#[derive(Clone, Debug, Default)]
+pub struct FrameGraph {
+    initial: Vec<AttachmentState>,
+    passes: Vec<Pass>,
+}
+
+impl FrameGraph {
+    // Starts out undefined, to be written before anything reads it
+    pub fn add_attachment(&mut self) -> usize {
+        self.initial.push(AttachmentState::UNDEFINED);
+        self.initial.len() - 1
+    }
+
+    // Filled outside the graph, such as an uploaded texture
+    pub fn import(&mut self, current: AttachmentUse) -> usize {
+        self.initial.push(current.state());
+        self.initial.len() - 1
+    }
+
+    pub fn add_pass(&mut self, pass: Pass) -> usize {
+        self.passes.push(pass);
+        self.passes.len() - 1
+    }
+
+    pub fn compile(&self) -> Result<Vec<CompiledPass>, &'static str> {
+        let order = self.order()?;
+
+        let mut states = self.initial.clone();
+        let compiled = order
+            .into_iter()
+            .map(|i| {
+                let pass = &self.passes[i];
+                let uses = pass
+                    .writes
+                    .iter()
+                    .map(|&a| (a, AttachmentUse::Color))
+                    .chain(pass.reads.iter().map(|&a| (a, AttachmentUse::Sampled)));
+
+                let mut transitions = vec![];
+                for (attachment, usage) in uses {
+                    let wanted = usage.state();
+                    if states[attachment] != wanted {
+                        transitions.push(Transition {
+                            attachment,
+                            states: states[attachment]..wanted,
+                        });
+                        states[attachment] = wanted;
+                    }
+                }
+                CompiledPass {
+                    pass: i,
+                    transitions,
+                }
+            })
+            .collect();
+        Ok(compiled)
+    }
+
+    // Each chain in turn, in the order their first passes were added
+    fn order(&self) -> Result<Vec<usize>, &'static str> {
+        let count = self.initial.len();
+        let mut writers = vec![None; count];
+        for (i, pass) in self.passes.iter().enumerate() {
+            for &attachment in &pass.writes {
+                if attachment >= count {
+                    return Err("Pass writes an unknown attachment");
+                }
+                if writers[attachment].replace(i).is_some() {
+                    return Err("Attachments can only be written by one pass");
+                }
+            }
+        }
+
+        let mut depends_on = vec![None; self.passes.len()];
+        let mut dependents = vec![None; self.passes.len()];
+        for (i, pass) in self.passes.iter().enumerate() {
+            for &attachment in &pass.reads {
+                let writer = match writers.get(attachment) {
+                    Some(&Some(writer)) => writer,
+                    // Imported, or read before anything is written
+                    Some(None) => continue,
+                    None => return Err("Pass reads an unknown attachment"),
+                };
+                if writer == i {
+                    return Err("A pass can't read an attachment it writes");
+                }
+                if depends_on[i].replace(writer).map_or(false, |w| w != writer) {
+                    return Err("Passes can only depend on one other pass");
+                }
+                if dependents[writer].replace(i).map_or(false, |d| d != i) {
+                    return Err("Only one pass can depend on each pass");
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.passes.len());
+        for start in (0..self.passes.len()).filter(|&i| depends_on[i].is_none()) {
+            let mut next = Some(start);
+            while let Some(i) = next {
+                order.push(i);
+                next = dependents[i];
+            }
+        }
+        // Passes in a loop never have a starting point
+        if order.len() < self.passes.len() {
+            return Err("Pass dependencies form a cycle");
+        }
+        Ok(order)
+    }
+}
+
+// Must be recorded outside a render pass. Images are
+// indexed the same way as the graph's attachments.
+pub unsafe fn record_transitions(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    transitions: &[Transition],
+    images: &[&<back::Backend as Backend>::Image],
+) {
+    for transition in transitions {
+        let states = &transition.states;
+        commands.pipeline_barrier(
+            states.start.stage..states.end.stage,
+            Dependencies::empty(),
+            &[Barrier::Image {
+                states: (states.start.access, states.start.layout)
+                    ..(states.end.access, states.end.layout),
+                target: images[transition.attachment],
+                families: None,
+                range: image::SubresourceRange {
+                    aspects: format::Aspects::COLOR,
+                    levels: 0..1,
+                    layers: 0..1,
+                },
+            }],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(attachment: usize, from: AttachmentState, to: AttachmentUse) -> Transition {
+        Transition {
+            attachment,
+            states: from..to.state(),
+        }
+    }
+
+    #[test]
+    fn chain_is_ordered_by_its_dependencies() {
+        let mut graph = FrameGraph::default();
+        let scene = graph.add_attachment();
+        let post = graph.add_attachment();
+        let ui = graph.add_attachment();
+        // Added back to front, so only the reads give the order
+        let third = graph.add_pass(Pass {
+            reads: vec![post],
+            writes: vec![ui],
+        });
+        let second = graph.add_pass(Pass {
+            reads: vec![scene],
+            writes: vec![post],
+        });
+        let first = graph.add_pass(Pass {
+            reads: vec![],
+            writes: vec![scene],
+        });
+
+        let color = AttachmentUse::Color.state();
+        assert_eq!(
+            graph.compile(),
+            Ok(vec![
+                CompiledPass {
+                    pass: first,
+                    transitions: vec![transition(
+                        scene,
+                        AttachmentState::UNDEFINED,
+                        AttachmentUse::Color
+                    )],
+                },
+                CompiledPass {
+                    pass: second,
+                    transitions: vec![
+                        transition(post, AttachmentState::UNDEFINED, AttachmentUse::Color),
+                        transition(scene, color, AttachmentUse::Sampled),
+                    ],
+                },
+                CompiledPass {
+                    pass: third,
+                    transitions: vec![
+                        transition(ui, AttachmentState::UNDEFINED, AttachmentUse::Color),
+                        transition(post, color, AttachmentUse::Sampled),
+                    ],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn imported_attachments_start_where_they_are() {
+        let mut graph = FrameGraph::default();
+        let target = graph.import(AttachmentUse::Sampled);
+        graph.add_pass(Pass {
+            reads: vec![],
+            writes: vec![target],
+        });
+        graph.add_pass(Pass {
+            reads: vec![target],
+            writes: vec![],
+        });
+        let compiled = graph.compile().unwrap();
+        assert_eq!(
+            compiled[0].transitions,
+            vec![transition(
+                target,
+                AttachmentUse::Sampled.state(),
+                AttachmentUse::Color
+            )]
+        );
+        // Nothing is left for the next frame to undo
+        assert_eq!(
+            compiled[1].transitions,
+            vec![transition(
+                target,
+                AttachmentUse::Color.state(),
+                AttachmentUse::Sampled
+            )]
+        );
+    }
+
+    #[test]
+    fn cycles_and_shared_writes_are_rejected() {
+        let mut cycle = FrameGraph::default();
+        let a = cycle.add_attachment();
+        let b = cycle.add_attachment();
+        cycle.add_pass(Pass {
+            reads: vec![a],
+            writes: vec![b],
+        });
+        cycle.add_pass(Pass {
+            reads: vec![b],
+            writes: vec![a],
+        });
+        assert_eq!(cycle.compile(), Err("Pass dependencies form a cycle"));
+
+        let mut shared = FrameGraph::default();
+        let a = shared.add_attachment();
+        for _ in 0..2 {
+            shared.add_pass(Pass {
+                reads: vec![],
+                writes: vec![a],
+            });
+        }
+        assert_eq!(
+            shared.compile(),
+            Err("Attachments can only be written by one pass")
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    command::Level,
+    device::Device,
+    pool::{CommandPool, CommandPoolCreateFlags},
+    queue::QueueFamilyId,
+    Backend,
+};
+use std::{mem::ManuallyDrop, ptr};
+
+// A command pool of its own for one frame in flight,
+// so everything the frame recorded can be reset in one call
+pub struct FramePool {
+    pub pool: ManuallyDrop<<back::Backend as Backend>::CommandPool>,
+    pub commands: <back::Backend as Backend>::CommandBuffer,
+}
+
+impl FramePool {
+    pub fn new(device: &back::Device, family: QueueFamilyId) -> Result<Self, &'static str> {
+        // Buffers are never reset on their own
+        let mut pool =
+            unsafe { device.create_command_pool(family, CommandPoolCreateFlags::empty()) }
+                .map_err(|_| "Could not create frame command pool")?;
+        let commands = unsafe { pool.allocate_one(Level::Primary) };
+        Ok(Self {
+            pool: ManuallyDrop::new(pool),
+            commands,
+        })
+    }
+
+    // Only once the frame's fence shows the GPU is done with it
+    pub unsafe fn reset(&mut self) {
+        self.pool.reset(false);
+    }
+
+    // Destroying the pool frees its buffers too
+    pub fn free(self, device: &back::Device) {
+        unsafe { device.destroy_command_pool(ManuallyDrop::into_inner(ptr::read(&self.pool))) }
+    }
+}
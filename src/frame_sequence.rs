@@ -0,0 +1,75 @@
+// Stands in for the frame number in the output pattern
+const PLACEHOLDER: &str = "{}";
+
+// A fixed number of frames saved one after another, for assembling
+// into a video elsewhere. Each frame is a fixed step of simulated
+// time after the last, however long it took to draw and save, so
+// the same run always produces the same files.
+pub struct FrameSequence {
+    pub frames: u64,
+    // Where each frame goes, with {} replaced by its number
+    pub pattern: String,
+    // Frames saved so far, and the number of the next one
+    pub saved: u64,
+}
+
+impl FrameSequence {
+    pub fn new(frames: u64, pattern: &str) -> Result<Self, &'static str> {
+        if frames == 0 {
+            return Err("A frame sequence needs at least one frame");
+        }
+        if !pattern.contains(PLACEHOLDER) {
+            return Err("The frame pattern needs a {} for the frame number");
+        }
+        Ok(Self {
+            frames,
+            pattern: pattern.to_owned(),
+            saved: 0,
+        })
+    }
+
+    // Zero padded so the files sort in order
+    pub fn path(&self) -> String {
+        self.pattern
+            .replacen(PLACEHOLDER, &format!("{:05}", self.saved), 1)
+    }
+
+    pub fn next(&mut self) {
+        self.saved += 1;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.saved >= self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_zero_padded() {
+        let mut sequence = FrameSequence::new(3, "out/frame_{}.png").unwrap();
+        assert_eq!(sequence.path(), "out/frame_00000.png");
+        sequence.next();
+        assert_eq!(sequence.path(), "out/frame_00001.png");
+        sequence.saved = 123_456;
+        assert_eq!(sequence.path(), "out/frame_123456.png");
+    }
+
+    #[test]
+    fn finishes_after_every_frame() {
+        let mut sequence = FrameSequence::new(2, "{}.png").unwrap();
+        assert!(!sequence.is_finished());
+        sequence.next();
+        assert!(!sequence.is_finished());
+        sequence.next();
+        assert!(sequence.is_finished());
+    }
+
+    #[test]
+    fn rejects_bad_sequences() {
+        assert!(FrameSequence::new(0, "{}.png").is_err());
+        assert!(FrameSequence::new(1, "frame.png").is_err());
+    }
+}
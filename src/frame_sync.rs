@@ -0,0 +1,62 @@
+use gfx_backend_vulkan as back;
+use gfx_hal::{device::Device, pso::PipelineStage, Backend};
+
+// Synchronization owned by one frame in flight.
+// The submission and present lists are derived from these
+// by reference, so nothing is rebuilt per frame.
+// A single timeline semaphore could replace the fences, but
+// gfx-hal has no timeline semaphores and can't enable
+// VK_KHR_timeline_semaphore, see device_extensions.
+pub struct FrameSync {
+    pub image_available: <back::Backend as Backend>::Semaphore,
+    pub render_finished: <back::Backend as Backend>::Semaphore,
+    // Signalled once the present family has the image,
+    // only when it differs from the graphics family
+    pub handed_off: <back::Backend as Backend>::Semaphore,
+    // Signalled when the frame's submission completes
+    pub in_flight: <back::Backend as Backend>::Fence,
+}
+
+impl FrameSync {
+    pub fn new(device: &back::Device) -> Result<Self, &'static str> {
+        let make_semaphore = || {
+            device
+                .create_semaphore()
+                .map_err(|_| "Could not create semaphore")
+        };
+
+        Ok(Self {
+            image_available: make_semaphore()?,
+            render_finished: make_semaphore()?,
+            handed_off: make_semaphore()?,
+            // Signalled so the first wait on each frame returns at once
+            in_flight: device
+                .create_fence(true)
+                .map_err(|_| "Could not create fence")?,
+        })
+    }
+
+    // Color output has to wait for the swapchain image,
+    // but earlier stages can start right away
+    pub fn submit_waits(&self) -> Option<(&<back::Backend as Backend>::Semaphore, PipelineStage)> {
+        Some((
+            &self.image_available,
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        ))
+    }
+
+    // Signalled by the submission, waited on by presentation
+    // or by the handoff to the present family
+    pub fn render_signals(&self) -> Option<&<back::Backend as Backend>::Semaphore> {
+        Some(&self.render_finished)
+    }
+
+    pub fn free(self, device: &back::Device) {
+        unsafe {
+            device.destroy_semaphore(self.image_available);
+            device.destroy_semaphore(self.render_finished);
+            device.destroy_semaphore(self.handed_off);
+            device.destroy_fence(self.in_flight);
+        }
+    }
+}
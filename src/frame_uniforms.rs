@@ -0,0 +1,174 @@
+use crate::{
+    descriptor::{DescriptorAllocation, DescriptorAllocator},
+    BufferInfo, PipelineInfo,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{adapter::Adapter, buffer::Usage, device::Device, pso, Backend};
+use std::{marker::PhantomData, mem::size_of};
+
+// A uniform buffer for each frame in flight, with a descriptor set
+// pointing at it. The CPU writes the buffer of the frame it's
+// recording, which the frame's fence has already freed, so it never
+// waits on or overwrites what an earlier frame is still reading.
+pub struct FrameUniforms<T> {
+    pub buffers: Vec<BufferInfo>,
+    pub sets: Vec<DescriptorAllocation>,
+    uniform: PhantomData<T>,
+}
+
+impl<T: Copy> FrameUniforms<T> {
+    // The uniform is binding 0 of the pipeline's set
+    pub fn new(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        pipeline: &PipelineInfo,
+        set: usize,
+        descriptors: &mut DescriptorAllocator,
+        frames: usize,
+    ) -> Result<Self, &'static str> {
+        let mut uniforms = Self {
+            buffers: vec![],
+            sets: vec![],
+            uniform: PhantomData,
+        };
+        for _ in 0..frames {
+            if let Err(e) = uniforms.add_frame(device, adapter, pipeline, set, descriptors) {
+                uniforms.free(device, descriptors);
+                return Err(e);
+            }
+        }
+        Ok(uniforms)
+    }
+
+    fn add_frame(
+        &mut self,
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        pipeline: &PipelineInfo,
+        set: usize,
+        descriptors: &mut DescriptorAllocator,
+    ) -> Result<(), &'static str> {
+        let mut buffer =
+            BufferInfo::with_size(device, adapter, size_of::<T>() as u64, Usage::UNIFORM)?;
+        // Rewritten every frame
+        let descriptor_set = match buffer
+            .map_persistent(device)
+            .and_then(|()| pipeline.allocate_set(device, descriptors, set))
+        {
+            Ok(descriptor_set) => descriptor_set,
+            Err(e) => {
+                buffer.free(device);
+                return Err(e);
+            }
+        };
+        unsafe {
+            device.write_descriptor_sets(vec![pso::DescriptorSetWrite {
+                set: &descriptor_set.set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Buffer(&*buffer.buffer, None..None)),
+            }]);
+        }
+        self.buffers.push(buffer);
+        self.sets.push(descriptor_set);
+        Ok(())
+    }
+
+    // Only touches this frame's buffer
+    pub fn write(&self, device: &back::Device, frame: usize, value: T) -> Result<(), &'static str> {
+        self.buffers
+            .get(frame)
+            .ok_or("No uniform buffer for that frame")?
+            .write(device, 0, &[value])
+    }
+
+    pub fn read(&self, device: &back::Device, frame: usize) -> Result<T, &'static str> {
+        self.buffers
+            .get(frame)
+            .ok_or("No uniform buffer for that frame")?
+            .read(device, 0)
+    }
+
+    // Bound while drawing the frame
+    pub fn set(&self, frame: usize) -> Option<&<back::Backend as Backend>::DescriptorSet> {
+        self.sets.get(frame).map(|allocation| &allocation.set)
+    }
+
+    pub fn free(self, device: &back::Device, descriptors: &mut DescriptorAllocator) {
+        for allocation in self.sets {
+            if let Err(e) = descriptors.free(allocation) {
+                log::error!("{}", e);
+            }
+        }
+        for mut buffer in self.buffers {
+            buffer.free(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        headless::Headless,
+        pipeline_info::{GradientUniforms, PipelineOptions},
+    };
+    use gfx_hal::{pass, Features};
+
+    fn uniforms(resolution: f32, time: f32) -> GradientUniforms {
+        GradientUniforms {
+            resolution: [resolution, resolution],
+            time,
+        }
+    }
+
+    #[test]
+    #[ignore] // Needs a Vulkan device
+    fn each_frame_keeps_its_own_data() {
+        let headless = Headless::new(16, 16, Features::empty());
+        let mut pipeline = PipelineInfo::gradient(
+            &headless.device,
+            pass::Subpass {
+                index: 0,
+                main_pass: headless.render_pass(),
+            },
+            1,
+            PipelineOptions::default(),
+        )
+        .unwrap();
+        let mut descriptors = DescriptorAllocator::new(
+            3,
+            vec![pso::DescriptorRangeDesc {
+                ty: pso::DescriptorType::UniformBuffer,
+                count: 3,
+            }],
+        );
+        let frames = FrameUniforms::new(
+            &headless.device,
+            &headless.adapter,
+            &pipeline,
+            0,
+            &mut descriptors,
+            3,
+        )
+        .unwrap();
+
+        // Frame 1 is still in flight while frame 2 is written
+        frames
+            .write(&headless.device, 1, uniforms(16.0, 1.0))
+            .unwrap();
+        frames
+            .write(&headless.device, 2, uniforms(32.0, 2.0))
+            .unwrap();
+        assert_eq!(frames.read(&headless.device, 1), Ok(uniforms(16.0, 1.0)));
+        assert_eq!(frames.read(&headless.device, 2), Ok(uniforms(32.0, 2.0)));
+        assert!(frames
+            .write(&headless.device, 3, uniforms(0.0, 0.0))
+            .is_err());
+
+        frames.free(&headless.device, &mut descriptors);
+        descriptors.free_pools(&headless.device);
+        pipeline.free(&headless.device);
+        headless.free();
+    }
+}
@@ -0,0 +1,60 @@
+// RGBA8 pixels written on the CPU, for software rendering
+#[derive(Clone, Debug, Default)]
+pub struct Framebuffer2D {
+    pub width: u32,
+    pub height: u32,
+    // Rows from the top, four sRGB encoded bytes per pixel
+    pub pixels: Vec<u8>,
+}
+
+impl Framebuffer2D {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    // Keeps whatever overlaps the new size, the rest is cleared
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let mut resized = Self::new(width, height);
+        let copy_width = (self.width.min(width) * 4) as usize;
+        for y in 0..self.height.min(height) {
+            let from = self.index(0, y);
+            let to = resized.index(0, y);
+            resized.pixels[to..to + copy_width]
+                .copy_from_slice(&self.pixels[from..from + copy_width]);
+        }
+        *self = resized;
+    }
+
+    pub fn clear(&mut self, rgba: [u8; 4]) {
+        for pixel in self.pixels.chunks_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+    }
+
+    // Out of bounds writes are ignored
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        if x < self.width && y < self.height {
+            let i = self.index(x, y);
+            self.pixels[i..i + 4].copy_from_slice(&rgba);
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x < self.width && y < self.height {
+            let i = self.index(x, y);
+            let mut rgba = [0; 4];
+            rgba.copy_from_slice(&self.pixels[i..i + 4]);
+            Some(rgba)
+        } else {
+            None
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y * self.width + x) * 4) as usize
+    }
+}
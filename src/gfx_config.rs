@@ -0,0 +1,590 @@
+use crate::{
+    device_extensions, utils,
+    vertex::{self, VertexStreams},
+    AttachmentConfig,
+};
+use gfx_hal::{
+    image::{self, NumSamples},
+    pool::CommandPoolCreateFlags,
+    pso::{self, Comparison},
+    window::CompositeAlpha,
+    Features,
+};
+use std::ops::Range;
+
+// Limits for render_scale. Below this the scene is mostly
+// blur, and above it the target gets very large.
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+// Depth bias pulling the wireframe overlay towards the viewer,
+// both constant and scaled by the slope of each face
+pub const WIREFRAME_BIAS: f32 = 1.0;
+
+// How the application names itself to the driver, which
+// tools like RenderDoc and per-app driver profiles go by.
+// The backend fills in its own engine name and version.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AppInfo {
+    pub name: &'static str,
+    pub version: u32,
+}
+
+impl AppInfo {
+    // Packed the way Vulkan expects
+    pub const fn version(major: u32, minor: u32, patch: u32) -> u32 {
+        (major << 22) | (minor << 12) | patch
+    }
+}
+
+impl Default for AppInfo {
+    fn default() -> Self {
+        Self {
+            name: utils::WINDOW_NAME,
+            version: Self::version(0, 1, 0),
+        }
+    }
+}
+
+// Where multisampled rendering is resolved to a single sample
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResolveTarget {
+    // Straight into the presented image
+    Swapchain,
+    // Into the render-to-texture target, so post effects
+    // work on the antialiased image. Needs `render_to_texture`.
+    Offscreen,
+}
+
+// How command buffers are made ready to record again
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PoolReset {
+    // A buffer per swapchain image, each reset as it's begun
+    Individual,
+    // A pool per frame in flight, reset all at once
+    // after the frame's fence. Usually cheaper.
+    WholePool,
+}
+
+// How many swapchain images to ask for, relative to the surface's
+// minimum. Fewer images means fewer frames queued between drawing
+// and the screen, so less latency, but nothing to draw into while
+// the display holds onto the rest.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageCount {
+    // Lowest latency. Acquiring stalls whenever
+    // presentation falls behind, which shows as stutter.
+    Minimum,
+    // A spare image to draw into while the others are queued
+    // or on screen. Smoother, for up to a frame more latency.
+    MinimumPlusOne,
+}
+
+// The bitwise ops a fragment can be combined with the target by,
+// as pso::LogicOp, which isn't Copy and so can't live in GfxConfig
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LogicOp {
+    Clear,
+    And,
+    AndReverse,
+    Copy,
+    AndInverted,
+    NoOp,
+    Xor,
+    Or,
+    Nor,
+    Equivalent,
+    Invert,
+    OrReverse,
+    CopyInverted,
+    OrInverted,
+    Nand,
+    Set,
+}
+
+impl LogicOp {
+    pub fn to_hal(self) -> pso::LogicOp {
+        match self {
+            LogicOp::Clear => pso::LogicOp::Clear,
+            LogicOp::And => pso::LogicOp::And,
+            LogicOp::AndReverse => pso::LogicOp::AndReverse,
+            LogicOp::Copy => pso::LogicOp::Copy,
+            LogicOp::AndInverted => pso::LogicOp::AndInverted,
+            LogicOp::NoOp => pso::LogicOp::NoOp,
+            LogicOp::Xor => pso::LogicOp::Xor,
+            LogicOp::Or => pso::LogicOp::Or,
+            LogicOp::Nor => pso::LogicOp::Nor,
+            LogicOp::Equivalent => pso::LogicOp::Equivalent,
+            LogicOp::Invert => pso::LogicOp::Invert,
+            LogicOp::OrReverse => pso::LogicOp::OrReverse,
+            LogicOp::CopyInverted => pso::LogicOp::CopyInverted,
+            LogicOp::OrInverted => pso::LogicOp::OrInverted,
+            LogicOp::Nand => pso::LogicOp::Nand,
+            LogicOp::Set => pso::LogicOp::Set,
+        }
+    }
+}
+
+impl PoolReset {
+    // For the shared pool, which the per-frame pools replace
+    // when drawing but which still hands out one-off buffers
+    pub fn create_flags(self) -> CommandPoolCreateFlags {
+        match self {
+            PoolReset::Individual => CommandPoolCreateFlags::RESET_INDIVIDUAL,
+            PoolReset::WholePool => CommandPoolCreateFlags::empty(),
+        }
+    }
+}
+
+// Choices made when building the graphics state.
+// Kept around so the state can be rebuilt the same way.
+#[derive(Copy, Clone, Debug)]
+pub struct GfxConfig {
+    pub app: AppInfo,
+    pub color_attachment: AttachmentConfig,
+    // Draw the scene into a texture, then copy that to the screen
+    pub render_to_texture: bool,
+    // Size of that texture relative to the window. It's
+    // stretched to fit with bilinear filtering when drawn.
+    // Needs render_to_texture, and is clamped to a sane range.
+    pub render_scale: f32,
+    // LessEqual suits skyboxes drawn at the far plane,
+    // Greater suits reverse-Z, and Always disables the test
+    pub depth_comparison: Comparison,
+    // Depth the buffer is cleared to, the far plane. Has to agree
+    // with the comparison: 1.0 for Less, 0.0 for reverse-Z's Greater.
+    pub clear_depth: f32,
+    // Where normalized depth lands in the depth buffer, within 0 to 1.
+    // A narrower range squeezes the scene into part of the buffer,
+    // for example 0.5..1.0 pushes everything into the back half.
+    pub min_depth: f32,
+    pub max_depth: f32,
+    // Faces to skip drawing, NONE to draw both sides
+    pub cull_face: pso::Face,
+    // Must match vertex::WINDING while culling,
+    // or the generated meshes disappear
+    pub front_face: pso::FrontFace,
+    // Clamp depth to the viewport's range rather than clipping at the
+    // near and far planes, so shadow casters in front of the light's
+    // near plane still land in the map. Needs depthClamp.
+    pub depth_clamp: bool,
+    // Point clip space Y up as in OpenGL, so shaders and assets
+    // written for it draw the right way up. Uses a negative viewport
+    // height where the device has NDC_Y_UP, and flips in the vertex
+    // shaders otherwise. Both mirror the winding, which the rasterizer
+    // accounts for, so front_face stays as the meshes are written.
+    pub flip_y: bool,
+    // Samples along the direction of stretch for the sprite texture's
+    // sampler, which keeps textures sharp at glancing angles.
+    // 1.0 disables it. Clamped to the device's limit, and
+    // left off where it lacks samplerAnisotropy.
+    pub anisotropy: f32,
+    // Draw the scene nodes' edges over their fill, with a depth bias
+    // so the lines don't z-fight with it. Needs fillModeNonSolid,
+    // which is only asked for when this starts out set, and can
+    // then be toggled either way.
+    pub wireframe: bool,
+    // Width of those edges in pixels. Anything but 1.0 needs
+    // wideLines, and is clamped to the device's line width range.
+    pub wireframe_width: f32,
+    // Draw a heatmap of how many times each pixel is drawn, toggled
+    // with O. Every fragment adds the same dim color over a black
+    // clear, with depth testing and logic ops off so all of them count.
+    pub overdraw: bool,
+    // Count primitives and shader invocations each frame, printed
+    // with S. Only the devices that support it enable the feature.
+    pub pipeline_stats: bool,
+    // Draw the colored triangle in place of the quad
+    pub triangle_example: bool,
+    // Cover the target with a gradient from the fragment shader
+    // before anything else is drawn, rather than a flat clear
+    pub gradient_background: bool,
+    // Draw scene node IDs into an integer target and read
+    // back the one under the cursor, for clicking on nodes
+    pub picking: bool,
+    // How the triangle's positions and colors are laid out
+    pub triangle_streams: VertexStreams,
+    // MSAA samples per pixel, one to disable
+    pub samples: NumSamples,
+    // Minimum fraction of samples shaded separately, from 0 to 1.
+    // Needs sampleRateShading, and is ignored without it.
+    pub sample_shading: Option<f32>,
+    // Combines fragments bitwise with what's already in the target,
+    // in place of alpha blending. Only applies to UNORM and integer
+    // targets, not sRGB or float ones. Needs logicOp.
+    pub logic_op: Option<LogicOp>,
+    pub resolve_target: ResolveTarget,
+    // Record the scene nodes into a secondary command buffer per frame
+    // in flight and reuse them, until the nodes, their tint or the
+    // pipelines change. Suits scenes that rarely change, since a moving
+    // scene records every frame as usual. Pipeline statistics aren't
+    // gathered with it, since queries can't span secondary buffers
+    // without inheritedQueries.
+    pub static_scene: bool,
+    // Cull the quad in a compute pass, which
+    // writes the index count for an indirect draw
+    pub indirect_draw: bool,
+    // Show a CPU framebuffer over the scene for software rendering
+    pub software_framebuffer: bool,
+    // How many frames the CPU may get ahead of the GPU
+    pub frames_in_flight: usize,
+    // Wait for the previous frame to finish rendering before
+    // acquiring the next image, so at most one frame is queued ahead
+    // of the present engine. Less input lag, at the cost of CPU and GPU
+    // overlap. Only the render fence is waited on, since nothing here
+    // reports when a present completes.
+    pub single_present: bool,
+    pub pool_reset: PoolReset,
+    // Present in a wider format where the surface supports one
+    pub hdr: bool,
+    // Write each shader's SPIR-V to a .spv file next
+    // to its source as it's compiled, for disassembling
+    pub dump_spirv: bool,
+    // Device features to turn on where the adapter has them,
+    // on top of the ones the other options need
+    pub optional_features: Features,
+    // Vulkan device extensions to enable where the adapter has them,
+    // such as VK_KHR_maintenance1. gfx-hal only reaches them through
+    // features, so ones without a feature are reported and skipped.
+    // See device_extensions for the mapping.
+    pub device_extensions: &'static [&'static str],
+    // How the window is blended with whatever is behind it.
+    // PREMULTIPLIED suits transparent windows and overlays.
+    // Falls back to OPAQUE where the surface doesn't support it.
+    pub composite_alpha: CompositeAlpha,
+    // Clamped to the range the surface supports.
+    // None leaves the choice to gfx-hal.
+    pub image_count: Option<ImageCount>,
+    // Extra ways to use the swapchain images, such as TRANSFER_SRC
+    // for copying frames out. Ones the surface doesn't support
+    // are left off, so check Swapchain::usage before relying on them.
+    pub swapchain_usage: image::Usage,
+    // An image file for every sprite to sample, tinted by the
+    // sprite's color. BC1, BC2 and BC3 come from DDS files.
+    pub sprite_texture: Option<&'static str>,
+    // A glTF file to draw over the scene nodes, fit to the view.
+    // Only one mesh with one material is read so far.
+    pub model: Option<&'static str>,
+    // Fill the depth buffer with the model first, with no color
+    // writes, then shade it testing for equal depth with writes off.
+    // Each pixel is shaded once however much of the model overlaps.
+    pub depth_prepass: bool,
+}
+
+impl Default for GfxConfig {
+    fn default() -> Self {
+        Self {
+            app: AppInfo::default(),
+            color_attachment: AttachmentConfig::CLEAR,
+            render_to_texture: false,
+            render_scale: 1.0,
+            depth_comparison: Comparison::Less,
+            clear_depth: 1.0,
+            min_depth: 0.0,
+            max_depth: 1.0,
+            cull_face: pso::Face::NONE,
+            front_face: vertex::WINDING,
+            depth_clamp: false,
+            flip_y: false,
+            anisotropy: 1.0,
+            wireframe: false,
+            wireframe_width: 1.0,
+            overdraw: false,
+            pipeline_stats: false,
+            triangle_example: false,
+            gradient_background: false,
+            picking: false,
+            triangle_streams: VertexStreams::Interleaved,
+            samples: 1,
+            sample_shading: None,
+            logic_op: None,
+            resolve_target: ResolveTarget::Swapchain,
+            static_scene: false,
+            indirect_draw: false,
+            software_framebuffer: false,
+            frames_in_flight: utils::FRAMES_IN_FLIGHT,
+            single_present: false,
+            pool_reset: PoolReset::Individual,
+            hdr: false,
+            dump_spirv: false,
+            optional_features: Features::empty(),
+            device_extensions: &[],
+            model: None,
+            depth_prepass: false,
+            image_count: None,
+            swapchain_usage: image::Usage::empty(),
+            sprite_texture: None,
+            model: None,
+        }
+    }
+}
+
+impl GfxConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.frames_in_flight < utils::MIN_FRAMES_IN_FLIGHT
+            || self.frames_in_flight > utils::MAX_FRAMES_IN_FLIGHT
+        {
+            return Err("Frames in flight out of range");
+        }
+        if !self.samples.is_power_of_two() {
+            return Err("MSAA sample count must be a power of two");
+        }
+        // A mismatched clear fails fragments that should pass
+        match self.depth_comparison {
+            Comparison::Less | Comparison::LessEqual if self.clear_depth != 1.0 => {
+                return Err("Standard depth needs a clear depth of 1.0");
+            }
+            Comparison::Greater | Comparison::GreaterEqual if self.clear_depth != 0.0 => {
+                return Err("Reverse-Z needs a clear depth of 0.0");
+            }
+            _ => {}
+        }
+        let in_unit = |depth: f32| depth >= 0.0 && depth <= 1.0;
+        if !in_unit(self.min_depth) || !in_unit(self.max_depth) {
+            return Err("Viewport depth range must be within 0 to 1");
+        }
+        if self.min_depth > self.max_depth {
+            return Err("Viewport min depth can't be past the max depth");
+        }
+        if !self.render_scale.is_finite() {
+            return Err("Render scale must be a finite number");
+        }
+        if self.render_scale != 1.0 && !self.render_to_texture {
+            return Err("Render scale needs render_to_texture");
+        }
+        if !self.anisotropy.is_finite() || self.anisotropy < 1.0 {
+            return Err("Anisotropy must be a finite number of at least 1.0");
+        }
+        if !self.wireframe_width.is_finite() || self.wireframe_width <= 0.0 {
+            return Err("Wireframe width must be a finite number above 0");
+        }
+        if self.composite_alpha.bits().count_ones() != 1 {
+            return Err("Exactly one composite alpha mode must be requested");
+        }
+        if self.samples > 1
+            && self.resolve_target == ResolveTarget::Offscreen
+            && !self.render_to_texture
+        {
+            return Err("Resolving to the offscreen target needs render_to_texture");
+        }
+        Ok(())
+    }
+
+    // Everything worth enabling for these options. None are required,
+    // so missing ones switch off whatever uses them.
+    pub fn features(&self) -> Features {
+        let mut features =
+            self.optional_features | device_extensions::features(self.device_extensions);
+        if self.wireframe {
+            features |= Features::FILL_MODE_NON_SOLID;
+        }
+        if self.wireframe && self.wireframe_width != 1.0 {
+            features |= Features::WIDE_LINES;
+        }
+        if self.pipeline_stats {
+            features |= Features::PIPELINE_STATISTICS_QUERY;
+        }
+        if self.sample_shading.is_some() {
+            features |= Features::SAMPLE_RATE_SHADING;
+        }
+        if self.logic_op.is_some() {
+            features |= Features::LOGIC_OP;
+        }
+        if self.depth_clamp {
+            features |= Features::DEPTH_CLAMP;
+        }
+        if self.flip_y {
+            features |= Features::NDC_Y_UP;
+        }
+        if self.anisotropy > 1.0 {
+            features |= Features::SAMPLER_ANISOTROPY;
+        }
+        features
+    }
+
+    pub fn depth_range(&self) -> Range<f32> {
+        self.min_depth..self.max_depth
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+            .max(MIN_RENDER_SCALE)
+            .min(MAX_RENDER_SCALE)
+    }
+
+    // Sets the comparison and clear depth together,
+    // since one without the other draws nothing
+    pub fn with_reverse_z(self) -> Self {
+        Self {
+            depth_comparison: Comparison::Greater,
+            clear_depth: 0.0,
+            ..self
+        }
+    }
+
+    // Everything flat is drawn at z = 0, so whatever goes over
+    // something drawn before it lands at the same depth and has to
+    // pass on equal. Reverse-Z also puts z = 0 at the far plane,
+    // which only passes on equal against the clear.
+    pub fn flat_depth_comparison(&self) -> Comparison {
+        match self.depth_comparison {
+            Comparison::Less => Comparison::LessEqual,
+            Comparison::Greater => Comparison::GreaterEqual,
+            comparison => comparison,
+        }
+    }
+
+    // The model's own test. After a pre-pass its nearest fragments
+    // are already in the buffer, so only those land on equal. The
+    // or-equal comparisons pass them too, and stay safe where the
+    // two passes' depths would otherwise differ.
+    pub fn model_depth_test(&self) -> pso::DepthTest {
+        if self.depth_prepass {
+            pso::DepthTest {
+                fun: self.flat_depth_comparison(),
+                write: false,
+            }
+        } else {
+            pso::DepthTest {
+                fun: self.depth_comparison,
+                write: true,
+            }
+        }
+    }
+
+    // For the pipelines that draw generated meshes
+    pub fn rasterizer(&self) -> pso::Rasterizer {
+        pso::Rasterizer {
+            cull_face: self.cull_face,
+            front_face: if self.flip_y {
+                vertex::mirrored(self.front_face)
+            } else {
+                self.front_face
+            },
+            depth_clamping: self.depth_clamp,
+            ..pso::Rasterizer::FILL
+        }
+    }
+
+    // Edges only, for the overlay on the scene nodes. Nudged towards
+    // the viewer so the lines win the depth test against the fill.
+    pub fn wireframe_rasterizer(&self) -> pso::Rasterizer {
+        // Reverse-Z puts nearer fragments at greater depths
+        let bias = match self.depth_comparison {
+            Comparison::Greater | Comparison::GreaterEqual => WIREFRAME_BIAS,
+            _ => -WIREFRAME_BIAS,
+        };
+        pso::Rasterizer {
+            // Set per draw, see DrawCommand::line_width
+            polygon_mode: pso::PolygonMode::Line(pso::State::Dynamic),
+            depth_bias: Some(pso::State::Static(pso::DepthBias {
+                const_factor: bias,
+                // Anything else needs depthBiasClamp
+                clamp: 0.0,
+                slope_factor: bias,
+            })),
+            ..self.rasterizer()
+        }
+    }
+
+    // Samples for the pass that draws into the swapchain image
+    pub fn swapchain_samples(&self) -> NumSamples {
+        match self.resolve_target {
+            ResolveTarget::Swapchain => self.samples,
+            ResolveTarget::Offscreen => 1,
+        }
+    }
+
+    // Samples for the pass that draws into the offscreen target
+    pub fn offscreen_samples(&self) -> NumSamples {
+        match self.resolve_target {
+            ResolveTarget::Swapchain => 1,
+            ResolveTarget::Offscreen => self.samples,
+        }
+    }
+
+    // Samples for the pass the scene is drawn in
+    pub fn scene_samples(&self) -> NumSamples {
+        if self.render_to_texture {
+            self.offscreen_samples()
+        } else {
+            self.swapchain_samples()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_mode_is_only_asked_for_with_wireframe() {
+        let config = GfxConfig::default();
+        assert!(!config.features().contains(Features::FILL_MODE_NON_SOLID));
+        let wireframe = GfxConfig {
+            wireframe: true,
+            ..config
+        };
+        assert!(wireframe.features().contains(Features::FILL_MODE_NON_SOLID));
+        assert!(!wireframe.features().contains(Features::WIDE_LINES));
+        let wide = GfxConfig {
+            wireframe_width: 3.0,
+            ..wireframe
+        };
+        assert!(wide.features().contains(Features::WIDE_LINES));
+    }
+
+    #[test]
+    fn reverse_z_is_valid() {
+        assert_eq!(GfxConfig::default().with_reverse_z().validate(), Ok(()));
+    }
+
+    #[test]
+    fn depth_clear_has_to_match_the_comparison() {
+        let reversed = GfxConfig {
+            depth_comparison: Comparison::Greater,
+            clear_depth: 1.0,
+            ..GfxConfig::default()
+        };
+        assert_eq!(
+            reversed.validate(),
+            Err("Reverse-Z needs a clear depth of 0.0")
+        );
+        let standard = GfxConfig {
+            depth_comparison: Comparison::Less,
+            clear_depth: 0.0,
+            ..GfxConfig::default()
+        };
+        assert_eq!(
+            standard.validate(),
+            Err("Standard depth needs a clear depth of 1.0")
+        );
+    }
+
+    #[test]
+    fn prepass_turns_model_depth_writes_off() {
+        let config = GfxConfig::default();
+        assert_eq!(
+            config.model_depth_test(),
+            pso::DepthTest {
+                fun: Comparison::Less,
+                write: true,
+            }
+        );
+        let prepass = GfxConfig {
+            depth_prepass: true,
+            ..config
+        };
+        assert_eq!(
+            prepass.model_depth_test(),
+            pso::DepthTest {
+                fun: Comparison::LessEqual,
+                write: false,
+            }
+        );
+        assert_eq!(
+            prepass.with_reverse_z().model_depth_test().fun,
+            Comparison::GreaterEqual
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use gfx_hal::{
+    device::OomOrDeviceLost,
+    window::{AcquireError, PresentError},
+};
+use std::fmt;
+
+// Most failures are only reported, but a lost device
+// invalidates every resource and needs a full rebuild
+#[derive(Debug, Copy, Clone)]
+pub enum GfxError {
+    DeviceLost,
+    Other(&'static str),
+}
+
+impl fmt::Display for GfxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GfxError::DeviceLost => write!(f, "The graphics device was lost"),
+            GfxError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<&'static str> for GfxError {
+    fn from(message: &'static str) -> Self {
+        GfxError::Other(message)
+    }
+}
+
+impl From<OomOrDeviceLost> for GfxError {
+    fn from(e: OomOrDeviceLost) -> Self {
+        match e {
+            OomOrDeviceLost::DeviceLost(_) => GfxError::DeviceLost,
+            OomOrDeviceLost::OutOfMemory(_) => {
+                GfxError::Other("Out of memory waiting on the fence")
+            }
+        }
+    }
+}
+
+impl From<AcquireError> for GfxError {
+    fn from(e: AcquireError) -> Self {
+        match e {
+            AcquireError::DeviceLost(_) => GfxError::DeviceLost,
+            _ => GfxError::Other("Failed to acquire an image from the swapchain"),
+        }
+    }
+}
+
+impl From<PresentError> for GfxError {
+    fn from(e: PresentError) -> Self {
+        match e {
+            PresentError::DeviceLost(_) => GfxError::DeviceLost,
+            _ => GfxError::Other("Failed to present into the swapchain"),
+        }
+    }
+}
@@ -1,312 +1,1399 @@
-use crate::{utils, BufferInfo, PipelineInfo};
-use arrayvec::ArrayVec;
+use crate::{
+    allocator_stats, attachment_config,
+    debug_names::set_debug_name,
+    demo::{Demo, DemoMeshes},
+    descriptor::DescriptorAllocator,
+    device_extensions,
+    drawing::DrawCommand,
+    pipeline_info::{GradientUniforms, YFlip},
+    subpass_dependencies,
+    swapchain::{self, Swapchain},
+    utils,
+    vertex::{self, VertexStreams},
+    AttachmentConfig, BufferHandle, BufferInfo, CpuFrameTiming, DebugDraw, FrameCapture, FramePool,
+    FrameSync, FrameUniforms, Framebuffer2D, GfxConfig, GpuModel, ImageInfo, IndirectDraw,
+    InstanceTransforms, LogicOp, MemoryBudget, Model, Offscreen, Picking, PipelineInfo,
+    PipelineOptions, PipelineStats, PixelBlit, PoolReset, PresentHandoff, ReadbackHandle,
+    ReadbackStatus, ResourceManager, RollingAverage, SpriteBatch, SpriteTexture, StagedBuffer,
+    StaticScene, StatsQueries, ValidationLog,
+};
 use gfx_backend_vulkan as back;
 use gfx_hal::{
-    adapter::{Gpu, PhysicalDevice},
+    adapter::{Adapter, Gpu, PhysicalDevice},
     buffer::Usage,
     command::Level,
     device::Device,
-    format::{self, Format},
+    format::Format,
     image,
-    pass::{self, AttachmentLayout, AttachmentOps},
-    pool::{CommandPool, CommandPoolCreateFlags},
-    pso::Rect,
-    queue::family::{QueueFamily, QueueGroup},
+    pass::{self, AttachmentLayout},
+    pool::CommandPool,
+    pso,
+    queue::family::{QueueFamily, QueueFamilyId, QueueGroup},
     window::{self, Surface},
-    Backend, Features, Instance,
+    Backend, Features, Instance, Limits,
+};
+use std::{
+    mem::{self, ManuallyDrop},
+    time::Duration,
 };
-use std::mem::ManuallyDrop;
 
-const FORMAT: Format = Format::Rgba8Srgb;
+// Frames averaged over for the acquire latency
+const ACQUIRE_SAMPLES: usize = 60;
 
 pub struct GfxState {
     pub current_frame: usize,
-    pub content_size: Rect,
+    pub config: GfxConfig,
+    // Enabled on the device, which may be fewer than the config asked for
+    pub features: Features,
+    // Of GfxConfig::device_extensions, the ones the device was opened with
+    pub device_extensions: Vec<&'static str>,
 
     pub device: back::Device,
     pub queue_group: QueueGroup<back::Backend>,
+    // Only set when the graphics family can't present
+    pub present_queue_group: Option<QueueGroup<back::Backend>>,
+    // Passes the swapchain images to that queue's family
+    pub present_handoff: Option<PresentHandoff>,
 
-    pub in_flight_fences: Vec<<back::Backend as Backend>::Fence>,
-    pub render_finished_semaphores: Vec<<back::Backend as Backend>::Semaphore>,
-    pub image_available_semaphores: Vec<<back::Backend as Backend>::Semaphore>,
+    pub frames: Vec<FrameSync>,
+    // The frame last submitted with each swapchain image,
+    // which has to finish before the image's commands are rerecorded
+    pub images_in_flight: Vec<Option<usize>>,
+    // Time the CPU spent blocked on earlier frames' render fences
+    // during the last frame, including GfxConfig::single_present's
+    // wait. Fences only cover rendering, so this says nothing about
+    // when those frames were actually presented.
+    pub render_fence_wait: Duration,
+    // Time spent blocked in acquire_image. Staying high
+    // means presentation is waiting on the GPU.
+    pub acquire_latency: RollingAverage,
+    // Breakdown of the last frame drawn
+    pub cpu_timing: CpuFrameTiming,
+    // Something changed since the last presented frame.
+    // Cleared once a frame is drawn successfully.
+    pub dirty: bool,
+    // Recreating the swapchain failed, so the next frame tries again
+    pub swapchain_stale: bool,
+    // One per swapchain image, unless the per-frame pools are used
     pub command_buffers: Vec<<back::Backend as Backend>::CommandBuffer>,
-    pub framebuffers: Vec<<back::Backend as Backend>::Framebuffer>,
-    pub image_views: Vec<<back::Backend as Backend>::ImageView>,
+    // One per frame in flight with PoolReset::WholePool
+    pub frame_pools: Vec<FramePool>,
 
     pub command_pool: ManuallyDrop<<back::Backend as Backend>::CommandPool>,
     pub render_pass: ManuallyDrop<<back::Backend as Backend>::RenderPass>,
-    pub swapchain: ManuallyDrop<<back::Backend as Backend>::Swapchain>,
+    // Begun instead for an image's first frame when the pass loads
+    pub first_pass: Option<<back::Backend as Backend>::RenderPass>,
 
+    pub swapchain: Swapchain,
+    pub offscreen: Option<Offscreen>,
+    pub descriptors: DescriptorAllocator,
     pub pipeline: PipelineInfo,
-    pub vertices: BufferInfo,
-    pub indices: BufferInfo,
+    pub sprite_pipeline: PipelineInfo,
+    // Only taken when freeing
+    pub sprite_texture: Option<SpriteTexture>,
+    pub triangle_pipeline: PipelineInfo,
+    pub node_pipeline: PipelineInfo,
+    // Only where the device has fillModeNonSolid
+    pub wireframe_pipeline: Option<PipelineInfo>,
+    pub gradient_pipeline: PipelineInfo,
+    // What the gradient reads, with GfxConfig::gradient_background
+    pub gradient_uniforms: Option<FrameUniforms<GradientUniforms>>,
+    pub debug_pipeline: PipelineInfo,
+    // Only with a model to draw
+    pub model_pipeline: Option<PipelineInfo>,
+    // Fills depth before the model pipeline draws, see
+    // GfxConfig::depth_prepass
+    pub model_depth_pipeline: Option<PipelineInfo>,
+    // From GfxConfig::model, drawn over the scene nodes
+    pub model: Option<GpuModel>,
+    pub sprites: SpriteBatch,
+    // Rewritten by the particle system every step, so
+    // it never clears sprites added by anything else
+    pub particle_sprites: SpriteBatch,
+    // World matrices for the batched scene nodes
+    pub node_instances: InstanceTransforms,
+    // Nodes recorded once and reused, with GfxConfig::static_scene
+    pub static_scene: Option<StaticScene>,
+    // Bumped whenever a render pass is replaced, for
+    // recordings made against the old one to notice
+    pub pass_generation: u64,
+    // Shapes added since the last frame, drawn over everything else
+    pub debug_draw: DebugDraw,
+    // Owns the buffers and images referred to by handle
+    pub resources: ResourceManager,
+    pub vertices: BufferHandle,
+    pub indices: BufferHandle,
+    // What was uploaded to them, for checking and de-indexing draws
+    pub meshes: DemoMeshes,
+    // Which of the meshes is drawn, switched with set_demo
+    pub demo: Demo,
+    // Draw the quad with draw_indexed, or with draw
+    // from a de-indexed copy of its vertices
+    pub indexed: bool,
+    // The copy and the command it was expanded for,
+    // built by the first frame drawn without indices
+    pub flat_vertices: Option<(DrawCommand, BufferInfo)>,
+    // One buffer per vertex stream, in binding order
+    pub triangle: Vec<StagedBuffer>,
+    pub indirect: Option<IndirectDraw>,
+    pub pixels: Option<PixelBlit>,
+    // Only where the device can count shader invocations
+    pub stats: Option<StatsQueries>,
+    pub picking: Option<Picking>,
+    // The offscreen target on its way back to the CPU
+    pub screenshot: Option<ReadbackHandle>,
+    pub capture: FrameCapture,
+    // Recent warnings and errors from the validation layers,
+    // which the backend enables in debug builds
+    pub validation: ValidationLog,
+
+    // Kept alive for swapchain recreation.
+    // Fields drop in order, so the instance must be last.
+    pub surface: <back::Backend as Backend>::Surface,
+    pub adapter: Adapter<back::Backend>,
+    pub instance: back::Instance,
 }
 
 impl GfxState {
-    pub fn new(window: &winit::window::Window) -> Result<Self, &'static str> {
+    pub fn new(
+        window: &winit::window::Window,
+        config: GfxConfig,
+        validation: ValidationLog,
+    ) -> Result<Self, &'static str> {
+        config.validate()?;
+
+        // Culling relies on the generated meshes all winding one way
+        let meshes = DemoMeshes::new();
+        meshes.check_winding()?;
+        let triangle_positions: Vec<_> = utils::TRIANGLE_DATA.iter().map(|v| v.position).collect();
+        vertex::check_winding(&triangle_positions, &[0, 1, 2])?;
+
         // Backend handle
-        let instance =
-            back::Instance::create(utils::WINDOW_NAME, 1).map_err(|_| "Unsupported backend")?;
+        let instance = back::Instance::create(config.app.name, config.app.version)
+            .map_err(|_| "Unsupported backend")?;
 
         // Window drawing surface
-        // TODO: Does this need to be paired with a destroy_surface call?
         let mut surface = unsafe { instance.create_surface(window) }
             .map_err(|_| "Could not get drawing surface")?;
 
-        // Supports our backend, probably a GPU
+        // Supports our backend, probably a GPU.
+        // Graphics and presentation may be on different queue families.
         let adapter = instance
             .enumerate_adapters()
             .into_iter()
             .find(|a| {
-                a.queue_families.iter().any(|qf| {
-                    qf.queue_type().supports_graphics() && surface.supports_queue_family(qf)
-                })
+                a.queue_families
+                    .iter()
+                    .any(|qf| qf.queue_type().supports_graphics())
+                    && a.queue_families
+                        .iter()
+                        .any(|qf| surface.supports_queue_family(qf))
             })
             .ok_or("No adapter supporting Vulkan")?;
 
-        let (device, queue_group) = {
-            // A set of queues with identical properties
+        let (device, mut queue_group, present_queue_group, features, device_extensions) = {
+            // A set of queues with identical properties.
+            // Prefer one family that does both so no handoff is needed.
             let queue_family = adapter
                 .queue_families
                 .iter()
                 .find(|qf| qf.queue_type().supports_graphics() && surface.supports_queue_family(qf))
+                .or_else(|| {
+                    adapter
+                        .queue_families
+                        .iter()
+                        .find(|qf| qf.queue_type().supports_graphics())
+                })
                 .ok_or("No queue family with graphics.")?;
 
-            // The adapter's underlying device
-            let gpu = unsafe {
-                adapter
-                    .physical_device
-                    // Request graphics queue with full priority and core features only
-                    .open(&[(queue_family, &[1.0f32])], Features::empty())
+            let present_family = if surface.supports_queue_family(queue_family) {
+                None
+            } else {
+                Some(
+                    adapter
+                        .queue_families
+                        .iter()
+                        .find(|qf| surface.supports_queue_family(qf))
+                        .ok_or("No queue family that can present.")?,
+                )
+            };
+
+            // Request queues with full priority
+            let mut families = vec![(queue_family, &[1.0f32][..])];
+            if let Some(family) = present_family {
+                families.push((family, &[1.0f32][..]));
             }
-            .map_err(|_| "Could not open physical device")?;
+
+            // Only what the config asks for, so nothing
+            // behaves differently from one device to the next
+            let (features, missing) =
+                negotiate_features(adapter.physical_device.features(), config.features());
+            if !missing.is_empty() {
+                log::warn!("Device features not supported: {:?}", missing);
+            }
+            let extensions = device_extensions::negotiate(config.device_extensions, features);
+            if !extensions.unsupported.is_empty() {
+                log::warn!(
+                    "Device extensions not supported: {:?}",
+                    extensions.unsupported
+                );
+            }
+            if !extensions.unreachable.is_empty() {
+                log::warn!(
+                    "Device extensions gfx-hal can't enable: {:?}",
+                    extensions.unreachable
+                );
+            }
+
+            // The adapter's underlying device
+            let gpu = unsafe { adapter.physical_device.open(&families, features) }
+                .map_err(|_| "Could not open physical device")?;
 
             // Take ownership of contents so the gpu can go
             // out of scope while the queue group lives on
             let Gpu {
                 device,
-                queue_groups,
+                mut queue_groups,
             } = gpu;
 
             // Queue group contains queues matching the queue family
-            let queue_group = queue_groups
-                .into_iter()
-                .find(|qg| qg.family == queue_family.id())
-                .ok_or("Matching queue group not found")?;
+            let mut take_group = |family: &back::QueueFamily| {
+                let i = queue_groups
+                    .iter()
+                    .position(|qg| qg.family == family.id())
+                    .ok_or("Matching queue group not found")?;
+                let group = queue_groups.remove(i);
+                if group.queues.is_empty() {
+                    Err("Queue group contains no command queues")
+                } else {
+                    Ok(group)
+                }
+            };
 
-            (device, queue_group)
-        };
+            let queue_group = take_group(queue_family)?;
+            let present_queue_group = match present_family {
+                Some(family) => Some(take_group(family)?),
+                None => None,
+            };
 
-        if !queue_group.queues.is_empty() {
-            Ok(())
-        } else {
-            Err("Queue group contains no command queues")
-        }?;
-
-        let content_size = window.inner_size();
-        let content_size = window::Extent2D {
-            width: content_size.width,
-            height: content_size.height,
-        };
+            // Culling is dispatched alongside the draws
+            if config.indirect_draw && !queue_family.queue_type().supports_compute() {
+                return Err("Indirect drawing needs a graphics queue that supports compute");
+            }
 
-        let swapchain_config = {
-            let capabilities = surface.capabilities(&adapter.physical_device);
-            window::SwapchainConfig::from_caps(&capabilities, FORMAT, content_size)
-                .with_present_mode(window::PresentMode::MAILBOX)
+            (
+                device,
+                queue_group,
+                present_queue_group,
+                features,
+                extensions.enabled,
+            )
         };
 
-        let (swapchain, image_views) = {
-            // Swapchain manages a collection of images
-            // Backbuffer contains handles to swapchain image memory
-            let (swapchain, backbuffer) =
-                unsafe { device.create_swapchain(&mut surface, swapchain_config, None) }
-                    .map_err(|_| "Could not create swapchain")?;
-
-            // Describe access to the underlying image memory,
-            // possibly a subregion
-            let image_views = backbuffer
-                .into_iter()
-                .map(|image| {
-                    unsafe {
-                        device.create_image_view(
-                            &image,
-                            image::ViewKind::D2,
-                            FORMAT,
-                            format::Swizzle::NO,
-                            image::SubresourceRange {
-                                // Properties that further specify the image format,
-                                // especially if it is ambiguous
-                                aspects: format::Aspects::COLOR,
-                                // Mipmaps
-                                levels: 0..1,
-                                // Image array layers
-                                layers: 0..1,
-                            },
-                        )
-                    }
-                    .map_err(|_| "Could not create a backbuffer image view")
-                })
-                .collect::<Result<Vec<_>, &str>>()?;
+        // The sample count limit is a mask of supported counts
+        let limits = adapter.physical_device.limits();
+        if limits.framebuffer_color_samples_count & config.samples == 0 {
+            return Err("MSAA sample count not supported by the device");
+        }
+        let rasterizer = rasterizer(&config, features)?;
+        let options = pipeline_options(&config, features, &limits);
 
-            (swapchain, image_views)
-        };
+        let content_size = window_extent(window);
 
-        // A render pass is collection of subpasses describing
-        // the type of images used during rendering operations,
-        // how they will be used,
-        // and the treatment of their contents
-        let render_pass = unsafe {
-            device.create_render_pass(
-                &[
-                    // Describes a render target,
-                    // to be attached as input or output
-                    pass::Attachment {
-                        format: Some(FORMAT),
-                        // Don't have MSAA yet anyway
-                        samples: 1,
-                        // Clear the render target to the clear color and preserve the result
-                        ops: AttachmentOps::new(
-                            pass::AttachmentLoadOp::Clear,
-                            pass::AttachmentStoreOp::Store,
-                        ),
-                        stencil_ops: AttachmentOps::DONT_CARE,
-                        // Begin uninitialized, end ready to present
-                        layouts: AttachmentLayout::Undefined..AttachmentLayout::Present,
-                    },
-                ],
-                &[
-                    // Render pass stage, distinct from multipass rendering
-                    pass::SubpassDesc {
-                        // Zero is color attachment ID
-                        colors: &[(0, AttachmentLayout::ColorAttachmentOptimal)],
-                        depth_stencil: None,
-                        inputs: &[],
-                        // For MSAA
-                        resolves: &[],
-                        // Attachments not used by subpass but which must preserved
-                        preserves: &[],
-                    },
-                ],
-                &[],
-            )
+        let supported_formats = surface.supported_formats(&adapter.physical_device);
+        let format =
+            swapchain::pick_format(supported_formats.as_ref().map(Vec::as_slice), config.hdr);
+        if config.hdr && format == swapchain::FORMAT {
+            log::warn!("No HDR swapchain format available, using {:?}", format);
         }
-        .map_err(|_| "Could not create render pass")?;
-
-        // Where a render pass describes the types of image attachments,
-        // a framebuffer binds specific images to its attachements
-        let framebuffers = image_views
-            .iter()
-            .map(|view| {
-                let view_vec: ArrayVec<[_; 1]> = [view].into();
-                unsafe {
-                    device.create_framebuffer(
-                        &render_pass,
-                        view_vec,
-                        image::Extent {
-                            width: content_size.width,
-                            height: content_size.height,
-                            // Layers
-                            depth: 1,
-                        },
-                    )
-                }
-                .map_err(|_| "Could not create framebuffer")
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+
+        let render_pass = main_render_pass(&device, &config, format, config.color_attachment)?;
+        let first_pass = first_render_pass(&device, &config, format)?;
+
+        let swapchain = Swapchain::new(
+            &device,
+            &mut surface,
+            &adapter,
+            &render_pass,
+            content_size,
+            format,
+            config.swapchain_samples(),
+            config.composite_alpha,
+            config.image_count,
+            config.swapchain_usage,
+        )?;
+
+        let present_handoff = match &present_queue_group {
+            Some(group) => Some(PresentHandoff::new(
+                &device,
+                queue_group.family..group.family,
+                &swapchain.images,
+            )?),
+            None => None,
+        };
 
         // Allocator for command buffers
         let mut command_pool = unsafe {
-            device.create_command_pool(queue_group.family, CommandPoolCreateFlags::RESET_INDIVIDUAL)
+            device.create_command_pool(queue_group.family, config.pool_reset.create_flags())
         }
         .map_err(|_| "Could not create command pool")?;
 
-        let content_size = content_size.to_extent().rect();
+        // Room for a handful of textures before another pool is needed
+        let mut descriptors = DescriptorAllocator::new(
+            16,
+            vec![
+                pso::DescriptorRangeDesc {
+                    ty: pso::DescriptorType::CombinedImageSampler,
+                    count: 16,
+                },
+                pso::DescriptorRangeDesc {
+                    ty: pso::DescriptorType::StorageBuffer,
+                    count: 4,
+                },
+                // The gradient's, one per frame in flight
+                pso::DescriptorRangeDesc {
+                    ty: pso::DescriptorType::UniformBuffer,
+                    count: utils::MAX_FRAMES_IN_FLIGHT,
+                },
+            ],
+        );
+
+        let offscreen = if config.render_to_texture {
+            Some(Offscreen::new(
+                &device,
+                &adapter,
+                &render_pass,
+                scaled_size(swapchain.content_size, config.render_scale()),
+                config.color_attachment,
+                config.offscreen_samples(),
+                config.swapchain_samples(),
+                &mut descriptors,
+                options,
+            )?)
+        } else {
+            None
+        };
+
+        let indirect = if config.indirect_draw {
+            Some(IndirectDraw::new(
+                &device,
+                &adapter,
+                &mut descriptors,
+                options,
+            )?)
+        } else {
+            None
+        };
+
+        // One CPU pixel per window pixel
+        let pixels = if config.software_framebuffer {
+            let size = swapchain.content_size;
+            Some(PixelBlit::new(
+                &device,
+                &adapter,
+                pass::Subpass {
+                    index: 0,
+                    main_pass: &render_pass,
+                },
+                config.swapchain_samples(),
+                Framebuffer2D::new(size.w as u32, size.h as u32),
+                config.frames_in_flight,
+                &mut descriptors,
+                options,
+            )?)
+        } else {
+            None
+        };
+
+        let picking = if config.picking {
+            Some(Picking::new(
+                &device,
+                &adapter,
+                swapchain.content_size,
+                config.frames_in_flight,
+                options,
+            )?)
+        } else {
+            None
+        };
+
+        let stats = if features.contains(Features::PIPELINE_STATISTICS_QUERY) {
+            Some(StatsQueries::new(&device, config.frames_in_flight)?)
+        } else {
+            None
+        };
+
+        // The scene is drawn in the offscreen pass when there is one
+        let scene_pass = scene_pass(&render_pass, &offscreen);
+
+        // Written once, since frames in flight may be reading them
+        // at any time. Kept mapped for changes made between frames.
+        let mut vertices = BufferInfo::new(&device, &adapter, &meshes.vertices, Usage::VERTEX)?;
+        vertices.map_persistent(&device)?;
+        vertices.load_data(&device, &meshes.vertices)?;
+        set_debug_name(&device, &mut *vertices.buffer, "vertex_buffer");
+        let mut indices = BufferInfo::new(&device, &adapter, &meshes.indices, Usage::INDEX)?;
+        indices.map_persistent(&device)?;
+        indices.load_data(&device, &meshes.indices)?;
+        set_debug_name(&device, &mut *indices.buffer, "index_buffer");
+        let mut resources = ResourceManager::default();
+        let vertices = resources.create_buffer(vertices);
+        let indices = resources.create_buffer(indices);
+
+        let sprite_pipeline = PipelineInfo::sprites(
+            &device,
+            pass::Subpass {
+                index: 0,
+                main_pass: scene_pass,
+            },
+            config.scene_samples(),
+            sample_shading(&config, features),
+            logic_op(&config, features),
+            rasterizer,
+            options,
+        )?;
+        let sprite_texture = SpriteTexture::new(
+            &device,
+            &adapter,
+            &mut command_pool,
+            &mut queue_group.queues[0],
+            &sprite_pipeline,
+            &mut descriptors,
+            config.sprite_texture,
+            anisotropy(&config, features, limits.max_sampler_anisotropy),
+        )?;
+
+        let gradient_pipeline = PipelineInfo::gradient(
+            &device,
+            pass::Subpass {
+                index: 0,
+                main_pass: scene_pass,
+            },
+            config.scene_samples(),
+            options,
+        )?;
+        let gradient_uniforms = if config.gradient_background {
+            Some(FrameUniforms::new(
+                &device,
+                &adapter,
+                &gradient_pipeline,
+                0,
+                &mut descriptors,
+                config.frames_in_flight,
+            )?)
+        } else {
+            None
+        };
+
+        let model_pipeline = model_pipeline(
+            &device,
+            pass::Subpass {
+                index: 0,
+                main_pass: scene_pass,
+            },
+            &config,
+            features,
+            options,
+        )?;
+        let model_depth_pipeline = model_depth_pipeline(
+            &device,
+            pass::Subpass {
+                index: 0,
+                main_pass: scene_pass,
+            },
+            &config,
+            features,
+            options,
+        )?;
+        // Loaded once, before anything draws with it
+        let model = match (config.model, &model_pipeline) {
+            (Some(path), Some(pipeline)) => Some(Model::from_gltf(path)?.upload(
+                &device,
+                &adapter,
+                &mut command_pool,
+                &mut queue_group.queues[0],
+                pipeline,
+                &mut descriptors,
+                anisotropy(&config, features, limits.max_sampler_anisotropy),
+            )?),
+            _ => None,
+        };
+
+        // Never changes, so it's copied into device local memory once
+        let triangle = triangle_buffers(&device, &adapter, config.triangle_streams)?;
 
-        let make_semaphore = || {
-            device
-                .create_semaphore()
-                .map_err(|_| "Could not create semaphore")
+        let frame_pools = frame_pools(&device, queue_group.family, &config)?;
+        let static_scene = if config.static_scene {
+            Some(StaticScene::new(&device, queue_group.family)?)
+        } else {
+            None
         };
 
         Ok(Self {
-            image_available_semaphores: full_flight(make_semaphore)?,
-            render_finished_semaphores: full_flight(make_semaphore)?,
-            in_flight_fences: full_flight(|| {
-                device
-                    .create_fence(true)
-                    .map_err(|_| "Could not create fence")
-            })?,
-
-            command_buffers: framebuffers
-                .iter()
-                // Primary command buffers cannot be reused across sub passes
-                .map(|_| unsafe { command_pool.allocate_one(Level::Primary) })
-                .collect::<Vec<_>>(),
+            frames: frame_syncs(&device, config.frames_in_flight)?,
+            images_in_flight: vec![None; swapchain.image_count],
+            render_fence_wait: Duration::default(),
+            acquire_latency: RollingAverage::new(ACQUIRE_SAMPLES),
+            cpu_timing: CpuFrameTiming::default(),
+            // Nothing has been drawn yet
+            dirty: true,
+            swapchain_stale: false,
+
+            command_buffers: allocate_command_buffers(
+                &mut command_pool,
+                image_buffer_count(&config, swapchain.image_count),
+            ),
+            frame_pools,
 
             pipeline: PipelineInfo::new(
                 &device,
                 pass::Subpass {
                     index: 0,
-                    main_pass: &render_pass,
+                    main_pass: scene_pass,
                 },
-                content_size,
+                config.scene_samples(),
+                sample_shading(&config, features),
+                logic_op(&config, features),
+                rasterizer,
+                config.flat_depth_comparison(),
+                options,
             )?,
 
-            vertices: BufferInfo::new(&device, &adapter, &utils::QUAD_DATA, Usage::VERTEX)?,
-            indices: BufferInfo::new(&device, &adapter, &utils::QUAD_INDICES, Usage::INDEX)?,
+            sprite_pipeline,
+            sprite_texture: Some(sprite_texture),
+            sprites: SpriteBatch::default(),
+            particle_sprites: SpriteBatch::default(),
+            node_instances: InstanceTransforms::default(),
+            static_scene,
+            pass_generation: 0,
+
+            triangle_pipeline: PipelineInfo::colored_vertices(
+                &device,
+                pass::Subpass {
+                    index: 0,
+                    main_pass: scene_pass,
+                },
+                config.scene_samples(),
+                sample_shading(&config, features),
+                logic_op(&config, features),
+                rasterizer,
+                config.triangle_streams,
+                options,
+            )?,
+
+            node_pipeline: PipelineInfo::scene_nodes(
+                &device,
+                pass::Subpass {
+                    index: 0,
+                    main_pass: scene_pass,
+                },
+                config.scene_samples(),
+                sample_shading(&config, features),
+                logic_op(&config, features),
+                rasterizer,
+                config.flat_depth_comparison(),
+                options,
+            )?,
+
+            wireframe_pipeline: wireframe_pipeline(
+                &device,
+                pass::Subpass {
+                    index: 0,
+                    main_pass: scene_pass,
+                },
+                &config,
+                features,
+                options,
+            )?,
+
+            gradient_pipeline,
+            gradient_uniforms,
+
+            debug_pipeline: debug_pipeline(
+                &device,
+                pass::Subpass {
+                    index: 0,
+                    main_pass: scene_pass,
+                },
+                &config,
+                features,
+                options,
+            )?,
+            debug_draw: DebugDraw::default(),
+            model_pipeline,
+            model_depth_pipeline,
+            model,
+
+            resources,
+            vertices,
+            indices,
+            meshes,
+            demo: Demo::current(&config),
+            indexed: true,
+            flat_vertices: None,
+            triangle,
+            indirect,
+            pixels,
+            stats,
+            picking,
+            screenshot: None,
+            capture: FrameCapture::attach(),
+            validation,
 
             command_pool: ManuallyDrop::new(command_pool),
             render_pass: ManuallyDrop::new(render_pass),
-            swapchain: ManuallyDrop::new(swapchain),
+            first_pass,
 
             current_frame: 0,
-            content_size,
+            config,
+            features,
+            device_extensions,
+            offscreen,
+            descriptors,
             queue_group,
-            framebuffers,
-            image_views,
+            present_queue_group,
+            present_handoff,
+            swapchain,
             device,
+            surface,
+            adapter,
+            instance,
         })
     }
 
-    pub fn free(&mut self) {
-        use std::ptr::read;
+    // Cheaper than a full rebuild, keeps the device and buffers alive
+    pub fn recreate_swapchain(
+        &mut self,
+        window: &winit::window::Window,
+    ) -> Result<(), &'static str> {
+        // A minimized window has no area to create images for,
+        // so the old swapchain stays until it is restored
+        let extent = window_extent(window);
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(());
+        }
 
         let _ = self.device.wait_idle();
+        // The offscreen target comes back with a new pass
+        self.pass_generation += 1;
 
-        // Don't need to destroy command buffers,
-        // they are freed with their pool
+        self.swapchain.recreate(
+            &self.device,
+            &mut self.surface,
+            &self.adapter,
+            &self.render_pass,
+            extent,
+        )?;
+
+        let buffer_count = image_buffer_count(&self.config, self.swapchain.image_count);
+        if self.command_buffers.len() != buffer_count {
+            unsafe { self.command_pool.free(self.command_buffers.drain(..)) }
+            self.command_buffers = allocate_command_buffers(&mut self.command_pool, buffer_count);
+        }
+
+        // The device is idle, so no image is in use
+        self.images_in_flight = vec![None; self.swapchain.image_count];
+        // Sized for the old images
+        if self
+            .screenshot
+            .as_ref()
+            .map_or(false, ReadbackHandle::awaits_frame)
+        {
+            if let Some(screenshot) = self.screenshot.take() {
+                screenshot.free(&self.device);
+            }
+            log::warn!("Screenshot dropped since the swapchain changed");
+        }
+        if let Some(handoff) = &mut self.present_handoff {
+            handoff.record(&self.swapchain.images);
+        }
+
+        // The offscreen target matches the render area
+        if let Some(offscreen) = self.offscreen.take() {
+            offscreen.free(&self.device, &mut self.descriptors);
+            self.offscreen = Some(Offscreen::new(
+                &self.device,
+                &self.adapter,
+                &self.render_pass,
+                scaled_size(self.swapchain.content_size, self.config.render_scale()),
+                self.config.color_attachment,
+                self.config.offscreen_samples(),
+                self.config.swapchain_samples(),
+                &mut self.descriptors,
+                pipeline_options(
+                    &self.config,
+                    self.features,
+                    &self.adapter.physical_device.limits(),
+                ),
+            )?);
+        }
+
+        self.rebuild_pixels()?;
+        self.rebuild_picking()?;
+
+        // The scene pipelines were built against the offscreen render
+        // pass just replaced. The new one is compatible, so the old
+        // pipelines still work if a shader being edited doesn't compile.
+        if self.offscreen.is_some() {
+            if let Err(e) = self.rebuild_pipelines() {
+                log::error!("{}, keeping the old pipelines", e);
+            }
+        }
+        self.mark_dirty();
+        Ok(())
+    }
+
+    // Everything a demo draws is built up front,
+    // so switching only changes what's recorded
+    pub fn set_demo(&mut self, demo: Demo) {
+        if demo != self.demo {
+            demo.apply(&mut self.config);
+            self.demo = demo;
+            self.mark_dirty();
+        }
+    }
+
+    // Switches between indexed and non-indexed drawing,
+    // which should look exactly the same
+    pub fn set_indexed(&mut self, indexed: bool) -> Result<(), &'static str> {
+        // The culling pass writes an indexed draw
+        if !indexed && self.indirect.is_some() {
+            return Err("Indirect drawing needs the index buffer");
+        }
+        self.indexed = indexed;
+        if indexed {
+            self.free_flat_vertices();
+        }
+        self.mark_dirty();
+        Ok(())
+    }
+
+    // Shows or hides the scene nodes' edges over their fill
+    pub fn set_wireframe(&mut self, wireframe: bool) -> Result<(), &'static str> {
+        if wireframe && self.wireframe_pipeline.is_none() {
+            return Err("Wireframe needs fillModeNonSolid, only asked for with --wireframe");
+        }
+        self.config.wireframe = wireframe;
+        // Recorded with or without the edges
+        if let Some(static_scene) = &mut self.static_scene {
+            static_scene.invalidate();
+        }
+        self.mark_dirty();
+        Ok(())
+    }
+
+    // Switches between drawing the scene and the overdraw
+    // heatmap, which every scene pipeline is rebuilt for
+    pub fn set_overdraw(&mut self, overdraw: bool) -> Result<(), &'static str> {
+        self.device
+            .wait_idle()
+            .map_err(|_| "Could not wait for the device to finish")?;
+        let previous = mem::replace(&mut self.config.overdraw, overdraw);
+        if let Err(e) = self.rebuild_pipelines() {
+            // The old pipelines are still in place
+            self.config.overdraw = previous;
+            return Err(e);
+        }
+        self.mark_dirty();
+        Ok(())
+    }
+
+    // Expands the quad for the given command, unless
+    // the current copy was already made for it
+    pub fn prepare_flat_vertices(&mut self, draw: DrawCommand) -> Result<(), &'static str> {
+        if let Some((built_for, _)) = &self.flat_vertices {
+            if *built_for == draw {
+                return Ok(());
+            }
+        }
+        self.free_flat_vertices();
+
+        let data = draw.deindex(&self.meshes.vertices, &self.meshes.indices);
+        let mut flat = BufferInfo::new(&self.device, &self.adapter, &data, Usage::VERTEX)?;
+        if let Err(e) = flat.load_data(&self.device, &data) {
+            flat.free(&self.device);
+            return Err(e);
+        }
+        set_debug_name(&self.device, &mut *flat.buffer, "flat_vertex_buffer");
+        self.flat_vertices = Some((draw, flat));
+        Ok(())
+    }
+
+    fn free_flat_vertices(&mut self) {
+        if let Some((_, mut flat)) = self.flat_vertices.take() {
+            // Frames in flight may still be reading it
+            let _ = self.device.wait_idle();
+            flat.free(&self.device);
+        }
+    }
+
+    // Moves to the surface's next supported format. The render pass
+    // and everything built against it is recreated along with the swapchain.
+    pub fn cycle_swapchain_format(
+        &mut self,
+        window: &winit::window::Window,
+    ) -> Result<Format, &'static str> {
+        let supported = self
+            .surface
+            .supported_formats(&self.adapter.physical_device);
+        let format =
+            swapchain::next_format(supported.as_ref().map(Vec::as_slice), self.swapchain.format);
+        if format == self.swapchain.format {
+            return Ok(format);
+        }
+
+        let _ = self.device.wait_idle();
+        // Both passes are built before anything is swapped, so
+        // a failure leaves the state drawing in the old format
+        let render_pass = main_render_pass(
+            &self.device,
+            &self.config,
+            format,
+            self.config.color_attachment,
+        )?;
+        let first_pass = match first_render_pass(&self.device, &self.config, format) {
+            Ok(first_pass) => first_pass,
+            Err(e) => {
+                unsafe { self.device.destroy_render_pass(render_pass) };
+                return Err(e);
+            }
+        };
+        let old_pass = mem::replace(&mut *self.render_pass, render_pass);
+        let old_first_pass = mem::replace(&mut self.first_pass, first_pass);
+        let old_format = mem::replace(&mut self.swapchain.format, format);
+        self.pass_generation += 1;
+
+        match self.rebuild_for_format(window) {
+            Ok(()) => {
+                unsafe { self.device.destroy_render_pass(old_pass) };
+                if let Some(old_first_pass) = old_first_pass {
+                    unsafe { self.device.destroy_render_pass(old_first_pass) };
+                }
+                Ok(format)
+            }
+            Err(e) => {
+                // Back to the old passes, rebuilding against them
+                let new_pass = mem::replace(&mut *self.render_pass, old_pass);
+                let new_first_pass = mem::replace(&mut self.first_pass, old_first_pass);
+                self.swapchain.format = old_format;
+                self.pass_generation += 1;
+                if let Err(e) = self.rebuild_for_format(window) {
+                    log::error!("{}, while restoring the old format", e);
+                }
+                unsafe { self.device.destroy_render_pass(new_pass) };
+                if let Some(new_first_pass) = new_first_pass {
+                    unsafe { self.device.destroy_render_pass(new_first_pass) };
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // Everything built against the main render pass
+    fn rebuild_for_format(&mut self, window: &winit::window::Window) -> Result<(), &'static str> {
+        self.recreate_swapchain(window)?;
+        // Otherwise already rebuilt against the offscreen pass
+        if self.offscreen.is_none() {
+            self.rebuild_pipelines()?;
+        }
+        Ok(())
+    }
+
+    // Asks for the next frame to be drawn, for anything that
+    // changes what's on screen without going through the state
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    // Replaces the sync objects without touching the device.
+    // Frame indices restart, since they only ever index these.
+    pub fn set_frames_in_flight(&mut self, count: usize) -> Result<(), &'static str> {
+        let config = GfxConfig {
+            frames_in_flight: count,
+            ..self.config
+        };
+        config.validate()?;
+
+        let _ = self.device.wait_idle();
+
+        let frames = frame_syncs(&self.device, count)?;
+        let pools = match frame_pools(&self.device, self.queue_group.family, &config) {
+            Ok(pools) => pools,
+            Err(e) => {
+                // The old ones are still in use
+                for frame in frames {
+                    frame.free(&self.device)
+                }
+                return Err(e);
+            }
+        };
+        for frame in self.frames.drain(..) {
+            frame.free(&self.device)
+        }
+        for pool in self.frame_pools.drain(..) {
+            pool.free(&self.device)
+        }
+        self.frames = frames;
+        self.frame_pools = pools;
+        self.config = config;
+        self.current_frame = 0;
+        self.images_in_flight = vec![None; self.swapchain.image_count];
+
+        // A query per frame, keeping the last results
+        if let Some(stats) = &mut self.stats {
+            let mut queries = StatsQueries::new(&self.device, count)?;
+            queries.last = stats.last;
+            stats.free(&self.device);
+            *stats = queries;
+        }
+
+        // Per-frame regions follow the count. Sprites
+        // reallocate on their next upload.
+        self.rebuild_gradient_uniforms()?;
+        self.rebuild_pixels()?;
+        self.rebuild_picking()
+    }
+
+    // Used and available memory per heap, where the driver reports it
+    pub fn memory_budget(&self) -> Option<MemoryBudget> {
+        MemoryBudget::query(&self.adapter)
+    }
+
+    // Captures the next frame drawn in RenderDoc, see FrameCapture
+    pub fn trigger_capture(&mut self) -> Result<(), &'static str> {
+        self.capture.trigger()
+    }
+
+    // Starts copying the last frame drawn to the offscreen target,
+    // to be collected with poll_screenshot on later frames. Without
+    // one, the next frame is copied out of the swapchain instead.
+    pub fn request_screenshot(&mut self) -> Result<(), &'static str> {
+        if self.screenshot.is_some() {
+            return Err("A screenshot is already on its way");
+        }
+        let screenshot = match &self.offscreen {
+            Some(offscreen) => ReadbackHandle::image(
+                &self.device,
+                &self.adapter,
+                &mut self.queue_group,
+                &offscreen.target,
+                image::Layout::ShaderReadOnlyOptimal,
+            )?,
+            None if self.swapchain.supports_readback() => {
+                // Saved as it is, the same as a PNG
+                match self.swapchain.format {
+                    Format::Rgba8Srgb | Format::Rgba8Unorm => (),
+                    _ => return Err("Swapchain screenshots need an RGBA8 format"),
+                }
+                let extent = self.swapchain.extent;
+                let screenshot = ReadbackHandle::swapchain(
+                    &self.device,
+                    &self.adapter,
+                    &self.queue_group,
+                    self.swapchain.format,
+                    image::Extent {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    },
+                )?;
+                self.mark_dirty();
+                screenshot
+            }
+            None => return Err("Screenshots need the offscreen target or --readback"),
+        };
+        self.screenshot = Some(screenshot);
+        Ok(())
+    }
+
+    pub fn screenshot_pending(&self) -> bool {
+        self.screenshot.is_some()
+    }
+
+    // The size and RGBA8 pixels, once the copy has finished
+    pub fn poll_screenshot(&mut self) -> Result<Option<(image::Extent, Vec<u8>)>, &'static str> {
+        let (extent, status) = match &mut self.screenshot {
+            Some(screenshot) => (screenshot.extent, screenshot.poll(&self.device)),
+            None => return Ok(None),
+        };
+        let pixels = match status {
+            Ok(ReadbackStatus::Pending) => return Ok(None),
+            Ok(ReadbackStatus::Ready(pixels)) => Ok(pixels),
+            Err(e) => Err(e),
+        };
+        // Finished with either way
+        if let Some(screenshot) = self.screenshot.take() {
+            screenshot.free(&self.device);
+        }
+        pixels.map(|pixels| Some((extent, pixels)))
+    }
+
+    // The scene node under the cursor as of the last finished frame
+    pub fn picked_node(&self) -> Option<usize> {
+        self.picking.as_ref().and_then(|picking| picking.last)
+    }
+
+    // Counts from the last frame the GPU finished
+    pub fn pipeline_stats(&self) -> Option<PipelineStats> {
+        self.stats.as_ref().and_then(|stats| stats.last)
+    }
+
+    // The swapchain's actual size, which the surface may have
+    // clamped away from the window's. Viewports, scissors and
+    // framebuffers all follow this.
+    pub fn extent(&self) -> window::Extent2D {
+        self.swapchain.extent
+    }
+
+    // Averaged over the last few frames
+    pub fn acquire_latency_ms(&self) -> Option<f64> {
+        self.acquire_latency
+            .average()
+            .map(|latency| latency.as_secs_f64() * 1000.0)
+    }
+
+    // Recompiles the scene's shaders. If any fail,
+    // the old pipelines are kept and the error returned.
+    pub fn reload_pipeline(&mut self) -> Result<(), &'static str> {
+        // Frames in flight may be using the old pipelines
+        let _ = self.device.wait_idle();
+        self.rebuild_pipelines()?;
+        self.mark_dirty();
+        Ok(())
+    }
+
+    // Follows the swapchain size and frame count,
+    // keeping the pixels that still fit
+    // One buffer for each frame in flight
+    fn rebuild_gradient_uniforms(&mut self) -> Result<(), &'static str> {
+        if let Some(uniforms) = self.gradient_uniforms.take() {
+            uniforms.free(&self.device, &mut self.descriptors);
+            self.gradient_uniforms = Some(FrameUniforms::new(
+                &self.device,
+                &self.adapter,
+                &self.gradient_pipeline,
+                0,
+                &mut self.descriptors,
+                self.config.frames_in_flight,
+            )?);
+        }
+        Ok(())
+    }
 
-        for fence in self.in_flight_fences.drain(..) {
-            unsafe { self.device.destroy_fence(fence) }
+    fn rebuild_pixels(&mut self) -> Result<(), &'static str> {
+        if let Some(pixels) = self.pixels.take() {
+            let size = self.swapchain.content_size;
+            let mut framebuffer = pixels.free(&self.device, &mut self.descriptors);
+            framebuffer.resize(size.w as u32, size.h as u32);
+            self.pixels = Some(PixelBlit::new(
+                &self.device,
+                &self.adapter,
+                pass::Subpass {
+                    index: 0,
+                    main_pass: &self.render_pass,
+                },
+                self.config.swapchain_samples(),
+                framebuffer,
+                self.config.frames_in_flight,
+                &mut self.descriptors,
+                pipeline_options(
+                    &self.config,
+                    self.features,
+                    &self.adapter.physical_device.limits(),
+                ),
+            )?);
         }
+        Ok(())
+    }
 
-        for semaphore in self.render_finished_semaphores.drain(..) {
-            unsafe { self.device.destroy_semaphore(semaphore) }
+    // Follows the window size and the number of frames in flight
+    fn rebuild_picking(&mut self) -> Result<(), &'static str> {
+        if let Some(picking) = self.picking.take() {
+            let hovered = picking.hovered;
+            picking.free(&self.device);
+            let mut picking = Picking::new(
+                &self.device,
+                &self.adapter,
+                self.swapchain.content_size,
+                self.config.frames_in_flight,
+                pipeline_options(
+                    &self.config,
+                    self.features,
+                    &self.adapter.physical_device.limits(),
+                ),
+            )?;
+            picking.hovered = hovered;
+            self.picking = Some(picking);
         }
+        Ok(())
+    }
 
-        for semaphore in self.image_available_semaphores.drain(..) {
-            unsafe { self.device.destroy_semaphore(semaphore) }
+    fn rebuild_pipelines(&mut self) -> Result<(), &'static str> {
+        let scene_pass = scene_pass(&self.render_pass, &self.offscreen);
+        let subpass = || pass::Subpass {
+            index: 0,
+            main_pass: scene_pass,
+        };
+        let samples = self.config.scene_samples();
+        let sample_shading = sample_shading(&self.config, self.features);
+        let logic_op = logic_op(&self.config, self.features);
+        let rasterizer = rasterizer(&self.config, self.features)?;
+        let options = pipeline_options(
+            &self.config,
+            self.features,
+            &self.adapter.physical_device.limits(),
+        );
+
+        let builders: [&dyn Fn() -> Result<PipelineInfo, &'static str>; 6] = [
+            &|| {
+                PipelineInfo::new(
+                    &self.device,
+                    subpass(),
+                    samples,
+                    sample_shading,
+                    logic_op.clone(),
+                    rasterizer,
+                    self.config.flat_depth_comparison(),
+                    options,
+                )
+            },
+            &|| {
+                PipelineInfo::sprites(
+                    &self.device,
+                    subpass(),
+                    samples,
+                    sample_shading,
+                    logic_op.clone(),
+                    rasterizer,
+                    options,
+                )
+            },
+            &|| {
+                PipelineInfo::colored_vertices(
+                    &self.device,
+                    subpass(),
+                    samples,
+                    sample_shading,
+                    logic_op.clone(),
+                    rasterizer,
+                    self.config.triangle_streams,
+                    options,
+                )
+            },
+            &|| {
+                PipelineInfo::scene_nodes(
+                    &self.device,
+                    subpass(),
+                    samples,
+                    sample_shading,
+                    logic_op.clone(),
+                    rasterizer,
+                    self.config.flat_depth_comparison(),
+                    options,
+                )
+            },
+            &|| PipelineInfo::gradient(&self.device, subpass(), samples, options),
+            &|| {
+                debug_pipeline(
+                    &self.device,
+                    subpass(),
+                    &self.config,
+                    self.features,
+                    options,
+                )
+            },
+        ];
+
+        // Built before anything is freed so a failure leaves things as they were
+        let mut built = Vec::with_capacity(builders.len());
+        for build in builders.iter() {
+            match build() {
+                Ok(pipeline) => built.push(pipeline),
+                Err(e) => {
+                    for mut pipeline in built {
+                        pipeline.free(&self.device);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let wireframe = match wireframe_pipeline(
+            &self.device,
+            subpass(),
+            &self.config,
+            self.features,
+            options,
+        ) {
+            Ok(wireframe) => wireframe,
+            Err(e) => {
+                for mut pipeline in built {
+                    pipeline.free(&self.device);
+                }
+                return Err(e);
+            }
+        };
+        let model = match model_pipeline(
+            &self.device,
+            subpass(),
+            &self.config,
+            self.features,
+            options,
+        ) {
+            Ok(model) => model,
+            Err(e) => {
+                for mut pipeline in built {
+                    pipeline.free(&self.device);
+                }
+                if let Some(mut wireframe) = wireframe {
+                    wireframe.free(&self.device);
+                }
+                return Err(e);
+            }
+        };
+        let model_depth = match model_depth_pipeline(
+            &self.device,
+            subpass(),
+            &self.config,
+            self.features,
+            options,
+        ) {
+            Ok(model_depth) => model_depth,
+            Err(e) => {
+                for mut pipeline in built {
+                    pipeline.free(&self.device);
+                }
+                for mut pipeline in wireframe.into_iter().chain(model) {
+                    pipeline.free(&self.device);
+                }
+                return Err(e);
+            }
+        };
+        if let Some(mut old) = mem::replace(&mut self.wireframe_pipeline, wireframe) {
+            old.free(&self.device);
+        }
+        // The model's descriptor set stays valid, the layout is the same
+        if let Some(mut old) = mem::replace(&mut self.model_pipeline, model) {
+            old.free(&self.device);
+        }
+        if let Some(mut old) = mem::replace(&mut self.model_depth_pipeline, model_depth) {
+            old.free(&self.device);
         }
 
-        for framebuffer in self.framebuffers.drain(..) {
-            unsafe { self.device.destroy_framebuffer(framebuffer) }
+        // In the same order as the builders
+        let mut built = built.into_iter();
+        for slot in &mut [
+            &mut self.pipeline,
+            &mut self.sprite_pipeline,
+            &mut self.triangle_pipeline,
+            &mut self.node_pipeline,
+            &mut self.gradient_pipeline,
+            &mut self.debug_pipeline,
+        ] {
+            let mut old = mem::replace(&mut **slot, built.next().unwrap());
+            old.free(&self.device);
+        }
+        // Recorded against the old node pipeline
+        if let Some(static_scene) = &mut self.static_scene {
+            static_scene.invalidate();
         }
 
-        for view in self.image_views.drain(..) {
-            unsafe { self.device.destroy_image_view(view) }
+        Ok(())
+    }
+
+    pub fn free(&mut self) {
+        use std::ptr::read;
+
+        let _ = self.device.wait_idle();
+
+        // Don't need to destroy command buffers,
+        // they are freed with their pool
+
+        for frame in self.frames.drain(..) {
+            frame.free(&self.device)
+        }
+        if let Some(handoff) = self.present_handoff.take() {
+            handoff.free(&self.device);
+        }
+        for pool in self.frame_pools.drain(..) {
+            pool.free(&self.device)
         }
 
-        self.vertices.free(&self.device);
-        self.indices.free(&self.device);
+        self.resources.free_all(&self.device);
+        self.free_flat_vertices();
+        for mut buffer in self.triangle.drain(..) {
+            buffer.free(&self.device);
+        }
         self.pipeline.free(&self.device);
+        self.sprite_pipeline.free(&self.device);
+        self.triangle_pipeline.free(&self.device);
+        self.node_pipeline.free(&self.device);
+        if let Some(mut wireframe) = self.wireframe_pipeline.take() {
+            wireframe.free(&self.device);
+        }
+        self.gradient_pipeline.free(&self.device);
+        self.debug_pipeline.free(&self.device);
+        if let Some(mut model_pipeline) = self.model_pipeline.take() {
+            model_pipeline.free(&self.device);
+        }
+        if let Some(mut model_depth_pipeline) = self.model_depth_pipeline.take() {
+            model_depth_pipeline.free(&self.device);
+        }
+        self.sprites.free(&self.device);
+        self.particle_sprites.free(&self.device);
+        self.debug_draw.free(&self.device);
+        self.node_instances.free(&self.device);
+        if let Some(static_scene) = self.static_scene.take() {
+            static_scene.free(&self.device);
+        }
+        if let Some(offscreen) = self.offscreen.take() {
+            offscreen.free(&self.device, &mut self.descriptors);
+        }
+        if let Some(indirect) = self.indirect.take() {
+            indirect.free(&self.device, &mut self.descriptors);
+        }
+        if let Some(pixels) = self.pixels.take() {
+            pixels.free(&self.device, &mut self.descriptors);
+        }
+        if let Some(texture) = self.sprite_texture.take() {
+            texture.free(&self.device, &mut self.descriptors);
+        }
+        if let Some(uniforms) = self.gradient_uniforms.take() {
+            uniforms.free(&self.device, &mut self.descriptors);
+        }
+        if let Some(model) = self.model.take() {
+            model.free(&self.device, &mut self.descriptors);
+        }
+        if let Some(mut stats) = self.stats.take() {
+            stats.free(&self.device);
+        }
+        if let Some(picking) = self.picking.take() {
+            picking.free(&self.device);
+        }
+        if let Some(screenshot) = self.screenshot.take() {
+            screenshot.free(&self.device);
+        }
+        self.descriptors.free_pools(&self.device);
+        self.swapchain.free(&self.device);
 
         unsafe {
             self.device
                 .destroy_command_pool(ManuallyDrop::into_inner(read(&self.command_pool)));
             self.device
                 .destroy_render_pass(ManuallyDrop::into_inner(read(&self.render_pass)));
-            self.device
-                .destroy_swapchain(ManuallyDrop::into_inner(read(&self.swapchain)));
+        }
+        if let Some(first_pass) = self.first_pass.take() {
+            unsafe { self.device.destroy_render_pass(first_pass) };
+        }
+
+        // Everything allocated should have been given back by now
+        let leaked = allocator_stats();
+        if leaked.live() > 0 {
+            log::warn!(
+                "{} allocations totalling {} bytes were never freed",
+                leaked.live(),
+                leaked.in_use()
+            );
         }
     }
 }
@@ -317,11 +1404,450 @@ impl std::ops::Drop for GfxState {
     }
 }
 
-fn full_flight<T, F>(cb: F) -> Result<Vec<T>, &'static str>
-where
-    F: Fn() -> Result<T, &'static str>,
-{
-    (0..utils::FRAMES_IN_FLIGHT)
-        .map(|_| cb())
-        .collect::<Result<Vec<_>, _>>()
+fn scene_pass<'a>(
+    render_pass: &'a <back::Backend as Backend>::RenderPass,
+    offscreen: &'a Option<Offscreen>,
+) -> &'a <back::Backend as Backend>::RenderPass {
+    match offscreen {
+        Some(offscreen) => &offscreen.render_pass,
+        None => render_pass,
+    }
+}
+
+// Debug shapes are always interleaved, and drawn from
+// both sides since they aren't wound consistently
+fn debug_pipeline(
+    device: &back::Device,
+    subpass: pass::Subpass<back::Backend>,
+    config: &GfxConfig,
+    features: Features,
+    options: PipelineOptions,
+) -> Result<PipelineInfo, &'static str> {
+    PipelineInfo::colored_vertices(
+        device,
+        subpass,
+        config.scene_samples(),
+        sample_shading(config, features),
+        logic_op(config, features),
+        pso::Rasterizer::FILL,
+        VertexStreams::Interleaved,
+        options,
+    )
+}
+
+// Only where the device can draw edges alone
+fn wireframe_pipeline(
+    device: &back::Device,
+    subpass: pass::Subpass<back::Backend>,
+    config: &GfxConfig,
+    features: Features,
+    options: PipelineOptions,
+) -> Result<Option<PipelineInfo>, &'static str> {
+    if !features.contains(Features::FILL_MODE_NON_SOLID) {
+        return Ok(None);
+    }
+    PipelineInfo::node_wireframe(
+        device,
+        subpass,
+        config.scene_samples(),
+        sample_shading(config, features),
+        logic_op(config, features),
+        config.wireframe_rasterizer(),
+        config.flat_depth_comparison(),
+        options,
+    )
+    .map(Some)
+}
+
+// Only when there's a model to draw with it
+fn model_pipeline(
+    device: &back::Device,
+    subpass: pass::Subpass<back::Backend>,
+    config: &GfxConfig,
+    features: Features,
+    options: PipelineOptions,
+) -> Result<Option<PipelineInfo>, &'static str> {
+    if config.model.is_none() {
+        return Ok(None);
+    }
+    PipelineInfo::model(
+        device,
+        subpass,
+        config.scene_samples(),
+        sample_shading(config, features),
+        logic_op(config, features),
+        rasterizer(config, features)?,
+        config.model_depth_test(),
+        options,
+    )
+    .map(Some)
+}
+
+// Only with a model and the pre-pass. The overdraw heatmap
+// counts every fragment, so it draws the model without one.
+fn model_depth_pipeline(
+    device: &back::Device,
+    subpass: pass::Subpass<back::Backend>,
+    config: &GfxConfig,
+    features: Features,
+    options: PipelineOptions,
+) -> Result<Option<PipelineInfo>, &'static str> {
+    if config.model.is_none() || !config.depth_prepass || options.overdraw {
+        return Ok(None);
+    }
+    PipelineInfo::model_depth(
+        device,
+        subpass,
+        config.scene_samples(),
+        rasterizer(config, features)?,
+        config.depth_comparison,
+        options,
+    )
+    .map(Some)
+}
+
+// At least a pixel each way, however small the scale
+fn scaled_size(content_size: pso::Rect, scale: f32) -> pso::Rect {
+    let scale = |size: i16| ((size as f32 * scale).round() as i16).max(1);
+    pso::Rect {
+        x: 0,
+        y: 0,
+        w: scale(content_size.w),
+        h: scale(content_size.h),
+    }
+}
+
+fn pipeline_options(config: &GfxConfig, features: Features, limits: &Limits) -> PipelineOptions {
+    PipelineOptions {
+        dump_spirv: config.dump_spirv,
+        y_flip: y_flip(config, features),
+        overdraw: config.overdraw,
+        limits: limits.into(),
+    }
+}
+
+// Left off where the device can't do it
+fn sample_shading(config: &GfxConfig, features: Features) -> Option<f32> {
+    config
+        .sample_shading
+        .filter(|_| features.contains(Features::SAMPLE_RATE_SHADING))
+}
+
+// Unlike the others, depth clamping fails rather than being
+// left off, since clipping instead would change the results
+fn rasterizer(config: &GfxConfig, features: Features) -> Result<pso::Rasterizer, &'static str> {
+    if config.depth_clamp && !features.contains(Features::DEPTH_CLAMP) {
+        return Err("Depth clamp needs the depthClamp feature");
+    }
+    Ok(config.rasterizer())
+}
+
+// Falls back to flipping in the vertex shaders
+// where the device can't use a negative viewport height
+fn y_flip(config: &GfxConfig, features: Features) -> YFlip {
+    if !config.flip_y {
+        YFlip::Off
+    } else if features.contains(Features::NDC_Y_UP) {
+        YFlip::Viewport
+    } else {
+        YFlip::Shader
+    }
+}
+
+// Only 1.0 where the device lacks wideLines,
+// and within its line width range otherwise
+pub fn line_width(config: &GfxConfig, features: Features, range: [f32; 2]) -> f32 {
+    if features.contains(Features::WIDE_LINES) {
+        config.wireframe_width.max(range[0]).min(range[1])
+    } else {
+        1.0
+    }
+}
+
+// Off where the device can't do it, and never past its limit
+fn anisotropy(config: &GfxConfig, features: Features, max: f32) -> f32 {
+    if features.contains(Features::SAMPLER_ANISOTROPY) {
+        config.anisotropy.min(max).max(1.0)
+    } else {
+        1.0
+    }
+}
+
+// Left off where the device can't do it,
+// which falls back to alpha blending
+fn logic_op(config: &GfxConfig, features: Features) -> Option<pso::LogicOp> {
+    config
+        .logic_op
+        .filter(|_| features.contains(Features::LOGIC_OP))
+        .map(LogicOp::to_hal)
+}
+
+// Splits the wanted features into those the adapter
+// supports, to enable, and those it doesn't
+fn negotiate_features(supported: Features, wanted: Features) -> (Features, Features) {
+    (wanted & supported, wanted - supported)
+}
+
+// Draws into the swapchain image, so it's rebuilt
+// whenever the swapchain format changes
+pub fn main_render_pass(
+    device: &back::Device,
+    config: &GfxConfig,
+    format: Format,
+    color_attachment: AttachmentConfig,
+) -> Result<<back::Backend as Backend>::RenderPass, &'static str> {
+    // A render pass is collection of subpasses describing
+    // the type of images used during rendering operations,
+    // how they will be used,
+    // and the treatment of their contents
+    let mut render_pass = unsafe {
+        device.create_render_pass(
+            // Describes a render target,
+            // to be attached as input or output.
+            // Ends ready to present.
+            color_attachment.attachments(
+                format,
+                config.swapchain_samples(),
+                AttachmentLayout::Present,
+            ),
+            &[
+                // Render pass stage, distinct from multipass rendering
+                pass::SubpassDesc {
+                    // Zero is color attachment ID
+                    colors: &[(0, AttachmentLayout::ColorAttachmentOptimal)],
+                    // Only drawn into when it's also the scene's pass
+                    depth_stencil: Some(&attachment_config::depth_ref(config.swapchain_samples())),
+                    inputs: &[],
+                    // For MSAA, into the swapchain image
+                    resolves: attachment_config::resolves(config.swapchain_samples()),
+                    // Attachments not used by subpass but which must preserved
+                    preserves: &[],
+                },
+            ],
+            // Explicit rather than relying on the implicit
+            // dependencies, which don't wait on the swapchain
+            &subpass_dependencies::color_chain(1),
+        )
+    }
+    .map_err(|_| "Could not create render pass")?;
+    set_debug_name(device, &mut render_pass, "main_render_pass");
+    Ok(render_pass)
+}
+
+// Swapchain images start out undefined rather than ready to present,
+// so loading them needs a variant of the main pass for their first frame
+fn first_render_pass(
+    device: &back::Device,
+    config: &GfxConfig,
+    format: Format,
+) -> Result<Option<<back::Backend as Backend>::RenderPass>, &'static str> {
+    if !config.color_attachment.loads() {
+        return Ok(None);
+    }
+    let attachment = config.color_attachment.first_use();
+    main_render_pass(device, config, format, attachment).map(Some)
+}
+
+fn window_extent(window: &winit::window::Window) -> window::Extent2D {
+    let size = window.inner_size();
+    window::Extent2D {
+        width: size.width,
+        height: size.height,
+    }
+}
+
+// Per-frame pools take the place of per-image buffers
+fn image_buffer_count(config: &GfxConfig, image_count: usize) -> usize {
+    match config.pool_reset {
+        PoolReset::Individual => image_count,
+        PoolReset::WholePool => 0,
+    }
+}
+
+fn frame_pools(
+    device: &back::Device,
+    family: QueueFamilyId,
+    config: &GfxConfig,
+) -> Result<Vec<FramePool>, &'static str> {
+    match config.pool_reset {
+        PoolReset::Individual => Ok(vec![]),
+        PoolReset::WholePool => {
+            let mut pools = Vec::with_capacity(config.frames_in_flight);
+            for _ in 0..config.frames_in_flight {
+                match FramePool::new(device, family) {
+                    Ok(pool) => pools.push(pool),
+                    Err(e) => {
+                        for pool in pools {
+                            pool.free(device);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(pools)
+        }
+    }
+}
+
+fn allocate_command_buffers(
+    command_pool: &mut <back::Backend as Backend>::CommandPool,
+    count: usize,
+) -> Vec<<back::Backend as Backend>::CommandBuffer> {
+    (0..count)
+        // Primary command buffers cannot be reused across sub passes
+        .map(|_| unsafe { command_pool.allocate_one(Level::Primary) })
+        .collect()
+}
+
+// A buffer per stream, in binding order
+// Uploaded with the first frame's draws
+fn triangle_buffers(
+    device: &back::Device,
+    adapter: &Adapter<back::Backend>,
+    streams: VertexStreams,
+) -> Result<Vec<StagedBuffer>, &'static str> {
+    // A closure can't be generic over the vertex type
+    fn buffer<T>(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        data: &[T],
+        name: &str,
+    ) -> Result<StagedBuffer, &'static str> {
+        let mut buffer = StagedBuffer::new(device, adapter, data, Usage::VERTEX)?;
+        set_debug_name(device, &mut *buffer.buffer, name);
+        Ok(buffer)
+    }
+
+    match streams {
+        VertexStreams::Interleaved => Ok(vec![buffer(
+            device,
+            adapter,
+            &utils::TRIANGLE_DATA,
+            "triangle_vertex_buffer",
+        )?]),
+        VertexStreams::Separate => {
+            let positions: Vec<_> = utils::TRIANGLE_DATA.iter().map(|v| v.position).collect();
+            let colors: Vec<_> = utils::TRIANGLE_DATA.iter().map(|v| v.color).collect();
+            Ok(vec![
+                buffer(device, adapter, &positions, "triangle_positions")?,
+                buffer(device, adapter, &colors, "triangle_colors")?,
+            ])
+        }
+    }
+}
+
+// Frees the ones already made if any fail
+fn frame_syncs(device: &back::Device, count: usize) -> Result<Vec<FrameSync>, &'static str> {
+    let mut frames = Vec::with_capacity(count);
+    for _ in 0..count {
+        match FrameSync::new(device) {
+            Ok(frame) => frames.push(frame),
+            Err(e) => {
+                for frame in frames {
+                    frame.free(device);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(w: i16, h: i16) -> pso::Rect {
+        pso::Rect { x: 0, y: 0, w, h }
+    }
+
+    #[test]
+    fn scaled_size_rounds() {
+        assert_eq!(scaled_size(rect(800, 600), 0.5), rect(400, 300));
+        assert_eq!(scaled_size(rect(801, 601), 0.5), rect(401, 301));
+        assert_eq!(scaled_size(rect(800, 600), 2.0), rect(1600, 1200));
+    }
+
+    #[test]
+    fn scaled_size_keeps_a_pixel() {
+        assert_eq!(scaled_size(rect(100, 1), 0.25), rect(25, 1));
+        assert_eq!(scaled_size(rect(1, 1), 0.1), rect(1, 1));
+    }
+
+    #[test]
+    fn y_flip_falls_back_to_the_shader() {
+        let flipped = GfxConfig {
+            flip_y: true,
+            ..GfxConfig::default()
+        };
+        let limits = Limits::default();
+        let pick = |config, features| pipeline_options(config, features, &limits).y_flip;
+        assert_eq!(pick(&GfxConfig::default(), Features::NDC_Y_UP), YFlip::Off);
+        assert_eq!(pick(&flipped, Features::NDC_Y_UP), YFlip::Viewport);
+        assert_eq!(pick(&flipped, Features::empty()), YFlip::Shader);
+    }
+
+    #[test]
+    fn anisotropy_is_clamped_to_the_device() {
+        let config = GfxConfig {
+            anisotropy: 16.0,
+            ..GfxConfig::default()
+        };
+        let on = Features::SAMPLER_ANISOTROPY;
+        assert_eq!(anisotropy(&config, on, 8.0), 8.0);
+        assert_eq!(anisotropy(&config, on, 16.0), 16.0);
+        // 1.0 leaves the sampler's anisotropy off
+        assert_eq!(anisotropy(&config, Features::empty(), 16.0), 1.0);
+        assert_eq!(anisotropy(&GfxConfig::default(), on, 16.0), 1.0);
+    }
+
+    #[test]
+    fn line_width_is_clamped_to_the_device() {
+        let config = GfxConfig {
+            wireframe_width: 4.0,
+            ..GfxConfig::default()
+        };
+        let wide = Features::WIDE_LINES;
+        assert_eq!(line_width(&config, wide, [1.0, 8.0]), 4.0);
+        assert_eq!(line_width(&config, wide, [1.0, 2.5]), 2.5);
+        let thin = GfxConfig {
+            wireframe_width: 0.25,
+            ..config
+        };
+        assert_eq!(line_width(&thin, wide, [0.5, 8.0]), 0.5);
+        assert_eq!(line_width(&config, Features::empty(), [1.0, 8.0]), 1.0);
+    }
+
+    #[test]
+    fn depth_clamp_needs_the_feature() {
+        let config = GfxConfig {
+            depth_clamp: true,
+            ..GfxConfig::default()
+        };
+        assert!(rasterizer(&config, Features::empty()).is_err());
+        let clamped = rasterizer(&config, Features::DEPTH_CLAMP).unwrap();
+        assert!(clamped.depth_clamping);
+    }
+
+    #[test]
+    fn negotiation_splits_wanted_features() {
+        let supported = Features::PIPELINE_STATISTICS_QUERY | Features::LOGIC_OP;
+        let wanted = Features::PIPELINE_STATISTICS_QUERY | Features::DEPTH_CLAMP;
+        assert_eq!(
+            negotiate_features(supported, wanted),
+            (Features::PIPELINE_STATISTICS_QUERY, Features::DEPTH_CLAMP)
+        );
+    }
+
+    #[test]
+    fn stats_are_only_wanted_when_enabled() {
+        let mut config = GfxConfig::default();
+        assert!(!config
+            .features()
+            .contains(Features::PIPELINE_STATISTICS_QUERY));
+        config.pipeline_stats = true;
+        assert!(config
+            .features()
+            .contains(Features::PIPELINE_STATISTICS_QUERY));
+    }
 }
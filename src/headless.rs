@@ -0,0 +1,199 @@
+use crate::{
+    descriptor::DescriptorAllocator, drawing, gfx_state, swapchain, utils, GfxConfig, Offscreen,
+    PipelineOptions, ReadbackHandle, ReadbackStatus,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::{Adapter, Gpu, PhysicalDevice},
+    command::{self, CommandBuffer, Level},
+    device::Device,
+    image,
+    pool::{CommandPool, CommandPoolCreateFlags},
+    pso,
+    queue::{family::QueueFamily, CommandQueue, QueueGroup},
+    Backend, Features, Instance,
+};
+
+// A device without a window, for tests that draw into an offscreen
+// target and read the pixels back. Needs a Vulkan driver, so tests
+// using it are ignored by default and run with --ignored.
+pub struct Headless {
+    pub device: back::Device,
+    pub adapter: Adapter<back::Backend>,
+    pub queue_group: QueueGroup<back::Backend>,
+    pub offscreen: Option<Offscreen>,
+    descriptors: DescriptorAllocator,
+    swapchain_pass: Option<<back::Backend as Backend>::RenderPass>,
+    // Keeps the backend loaded for as long as the device
+    _instance: back::Instance,
+}
+
+impl Headless {
+    pub fn new(width: i16, height: i16, features: Features) -> Self {
+        let instance = back::Instance::create("learn_gfx_hal_tests", 1).expect("No Vulkan backend");
+        let adapter = instance
+            .enumerate_adapters()
+            .into_iter()
+            .find(|a| {
+                a.queue_families
+                    .iter()
+                    .any(|qf| qf.queue_type().supports_graphics())
+            })
+            .expect("No adapter with graphics");
+        let family = adapter
+            .queue_families
+            .iter()
+            .find(|qf| qf.queue_type().supports_graphics())
+            .expect("No queue family with graphics");
+        let Gpu {
+            device,
+            mut queue_groups,
+        } = unsafe {
+            adapter
+                .physical_device
+                .open(&[(family, &[1.0f32][..])], features)
+        }
+        .expect("Could not open physical device");
+        let queue_group = queue_groups.remove(0);
+
+        // Only needed to build the offscreen target's blit pipeline
+        let config = GfxConfig::default();
+        let swapchain_pass = gfx_state::main_render_pass(
+            &device,
+            &config,
+            swapchain::FORMAT,
+            config.color_attachment,
+        )
+        .expect("Could not create render pass");
+        let mut descriptors = DescriptorAllocator::new(
+            1,
+            vec![pso::DescriptorRangeDesc {
+                ty: pso::DescriptorType::CombinedImageSampler,
+                count: 1,
+            }],
+        );
+        let offscreen = Offscreen::new(
+            &device,
+            &adapter,
+            &swapchain_pass,
+            pso::Rect {
+                x: 0,
+                y: 0,
+                w: width,
+                h: height,
+            },
+            config.color_attachment,
+            1,
+            1,
+            &mut descriptors,
+            PipelineOptions::default(),
+        )
+        .expect("Could not create offscreen target");
+
+        Self {
+            device,
+            adapter,
+            queue_group,
+            offscreen: Some(offscreen),
+            descriptors,
+            swapchain_pass: Some(swapchain_pass),
+            _instance: instance,
+        }
+    }
+
+    // The pass drawn in, for building pipelines against
+    pub fn render_pass(&self) -> &<back::Backend as Backend>::RenderPass {
+        &self.offscreen().render_pass
+    }
+
+    pub fn area(&self) -> pso::Rect {
+        self.offscreen().content_size
+    }
+
+    // Records into a cleared target with the viewport covering
+    // all of it, then waits for the RGBA8 pixels to come back
+    pub fn draw(
+        &mut self,
+        clear: utils::Vec4,
+        record: impl FnOnce(&mut <back::Backend as Backend>::CommandBuffer),
+    ) -> Vec<u8> {
+        let offscreen = self.offscreen.as_ref().expect("Already freed");
+        let area = offscreen.content_size;
+        let clear = drawing::clear_values(clear, swapchain::FORMAT, 1, 1.0)
+            .expect("Could not build clear values");
+
+        let mut pool = unsafe {
+            self.device
+                .create_command_pool(self.queue_group.family, CommandPoolCreateFlags::TRANSIENT)
+        }
+        .expect("Could not create command pool");
+        let fence = self
+            .device
+            .create_fence(false)
+            .expect("Could not create fence");
+        unsafe {
+            let mut commands = pool.allocate_one(Level::Primary);
+            commands.begin_primary(command::CommandBufferFlags::ONE_TIME_SUBMIT);
+            commands.begin_render_pass(
+                offscreen.begin_pass(),
+                &offscreen.framebuffer,
+                area,
+                clear.iter(),
+                command::SubpassContents::Inline,
+            );
+            drawing::set_area(&mut commands, area, 0.0..1.0);
+            record(&mut commands);
+            commands.end_render_pass();
+            commands.finish();
+            self.queue_group.queues[0].submit_without_semaphores(Some(&commands), Some(&fence));
+        }
+
+        // Nothing samples it here, so it stays
+        // where the pass leaves it for the graph
+        let mut readback = ReadbackHandle::image(
+            &self.device,
+            &self.adapter,
+            &mut self.queue_group,
+            &offscreen.target,
+            image::Layout::ColorAttachmentOptimal,
+        )
+        .expect("Could not start the readback");
+        self.device.wait_idle().expect("Lost the device");
+        let pixels = match readback.poll(&self.device) {
+            Ok(ReadbackStatus::Ready(pixels)) => pixels,
+            Ok(ReadbackStatus::Pending) => panic!("Readback still pending once idle"),
+            Err(e) => panic!("{}", e),
+        };
+
+        readback.free(&self.device);
+        unsafe {
+            self.device.destroy_fence(fence);
+            self.device.destroy_command_pool(pool);
+        }
+        if let Some(offscreen) = &mut self.offscreen {
+            offscreen.drawn = true;
+        }
+        pixels
+    }
+
+    pub fn free(mut self) {
+        let _ = self.device.wait_idle();
+        if let Some(offscreen) = self.offscreen.take() {
+            offscreen.free(&self.device, &mut self.descriptors);
+        }
+        self.descriptors.free_pools(&self.device);
+        if let Some(swapchain_pass) = self.swapchain_pass.take() {
+            unsafe { self.device.destroy_render_pass(swapchain_pass) };
+        }
+    }
+
+    fn offscreen(&self) -> &Offscreen {
+        self.offscreen.as_ref().expect("Already freed")
+    }
+}
+
+// The RGBA8 pixel at a position, rows from the top
+pub fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+    let i = (y * width + x) * 4;
+    [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+}
@@ -1,19 +1,831 @@
+use crate::{
+    allocator::{self, Allocation},
+    attachment_config::DEPTH_FORMAT,
+    BufferInfo,
+};
 use gfx_backend_vulkan as back;
-use gfx_hal::{memory::Requirements, Backend};
-use std::mem::ManuallyDrop;
+use gfx_hal::{
+    adapter::{Adapter, PhysicalDevice},
+    buffer,
+    command::{self, CommandBuffer, Level},
+    device::Device,
+    format::{self, Format, ImageFeature},
+    image::{self, Layout},
+    memory::{Barrier, Dependencies, Properties, Requirements},
+    pool::CommandPool,
+    pso::PipelineStage,
+    queue::CommandQueue,
+    Backend,
+};
+// The image crate, not gfx_hal::image
+use ::image::{
+    dxt::{DXTVariant, DxtDecoder},
+    ImageDecoder,
+};
+use std::{
+    fs, iter,
+    mem::{self, ManuallyDrop},
+    ops::Range,
+    path::Path,
+    ptr,
+};
+
+// Block-compressed formats that can be uploaded as-is
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CompressedFormat {
+    Bc1,
+    Bc2,
+    Bc3,
+}
+
+impl CompressedFormat {
+    pub fn format(self) -> Format {
+        match self {
+            CompressedFormat::Bc1 => Format::Bc1RgbUnorm,
+            CompressedFormat::Bc2 => Format::Bc2Unorm,
+            CompressedFormat::Bc3 => Format::Bc3Unorm,
+        }
+    }
+
+    // Each block covers four by four texels
+    pub fn block_bytes(self) -> usize {
+        match self {
+            CompressedFormat::Bc1 => 8,
+            CompressedFormat::Bc2 | CompressedFormat::Bc3 => 16,
+        }
+    }
+
+    fn dxt_variant(self) -> DXTVariant {
+        match self {
+            CompressedFormat::Bc1 => DXTVariant::DXT1,
+            CompressedFormat::Bc2 => DXTVariant::DXT3,
+            CompressedFormat::Bc3 => DXTVariant::DXT5,
+        }
+    }
+}
+
+// Common filtering setups, so samplers don't need every field spelled out
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SamplerPreset {
+    // Texels stay sharp, for pixel art and data textures
+    Nearest,
+    // Also blends between mip levels
+    Trilinear,
+    // Trilinear plus up to this many samples along the
+    // direction of stretch. Needs SAMPLER_ANISOTROPY, so is
+    // clamped to the maximum desc is given, and off without it.
+    Anisotropic(f32),
+}
+
+impl SamplerPreset {
+    // For textures drawn at an angle or scale, with
+    // anisotropy already checked against the device
+    pub fn filtered(anisotropy: f32) -> Self {
+        if anisotropy > 1.0 {
+            SamplerPreset::Anisotropic(anisotropy)
+        } else {
+            SamplerPreset::Trilinear
+        }
+    }
+
+    pub fn desc(self, max_anisotropy: f32) -> image::SamplerDesc {
+        let (filter, mip_filter) = match self {
+            SamplerPreset::Nearest => (image::Filter::Nearest, image::Filter::Nearest),
+            SamplerPreset::Trilinear | SamplerPreset::Anisotropic(_) => {
+                (image::Filter::Linear, image::Filter::Linear)
+            }
+        };
+        // A single sample is the same as none, and the
+        // maximum is 1 on devices without the feature
+        let anisotropic = match self {
+            SamplerPreset::Anisotropic(samples) => match samples.min(max_anisotropy) as u8 {
+                0 | 1 => image::Anisotropic::Off,
+                samples => image::Anisotropic::On(samples),
+            },
+            _ => image::Anisotropic::Off,
+        };
+        image::SamplerDesc {
+            mag_filter: filter,
+            mip_filter,
+            anisotropic,
+            ..image::SamplerDesc::new(filter, image::WrapMode::Clamp)
+        }
+    }
+}
+
+// Whether images of the format can be sampled with optimal tiling
+pub fn supports_sampling(adapter: &Adapter<back::Backend>, format: Format) -> bool {
+    adapter
+        .physical_device
+        .format_properties(Some(format))
+        .optimal_tiling
+        .contains(ImageFeature::SAMPLED)
+}
 
 pub struct ImageInfo {
+    // Of the first mip level
+    pub extent: image::Extent,
+    pub format: Format,
+    pub levels: image::Level,
+    // Six for a cubemap, one face per layer
+    pub layers: image::Layer,
     pub requirements: Requirements,
     pub image: ManuallyDrop<<back::Backend as Backend>::Image>,
     pub memory: ManuallyDrop<<back::Backend as Backend>::Memory>,
+    pub allocation: Allocation,
     pub image_view: ManuallyDrop<<back::Backend as Backend>::ImageView>,
     pub sampler: ManuallyDrop<<back::Backend as Backend>::Sampler>,
 }
 
 impl ImageInfo {
-    pub fn new(_device: &back::Device) -> Result<(), &'static str> {
+    // A two dimensional image living in GPU memory,
+    // along with what is needed to sample from it
+    pub fn new(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        extent: image::Extent,
+        format: Format,
+        usage: image::Usage,
+    ) -> Result<Self, &'static str> {
+        Self::with_samples(device, adapter, extent, format, 1, usage)
+    }
+
+    // A color target for MSAA. It is only ever resolved,
+    // so its contents never need to leave the GPU.
+    pub fn multisampled(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        extent: image::Extent,
+        format: Format,
+        samples: image::NumSamples,
+    ) -> Result<Self, &'static str> {
+        Self::with_samples(
+            device,
+            adapter,
+            extent,
+            format,
+            samples,
+            image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSIENT_ATTACHMENT,
+        )
+    }
+
+    // The depth buffer for a scene pass, with as many samples
+    // as its color. Also never leaves the GPU.
+    pub fn depth(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        extent: image::Extent,
+        samples: image::NumSamples,
+    ) -> Result<Self, &'static str> {
+        Self::with_samples(
+            device,
+            adapter,
+            extent,
+            DEPTH_FORMAT,
+            samples,
+            image::Usage::DEPTH_STENCIL_ATTACHMENT | image::Usage::TRANSIENT_ATTACHMENT,
+        )
+    }
+
+    fn with_samples(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        extent: image::Extent,
+        format: Format,
+        samples: image::NumSamples,
+        usage: image::Usage,
+    ) -> Result<Self, &'static str> {
+        Self::create(
+            device,
+            adapter,
+            image::Kind::D2(extent.width, extent.height, 1, samples),
+            1,
+            image::ViewKind::D2,
+            image::ViewCapabilities::empty(),
+            format,
+            usage,
+        )
+    }
+
+    fn create(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        kind: image::Kind,
+        levels: image::Level,
+        view_kind: image::ViewKind,
+        capabilities: image::ViewCapabilities,
+        format: Format,
+        usage: image::Usage,
+    ) -> Result<Self, &'static str> {
+        let extent = kind.extent();
+        let layers = kind.num_layers();
+        let mut image = unsafe {
+            device.create_image(
+                kind,
+                levels,
+                format,
+                image::Tiling::Optimal,
+                usage,
+                capabilities,
+            )
+        }
+        .map_err(|_| "Failed to create an image")?;
+
+        // As with buffers, creating the image does not allocate it
+        let requirements = unsafe { device.get_image_requirements(&image) };
+
+        // Only the GPU touches the image, so keep it local
+        let (memory, allocation) = allocator::allocate(
+            device,
+            adapter,
+            &requirements,
+            Properties::DEVICE_LOCAL,
+            Properties::empty(),
+        )?;
+
+        unsafe { device.bind_image_memory(&memory, 0, &mut image) }
+            .map_err(|_| "Failed to bind the image memory")?;
+
+        let image_view = unsafe {
+            device.create_image_view(
+                &image,
+                view_kind,
+                format,
+                format::Swizzle::NO,
+                // Depth formats are viewed through their depth aspect
+                image::SubresourceRange {
+                    aspects: format.surface_desc().aspects,
+                    levels: 0..levels,
+                    layers: 0..layers,
+                },
+            )
+        }
+        .map_err(|_| "Failed to create an image view")?;
+
+        // Everything gets one to begin with, and it can be
+        // swapped out with set_sampler before it's first bound
+        let sampler = unsafe { device.create_sampler(&SamplerPreset::Trilinear.desc(1.0)) }
+            .map_err(|_| "Failed to create a sampler")?;
+
+        Ok(Self {
+            extent,
+            format,
+            levels,
+            layers,
+            requirements,
+            image: ManuallyDrop::new(image),
+            memory: ManuallyDrop::new(memory),
+            allocation,
+            image_view: ManuallyDrop::new(image_view),
+            sampler: ManuallyDrop::new(sampler),
+        })
+    }
+
+    // Uploads block-compressed data without decompressing it.
+    // Devices without support for the format get RGBA8 instead,
+    // decoded on the CPU.
+    pub fn from_compressed(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        command_pool: &mut <back::Backend as Backend>::CommandPool,
+        queue: &mut <back::Backend as Backend>::CommandQueue,
+        extent: image::Extent,
+        compressed: CompressedFormat,
+        data: &[u8],
+    ) -> Result<Self, &'static str> {
+        let usage = image::Usage::TRANSFER_DST | image::Usage::SAMPLED;
+
+        if supports_sampling(adapter, compressed.format()) {
+            let blocks_wide = (extent.width as usize + 3) / 4;
+            let blocks_high = (extent.height as usize + 3) / 4;
+            if data.len() < blocks_wide * blocks_high * compressed.block_bytes() {
+                return Err("Compressed image data is too short");
+            }
+
+            let image = Self::new(device, adapter, extent, compressed.format(), usage)?;
+            image.upload(device, adapter, command_pool, queue, data, 4)?;
+            Ok(image)
+        } else {
+            log::info!(
+                "{:?} is not supported by the device, decoding to RGBA8",
+                compressed.format()
+            );
+
+            let decoder =
+                DxtDecoder::new(data, extent.width, extent.height, compressed.dxt_variant())
+                    .map_err(|_| "Could not decode compressed image data")?;
+            let pixels = decoder
+                .read_image()
+                .map_err(|_| "Could not decode compressed image data")?;
+
+            // BC1 has no alpha channel
+            let pixels = match compressed {
+                CompressedFormat::Bc1 => pixels
+                    .chunks(3)
+                    .flat_map(|rgb| rgb.iter().cloned().chain(iter::once(255)))
+                    .collect(),
+                CompressedFormat::Bc2 | CompressedFormat::Bc3 => pixels,
+            };
+
+            let image = Self::new(device, adapter, extent, Format::Rgba8Unorm, usage)?;
+            image.upload(device, adapter, command_pool, queue, &pixels, 1)?;
+            Ok(image)
+        }
+    }
+
+    // Loads the first mip level of a DDS file in one of the BC formats
+    pub fn from_dds(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        command_pool: &mut <back::Backend as Backend>::CommandPool,
+        queue: &mut <back::Backend as Backend>::CommandQueue,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, &'static str> {
+        let data = fs::read(path).map_err(|_| "Could not read DDS file")?;
+        let (extent, compressed, blocks) = parse_dds(&data)?;
+        Self::from_compressed(
+            device,
+            adapter,
+            command_pool,
+            queue,
+            extent,
+            compressed,
+            blocks,
+        )
+    }
+
+    // Decodes a PNG, JPEG or anything else the image crate reads.
+    // Grayscale and RGB sources are expanded to RGBA. Any size works,
+    // since there are no mipmaps to halve down.
+    pub fn from_file(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        command_pool: &mut <back::Backend as Backend>::CommandPool,
+        queue: &mut <back::Backend as Backend>::CommandQueue,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, &'static str> {
+        let pixels = ::image::open(path)
+            .map_err(|_| "Could not load image file")?
+            .to_rgba();
+        let (width, height) = pixels.dimensions();
+        let extent = image::Extent {
+            width,
+            height,
+            depth: 1,
+        };
+
+        // Image files store sRGB, which sampling converts to linear
+        let image = Self::new(
+            device,
+            adapter,
+            extent,
+            Format::Rgba8Srgb,
+            image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+        )?;
+        image.upload(device, adapter, command_pool, queue, &pixels.into_raw(), 1)?;
+        Ok(image)
+    }
+
+    // Copies tightly packed data into the image through a staging buffer
+    // and leaves it ready for sampling. Blocks until the copy is done.
+    // Block size is in texels, four for BC formats and one otherwise.
+    // Layers follow one another in the data, each the same size.
+    pub fn upload(
+        &self,
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        command_pool: &mut <back::Backend as Backend>::CommandPool,
+        queue: &mut <back::Backend as Backend>::CommandQueue,
+        data: &[u8],
+        block_size: u32,
+    ) -> Result<(), &'static str> {
+        if data.len() % self.layers as usize != 0 {
+            return Err("Image data doesn't split evenly between layers");
+        }
+        let layer_bytes = (data.len() / self.layers as usize) as u64;
+
+        let mut staging = BufferInfo::new(device, adapter, data, buffer::Usage::TRANSFER_SRC)?;
+        staging.load_data(device, data)?;
+
+        let mut commands = unsafe { command_pool.allocate_one(Level::Primary) };
+        unsafe {
+            commands.begin_primary(command::CommandBufferFlags::ONE_TIME_SUBMIT);
+            // Nothing has used the image yet
+            self.record_copy(
+                &mut commands,
+                &staging.buffer,
+                0,
+                layer_bytes,
+                block_size,
+                PipelineStage::TOP_OF_PIPE,
+            );
+            commands.finish();
+        }
+
+        let fence = device
+            .create_fence(false)
+            .map_err(|_| "Could not create fence")?;
+        unsafe {
+            queue.submit_without_semaphores(Some(&commands), Some(&fence));
+            let waited = device.wait_for_fence(&fence, core::u64::MAX);
+            device.destroy_fence(fence);
+            command_pool.free(Some(commands));
+            staging.free(device);
+            waited.map_err(|_| "Failed to wait for the image upload")?;
+        }
+
+        Ok(())
+    }
+
+    // Records a copy of tightly packed data from a buffer,
+    // leaving the image ready for sampling. The previous contents
+    // are discarded once work in the given stage is done with them.
+    // Each layer is copied from layer_bytes further into the buffer.
+    pub unsafe fn record_copy(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        buffer: &<back::Backend as Backend>::Buffer,
+        offset: u64,
+        layer_bytes: u64,
+        block_size: u32,
+        previous_use: PipelineStage,
+    ) {
+        // Rows are measured in texels but must cover whole blocks
+        let round_up = |texels: u32| (texels + block_size - 1) / block_size * block_size;
+        let range = image::SubresourceRange {
+            aspects: format::Aspects::COLOR,
+            levels: 0..1,
+            layers: 0..self.layers,
+        };
+
+        // Contents are discarded on the way in
+        commands.pipeline_barrier(
+            previous_use..PipelineStage::TRANSFER,
+            Dependencies::empty(),
+            &[Barrier::Image {
+                states: (image::Access::empty(), Layout::Undefined)
+                    ..(image::Access::TRANSFER_WRITE, Layout::TransferDstOptimal),
+                target: &*self.image,
+                families: None,
+                range: range.clone(),
+            }],
+        );
+
+        // A region per layer, so each face is read from its own offset
+        commands.copy_buffer_to_image(
+            buffer,
+            &self.image,
+            Layout::TransferDstOptimal,
+            (0..self.layers).map(|layer| command::BufferImageCopy {
+                buffer_offset: offset + layer as u64 * layer_bytes,
+                buffer_width: round_up(self.extent.width),
+                buffer_height: round_up(self.extent.height),
+                image_layers: image::SubresourceLayers {
+                    aspects: format::Aspects::COLOR,
+                    level: 0,
+                    layers: layer..layer + 1,
+                },
+                image_offset: image::Offset { x: 0, y: 0, z: 0 },
+                image_extent: self.extent,
+            }),
+        );
+
+        commands.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+            Dependencies::empty(),
+            &[Barrier::Image {
+                states: (image::Access::TRANSFER_WRITE, Layout::TransferDstOptimal)
+                    ..(image::Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+                target: &*self.image,
+                families: None,
+                range,
+            }],
+        );
+    }
+
+    // Descriptor sets written with the old sampler have to be rewritten,
+    // and nothing in flight can still be using it. The maximum is 1
+    // unless the device was opened with SAMPLER_ANISOTROPY.
+    pub fn set_sampler(
+        &mut self,
+        device: &back::Device,
+        preset: SamplerPreset,
+        max_anisotropy: f32,
+    ) -> Result<(), &'static str> {
+        let sampler = unsafe { device.create_sampler(&preset.desc(max_anisotropy)) }
+            .map_err(|_| "Failed to create a sampler")?;
+        let old = mem::replace(&mut self.sampler, ManuallyDrop::new(sampler));
+        unsafe { device.destroy_sampler(ManuallyDrop::into_inner(old)) }
         Ok(())
     }
 
-    pub fn free(&mut self, _device: &back::Device) {}
+    pub fn free(&mut self, device: &back::Device) {
+        unsafe {
+            device.destroy_sampler(ManuallyDrop::into_inner(ptr::read(&self.sampler)));
+            device.destroy_image_view(ManuallyDrop::into_inner(ptr::read(&self.image_view)));
+            device.destroy_image(ManuallyDrop::into_inner(ptr::read(&self.image)));
+            let memory = ManuallyDrop::into_inner(ptr::read(&self.memory));
+            allocator::free(device, memory, &self.allocation);
+        }
+    }
+}
+
+// The size, format and first mip level's blocks of a DDS file.
+// Only the plain header is understood, not the DX10 extension.
+fn parse_dds(data: &[u8]) -> Result<(image::Extent, CompressedFormat, &[u8]), &'static str> {
+    const HEADER_BYTES: usize = 128;
+    if data.len() < HEADER_BYTES || &data[0..4] != b"DDS " {
+        return Err("Not a DDS file");
+    }
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+
+    let compressed = match &data[84..88] {
+        b"DXT1" => CompressedFormat::Bc1,
+        b"DXT3" => CompressedFormat::Bc2,
+        b"DXT5" => CompressedFormat::Bc3,
+        _ => return Err("DDS file isn't BC1, BC2 or BC3"),
+    };
+    let extent = image::Extent {
+        width: read_u32(16),
+        height: read_u32(12),
+        depth: 1,
+    };
+    if extent.width == 0 || extent.height == 0 {
+        return Err("DDS image is empty");
+    }
+
+    let blocks_wide = (extent.width as usize + 3) / 4;
+    let blocks_high = (extent.height as usize + 3) / 4;
+    let end = HEADER_BYTES + blocks_wide * blocks_high * compressed.block_bytes();
+    let blocks = data.get(HEADER_BYTES..end).ok_or("DDS file is too short")?;
+    Ok((extent, compressed, blocks))
+}
+
+fn color_range(
+    levels: Range<image::Level>,
+    layers: Range<image::Layer>,
+) -> image::SubresourceRange {
+    image::SubresourceRange {
+        aspects: format::Aspects::COLOR,
+        levels,
+        layers,
+    }
+}
+
+// Records a scaled copy of the first mip level of one image over all
+// of another's, for downsampling or upscaling between offscreen images.
+// Both start and end in the given layout, so whatever drew into or
+// sampled them carries on as before. The source needs TRANSFER_SRC
+// usage, the destination TRANSFER_DST, and they must be different
+// images whose formats support blitting, and linear filtering for a
+// linear filter. Only the layers they both have are copied.
+pub unsafe fn blit_image(
+    commands: &mut <back::Backend as Backend>::CommandBuffer,
+    src: &ImageInfo,
+    dst: &ImageInfo,
+    filter: image::Filter,
+    layout: Layout,
+) {
+    let layers = 0..src.layers.min(dst.layers);
+    let range = color_range(0..1, layers.clone());
+    let corner = |extent: image::Extent| image::Offset {
+        x: extent.width as i32,
+        y: extent.height as i32,
+        z: extent.depth as i32,
+    };
+
+    // Waits for anything earlier on the queue that drew into or sampled
+    // them. The destination is overwritten, so its contents are dropped.
+    commands.pipeline_barrier(
+        (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::FRAGMENT_SHADER)
+            ..PipelineStage::TRANSFER,
+        Dependencies::empty(),
+        &[
+            Barrier::Image {
+                states: (image::Access::COLOR_ATTACHMENT_WRITE, layout)
+                    ..(image::Access::TRANSFER_READ, Layout::TransferSrcOptimal),
+                target: &*src.image,
+                families: None,
+                range: range.clone(),
+            },
+            Barrier::Image {
+                states: (image::Access::empty(), Layout::Undefined)
+                    ..(image::Access::TRANSFER_WRITE, Layout::TransferDstOptimal),
+                target: &*dst.image,
+                families: None,
+                range: range.clone(),
+            },
+        ],
+    );
+
+    commands.blit_image(
+        &src.image,
+        Layout::TransferSrcOptimal,
+        &dst.image,
+        Layout::TransferDstOptimal,
+        filter,
+        &[command::ImageBlit {
+            src_subresource: image::SubresourceLayers {
+                aspects: format::Aspects::COLOR,
+                level: 0,
+                layers: layers.clone(),
+            },
+            src_bounds: image::Offset { x: 0, y: 0, z: 0 }..corner(src.extent),
+            dst_subresource: image::SubresourceLayers {
+                aspects: format::Aspects::COLOR,
+                level: 0,
+                layers,
+            },
+            dst_bounds: image::Offset { x: 0, y: 0, z: 0 }..corner(dst.extent),
+        }],
+    );
+
+    // Back how they were for whatever draws into or samples them next
+    commands.pipeline_barrier(
+        PipelineStage::TRANSFER
+            ..(PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::FRAGMENT_SHADER),
+        Dependencies::empty(),
+        &[
+            Barrier::Image {
+                states: (image::Access::TRANSFER_READ, Layout::TransferSrcOptimal)
+                    ..(image::Access::empty(), layout),
+                target: &*src.image,
+                families: None,
+                range: range.clone(),
+            },
+            Barrier::Image {
+                states: (image::Access::TRANSFER_WRITE, Layout::TransferDstOptimal)
+                    ..(image::Access::SHADER_READ, layout),
+                target: &*dst.image,
+                families: None,
+                range,
+            },
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        headless::{self, Headless},
+        ReadbackHandle, ReadbackStatus,
+    };
+    use gfx_hal::{pool::CommandPoolCreateFlags, Features};
+
+    fn dds(width: u32, height: u32, four_cc: &[u8; 4], blocks: usize) -> Vec<u8> {
+        let mut data = vec![0; 128 + blocks];
+        data[0..4].copy_from_slice(b"DDS ");
+        data[12..16].copy_from_slice(&height.to_le_bytes());
+        data[16..20].copy_from_slice(&width.to_le_bytes());
+        data[84..88].copy_from_slice(four_cc);
+        data
+    }
+
+    #[test]
+    fn parses_dds_header() {
+        // 5x3 rounds up to 2x1 blocks of 8 bytes each
+        let data = dds(5, 3, b"DXT1", 16);
+        let (extent, compressed, blocks) = parse_dds(&data).unwrap();
+        assert_eq!((extent.width, extent.height), (5, 3));
+        assert_eq!(compressed, CompressedFormat::Bc1);
+        assert_eq!(blocks.len(), 16);
+    }
+
+    #[test]
+    fn rejects_short_dds() {
+        assert!(parse_dds(&dds(8, 8, b"DXT5", 16)).is_err());
+        assert!(parse_dds(&dds(4, 4, b"ATI2", 16)).is_err());
+    }
+
+    #[test]
+    fn anisotropy_is_clamped() {
+        let desc = SamplerPreset::Anisotropic(16.0).desc(4.0);
+        assert_eq!(desc.anisotropic, image::Anisotropic::On(4));
+        assert_eq!(desc.mip_filter, image::Filter::Linear);
+    }
+
+    #[test]
+    fn single_sample_is_off() {
+        // Without the feature the maximum is 1
+        let desc = SamplerPreset::Anisotropic(16.0).desc(1.0);
+        assert_eq!(desc.anisotropic, image::Anisotropic::Off);
+        let desc = SamplerPreset::Anisotropic(1.0).desc(16.0);
+        assert_eq!(desc.anisotropic, image::Anisotropic::Off);
+    }
+
+    #[test]
+    fn filtering_is_anisotropic_past_one() {
+        assert_eq!(SamplerPreset::filtered(1.0), SamplerPreset::Trilinear);
+        assert_eq!(
+            SamplerPreset::filtered(8.0),
+            SamplerPreset::Anisotropic(8.0)
+        );
+    }
+
+    #[test]
+    fn other_presets_are_never_anisotropic() {
+        let desc = SamplerPreset::Nearest.desc(16.0);
+        assert_eq!(desc.anisotropic, image::Anisotropic::Off);
+        assert_eq!(desc.mag_filter, image::Filter::Nearest);
+        let desc = SamplerPreset::Trilinear.desc(16.0);
+        assert_eq!(desc.anisotropic, image::Anisotropic::Off);
+    }
+
+    #[test]
+    #[ignore] // Needs a Vulkan device
+    fn blit_halves_the_image() {
+        let mut headless = Headless::new(4, 4, Features::empty());
+        let extent = |size| image::Extent {
+            width: size,
+            height: size,
+            depth: 1,
+        };
+        let usage = image::Usage::TRANSFER_SRC | image::Usage::TRANSFER_DST | image::Usage::SAMPLED;
+        let new = |size| {
+            ImageInfo::new(
+                &headless.device,
+                &headless.adapter,
+                extent(size),
+                Format::Rgba8Unorm,
+                usage,
+            )
+            .unwrap()
+        };
+        let (mut src, mut dst) = (new(4), new(2));
+
+        // A solid color in each 2x2 quarter, which a linear
+        // filter samples from the middle of without bleeding
+        let quarters = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [255, 255, 255, 255],
+        ];
+        let data: Vec<u8> = (0..16)
+            .flat_map(|i| quarters[i / 8 * 2 + i % 4 / 2].to_vec())
+            .collect();
+
+        let mut pool = unsafe {
+            headless.device.create_command_pool(
+                headless.queue_group.family,
+                CommandPoolCreateFlags::TRANSIENT,
+            )
+        }
+        .unwrap();
+        let queue = &mut headless.queue_group.queues[0];
+        src.upload(
+            &headless.device,
+            &headless.adapter,
+            &mut pool,
+            queue,
+            &data,
+            1,
+        )
+        .unwrap();
+        unsafe {
+            let mut commands = pool.allocate_one(Level::Primary);
+            commands.begin_primary(command::CommandBufferFlags::ONE_TIME_SUBMIT);
+            blit_image(
+                &mut commands,
+                &src,
+                &dst,
+                image::Filter::Linear,
+                Layout::ShaderReadOnlyOptimal,
+            );
+            commands.finish();
+            queue.submit_without_semaphores(Some(&commands), None);
+        }
+
+        // Later on the same queue, so its barrier waits for the blit
+        let mut readback = ReadbackHandle::image(
+            &headless.device,
+            &headless.adapter,
+            &mut headless.queue_group,
+            &dst,
+            Layout::ShaderReadOnlyOptimal,
+        )
+        .unwrap();
+        headless.device.wait_idle().unwrap();
+        let pixels = match readback.poll(&headless.device) {
+            Ok(ReadbackStatus::Ready(pixels)) => pixels,
+            _ => panic!("Readback not ready once idle"),
+        };
+        for (i, quarter) in quarters.iter().enumerate() {
+            assert_eq!(headless::pixel(&pixels, 2, i % 2, i / 2), *quarter);
+        }
+
+        readback.free(&headless.device);
+        src.free(&headless.device);
+        dst.free(&headless.device);
+        unsafe { headless.device.destroy_command_pool(pool) };
+        headless.free();
+    }
 }
@@ -0,0 +1,142 @@
+use crate::{
+    debug_names::set_debug_name,
+    descriptor::{DescriptorAllocation, DescriptorAllocator},
+    utils::Vec2,
+    BufferInfo, ComputeInfo, PipelineOptions,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter,
+    buffer,
+    command::CommandBuffer,
+    device::Device,
+    memory::{Barrier, Dependencies},
+    pso::{self, PipelineStage},
+    Backend,
+};
+use std::{mem::size_of, ops::Range};
+
+// The mouse offset, as pushed to the scene's vertex shader
+pub const CULL_PUSH_CONSTANTS: Range<u32> = 0..8;
+
+// Matches VkDrawIndexedIndirectCommand
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug)]
+pub struct DrawIndexedIndirectCommand {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+}
+
+// A compute pass that culls the quad on the GPU and writes
+// the draw parameters for draw_indexed_indirect to read
+pub struct IndirectDraw {
+    pub commands: BufferInfo,
+    pub pipeline: ComputeInfo,
+    pub descriptor_set: DescriptorAllocation,
+}
+
+impl IndirectDraw {
+    pub fn new(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        descriptors: &mut DescriptorAllocator,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        // Written by the compute shader, read by the draw
+        let mut commands = BufferInfo::with_size(
+            device,
+            adapter,
+            size_of::<DrawIndexedIndirectCommand>() as u64,
+            buffer::Usage::STORAGE | buffer::Usage::INDIRECT,
+        )?;
+        set_debug_name(device, &mut *commands.buffer, "indirect_commands");
+
+        let pipeline = ComputeInfo::new(
+            device,
+            "shaders/cull_comp.glsl",
+            vec![pso::DescriptorSetLayoutBinding {
+                binding: 0,
+                ty: pso::DescriptorType::StorageBuffer,
+                count: 1,
+                stage_flags: pso::ShaderStageFlags::COMPUTE,
+                immutable_samplers: false,
+            }],
+            CULL_PUSH_CONSTANTS,
+            options,
+        )?;
+
+        let descriptor_set = pipeline.allocate_set(device, descriptors)?;
+
+        unsafe {
+            device.write_descriptor_sets(vec![pso::DescriptorSetWrite {
+                set: &descriptor_set.set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Buffer(&*commands.buffer, None..None)),
+            }]);
+        }
+
+        Ok(Self {
+            commands,
+            pipeline,
+            descriptor_set,
+        })
+    }
+
+    // Must be recorded outside of a render pass,
+    // before the draw that reads the commands
+    pub unsafe fn record(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        mouse: Vec2,
+    ) {
+        // The previous frame's draw may still be reading the commands
+        commands.pipeline_barrier(
+            PipelineStage::DRAW_INDIRECT..PipelineStage::COMPUTE_SHADER,
+            Dependencies::empty(),
+            &[Barrier::Buffer {
+                states: buffer::Access::INDIRECT_COMMAND_READ..buffer::Access::SHADER_WRITE,
+                target: &*self.commands.buffer,
+                families: None,
+                range: None..None,
+            }],
+        );
+
+        commands.bind_compute_pipeline(&self.pipeline.handle);
+        commands.bind_compute_descriptor_sets(
+            &self.pipeline.layout,
+            0,
+            Some(&self.descriptor_set.set),
+            &[],
+        );
+        commands.push_compute_constants(
+            &self.pipeline.layout,
+            CULL_PUSH_CONSTANTS.start,
+            &[mouse.x.to_bits(), mouse.y.to_bits()],
+        );
+        commands.dispatch([1, 1, 1]);
+
+        // The draw reads the parameters before any vertex work
+        commands.pipeline_barrier(
+            PipelineStage::COMPUTE_SHADER..PipelineStage::DRAW_INDIRECT,
+            Dependencies::empty(),
+            &[Barrier::Buffer {
+                states: buffer::Access::SHADER_WRITE..buffer::Access::INDIRECT_COMMAND_READ,
+                target: &*self.commands.buffer,
+                families: None,
+                range: None..None,
+            }],
+        );
+    }
+
+    pub fn free(mut self, device: &back::Device, descriptors: &mut DescriptorAllocator) {
+        if let Err(e) = descriptors.free(self.descriptor_set) {
+            log::error!("{}", e);
+        }
+        self.pipeline.free(device);
+        self.commands.free(device);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::utils::Vec2;
+use std::{
+    collections::VecDeque,
+    fs,
+    time::{Duration, Instant},
+};
+
+const HEADER: &str = "# learn_gfx input v1";
+
+#[derive(Default, Copy, Clone, PartialEq, Debug)]
+pub struct InputState {
+    pub mouse: Vec2,
+}
+
+// Logs each change to the input state along with when it happened.
+// The file has a header line, then one `millis mouse_x mouse_y` line per change.
+pub struct InputRecorder {
+    start: Instant,
+    events: Vec<(Duration, InputState)>,
+}
+
+impl InputRecorder {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            events: vec![],
+        }
+    }
+
+    pub fn record(&mut self, state: InputState) {
+        self.events.push((self.start.elapsed(), state));
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), &'static str> {
+        let mut contents = format!("{}\n", HEADER);
+        for (time, state) in &self.events {
+            contents.push_str(&format!(
+                "{} {} {}\n",
+                time.as_millis(),
+                state.mouse.x,
+                state.mouse.y
+            ));
+        }
+        fs::write(path, contents).map_err(|_| "Could not write input recording")
+    }
+}
+
+impl Default for InputRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Feeds a recording back in at the same pace it was captured
+pub struct InputPlayer {
+    start: Instant,
+    events: VecDeque<(Duration, InputState)>,
+}
+
+impl InputPlayer {
+    pub fn load(path: &str) -> Result<Self, &'static str> {
+        let contents = fs::read_to_string(path).map_err(|_| "Could not read input recording")?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("Input recording has an unknown format");
+        }
+
+        let events = lines
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_event)
+            .collect::<Result<VecDeque<_>, _>>()?;
+
+        Ok(Self {
+            start: Instant::now(),
+            events,
+        })
+    }
+
+    // Applies every event that is due, returning whether the state changed
+    pub fn poll(&mut self, state: &mut InputState) -> bool {
+        let elapsed = self.start.elapsed();
+        let mut changed = false;
+        while let Some(&(time, next)) = self.events.front() {
+            if time > elapsed {
+                break;
+            }
+            *state = next;
+            changed = true;
+            self.events.pop_front();
+        }
+        changed
+    }
+
+    // When the event loop should wake for the next event
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.events.front().map(|&(time, _)| self.start + time)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+fn parse_event(line: &str) -> Result<(Duration, InputState), &'static str> {
+    let mut fields = line.split_whitespace();
+    let mut next = || {
+        fields
+            .next()
+            .ok_or("Input recording line is missing fields")
+    };
+    let millis = next()?
+        .parse::<u64>()
+        .map_err(|_| "Invalid input recording time")?;
+    let x = next()?
+        .parse::<f32>()
+        .map_err(|_| "Invalid input recording mouse position")?;
+    let y = next()?
+        .parse::<f32>()
+        .map_err(|_| "Invalid input recording mouse position")?;
+
+    Ok((
+        Duration::from_millis(millis),
+        InputState {
+            mouse: Vec2 { x, y },
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_recorded_line() {
+        let (time, state) = parse_event("1500 0.25 -0.5").unwrap();
+        assert_eq!(time, Duration::from_millis(1500));
+        assert_eq!(state.mouse, Vec2 { x: 0.25, y: -0.5 });
+    }
+
+    #[test]
+    fn tolerates_extra_whitespace() {
+        let (time, state) = parse_event("  20\t1   2 ").unwrap();
+        assert_eq!(time, Duration::from_millis(20));
+        assert_eq!(state.mouse, Vec2 { x: 1.0, y: 2.0 });
+    }
+
+    #[test]
+    fn rejects_missing_and_invalid_fields() {
+        assert!(parse_event("").is_err());
+        assert!(parse_event("20 1").is_err());
+        assert!(parse_event("-20 1 2").is_err());
+        assert!(parse_event("20 x 2").is_err());
+    }
+}
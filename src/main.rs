@@ -2,59 +2,330 @@ mod gfx_state;
 use gfx_state::GfxState;
 
 use fern::colors::ColoredLevelConfig;
+use gfx_hal::{image::Usage as ImageUsage, window::CompositeAlpha};
+use std::time::{Duration, Instant};
 use winit::{
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 
 pub mod utils;
-use utils::Vec2;
+use utils::{Vec2, Vec4};
+
+mod vertex;
+
+mod allocator;
+pub use allocator::{allocator_stats, AllocatorStats};
+
+mod debug_names;
+mod descriptor;
+mod device_extensions;
+mod frame_graph;
+mod subpass_dependencies;
 
 mod buffer_info;
 pub use buffer_info::BufferInfo;
 
+mod staged_buffer;
+pub use staged_buffer::StagedBuffer;
+
 mod pipeline_info;
-pub use pipeline_info::PipelineInfo;
+pub use pipeline_info::{PipelineInfo, PipelineOptions};
+
+mod compute_info;
+pub use compute_info::ComputeInfo;
+
+mod indirect_draw;
+pub use indirect_draw::IndirectDraw;
 
 mod image_info;
-pub use image_info::ImageInfo;
+pub use image_info::{blit_image, ImageInfo};
+
+mod resource_manager;
+pub use resource_manager::{BufferHandle, ResourceManager};
+
+mod attachment_config;
+pub use attachment_config::AttachmentConfig;
+
+mod clear_values;
+pub use clear_values::{ClearValues, ClearValuesBuilder};
+
+mod gfx_config;
+pub use gfx_config::{AppInfo, GfxConfig, ImageCount, LogicOp, PoolReset};
+
+mod offscreen;
+pub use offscreen::Offscreen;
+
+mod framebuffer_2d;
+pub use framebuffer_2d::Framebuffer2D;
+
+mod pixel_blit;
+pub use pixel_blit::PixelBlit;
+
+mod sprite_batch;
+pub use sprite_batch::{Sprite, SpriteBatch};
+
+mod sprite_texture;
+pub use sprite_texture::SpriteTexture;
+
+mod debug_draw;
+pub use debug_draw::DebugDraw;
+
+mod demo;
+use demo::Demo;
+
+mod model;
+pub use model::{GpuModel, Material, Model};
+
+mod draw_batch;
+pub use draw_batch::{Batch, InstanceTransforms, MeshDraw};
+
+mod static_scene;
+pub use static_scene::StaticScene;
+
+mod frame_uniforms;
+pub use frame_uniforms::FrameUniforms;
+
+mod swapchain;
+pub use swapchain::Swapchain;
+
+mod memory_budget;
+pub use memory_budget::MemoryBudget;
+
+mod pipeline_stats;
+pub use pipeline_stats::{PipelineStats, StatsQueries};
+
+mod frame_capture;
+pub use frame_capture::FrameCapture;
+
+mod picking;
+pub use picking::Picking;
+
+mod readback;
+pub use readback::{ReadbackHandle, ReadbackStatus};
+
+#[cfg(test)]
+mod headless;
+
+mod rolling_average;
+pub use rolling_average::RollingAverage;
+
+mod cpu_frame_timing;
+pub use cpu_frame_timing::CpuFrameTiming;
+
+mod frame_sync;
+pub use frame_sync::FrameSync;
+
+mod frame_pool;
+pub use frame_pool::FramePool;
+
+mod present_handoff;
+pub use present_handoff::PresentHandoff;
+
+mod gfx_error;
+pub use gfx_error::GfxError;
+
+mod validation_log;
+pub use validation_log::{ValidationLog, ValidationMessage};
+
+mod input;
+use input::{InputPlayer, InputRecorder, InputState};
+
+mod simulation;
+use simulation::Simulation;
+
+mod frame_sequence;
+use frame_sequence::FrameSequence;
+
+mod scene;
+use scene::Scene;
+
+mod particles;
+use particles::ParticleSystem;
+
+mod transform;
+use transform::Transform;
 
 mod drawing;
 
-#[derive(Default, Copy, Clone)]
-struct InputState {
-    pub mouse: Vec2,
-}
+mod shader_watcher;
+use shader_watcher::ShadersChanged;
 
 fn main() -> Result<(), &'static str> {
     let colors = ColoredLevelConfig::default();
+    let validation = ValidationLog::new(utils::VALIDATION_MESSAGES);
     fern::Dispatch::new()
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                "[{}][{}] {}",
-                colors.color(record.level()),
-                record.target(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Error)
-        .chain(std::io::stdout())
+        .chain(
+            fern::Dispatch::new()
+                .format(move |out, message, record| {
+                    out.finish(format_args!(
+                        "[{}][{}] {}",
+                        colors.color(record.level()),
+                        record.target(),
+                        message
+                    ))
+                })
+                .level(log::LevelFilter::Error)
+                // Our own fallbacks and notes, the backend stays quieter
+                .level_for("learn_gfx_hal", log::LevelFilter::Info)
+                .chain(std::io::stdout()),
+        )
+        .chain(validation.dispatch())
         .apply()
         .map_err(|_| "Failed to start logger")?;
 
-    let event_loop = EventLoop::new();
+    let event_loop = EventLoop::<ShadersChanged>::with_user_event();
     let window = WindowBuilder::new()
         .with_title(utils::WINDOW_NAME)
         .build(&event_loop)
         .unwrap();
 
-    let mut gfx_state = GfxState::new(&window)?;
+    for monitor in event_loop.available_monitors() {
+        let size = monitor.size();
+        println!(
+            "Monitor {}: {}x{}",
+            monitor.name().unwrap_or_else(|| "unnamed".into()),
+            size.width,
+            size.height
+        );
+    }
+
+    // Either `record <file>`, `replay <file>`, `triangle`, `indirect`, `pixels`, `scene`
+    // or `sequence <frames> <pattern>`, which saves that many frames to files named
+    // by the pattern with {} replaced by the frame number, then exits.
+    // Flags starting with -- can go anywhere, see apply_flag.
+    let mut config = GfxConfig::default();
+    let mut scene_example = false;
+    let mut sequence = None;
+    let mut particles = None;
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with("--"));
+    for flag in &flags {
+        apply_flag(&mut config, flag)?;
+    }
+    let (mut recorder, mut player) = match args.as_slice() {
+        [mode, path] if mode == "record" => (Some((InputRecorder::new(), path.clone())), None),
+        [mode, path] if mode == "replay" => (None, Some(InputPlayer::load(path)?)),
+        [mode] if mode == "triangle" => {
+            config.triangle_example = true;
+            (None, None)
+        }
+        [mode] if mode == "indirect" => {
+            config.indirect_draw = true;
+            (None, None)
+        }
+        [mode] if mode == "pixels" => {
+            config.software_framebuffer = true;
+            (None, None)
+        }
+        [mode] if mode == "scene" => {
+            scene_example = true;
+            (None, None)
+        }
+        // The nodes are only recorded again when they move,
+        // so pausing leaves the same recording in use
+        [mode] if mode == "static" => {
+            scene_example = true;
+            config.static_scene = true;
+            (None, None)
+        }
+        [mode] if mode == "particles" => {
+            particles = Some(ParticleSystem::new(particles::DEFAULT_PARTICLES));
+            (None, None)
+        }
+        [mode, count] if mode == "particles" => {
+            let count = count
+                .parse()
+                .map_err(|_| "The particle count must be a whole number")?;
+            particles = Some(ParticleSystem::new(count));
+            (None, None)
+        }
+        // Particles drawn with the texture in an image or DDS file
+        [mode, path] if mode == "texture" => {
+            config.sprite_texture = Some(Box::leak(path.clone().into_boxed_str()));
+            particles = Some(ParticleSystem::new(particles::DEFAULT_PARTICLES));
+            (None, None)
+        }
+        // A glTF model drawn over the scene
+        [mode, path] if mode == "model" => {
+            config.model = Some(Box::leak(path.clone().into_boxed_str()));
+            (None, None)
+        }
+        [mode, frames, pattern] if mode == "sequence" => {
+            let frames = frames
+                .parse()
+                .map_err(|_| "The frame count must be a whole number")?;
+            sequence = Some(FrameSequence::new(frames, pattern)?);
+            // Frames are copied back from the offscreen target
+            config.render_to_texture = true;
+            (None, None)
+        }
+        [] => (None, None),
+        _ => {
+            return Err(
+                "Usage: learn_gfx_hal [record|replay <file>|triangle|indirect|pixels|scene|static|particles [count]|texture <file>|model <file>|sequence <frames> <pattern>] [--separate-streams] [--reverse-z] [--stats] [--gradient] [--picking] [--dump-spirv] [--readback] [--wireframe] [--wireframe-width=<pixels>] [--depth-prepass] [--depth-clamp] [--flip-y] [--extension=<name>]... [--images=<min|min+1>] [--anisotropy=<samples>] [--depth-range=<min>,<max>] [--composite-alpha=<mode>]",
+            )
+        }
+    };
+
+    // Only empty while being rebuilt after device loss
+    let mut gfx_state = Some(GfxState::new(&window, config, validation)?);
     let mut input_state = InputState::default();
 
-    render(&mut gfx_state, &input_state);
+    // Only drawn with the scene pipeline, so empty unless asked for
+    let mut scene = Scene::default();
+    if scene_example {
+        build_arm(&mut scene);
+    }
+
+    // Hot reloading is a convenience, so carry on without it
+    if let Err(e) = shader_watcher::watch("shaders", event_loop.create_proxy()) {
+        println!("{}", e);
+    }
+
+    // P pauses, N advances one frame while paused
+    let mut simulation = Simulation::default();
+    let mut paused = false;
+    let mut step = false;
+    let mut last_update = Instant::now();
+
+    // D outlines the cursor, in clip space while it's over the window
+    let mut show_cursor = false;
+    let mut cursor = None;
+
+    render(
+        &mut gfx_state,
+        &window,
+        &input_state,
+        &simulation,
+        &scene,
+        cursor,
+    );
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+        // Sleep until something happens. Running time wakes the loop
+        // for its next step, replay for the next recorded event.
+        // Only screenshots and sequences have to check back constantly.
+        let screenshot_pending = gfx_state
+            .as_ref()
+            .map_or(false, GfxState::screenshot_pending);
+        let next_step = if paused {
+            None
+        } else {
+            Some(last_update + simulation::STEP)
+        };
+        let deadline = player
+            .as_ref()
+            .and_then(InputPlayer::next_deadline)
+            .into_iter()
+            .chain(next_step)
+            .min();
+        *control_flow = match deadline {
+            _ if screenshot_pending || sequence.is_some() => ControlFlow::Poll,
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        };
 
         match event {
             Event::WindowEvent { event, .. } => match event {
@@ -67,29 +338,427 @@ fn main() -> Result<(), &'static str> {
                     ..
                 }
                 | WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                WindowEvent::Resized(_) => {
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::M),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => move_to_next_monitor(&window),
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::R),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => reload_shaders(&mut gfx_state),
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::P),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    paused = !paused;
+                    // Time spent paused doesn't count
+                    last_update = Instant::now();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::N),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } if paused => step = true,
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::B),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &gfx_state {
+                        match state.memory_budget() {
+                            Some(budget) => print!("{}", budget),
+                            None => println!("Memory budget not available"),
+                        }
+                        print!("{}", allocator_stats());
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::V),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &gfx_state {
+                        // Print what the layers reported since the last press
+                        for message in state.validation.recent() {
+                            println!("[{}] {}", message.level, message.text);
+                        }
+                        if state.validation.has_errors() {
+                            println!("Validation reported errors");
+                        }
+                        state.validation.clear();
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::S),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &gfx_state {
+                        match state.pipeline_stats() {
+                            Some(stats) => print!("{}", stats),
+                            None => println!("Pipeline statistics not available, see --stats"),
+                        }
+                        if let Some(latency) = state.acquire_latency_ms() {
+                            println!("Image acquire latency: {:.2} ms", latency);
+                        }
+                        // Render fences only, presents aren't tracked
+                        println!(
+                            "Waiting on earlier rendering: {:.2} ms",
+                            state.render_fence_wait.as_secs_f64() * 1000.0
+                        );
+                        print!("{}", state.cpu_timing);
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::I),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &mut gfx_state {
+                        let indexed = !state.indexed;
+                        match state.set_indexed(indexed) {
+                            Ok(()) if indexed => println!("Drawing with the index buffer"),
+                            Ok(()) => println!("Drawing without the index buffer"),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::W),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &mut gfx_state {
+                        let wireframe = !state.config.wireframe;
+                        match state.set_wireframe(wireframe) {
+                            Ok(()) if wireframe => println!("Drawing node edges over the fill"),
+                            Ok(()) => println!("Drawing nodes filled only"),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::O),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &mut gfx_state {
+                        let overdraw = !state.config.overdraw;
+                        match state.set_overdraw(overdraw) {
+                            Ok(()) if overdraw => println!("Drawing the overdraw heatmap"),
+                            Ok(()) => println!("Drawing the scene"),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+                // Number keys pick a demo
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } if Demo::from_key(key).is_some() => {
+                    if let (Some(state), Some(demo)) = (&mut gfx_state, Demo::from_key(key)) {
+                        state.set_demo(demo);
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::L),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &mut gfx_state {
+                        state.config.single_present = !state.config.single_present;
+                        if state.config.single_present {
+                            println!(
+                                "Waiting for each frame's rendering before acquiring the next"
+                            );
+                        } else {
+                            println!("Letting frames queue up to the flight limit");
+                        }
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::F),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &mut gfx_state {
+                        match state.cycle_swapchain_format(&window) {
+                            Ok(format) => println!("Swapchain format {:?}", format),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::C),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &mut gfx_state {
+                        match state.trigger_capture() {
+                            // Draw even while paused, so the capture happens now
+                            Ok(()) => state.mark_dirty(),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::F12),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &mut gfx_state {
+                        if let Err(e) = state.request_screenshot() {
+                            println!("{}", e);
+                        }
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key @ VirtualKeyCode::Equals),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                }
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key @ VirtualKeyCode::Minus),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(state) = &mut gfx_state {
+                        let count = match key {
+                            VirtualKeyCode::Equals => state.frames.len() + 1,
+                            _ => state.frames.len().saturating_sub(1),
+                        };
+                        match state.set_frames_in_flight(count) {
+                            Ok(()) => println!("{} frames in flight", count),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+
+                // Moving to another monitor can change the scale factor,
+                // which resizes the window just the same
+                WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
                     // Winit logs some warnings from this,
                     // but it seems to work alright
-                    gfx_state.free();
-                    gfx_state = match GfxState::new(&window) {
-                        Ok(state) => state,
-                        Err(e) => panic!(e),
+                    if let Some(state) = &mut gfx_state {
+                        if let Err(e) = state.recreate_swapchain(&window) {
+                            log::error!("{}, trying again before the next frame", e);
+                            state.swapchain_stale = true;
+                            state.mark_dirty();
+                        }
                     }
                 }
 
-                WindowEvent::CursorMoved { position, .. } => {
-                    input_state.mouse = Vec2 {
-                        x: position.x as f32 / window.inner_size().width as f32,
-                        y: position.y as f32 / window.inner_size().height as f32,
-                    };
-                    window.request_redraw();
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    ..
+                } => {
+                    if let Some(state) = &gfx_state {
+                        match (&state.picking, state.picked_node()) {
+                            (None, _) => {}
+                            (Some(_), Some(node)) => println!("Picked scene node {}", node),
+                            (Some(_), None) => println!("Nothing picked"),
+                        }
+                    }
+                }
+
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(VirtualKeyCode::D),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    show_cursor = !show_cursor;
+                    if !show_cursor {
+                        cursor = None;
+                    }
+                    mark_dirty(&mut gfx_state);
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    if let Some(picking) = gfx_state.as_mut().and_then(|s| s.picking.as_mut()) {
+                        picking.cursor_left();
+                    }
+                    if cursor.is_some() {
+                        cursor = None;
+                        mark_dirty(&mut gfx_state);
+                    }
+                }
+                WindowEvent::CursorEntered { .. } => {
+                    if let Some(picking) = gfx_state.as_mut().and_then(|s| s.picking.as_mut()) {
+                        picking.hovered = true;
+                    }
+                }
+
+                // The real mouse is ignored during replay,
+                // apart from the outline
+                WindowEvent::CursorMoved { position, .. } if player.is_none() || show_cursor => {
+                    if show_cursor {
+                        cursor = Some(utils::window_to_ndc(position, window.inner_size()));
+                    }
+                    if player.is_none() {
+                        input_state.mouse = utils::window_to_unit(position, window.inner_size());
+                        if let Some((recorder, _)) = &mut recorder {
+                            recorder.record(input_state);
+                        }
+                    }
+                    mark_dirty(&mut gfx_state);
                 }
 
                 _ => {}
             },
 
+            Event::NewEvents(_) => {
+                if let Some(player) = &mut player {
+                    if player.poll(&mut input_state) {
+                        mark_dirty(&mut gfx_state);
+                    }
+                }
+            }
+
+            Event::UserEvent(ShadersChanged) => reload_shaders(&mut gfx_state),
+
+            // Time only moves a fixed step per saved frame
+            Event::MainEventsCleared if sequence.is_some() => {
+                if let Some(frames) = &mut sequence {
+                    if !advance_sequence(
+                        frames,
+                        &mut gfx_state,
+                        &window,
+                        &input_state,
+                        &mut simulation,
+                        &mut scene,
+                        &mut particles,
+                    ) {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+
+            // Time only moves while running or stepping. Input
+            // and resizes are still drawn while paused, but
+            // nothing is drawn until something has changed.
+            Event::MainEventsCleared => {
+                if !paused || step {
+                    let now = Instant::now();
+                    let dt = if paused {
+                        simulation::STEP
+                    } else {
+                        now - last_update
+                    };
+                    last_update = now;
+                    step = false;
+
+                    // A still frame is left as it was
+                    let tinted = simulation.update(dt);
+                    let moved = animate_arm(&mut scene, simulation.time);
+                    let emitted = update_particles(&mut particles, &mut gfx_state, dt);
+                    if tinted || moved || emitted {
+                        mark_dirty(&mut gfx_state);
+                    }
+                }
+
+                if gfx_state.as_ref().map_or(false, |state| state.dirty) {
+                    window.request_redraw();
+                }
+                save_screenshot(&mut gfx_state, utils::SCREENSHOT_PATH);
+            }
+
             Event::RedrawRequested(_) => {
-                render(&mut gfx_state, &input_state);
+                render(
+                    &mut gfx_state,
+                    &window,
+                    &input_state,
+                    &simulation,
+                    &scene,
+                    cursor,
+                );
+                // Dropped when a lost device couldn't be rebuilt
+                if gfx_state.is_none() {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+
+            Event::LoopDestroyed => {
+                if let Some((recorder, path)) = &recorder {
+                    if let Err(e) = recorder.save(path) {
+                        println!("{}", e);
+                    }
+                }
             }
 
             _ => (),
@@ -97,8 +766,343 @@ fn main() -> Result<(), &'static str> {
     });
 }
 
-fn render(gfx_state: &mut GfxState, input_state: &InputState) {
-    if let Err(e) = drawing::draw_frame(gfx_state, [0.2, 0.2, 0.2, 1.0], input_state.mouse) {
-        println!("{}", e);
+// Config that isn't tied to a mode, as `--name` or `--name=value`
+fn apply_flag(config: &mut GfxConfig, flag: &str) -> Result<(), &'static str> {
+    let mut parts = flag.splitn(2, '=');
+    let name = parts.next().unwrap_or_default();
+    match (name, parts.next()) {
+        // The triangle's positions and colors in a buffer each
+        ("--separate-streams", None) => config.triangle_streams = vertex::VertexStreams::Separate,
+        ("--reverse-z", None) => *config = config.with_reverse_z(),
+        ("--stats", None) => config.pipeline_stats = true,
+        ("--gradient", None) => config.gradient_background = true,
+        // Click on scene nodes to print which one is under the cursor
+        ("--picking", None) => config.picking = true,
+        ("--dump-spirv", None) => config.dump_spirv = true,
+        // Starts with the node edges showing, which W then toggles
+        ("--wireframe", None) => config.wireframe = true,
+        // Wider edges where the device has wideLines
+        ("--wireframe-width", Some(pixels)) => {
+            config.wireframe_width = pixels
+                .parse()
+                .map_err(|_| "The wireframe width is a number of pixels, such as 2")?
+        }
+        // Clip space Y up, as in OpenGL
+        ("--flip-y", None) => config.flip_y = true,
+        // Fails at startup where depthClamp isn't supported
+        // Only changes how a model is drawn
+        ("--depth-prepass", None) => config.depth_prepass = true,
+        ("--depth-clamp", None) => config.depth_clamp = true,
+        // Lets screenshots copy straight out of the swapchain
+        ("--readback", None) => config.swapchain_usage |= ImageUsage::TRANSFER_SRC,
+        // Repeated for each one, such as --extension=VK_KHR_maintenance1.
+        // Only those gfx-hal reaches through a feature are enabled.
+        ("--extension", Some(extension)) => {
+            let mut extensions = config.device_extensions.to_vec();
+            extensions.push(Box::leak(extension.to_string().into_boxed_str()));
+            config.device_extensions = Box::leak(extensions.into_boxed_slice());
+        }
+        // Samples along the direction of stretch, clamped to the device
+        ("--anisotropy", Some(samples)) => {
+            config.anisotropy = samples
+                .parse()
+                .map_err(|_| "Anisotropy is a number of samples, such as 16")?
+        }
+        // Clamped to what the surface supports
+        ("--images", Some(count)) => {
+            config.image_count = match count {
+                "min" => Some(ImageCount::Minimum),
+                "min+1" => Some(ImageCount::MinimumPlusOne),
+                _ => return Err("Image count is min or min+1"),
+            }
+        }
+        // Checked against 0 to 1 when the state is created
+        ("--depth-range", Some(range)) => {
+            let mut bounds = range.splitn(2, ',').map(str::parse::<f32>);
+            match (bounds.next(), bounds.next()) {
+                (Some(Ok(min)), Some(Ok(max))) => {
+                    config.min_depth = min;
+                    config.max_depth = max;
+                }
+                _ => return Err("Depth range is two numbers, such as 0.5,1"),
+            }
+        }
+        ("--composite-alpha", Some(mode)) => {
+            config.composite_alpha = match mode {
+                "opaque" => CompositeAlpha::OPAQUE,
+                "premultiplied" => CompositeAlpha::PREMULTIPLIED,
+                "postmultiplied" => CompositeAlpha::POSTMULTIPLIED,
+                "inherit" => CompositeAlpha::INHERIT,
+                _ => {
+                    return Err(
+                        "Composite alpha is opaque, premultiplied, postmultiplied or inherit",
+                    )
+                }
+            }
+        }
+        _ => return Err("Unknown flag, or a value where there shouldn't be one"),
+    }
+    Ok(())
+}
+
+// Cycles the window through the available monitors
+fn move_to_next_monitor(window: &Window) {
+    let monitors: Vec<_> = window.available_monitors().into_iter().collect();
+    if monitors.len() < 2 {
+        println!("No other monitor to move to");
+        return;
+    }
+
+    let current = window.current_monitor();
+    let i = monitors.iter().position(|m| *m == current).unwrap_or(0);
+    let next = &monitors[(i + 1) % monitors.len()];
+    window.set_outer_position(next.position());
+}
+
+// Three segments, each hanging off the end of the last
+fn build_arm(scene: &mut Scene) {
+    let segment = |x| Transform {
+        translation: Vec2 { x, y: 0.0 },
+        rotation: 0.0,
+        scale: Vec2 { x: 0.5, y: 0.5 },
+    };
+    let shoulder = scene.add_node(None, segment(-0.5));
+    let elbow = scene.add_node(Some(shoulder), segment(1.5));
+    scene.add_node(Some(elbow), segment(1.5));
+}
+
+// Every joint bends, so the motion compounds down the arm
+// Whether any of the nodes turned
+fn animate_arm(scene: &mut Scene, time: f32) -> bool {
+    let mut moved = false;
+    for (i, node) in scene.nodes.iter_mut().enumerate() {
+        let rotation = (time * (i + 1) as f32).sin() * 0.5;
+        moved |= node.local.rotation != rotation;
+        node.local.rotation = rotation;
+    }
+    moved
+}
+
+// Steps the particles and hands them to their sprite batch,
+// which uploads them to its instance buffer when drawn.
+// Whether there were any to move.
+fn update_particles(
+    particles: &mut Option<ParticleSystem>,
+    gfx_state: &mut Option<GfxState>,
+    dt: Duration,
+) -> bool {
+    if let (Some(particles), Some(state)) = (particles, gfx_state) {
+        if particles.particles.is_empty() || dt == Duration::from_secs(0) {
+            return false;
+        }
+        particles.update(dt);
+        // The sprites are placed in pixels of the swapchain's
+        // images, which can differ from the window's size
+        let extent = state.extent();
+        let size = Vec2 {
+            x: extent.width as f32,
+            y: extent.height as f32,
+        };
+        particles.write_sprites(&mut state.particle_sprites, size);
+        return true;
+    }
+    false
+}
+
+// A shader that fails to compile is reported and the old pipeline kept
+fn reload_shaders(gfx_state: &mut Option<GfxState>) {
+    if let Some(state) = gfx_state {
+        if let Err(e) = state.reload_pipeline() {
+            println!("{}", e);
+        }
+    }
+}
+
+// Writes out a requested screenshot once the GPU has copied it back,
+// without holding up the frames drawn in the meantime. Returns None
+// while it's still on its way, and otherwise whether it was saved.
+fn save_screenshot(gfx_state: &mut Option<GfxState>, path: &str) -> Option<bool> {
+    let (extent, pixels) = match gfx_state.as_mut().map(GfxState::poll_screenshot) {
+        Some(Ok(Some(screenshot))) => screenshot,
+        Some(Err(e)) => {
+            println!("{}", e);
+            return Some(false);
+        }
+        _ => return None,
+    };
+    // Either target is RGBA8, the same as a PNG
+    match image::save_buffer(
+        path,
+        &pixels,
+        extent.width,
+        extent.height,
+        image::ColorType::RGBA(8),
+    ) {
+        Ok(()) => {
+            println!("Saved {}", path);
+            Some(true)
+        }
+        Err(_) => {
+            println!("Could not save {}", path);
+            Some(false)
+        }
+    }
+}
+
+// Draws and captures the sequence's frames one at a time.
+// Returns false once every frame has been saved, or one couldn't be.
+fn advance_sequence(
+    sequence: &mut FrameSequence,
+    gfx_state: &mut Option<GfxState>,
+    window: &Window,
+    input_state: &InputState,
+    simulation: &mut Simulation,
+    scene: &mut Scene,
+    particles: &mut Option<ParticleSystem>,
+) -> bool {
+    if gfx_state
+        .as_ref()
+        .map_or(false, GfxState::screenshot_pending)
+    {
+        match save_screenshot(gfx_state, &sequence.path()) {
+            Some(true) => {
+                sequence.next();
+                simulation.update(simulation::STEP);
+                animate_arm(scene, simulation.time);
+                update_particles(particles, gfx_state, simulation::STEP);
+            }
+            // A gap would throw off the timing of everything after it
+            Some(false) => {
+                println!(
+                    "Stopping the sequence after {} of {} frames",
+                    sequence.saved, sequence.frames
+                );
+                return false;
+            }
+            None => {}
+        }
+        return true;
+    }
+    if sequence.is_finished() {
+        return false;
+    }
+
+    mark_dirty(gfx_state);
+    // The cursor isn't part of the recording
+    render(gfx_state, window, input_state, simulation, scene, None);
+    match gfx_state.as_mut().map(GfxState::request_screenshot) {
+        Some(Ok(())) => true,
+        Some(Err(e)) => {
+            println!("{}", e);
+            false
+        }
+        None => false,
+    }
+}
+
+// For input and animation, which change what's
+// on screen without the graphics state knowing
+fn mark_dirty(gfx_state: &mut Option<GfxState>) {
+    if let Some(state) = gfx_state {
+        state.mark_dirty();
+    }
+}
+
+fn render(
+    gfx_state: &mut Option<GfxState>,
+    window: &Window,
+    input_state: &InputState,
+    simulation: &Simulation,
+    scene: &Scene,
+    cursor: Option<Vec2>,
+) {
+    // The size is the same as when it failed, but whatever
+    // stopped it then may have passed
+    if let Some(state) = gfx_state.as_mut().filter(|state| state.swapchain_stale) {
+        if let Err(e) = state.recreate_swapchain(window) {
+            log::error!("{}, trying again before the next frame", e);
+            return;
+        }
+        state.swapchain_stale = false;
+    }
+
+    let (config, result) = match gfx_state {
+        // The last frame is still up to date
+        Some(state) if !state.dirty => return,
+        // Minimized, with the swapchain left at its old size
+        Some(_) if window.inner_size().width == 0 || window.inner_size().height == 0 => return,
+        Some(state) => {
+            // Paint with the mouse
+            if let Some(pixels) = &mut state.pixels {
+                let framebuffer = &mut pixels.framebuffer;
+                framebuffer.set_pixel(
+                    (input_state.mouse.x * framebuffer.width as f32) as u32,
+                    (input_state.mouse.y * framebuffer.height as f32) as u32,
+                    [255, 255, 255, 255],
+                );
+            }
+            // The cursor's outline and its bounds
+            if let Some(cursor) = cursor {
+                let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+                let radius = debug_draw::CURSOR_RADIUS;
+                state.debug_draw.circle(cursor, radius, white);
+                let min = Vec2 {
+                    x: cursor.x - radius,
+                    y: cursor.y - radius,
+                };
+                let max = Vec2 {
+                    x: cursor.x + radius,
+                    y: cursor.y + radius,
+                };
+                state.debug_draw.rect(min, max, white);
+            }
+
+            state.capture.start_frame();
+            let result = drawing::draw_frame(
+                state,
+                // A dark gray as it would be picked in an image editor
+                Vec4::from_srgb8(124, 124, 124, 255),
+                input_state.mouse,
+                simulation.tint(),
+                simulation.time,
+                state.demo.draw(),
+                scene,
+                None,
+            );
+            state.capture.end_frame();
+            // Immediate mode, shapes only last the frame they were
+            // added for, whether or not it could be drawn
+            state.debug_draw.clear();
+            (state.config, result)
+        }
+        None => return,
+    };
+
+    match result {
+        Ok(()) => {
+            if let Some(state) = gfx_state {
+                state.dirty = false;
+            }
+        }
+        Err(GfxError::DeviceLost) => {
+            println!("{}, rebuilding graphics state", GfxError::DeviceLost);
+            // Everything from the lost device has to be released
+            // before rebuilding from a fresh instance, or the
+            // new swapchain would conflict with the old one.
+            // The messages carry over, since the logger still feeds them.
+            let validation = match gfx_state.take() {
+                Some(state) => state.validation.clone(),
+                None => return,
+            };
+            // Left empty on failure, which ends the event loop
+            *gfx_state = match GfxState::new(window, config, validation) {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    log::error!("Could not rebuild the graphics state: {}", e);
+                    None
+                }
+            };
+        }
+        Err(e) => println!("{}", e),
     }
 }
@@ -0,0 +1,185 @@
+use crate::allocator_stats;
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::{Adapter, MemoryProperties, PhysicalDevice},
+    memory::Properties,
+};
+use std::fmt;
+
+// Vulkan reports budgets in fixed size arrays of this many heaps
+const MAX_MEMORY_HEAPS: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeapBudget {
+    // Bytes the process can use before things start failing or paging
+    pub budget: u64,
+    // Bytes the process is using now
+    pub usage: u64,
+    pub device_local: bool,
+}
+
+impl HeapBudget {
+    pub fn available(&self) -> u64 {
+        self.budget.saturating_sub(self.usage)
+    }
+}
+
+// Per-heap numbers in the shape VK_EXT_memory_budget reports them
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryBudget {
+    pub heaps: Vec<HeapBudget>,
+}
+
+impl MemoryBudget {
+    // Pairs the raw budget and usage arrays with the device's heaps.
+    // Entries past the heap count are unused and ignored.
+    pub fn from_raw(
+        properties: &MemoryProperties,
+        budgets: &[u64],
+        usages: &[u64],
+    ) -> Option<Self> {
+        let heap_count = properties.memory_heaps.len();
+        if heap_count > MAX_MEMORY_HEAPS || budgets.len() < heap_count || usages.len() < heap_count
+        {
+            return None;
+        }
+
+        // Heaps don't carry flags here, so a heap is device local
+        // when a device local memory type lives in it
+        let device_local = |heap: usize| {
+            properties
+                .memory_types
+                .iter()
+                .any(|ty| ty.heap_index == heap && ty.properties.contains(Properties::DEVICE_LOCAL))
+        };
+
+        Some(Self {
+            heaps: (0..heap_count)
+                .map(|i| HeapBudget {
+                    budget: budgets[i],
+                    usage: usages[i],
+                    device_local: device_local(i),
+                })
+                .collect(),
+        })
+    }
+
+    // Roughly the VRAM left to allocate
+    pub fn device_local_available(&self) -> u64 {
+        self.heaps
+            .iter()
+            .filter(|heap| heap.device_local)
+            .map(HeapBudget::available)
+            .sum()
+    }
+
+    pub fn query(adapter: &Adapter<back::Backend>) -> Option<Self> {
+        let (budgets, usages) = query_raw(adapter)?;
+        Self::from_raw(
+            &adapter.physical_device.memory_properties(),
+            &budgets,
+            &usages,
+        )
+    }
+}
+
+impl fmt::Display for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MIB: u64 = 1024 * 1024;
+        for (i, heap) in self.heaps.iter().enumerate() {
+            writeln!(
+                f,
+                "Heap {}{}: {} of {} MiB used",
+                i,
+                if heap.device_local {
+                    " (device local)"
+                } else {
+                    ""
+                },
+                heap.usage / MIB,
+                heap.budget / MIB
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// The real budget comes from vkGetPhysicalDeviceMemoryProperties2 with
+// VkPhysicalDeviceMemoryBudgetPropertiesEXT chained on, but gfx-backend-vulkan
+// 0.4 doesn't enable VK_EXT_memory_budget or expose the raw physical device
+// handle. Instead the budget is the whole heap, and the usage is what this
+// process allocated through the allocator, which every allocation goes through.
+// Other processes' usage and driver overhead aren't counted.
+fn query_raw(adapter: &Adapter<back::Backend>) -> Option<(Vec<u64>, Vec<u64>)> {
+    let budgets = adapter.physical_device.memory_properties().memory_heaps;
+    let stats = allocator_stats();
+    let usages = (0..budgets.len())
+        .map(|i| stats.heaps.get(i).map_or(0, |heap| heap.in_use))
+        .collect();
+    Some((budgets, usages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gfx_hal::adapter::MemoryType;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn props(heaps: usize) -> MemoryProperties {
+        MemoryProperties {
+            memory_types: vec![
+                MemoryType {
+                    properties: Properties::DEVICE_LOCAL,
+                    heap_index: 0,
+                },
+                MemoryType {
+                    properties: Properties::CPU_VISIBLE,
+                    heap_index: 1,
+                },
+            ],
+            memory_heaps: vec![256 * MIB; heaps],
+        }
+    }
+
+    #[test]
+    fn pairs_entries_with_heaps() {
+        let budget = MemoryBudget::from_raw(
+            &props(2),
+            &[100 * MIB, 50 * MIB, 0],
+            &[40 * MIB, 60 * MIB, 0],
+        )
+        .unwrap();
+        assert_eq!(
+            budget.heaps,
+            vec![
+                HeapBudget {
+                    budget: 100 * MIB,
+                    usage: 40 * MIB,
+                    device_local: true,
+                },
+                HeapBudget {
+                    budget: 50 * MIB,
+                    usage: 60 * MIB,
+                    device_local: false,
+                },
+            ]
+        );
+        // Over budget counts as nothing left
+        assert_eq!(budget.heaps[1].available(), 0);
+        assert_eq!(budget.device_local_available(), 60 * MIB);
+    }
+
+    #[test]
+    fn rejects_too_few_entries() {
+        assert_eq!(MemoryBudget::from_raw(&props(2), &[MIB], &[0, 0]), None);
+        assert_eq!(MemoryBudget::from_raw(&props(2), &[MIB, MIB], &[0]), None);
+    }
+
+    #[test]
+    fn rejects_too_many_heaps() {
+        let entries = [0; MAX_MEMORY_HEAPS + 1];
+        let props = props(MAX_MEMORY_HEAPS + 1);
+        assert_eq!(MemoryBudget::from_raw(&props, &entries, &entries), None);
+    }
+}
@@ -0,0 +1,388 @@
+use crate::{
+    debug_names::set_debug_name,
+    descriptor::{DescriptorAllocation, DescriptorAllocator},
+    image_info::SamplerPreset,
+    sprite_texture,
+    transform::Mat4,
+    utils::Vec4,
+    vertex::MeshVertex,
+    BufferInfo, ImageInfo, PipelineInfo,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter, buffer::Usage, device::Device, format::Format, image, pso, Backend,
+};
+use std::path::Path;
+
+// What a surface looks like, from glTF's metallic-roughness model.
+// Only the base color is read so far.
+#[derive(Clone, Debug, Default)]
+pub struct Material {
+    // Multiplies the texture, or stands in for it without one
+    pub base_color: Vec4,
+    pub base_color_texture: Option<TexturePixels>,
+}
+
+// Decoded RGBA8 texels, tightly packed, ready to upload
+#[derive(Clone, Debug)]
+pub struct TexturePixels {
+    pub extent: image::Extent,
+    pub pixels: Vec<u8>,
+}
+
+// A triangle mesh with one material, loaded on the CPU
+#[derive(Clone, Debug, Default)]
+pub struct Model {
+    pub vertices: Vec<MeshVertex>,
+    // Files can hold more vertices than u16 indices reach
+    pub indices: Vec<u32>,
+    pub material: Material,
+}
+
+impl Model {
+    // Reads a .gltf with its buffers and images beside it, or a .glb
+    pub fn from_gltf(path: impl AsRef<Path>) -> Result<Self, &'static str> {
+        let (document, buffers, images) =
+            gltf::import(path).map_err(|_| "Could not load glTF file")?;
+        Self::from_document(&document, &buffers, &images)
+    }
+
+    // For glTF already in memory, with its buffers and images
+    // embedded as data URIs or in a .glb's binary chunk
+    pub fn from_gltf_slice(bytes: &[u8]) -> Result<Self, &'static str> {
+        let (document, buffers, images) =
+            gltf::import_slice(bytes).map_err(|_| "Could not parse glTF data")?;
+        Self::from_document(&document, &buffers, &images)
+    }
+
+    // Only a single mesh with a single primitive for now,
+    // so there is only ever one material to deal with
+    fn from_document(
+        document: &gltf::Document,
+        buffers: &[gltf::buffer::Data],
+        images: &[gltf::image::Data],
+    ) -> Result<Self, &'static str> {
+        let mut meshes = document.meshes();
+        let mesh = meshes.next().ok_or("glTF file has no meshes")?;
+        if meshes.next().is_some() {
+            return Err("glTF files with more than one mesh aren't supported yet");
+        }
+        let mut primitives = mesh.primitives();
+        let primitive = primitives.next().ok_or("glTF mesh has no primitives")?;
+        if primitives.next().is_some() {
+            return Err("glTF meshes with more than one primitive aren't supported yet");
+        }
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Err("glTF mesh isn't made of triangles");
+        }
+
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &*data.0));
+        let positions = reader
+            .read_positions()
+            .ok_or("glTF mesh has no positions")?;
+        let mut vertices: Vec<MeshVertex> = positions
+            .map(|position| MeshVertex {
+                position,
+                ..MeshVertex::default()
+            })
+            .collect();
+
+        // Either can be missing, which leaves them at zero
+        if let Some(normals) = reader.read_normals() {
+            for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                vertex.normal = normal;
+            }
+        }
+        let pbr = primitive.material().pbr_metallic_roughness();
+        let uv_set = pbr
+            .base_color_texture()
+            .map_or(0, |texture| texture.tex_coord());
+        if let Some(uvs) = reader.read_tex_coords(uv_set) {
+            for (vertex, [x, y]) in vertices.iter_mut().zip(uvs.into_f32()) {
+                vertex.uv.x = x;
+                vertex.uv.y = y;
+            }
+        }
+
+        // Without indices, each run of three vertices is a triangle
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+        if indices.len() % 3 != 0 {
+            return Err("glTF mesh index count isn't a multiple of three");
+        }
+        if indices.iter().any(|&i| i as usize >= vertices.len()) {
+            return Err("glTF mesh index out of range");
+        }
+
+        let [r, g, b, a] = pbr.base_color_factor();
+        let base_color_texture = match pbr.base_color_texture() {
+            Some(info) => {
+                let source = info.texture().source().index();
+                let data = images.get(source).ok_or("glTF texture has no image")?;
+                Some(to_rgba8(data)?)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            vertices,
+            indices,
+            material: Material {
+                base_color: Vec4::new(r, g, b, a),
+                base_color_texture,
+            },
+        })
+    }
+
+    // Centers the mesh on the origin and scales it into a unit
+    // sphere, so files of any size fill about the same space
+    pub fn fit(&self) -> Mat4 {
+        if self.vertices.is_empty() {
+            return Mat4::IDENTITY;
+        }
+        let mut min = [std::f32::INFINITY; 3];
+        let mut max = [std::f32::NEG_INFINITY; 3];
+        for vertex in &self.vertices {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex.position[axis]);
+                max[axis] = max[axis].max(vertex.position[axis]);
+            }
+        }
+        let center = [
+            (min[0] + max[0]) / 2.0,
+            (min[1] + max[1]) / 2.0,
+            (min[2] + max[2]) / 2.0,
+        ];
+        let radius = self
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let [x, y, z] = vertex.position;
+                let (x, y, z) = (x - center[0], y - center[1], z - center[2]);
+                (x * x + y * y + z * z).sqrt()
+            })
+            .fold(0.0, f32::max);
+        // A single point stays where it is
+        let scale = if radius > 0.0 { 1.0 / radius } else { 1.0 };
+
+        Mat4 {
+            columns: [
+                Vec4::new(scale, 0.0, 0.0, 0.0),
+                Vec4::new(0.0, scale, 0.0, 0.0),
+                Vec4::new(0.0, 0.0, scale, 0.0),
+                Vec4::new(
+                    -center[0] * scale,
+                    -center[1] * scale,
+                    -center[2] * scale,
+                    1.0,
+                ),
+            ],
+        }
+    }
+
+    // Copies the mesh into vertex and index buffers and the base
+    // color texture into an image, blocking until it's there, then
+    // points a descriptor set from the pipeline's layout at it
+    pub fn upload(
+        &self,
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        command_pool: &mut <back::Backend as Backend>::CommandPool,
+        queue: &mut <back::Backend as Backend>::CommandQueue,
+        pipeline: &PipelineInfo,
+        descriptors: &mut DescriptorAllocator,
+        // Checked against the device, see GfxConfig::anisotropy
+        anisotropy: f32,
+    ) -> Result<GpuModel, &'static str> {
+        let mut vertices = BufferInfo::new(device, adapter, &self.vertices, Usage::VERTEX)?;
+        set_debug_name(device, &mut *vertices.buffer, "model_vertices");
+        let mut indices = match BufferInfo::new(device, adapter, &self.indices, Usage::INDEX) {
+            Ok(indices) => indices,
+            Err(e) => {
+                vertices.free(device);
+                return Err(e);
+            }
+        };
+        set_debug_name(device, &mut *indices.buffer, "model_indices");
+
+        let loaded = vertices
+            .load_data(device, &self.vertices)
+            .and_then(|_| indices.load_data(device, &self.indices))
+            // Without a texture the base color is all there is
+            .and_then(|_| match &self.material.base_color_texture {
+                Some(texture) => {
+                    upload_texture(device, adapter, command_pool, queue, texture, anisotropy)
+                }
+                None => sprite_texture::white(device, adapter, command_pool, queue),
+            });
+        let mut base_color_texture = match loaded {
+            Ok(texture) => texture,
+            Err(e) => {
+                vertices.free(device);
+                indices.free(device);
+                return Err(e);
+            }
+        };
+
+        // Compatible with the pipeline's layout however often it's rebuilt
+        let descriptor_set = match pipeline.allocate_set(device, descriptors, 0) {
+            Ok(set) => set,
+            Err(e) => {
+                vertices.free(device);
+                indices.free(device);
+                base_color_texture.free(device);
+                return Err(e);
+            }
+        };
+        unsafe {
+            device.write_descriptor_sets(vec![pso::DescriptorSetWrite {
+                set: &descriptor_set.set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::CombinedImageSampler(
+                    &*base_color_texture.image_view,
+                    image::Layout::ShaderReadOnlyOptimal,
+                    &*base_color_texture.sampler,
+                )),
+            }]);
+        }
+
+        Ok(GpuModel {
+            vertices,
+            indices,
+            index_count: self.indices.len() as u32,
+            fit: self.fit(),
+            base_color: self.material.base_color,
+            base_color_texture,
+            descriptor_set,
+        })
+    }
+}
+
+// A model's buffers and texture on the GPU. Indices are u32.
+pub struct GpuModel {
+    pub vertices: BufferInfo,
+    pub indices: BufferInfo,
+    pub index_count: u32,
+    // From Model::fit, placing it before the projection
+    pub fit: Mat4,
+    pub base_color: Vec4,
+    // White where the material has no texture
+    pub base_color_texture: ImageInfo,
+    pub descriptor_set: DescriptorAllocation,
+}
+
+impl GpuModel {
+    pub fn free(mut self, device: &back::Device, descriptors: &mut DescriptorAllocator) {
+        if let Err(e) = descriptors.free(self.descriptor_set) {
+            log::error!("{}", e);
+        }
+        self.vertices.free(device);
+        self.indices.free(device);
+        self.base_color_texture.free(device);
+    }
+}
+
+// Eight bits a channel covers most textures. RGB
+// gets an opaque alpha, since images are all RGBA.
+fn to_rgba8(data: &gltf::image::Data) -> Result<TexturePixels, &'static str> {
+    let pixels = match data.format {
+        gltf::image::Format::R8G8B8A8 => data.pixels.clone(),
+        gltf::image::Format::R8G8B8 => data
+            .pixels
+            .chunks(3)
+            .flat_map(|rgb| rgb.iter().cloned().chain(Some(255)))
+            .collect(),
+        _ => return Err("glTF texture format isn't supported"),
+    };
+    Ok(TexturePixels {
+        extent: image::Extent {
+            width: data.width,
+            height: data.height,
+            depth: 1,
+        },
+        pixels,
+    })
+}
+
+// Base color is stored sRGB encoded, which sampling converts to linear
+fn upload_texture(
+    device: &back::Device,
+    adapter: &Adapter<back::Backend>,
+    command_pool: &mut <back::Backend as Backend>::CommandPool,
+    queue: &mut <back::Backend as Backend>::CommandQueue,
+    texture: &TexturePixels,
+    anisotropy: f32,
+) -> Result<ImageInfo, &'static str> {
+    let mut image = ImageInfo::new(
+        device,
+        adapter,
+        texture.extent,
+        Format::Rgba8Srgb,
+        image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+    )?;
+    set_debug_name(device, &mut *image.image, "model_base_color");
+    let uploaded = image
+        .upload(device, adapter, command_pool, queue, &texture.pixels, 1)
+        .and_then(|_| image.set_sampler(device, SamplerPreset::filtered(anisotropy), anisotropy));
+    if let Err(e) = uploaded {
+        image.free(device);
+        return Err(e);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A single triangle, its positions embedded as a data URI
+    const TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{
+            "byteLength": 36,
+            "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+        }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "accessors": [{
+            "bufferView": 0,
+            "componentType": 5126,
+            "count": 3,
+            "type": "VEC3",
+            "min": [0.0, 0.0, 0.0],
+            "max": [1.0, 1.0, 0.0]
+        }],
+        "materials": [{
+            "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.5, 0.25, 1.0] }
+        }],
+        "meshes": [{
+            "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }]
+        }]
+    }"#;
+
+    #[test]
+    fn loads_an_embedded_triangle() {
+        let model = Model::from_gltf_slice(TRIANGLE.as_bytes()).unwrap();
+        assert_eq!(model.vertices.len(), 3);
+        // Unindexed, so each vertex is used once
+        assert_eq!(model.indices, vec![0, 1, 2]);
+        assert_eq!(model.vertices[1].position, [1.0, 0.0, 0.0]);
+        assert_eq!(model.material.base_color, Vec4::new(1.0, 0.5, 0.25, 1.0));
+        assert!(model.material.base_color_texture.is_none());
+    }
+
+    #[test]
+    fn fit_centers_within_a_unit_sphere() {
+        let model = Model::from_gltf_slice(TRIANGLE.as_bytes()).unwrap();
+        let fit = model.fit();
+        for vertex in &model.vertices {
+            let [x, y, z] = vertex.position;
+            let fitted = fit.transform(Vec4::new(x, y, z, 1.0));
+            let length = (fitted.x * fitted.x + fitted.y * fitted.y + fitted.z * fitted.z).sqrt();
+            // Every corner is as far from the middle as the others
+            assert!((length - 1.0).abs() < 1e-5);
+        }
+    }
+}
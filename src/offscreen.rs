@@ -0,0 +1,196 @@
+use crate::{
+    attachment_config,
+    debug_names::set_debug_name,
+    descriptor::{DescriptorAllocation, DescriptorAllocator},
+    subpass_dependencies,
+    swapchain::FORMAT,
+    AttachmentConfig, ImageInfo, PipelineInfo, PipelineOptions,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter,
+    device::Device,
+    image,
+    pass::{self, AttachmentLayout},
+    pso, Backend,
+};
+use std::{mem::ManuallyDrop, ptr};
+
+// A color target the scene is rendered into before being
+// sampled onto the swapchain, the basis for post effects
+pub struct Offscreen {
+    // May differ from the swapchain's with a render scale
+    pub content_size: pso::Rect,
+    pub target: ImageInfo,
+    // Drawn into and resolved to the target with MSAA
+    pub msaa: Option<ImageInfo>,
+    // Shared between frames like the target,
+    // with the render pass waiting on the last frame's use
+    pub depth: ImageInfo,
+    pub framebuffer: ManuallyDrop<<back::Backend as Backend>::Framebuffer>,
+    pub render_pass: ManuallyDrop<<back::Backend as Backend>::RenderPass>,
+    // Begun instead the first time, when the target has nothing to load
+    pub first_pass: Option<<back::Backend as Backend>::RenderPass>,
+    // Whether a frame has been submitted into the target yet
+    pub drawn: bool,
+    // Draws the target in the swapchain's render pass
+    pub pipeline: PipelineInfo,
+    pub descriptor_set: DescriptorAllocation,
+}
+
+impl Offscreen {
+    pub fn new(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        swapchain_pass: &<back::Backend as Backend>::RenderPass,
+        content_size: pso::Rect,
+        color_attachment: AttachmentConfig,
+        samples: image::NumSamples,
+        swapchain_samples: image::NumSamples,
+        descriptors: &mut DescriptorAllocator,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        let extent = image::Extent {
+            width: content_size.w as u32,
+            height: content_size.h as u32,
+            depth: 1,
+        };
+
+        let mut target = ImageInfo::new(
+            device,
+            adapter,
+            extent,
+            FORMAT,
+            // Copied from for screenshots
+            image::Usage::COLOR_ATTACHMENT | image::Usage::SAMPLED | image::Usage::TRANSFER_SRC,
+        )?;
+        set_debug_name(device, &mut *target.image, "offscreen_target");
+
+        let msaa = if samples > 1 {
+            Some(ImageInfo::multisampled(
+                device, adapter, extent, FORMAT, samples,
+            )?)
+        } else {
+            None
+        };
+
+        let depth = ImageInfo::depth(device, adapter, extent, samples)?;
+
+        let render_pass = offscreen_pass(device, color_attachment, samples)?;
+        let first_pass = if color_attachment.loads() {
+            Some(offscreen_pass(
+                device,
+                color_attachment.first_use(),
+                samples,
+            )?)
+        } else {
+            None
+        };
+
+        // The multisampled image comes first and
+        // the depth buffer last, matching the render pass
+        let mut framebuffer = {
+            let views = msaa
+                .iter()
+                .map(|msaa| &*msaa.image_view)
+                .chain(Some(&*target.image_view))
+                .chain(Some(&*depth.image_view));
+            unsafe { device.create_framebuffer(&render_pass, views, extent) }
+                .map_err(|_| "Could not create offscreen framebuffer")?
+        };
+        set_debug_name(device, &mut framebuffer, "offscreen_framebuffer");
+
+        let pipeline = PipelineInfo::textured_quad(
+            device,
+            pass::Subpass {
+                index: 0,
+                main_pass: swapchain_pass,
+            },
+            swapchain_samples,
+            options,
+        )?;
+
+        let descriptor_set = pipeline.allocate_set(device, descriptors, 0)?;
+
+        unsafe {
+            device.write_descriptor_sets(vec![pso::DescriptorSetWrite {
+                set: &descriptor_set.set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::CombinedImageSampler(
+                    &*target.image_view,
+                    image::Layout::ShaderReadOnlyOptimal,
+                    &*target.sampler,
+                )),
+            }]);
+        }
+
+        Ok(Self {
+            content_size,
+            framebuffer: ManuallyDrop::new(framebuffer),
+            render_pass: ManuallyDrop::new(render_pass),
+            first_pass,
+            drawn: false,
+            descriptor_set,
+            pipeline,
+            target,
+            msaa,
+            depth,
+        })
+    }
+
+    pub fn free(mut self, device: &back::Device, descriptors: &mut DescriptorAllocator) {
+        if let Err(e) = descriptors.free(self.descriptor_set) {
+            log::error!("{}", e);
+        }
+
+        unsafe {
+            device.destroy_framebuffer(ManuallyDrop::into_inner(ptr::read(&self.framebuffer)));
+            device.destroy_render_pass(ManuallyDrop::into_inner(ptr::read(&self.render_pass)));
+        }
+        if let Some(first_pass) = self.first_pass.take() {
+            unsafe { device.destroy_render_pass(first_pass) }
+        }
+
+        self.pipeline.free(device);
+        self.target.free(device);
+        self.depth.free(device);
+        if let Some(mut msaa) = self.msaa.take() {
+            msaa.free(device);
+        }
+    }
+
+    // The pass to begin this frame
+    pub fn begin_pass(&self) -> &<back::Backend as Backend>::RenderPass {
+        match (&self.first_pass, self.drawn) {
+            (Some(first_pass), false) => first_pass,
+            _ => &self.render_pass,
+        }
+    }
+}
+
+// The target is always single sample so it can be sampled,
+// with multisampled drawing resolved into it
+fn offscreen_pass(
+    device: &back::Device,
+    color_attachment: AttachmentConfig,
+    samples: image::NumSamples,
+) -> Result<<back::Backend as Backend>::RenderPass, &'static str> {
+    let mut render_pass = unsafe {
+        device.create_render_pass(
+            // Moved to be sampled by the frame graph, see drawing::frame_graph
+            color_attachment.attachments(FORMAT, samples, AttachmentLayout::ColorAttachmentOptimal),
+            &[pass::SubpassDesc {
+                colors: &[(0, AttachmentLayout::ColorAttachmentOptimal)],
+                depth_stencil: Some(&attachment_config::depth_ref(samples)),
+                inputs: &[],
+                resolves: attachment_config::resolves(samples),
+                preserves: &[],
+            }],
+            &[subpass_dependencies::external_to_first()],
+        )
+    }
+    .map_err(|_| "Could not create offscreen render pass")?;
+    set_debug_name(device, &mut render_pass, "offscreen_render_pass");
+    Ok(render_pass)
+}
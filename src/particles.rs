@@ -0,0 +1,169 @@
+use crate::{
+    utils::{Vec2, Vec4},
+    Sprite, SpriteBatch,
+};
+use std::time::Duration;
+
+// Particles drawn when no count is given
+pub const DEFAULT_PARTICLES: usize = 1000;
+
+// Longest a particle lives before respawning, in seconds
+pub const LIFETIME: f32 = 2.0;
+
+// Positions and velocities are fractions of the render area,
+// so the fountain looks the same at any window size
+pub const GRAVITY: f32 = 0.8;
+
+// Width and height of each particle's quad, in pixels
+pub const PARTICLE_SIZE: f32 = 4.0;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Particle {
+    // From the top left of the area, like the sprites
+    pub position: Vec2,
+    pub velocity: Vec2,
+    // Seconds left until it respawns
+    pub life: f32,
+}
+
+// A fountain simulated on the CPU. Particles are drawn as sprites,
+// so their positions reach the GPU through a sprite batch's
+// instance buffer, rewritten every frame.
+pub struct ParticleSystem {
+    pub particles: Vec<Particle>,
+    // Where particles respawn, as a fraction of the area
+    pub emitter: Vec2,
+    // Xorshift state, seeded the same every run so
+    // recorded sequences come out the same
+    seed: u32,
+}
+
+impl ParticleSystem {
+    pub fn new(count: usize) -> Self {
+        let mut system = Self {
+            particles: vec![],
+            emitter: Vec2 { x: 0.5, y: 0.9 },
+            seed: 0x9e37_79b9,
+        };
+        system.set_count(count);
+        system
+    }
+
+    // New particles start part way through their lives,
+    // so they don't all respawn on the same frame
+    pub fn set_count(&mut self, count: usize) {
+        let emitter = self.emitter;
+        let seed = &mut self.seed;
+        self.particles.truncate(count);
+        while self.particles.len() < count {
+            let mut particle = spawn(emitter, seed);
+            particle.life *= random(seed);
+            self.particles.push(particle);
+        }
+    }
+
+    // Respawns the particles that run out of life at the emitter,
+    // so the count stays the same from step to step
+    pub fn update(&mut self, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        let emitter = self.emitter;
+        let seed = &mut self.seed;
+        for particle in &mut self.particles {
+            particle.life -= dt;
+            if particle.life <= 0.0 {
+                *particle = spawn(emitter, seed);
+                continue;
+            }
+            particle.velocity.y += GRAVITY * dt;
+            particle.position.x += particle.velocity.x * dt;
+            particle.position.y += particle.velocity.y * dt;
+        }
+    }
+
+    // Replaces the batch's sprites with one per particle, fading
+    // out as it ages, so the batch has to be the particles' own.
+    // Size is the area in pixels.
+    pub fn write_sprites(&self, batch: &mut SpriteBatch, size: Vec2) {
+        batch.clear();
+        for particle in &self.particles {
+            let fade = (particle.life / LIFETIME).min(1.0);
+            batch.push(Sprite {
+                pos: Vec2 {
+                    x: particle.position.x * size.x - PARTICLE_SIZE * 0.5,
+                    y: particle.position.y * size.y - PARTICLE_SIZE * 0.5,
+                },
+                size: Vec2 {
+                    x: PARTICLE_SIZE,
+                    y: PARTICLE_SIZE,
+                },
+                color: Vec4::new(1.0, 0.6, 0.2, fade),
+                uv_rect: Vec4::new(0.0, 0.0, 1.0, 1.0),
+            });
+        }
+    }
+}
+
+// Thrown upwards and outwards, to fall back under gravity
+fn spawn(emitter: Vec2, seed: &mut u32) -> Particle {
+    Particle {
+        position: emitter,
+        velocity: Vec2 {
+            x: (random(seed) - 0.5) * 0.3,
+            y: -0.6 - random(seed) * 0.3,
+        },
+        life: LIFETIME * (0.5 + 0.5 * random(seed)),
+    }
+}
+
+// From 0 to 1. Plenty for scattering particles,
+// without pulling in a crate for it.
+fn random(seed: &mut u32) -> f32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    // The top 24 bits fit exactly in an f32
+    (*seed >> 8) as f32 / (1 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_stays_the_same() {
+        let mut system = ParticleSystem::new(100);
+        for _ in 0..100 {
+            system.update(Duration::from_millis(50));
+            assert_eq!(system.particles.len(), 100);
+        }
+        system.set_count(10);
+        assert_eq!(system.particles.len(), 10);
+    }
+
+    #[test]
+    fn spent_particles_respawn_at_the_emitter() {
+        let mut system = ParticleSystem::new(10);
+        for particle in &mut system.particles {
+            particle.life = 0.01;
+            particle.position = Vec2 { x: 0.0, y: 0.0 };
+        }
+        system.update(Duration::from_millis(20));
+        for particle in &system.particles {
+            assert_eq!(particle.position, system.emitter);
+            assert!(particle.life > 0.0 && particle.life <= LIFETIME);
+            // Thrown upwards, which is negative y
+            assert!(particle.velocity.y < 0.0);
+        }
+    }
+
+    #[test]
+    fn living_particles_fall() {
+        let mut system = ParticleSystem::new(1);
+        system.particles[0].life = LIFETIME;
+        let before = system.particles[0];
+        system.update(Duration::from_millis(10));
+        let after = system.particles[0];
+        assert!(after.velocity.y > before.velocity.y);
+        assert!(after.life < before.life);
+    }
+}
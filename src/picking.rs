@@ -0,0 +1,282 @@
+use crate::{
+    debug_names::set_debug_name, utils::Vec2, BufferInfo, ClearValues, ClearValuesBuilder,
+    ImageInfo, PipelineInfo, PipelineOptions,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter,
+    buffer,
+    command::{self, CommandBuffer},
+    device::Device,
+    format::{self, Format},
+    image,
+    memory::{Barrier, Dependencies},
+    pass::{
+        self, Attachment, AttachmentLayout, AttachmentLoadOp, AttachmentOps, AttachmentStoreOp,
+        SubpassDependency, SubpassRef,
+    },
+    pso::{self, PipelineStage},
+    Backend,
+};
+use std::{
+    mem::{self, ManuallyDrop},
+    ptr,
+};
+
+pub const ID_FORMAT: Format = Format::R32Uint;
+
+// Written where nothing was drawn, so node IDs start at one
+pub const NO_ID: u32 = 0;
+
+const ID_BYTES: u64 = 4;
+
+// Draws the scene nodes a second time, writing each one's ID
+// instead of its color, then copies the ID under the cursor
+// back to the CPU. A pass of its own rather than another target
+// in the scene's, so no other pipeline has to know about it.
+pub struct Picking {
+    pub ids: ImageInfo,
+    pub render_pass: ManuallyDrop<<back::Backend as Backend>::RenderPass>,
+    pub framebuffer: ManuallyDrop<<back::Backend as Backend>::Framebuffer>,
+    pub pipeline: PipelineInfo,
+    pub clear: ClearValues,
+    // An ID for each frame in flight, read once the frame's fence
+    // shows the copy has finished
+    pub readback: BufferInfo,
+    recorded: Vec<bool>,
+    // Index of the node under the cursor in the last finished frame
+    pub last: Option<usize>,
+    // Whether the cursor is over the window. The mouse position
+    // goes stale once it leaves, so nothing is picked until it's back.
+    pub hovered: bool,
+}
+
+impl Picking {
+    pub fn new(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        content_size: pso::Rect,
+        frames: usize,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        // Before anything is created that would need freeing
+        let clear = ClearValuesBuilder::new().color_uint([NO_ID; 4]).build(1)?;
+
+        let extent = image::Extent {
+            width: content_size.w as u32,
+            height: content_size.h as u32,
+            depth: 1,
+        };
+
+        let mut ids = ImageInfo::new(
+            device,
+            adapter,
+            extent,
+            ID_FORMAT,
+            image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSFER_SRC,
+        )?;
+        set_debug_name(device, &mut *ids.image, "picking_ids");
+
+        // Left ready for the copy once the pass ends
+        let mut render_pass = unsafe {
+            device.create_render_pass(
+                &[Attachment {
+                    format: Some(ID_FORMAT),
+                    samples: 1,
+                    ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::Store),
+                    stencil_ops: AttachmentOps::DONT_CARE,
+                    layouts: AttachmentLayout::Undefined..AttachmentLayout::TransferSrcOptimal,
+                }],
+                &[pass::SubpassDesc {
+                    colors: &[(0, AttachmentLayout::ColorAttachmentOptimal)],
+                    depth_stencil: None,
+                    inputs: &[],
+                    resolves: &[],
+                    preserves: &[],
+                }],
+                &[
+                    // The image is shared between frames, so the last
+                    // frame's copy has to finish before it's cleared
+                    SubpassDependency {
+                        passes: SubpassRef::External..SubpassRef::Pass(0),
+                        stages: (PipelineStage::TRANSFER | PipelineStage::COLOR_ATTACHMENT_OUTPUT)
+                            ..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+                        accesses: image::Access::TRANSFER_READ
+                            ..image::Access::COLOR_ATTACHMENT_WRITE,
+                    },
+                    SubpassDependency {
+                        passes: SubpassRef::Pass(0)..SubpassRef::External,
+                        stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::TRANSFER,
+                        accesses: image::Access::COLOR_ATTACHMENT_WRITE
+                            ..image::Access::TRANSFER_READ,
+                    },
+                ],
+            )
+        }
+        .map_err(|_| "Could not create picking render pass")?;
+        set_debug_name(device, &mut render_pass, "picking_render_pass");
+
+        let mut framebuffer =
+            unsafe { device.create_framebuffer(&render_pass, Some(&*ids.image_view), extent) }
+                .map_err(|_| "Could not create picking framebuffer")?;
+        set_debug_name(device, &mut framebuffer, "picking_framebuffer");
+
+        let pipeline = PipelineInfo::node_ids(
+            device,
+            pass::Subpass {
+                index: 0,
+                main_pass: &render_pass,
+            },
+            options,
+        )?;
+
+        let mut readback = BufferInfo::with_size(
+            device,
+            adapter,
+            ID_BYTES * frames as u64,
+            buffer::Usage::TRANSFER_DST,
+        )?;
+        readback.map_persistent(device)?;
+        set_debug_name(device, &mut *readback.buffer, "picking_readback");
+
+        Ok(Self {
+            ids,
+            render_pass: ManuallyDrop::new(render_pass),
+            framebuffer: ManuallyDrop::new(framebuffer),
+            pipeline,
+            clear,
+            readback,
+            recorded: vec![false; frames],
+            last: None,
+            hovered: true,
+        })
+    }
+
+    // Also drops copies still in flight, which
+    // would otherwise report a node once they land
+    pub fn cursor_left(&mut self) {
+        self.hovered = false;
+        self.last = None;
+        for recorded in &mut self.recorded {
+            *recorded = false;
+        }
+    }
+
+    // Call once the frame's fence has been waited on
+    pub fn read(&mut self, device: &back::Device, frame: usize) {
+        if !mem::replace(&mut self.recorded[frame], false) {
+            return;
+        }
+        if let Ok(id) = self.readback.read::<u32>(device, frame as u64 * ID_BYTES) {
+            self.last = id.checked_sub(1).map(|index| index as usize);
+        }
+    }
+
+    // Copies the ID at a pixel to the frame's region. Must be
+    // recorded outside a render pass, after the ID pass.
+    pub unsafe fn record_readback(
+        &mut self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        frame: usize,
+        pixel: (u32, u32),
+    ) {
+        let (x, y) = pixel;
+        if x >= self.ids.extent.width || y >= self.ids.extent.height {
+            return;
+        }
+
+        commands.copy_image_to_buffer(
+            &self.ids.image,
+            image::Layout::TransferSrcOptimal,
+            &self.readback.buffer,
+            &[command::BufferImageCopy {
+                buffer_offset: frame as u64 * ID_BYTES,
+                // Tightly packed
+                buffer_width: 0,
+                buffer_height: 0,
+                image_layers: image::SubresourceLayers {
+                    aspects: format::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: image::Offset {
+                    x: x as i32,
+                    y: y as i32,
+                    z: 0,
+                },
+                image_extent: image::Extent {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+            }],
+        );
+
+        // Makes the copy visible to the host once the fence signals
+        commands.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::HOST,
+            Dependencies::empty(),
+            &[Barrier::Buffer {
+                states: buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                target: &*self.readback.buffer,
+                families: None,
+                range: None..None,
+            }],
+        );
+        self.recorded[frame] = true;
+    }
+
+    pub fn free(mut self, device: &back::Device) {
+        unsafe {
+            device.destroy_framebuffer(ManuallyDrop::into_inner(ptr::read(&self.framebuffer)));
+            device.destroy_render_pass(ManuallyDrop::into_inner(ptr::read(&self.render_pass)));
+        }
+        self.pipeline.free(device);
+        self.readback.free(device);
+        self.ids.free(device);
+    }
+}
+
+// The pixel of the target under the cursor, with
+// the mouse from 0 to 1 across the window
+pub fn cursor_pixel(target: pso::Rect, mouse: Vec2) -> Option<(u32, u32)> {
+    let within = |v: f32| v >= 0.0 && v < 1.0;
+    if !within(mouse.x) || !within(mouse.y) {
+        return None;
+    }
+    Some((
+        (mouse.x * target.w as f32) as u32,
+        (mouse.y * target.h as f32) as u32,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: pso::Rect = pso::Rect {
+        x: 0,
+        y: 0,
+        w: 200,
+        h: 100,
+    };
+
+    #[test]
+    fn cursor_scales_to_the_target() {
+        assert_eq!(cursor_pixel(TARGET, Vec2 { x: 0.0, y: 0.0 }), Some((0, 0)));
+        assert_eq!(
+            cursor_pixel(TARGET, Vec2 { x: 0.5, y: 0.5 }),
+            Some((100, 50))
+        );
+        assert_eq!(
+            cursor_pixel(TARGET, Vec2 { x: 0.999, y: 0.999 }),
+            Some((199, 99))
+        );
+    }
+
+    #[test]
+    fn cursor_outside_picks_nothing() {
+        assert_eq!(cursor_pixel(TARGET, Vec2 { x: 1.0, y: 0.5 }), None);
+        assert_eq!(cursor_pixel(TARGET, Vec2 { x: 0.5, y: -0.1 }), None);
+    }
+}
@@ -1,12 +1,167 @@
+use crate::{
+    descriptor::{DescriptorAllocation, DescriptorAllocator},
+    sprite_batch::Sprite,
+    transform::Mat4,
+    utils::{Vec2, Vec4},
+    vertex::{self, ColorVertex, MeshVertex, Vertex, VertexLayout, VertexStreams},
+};
 use gfx_backend_vulkan as back;
-use gfx_hal::{device::Device, format::Format, pass::Subpass, pso, Backend};
+use gfx_hal::{device::Device, format::Format, image, pass::Subpass, pso, Backend, Limits};
 use shaderc::{Compiler, ShaderKind};
-use std::{mem::ManuallyDrop, ops::Range};
+use std::{borrow::Cow, mem::ManuallyDrop, ops::Range};
+
+// Byte ranges within the push constant block for each stage.
+// They must be four byte aligned, must not overlap,
+// and must fit within the device's maxPushConstantsSize.
+// The fragment range starts at 16 to satisfy vec4 alignment.
+pub const VERTEX_PUSH_CONSTANTS: Range<u32> = 0..8;
+pub const FRAGMENT_PUSH_CONSTANTS: Range<u32> = 16..40;
+
+// Render target size in pixels, after the tint in the fragment range
+pub const RESOLUTION_OFFSET: u32 = 32;
+
+// What the gradient reads from its uniform buffer each frame
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GradientUniforms {
+    // Render target size in pixels
+    pub resolution: [f32; 2],
+    // Seconds of simulated time
+    pub time: f32,
+}
+
+// Pixel to clip space scale and offset for sprites
+pub const SPRITE_PUSH_CONSTANTS: Range<u32> = 0..16;
+
+// The projection, with each node's world matrix coming
+// from the instance buffer, then the nodes' tint
+pub const NODE_VERTEX_PUSH_CONSTANTS: Range<u32> = 0..64;
+pub const NODE_FRAGMENT_PUSH_CONSTANTS: Range<u32> = 64..80;
+
+// The model's fit and projection combined, then its base color
+pub const MODEL_VERTEX_PUSH_CONSTANTS: Range<u32> = 0..64;
+pub const MODEL_FRAGMENT_PUSH_CONSTANTS: Range<u32> = 64..80;
+
+// A node's MVP matrix, then the ID written for picking
+pub const NODE_ID_PUSH_CONSTANTS: Range<u32> = 64..68;
+
+// Settings every pipeline is built with, from GfxConfig
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PipelineOptions {
+    // Writes each shader's SPIR-V next to its source
+    pub dump_spirv: bool,
+    // Picked from GfxConfig::flip_y once the device's features are known
+    pub y_flip: YFlip,
+    // Draws the heatmap in place of the scene, see GfxConfig::overdraw
+    pub overdraw: bool,
+    pub limits: PipelineLimits,
+}
+
+impl PipelineOptions {
+    // For pipelines that draw the scene, which needs flipping
+    // the same way whichever does it
+    fn flips_in_shader(&self) -> bool {
+        self.y_flip == YFlip::Shader
+    }
+
+    // For pipelines that draw in window space, which undo
+    // the viewport's flip rather than adding their own
+    fn undoes_viewport(&self) -> bool {
+        self.y_flip == YFlip::Viewport
+    }
+}
+
+// The device limits pipelines are checked against before building,
+// since creation fails without saying why past them
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PipelineLimits {
+    pub push_constants_size: usize,
+    pub vertex_bindings: usize,
+    pub vertex_attributes: usize,
+    pub vertex_stride: usize,
+}
+
+// The least Vulkan guarantees on any device
+impl Default for PipelineLimits {
+    fn default() -> Self {
+        Self {
+            push_constants_size: 128,
+            vertex_bindings: 16,
+            vertex_attributes: 16,
+            vertex_stride: 2048,
+        }
+    }
+}
+
+impl From<&Limits> for PipelineLimits {
+    fn from(limits: &Limits) -> Self {
+        Self {
+            push_constants_size: limits.max_push_constants_size,
+            vertex_bindings: limits.max_vertex_input_bindings,
+            vertex_attributes: limits.max_vertex_input_attributes,
+            vertex_stride: limits.max_vertex_input_binding_stride,
+        }
+    }
+}
+
+// How clip space Y is made to point up, as in OpenGL
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum YFlip {
+    Off,
+    // A negative viewport height, which needs maintenance1.
+    // Flips everything drawn, fullscreen copies included.
+    Viewport,
+    // Negated at the end of each vertex shader, after the projection
+    Shader,
+}
+
+impl Default for YFlip {
+    fn default() -> Self {
+        YFlip::Off
+    }
+}
+
+// Replaces every fragment shader in the scene
+pub const OVERDRAW_FRAG: &str = "shaders/overdraw_frag.glsl";
+
+// The parts that differ between pipelines
+struct PipelineParts<'a> {
+    vert: &'a str,
+    frag: &'a str,
+    depth: Option<pso::DepthTest>,
+    // Must match the subpass's color attachment
+    samples: image::NumSamples,
+    // Fraction of samples shaded individually, for MSAA
+    // that also smooths shading rather than just edges
+    sample_shading: Option<f32>,
+    // Only one of these can be set, Vulkan ignores
+    // blending entirely while a logic op is enabled
+    blend: Option<pso::BlendState>,
+    logic_op: Option<pso::LogicOp>,
+    // NONE for pipelines that only fill depth
+    color_mask: pso::ColorMask,
+    rasterizer: pso::Rasterizer,
+    // Negates clip space Y in the vertex shader,
+    // through its FLIP_Y specialization constant
+    flip_y: bool,
+    // Draws with OVERDRAW_FRAG and additive blending in place of
+    // the usual shader and blend, for the overdraw heatmap
+    overdraw: bool,
+    // Bindings for each descriptor set, in set order
+    sets: Vec<Vec<pso::DescriptorSetLayoutBinding>>,
+    push_constants: &'a [(pso::ShaderStageFlags, Range<u32>)],
+    vertex_buffers: Vec<pso::VertexBufferDesc>,
+    attributes: Vec<pso::AttributeDesc>,
+}
 
 pub struct PipelineInfo {
+    // Descriptors used by each set, indexed like the layouts
+    pub descriptor_ranges: Vec<Vec<pso::DescriptorRangeDesc>>,
     pub descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout>,
     pub layout: ManuallyDrop<<back::Backend as Backend>::PipelineLayout>,
     pub handle: ManuallyDrop<<back::Backend as Backend>::GraphicsPipeline>,
+    // Draws lines whose width has to be set before each draw
+    pub dynamic_line_width: bool,
 }
 
 // A pipeline describes all configurable and programmable state on the GPU
@@ -14,39 +169,570 @@ impl PipelineInfo {
     pub fn new(
         device: &back::Device,
         subpass: Subpass<back::Backend>,
-        content_size: pso::Rect,
+        samples: image::NumSamples,
+        sample_shading: Option<f32>,
+        logic_op: Option<pso::LogicOp>,
+        rasterizer: pso::Rasterizer,
+        depth_comparison: pso::Comparison,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        let parts = PipelineParts {
+            vert: "shaders/vert.glsl",
+            frag: "shaders/frag.glsl",
+            depth: Some(pso::DepthTest {
+                fun: depth_comparison,
+                write: true,
+            }),
+            samples,
+            sample_shading,
+            blend: blend_unless(&logic_op),
+            logic_op,
+            color_mask: pso::ColorMask::ALL,
+            rasterizer,
+            flip_y: options.flips_in_shader(),
+            overdraw: options.overdraw,
+            // This machinery is only used when graphics pipeline data
+            // comes from somewhere other than the vertex buffer.
+            // We still have to explicitly declare all these empty
+            // bits and bobs.
+            sets: vec![],
+            push_constants: &[
+                (pso::ShaderStageFlags::VERTEX, VERTEX_PUSH_CONSTANTS),
+                (pso::ShaderStageFlags::FRAGMENT, FRAGMENT_PUSH_CONSTANTS),
+            ],
+            vertex_buffers: vec![
+                // Not the location listed on the shader,
+                // this is just a unique id for the buffer
+                Vertex::buffer_desc(0, pso::VertexInputRate::Vertex),
+            ],
+            // Locations start at zero to match the shader
+            attributes: Vertex::attributes(0, 0),
+        };
+        Self::build(device, subpass, parts, options)
+    }
+
+    // Draws the shared quad once per sprite,
+    // with the per-sprite data in a second vertex buffer
+    pub fn sprites(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        sample_shading: Option<f32>,
+        logic_op: Option<pso::LogicOp>,
+        rasterizer: pso::Rasterizer,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        // Sprites are placed in window pixels, so they stay the right
+        // way up and keep the winding the flipped rasterizer expects
+        // the scene to have
+        let rasterizer = if options.y_flip == YFlip::Off {
+            rasterizer
+        } else {
+            pso::Rasterizer {
+                front_face: vertex::mirrored(rasterizer.front_face),
+                ..rasterizer
+            }
+        };
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                vert: "shaders/sprite_vert.glsl",
+                frag: "shaders/sprite_frag.glsl",
+                depth: None,
+                samples,
+                sample_shading,
+                blend: blend_unless(&logic_op),
+                logic_op,
+                color_mask: pso::ColorMask::ALL,
+                rasterizer,
+                flip_y: options.undoes_viewport(),
+                overdraw: options.overdraw,
+                // The texture every sprite samples
+                sets: vec![vec![pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::CombinedImageSampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                }]],
+                push_constants: &[(pso::ShaderStageFlags::VERTEX, SPRITE_PUSH_CONSTANTS)],
+                vertex_buffers: vec![
+                    Vertex::buffer_desc(0, pso::VertexInputRate::Vertex),
+                    // Advance once per instance rather than per vertex
+                    Sprite::buffer_desc(1, pso::VertexInputRate::Instance(1)),
+                ],
+                attributes: [Vertex::attributes(0, 0), Sprite::attributes(1, 1)].concat(),
+            },
+            options,
+        )
+    }
+
+    // Draws the shared quad once per scene node, placed by
+    // a matrix rather than the mouse. Nodes with the same mesh
+    // are drawn together, with a world matrix per instance.
+    pub fn scene_nodes(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        sample_shading: Option<f32>,
+        logic_op: Option<pso::LogicOp>,
+        rasterizer: pso::Rasterizer,
+        depth_comparison: pso::Comparison,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                vert: "shaders/node_instanced_vert.glsl",
+                frag: "shaders/node_frag.glsl",
+                depth: Some(pso::DepthTest {
+                    fun: depth_comparison,
+                    write: true,
+                }),
+                samples,
+                sample_shading,
+                blend: blend_unless(&logic_op),
+                logic_op,
+                color_mask: pso::ColorMask::ALL,
+                rasterizer,
+                flip_y: options.flips_in_shader(),
+                overdraw: options.overdraw,
+                sets: vec![],
+                push_constants: &[
+                    (pso::ShaderStageFlags::VERTEX, NODE_VERTEX_PUSH_CONSTANTS),
+                    (
+                        pso::ShaderStageFlags::FRAGMENT,
+                        NODE_FRAGMENT_PUSH_CONSTANTS,
+                    ),
+                ],
+                vertex_buffers: vec![
+                    Vertex::buffer_desc(0, pso::VertexInputRate::Vertex),
+                    Mat4::buffer_desc(1, pso::VertexInputRate::Instance(1)),
+                ],
+                attributes: [Vertex::attributes(0, 0), Mat4::attributes(1, 1)].concat(),
+            },
+            options,
+        )
+    }
+
+    // The scene nodes' edges, drawn over their fill from the
+    // same buffers and instances in a color that stands out
+    pub fn node_wireframe(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        sample_shading: Option<f32>,
+        logic_op: Option<pso::LogicOp>,
+        rasterizer: pso::Rasterizer,
+        depth_comparison: pso::Comparison,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                vert: "shaders/node_instanced_vert.glsl",
+                frag: "shaders/node_wire_frag.glsl",
+                depth: Some(pso::DepthTest {
+                    fun: depth_comparison,
+                    write: true,
+                }),
+                samples,
+                sample_shading,
+                blend: blend_unless(&logic_op),
+                logic_op,
+                color_mask: pso::ColorMask::ALL,
+                rasterizer,
+                flip_y: options.flips_in_shader(),
+                overdraw: options.overdraw,
+                sets: vec![],
+                push_constants: &[
+                    (pso::ShaderStageFlags::VERTEX, NODE_VERTEX_PUSH_CONSTANTS),
+                    (
+                        pso::ShaderStageFlags::FRAGMENT,
+                        NODE_FRAGMENT_PUSH_CONSTANTS,
+                    ),
+                ],
+                vertex_buffers: vec![
+                    Vertex::buffer_desc(0, pso::VertexInputRate::Vertex),
+                    Mat4::buffer_desc(1, pso::VertexInputRate::Instance(1)),
+                ],
+                attributes: [Vertex::attributes(0, 0), Mat4::attributes(1, 1)].concat(),
+            },
+            options,
+        )
+    }
+
+    // The scene nodes again, writing each one's ID rather
+    // than a color. Always single sampled, since integer
+    // targets can't be averaged when resolving.
+    pub fn node_ids(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                vert: "shaders/node_vert.glsl",
+                frag: "shaders/node_id_frag.glsl",
+                depth: None,
+                samples: 1,
+                sample_shading: None,
+                // Integer formats can't be blended
+                blend: None,
+                logic_op: None,
+                color_mask: pso::ColorMask::ALL,
+                rasterizer: pso::Rasterizer::FILL,
+                // Matches the scene, so IDs line up with what's on screen
+                flip_y: options.flips_in_shader(),
+                // Integer targets can't be blended
+                overdraw: false,
+                sets: vec![],
+                push_constants: &[
+                    (pso::ShaderStageFlags::VERTEX, NODE_VERTEX_PUSH_CONSTANTS),
+                    (pso::ShaderStageFlags::FRAGMENT, NODE_ID_PUSH_CONSTANTS),
+                ],
+                vertex_buffers: vec![Vertex::buffer_desc(0, pso::VertexInputRate::Vertex)],
+                attributes: Vertex::attributes(0, 0),
+            },
+            options,
+        )
+    }
+
+    // A mesh loaded from a file, tinted by its material's base
+    // color and texture. Unlike everything else it has depth of its
+    // own, so it takes the configured test rather than the flat one,
+    // see GfxConfig::model_depth_test.
+    pub fn model(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        sample_shading: Option<f32>,
+        logic_op: Option<pso::LogicOp>,
+        rasterizer: pso::Rasterizer,
+        depth: pso::DepthTest,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                vert: "shaders/model_vert.glsl",
+                frag: "shaders/model_frag.glsl",
+                depth: Some(depth),
+                samples,
+                sample_shading,
+                blend: blend_unless(&logic_op),
+                logic_op,
+                color_mask: pso::ColorMask::ALL,
+                rasterizer,
+                flip_y: options.flips_in_shader(),
+                overdraw: options.overdraw,
+                // The base color texture
+                sets: vec![vec![pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::CombinedImageSampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                }]],
+                push_constants: &[
+                    (pso::ShaderStageFlags::VERTEX, MODEL_VERTEX_PUSH_CONSTANTS),
+                    (
+                        pso::ShaderStageFlags::FRAGMENT,
+                        MODEL_FRAGMENT_PUSH_CONSTANTS,
+                    ),
+                ],
+                vertex_buffers: vec![MeshVertex::buffer_desc(0, pso::VertexInputRate::Vertex)],
+                attributes: MeshVertex::attributes(0, 0),
+            },
+            options,
+        )
+    }
+
+    // The model's depth alone, drawn before it so only its nearest
+    // fragments are shaded, see GfxConfig::depth_prepass. The layout
+    // matches the model's, so it's drawn with the same set and constants.
+    pub fn model_depth(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        rasterizer: pso::Rasterizer,
+        depth_comparison: pso::Comparison,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                // The same vertex shader, so the depths match exactly
+                vert: "shaders/model_vert.glsl",
+                frag: "shaders/depth_only_frag.glsl",
+                depth: Some(pso::DepthTest {
+                    fun: depth_comparison,
+                    write: true,
+                }),
+                samples,
+                sample_shading: None,
+                blend: None,
+                logic_op: None,
+                color_mask: pso::ColorMask::NONE,
+                rasterizer,
+                flip_y: options.flips_in_shader(),
+                overdraw: false,
+                sets: vec![vec![pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::CombinedImageSampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                }]],
+                push_constants: &[
+                    (pso::ShaderStageFlags::VERTEX, MODEL_VERTEX_PUSH_CONSTANTS),
+                    (
+                        pso::ShaderStageFlags::FRAGMENT,
+                        MODEL_FRAGMENT_PUSH_CONSTANTS,
+                    ),
+                ],
+                vertex_buffers: vec![MeshVertex::buffer_desc(0, pso::VertexInputRate::Vertex)],
+                attributes: MeshVertex::attributes(0, 0),
+            },
+            options,
+        )
+    }
+
+    // The first triangle example, with a color per vertex
+    // that the rasterizer blends across the face.
+    // The shaders are the same whichever way the data is laid out.
+    pub fn colored_vertices(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        sample_shading: Option<f32>,
+        logic_op: Option<pso::LogicOp>,
+        rasterizer: pso::Rasterizer,
+        streams: VertexStreams,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        let rate = pso::VertexInputRate::Vertex;
+        let (vertex_buffers, attributes) = match streams {
+            VertexStreams::Interleaved => (
+                vec![ColorVertex::buffer_desc(0, rate)],
+                ColorVertex::attributes(0, 0),
+            ),
+            // Positions in binding zero, colors in binding one
+            VertexStreams::Separate => (
+                vec![Vec2::buffer_desc(0, rate), Vec4::buffer_desc(1, rate)],
+                [Vec2::attributes(0, 0), Vec4::attributes(1, 1)].concat(),
+            ),
+        };
+
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                vert: "shaders/triangle_vert.glsl",
+                frag: "shaders/triangle_frag.glsl",
+                depth: None,
+                samples,
+                sample_shading,
+                blend: blend_unless(&logic_op),
+                logic_op,
+                color_mask: pso::ColorMask::ALL,
+                rasterizer,
+                flip_y: options.flips_in_shader(),
+                overdraw: options.overdraw,
+                sets: vec![],
+                push_constants: &[],
+                vertex_buffers,
+                attributes,
+            },
+            options,
+        )
+    }
+
+    // Samples a single texture across the whole render area.
+    // Vertices are generated in the shader, so no buffers are bound.
+    pub fn textured_quad(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                vert: "shaders/blit_vert.glsl",
+                frag: "shaders/blit_frag.glsl",
+                depth: None,
+                samples,
+                sample_shading: None,
+                blend: Some(pso::BlendState::ALPHA),
+                logic_op: None,
+                color_mask: pso::ColorMask::ALL,
+                // The generated triangle winds clockwise,
+                // which doesn't matter without culling
+                rasterizer: pso::Rasterizer::FILL,
+                // The source was drawn through the same flipped
+                // viewport as the target, so the copy undoes it
+                flip_y: options.undoes_viewport(),
+                // Copies the heatmap rather than adding to it
+                overdraw: false,
+                sets: vec![vec![pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::CombinedImageSampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                }]],
+                push_constants: &[],
+                vertex_buffers: vec![],
+                attributes: vec![],
+            },
+            options,
+        )
+    }
+
+    // Fills the target with a gradient computed per pixel.
+    // Shares the fullscreen triangle with textured_quad.
+    pub fn gradient(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        Self::build(
+            device,
+            subpass,
+            PipelineParts {
+                vert: "shaders/blit_vert.glsl",
+                frag: "shaders/gradient_frag.glsl",
+                depth: None,
+                samples,
+                sample_shading: None,
+                // Opaque, it's the first thing drawn
+                blend: None,
+                logic_op: None,
+                color_mask: pso::ColorMask::ALL,
+                rasterizer: pso::Rasterizer::FILL,
+                // Colored by pixel position, which no flip changes
+                flip_y: false,
+                overdraw: options.overdraw,
+                // A uniform buffer per frame in flight, see FrameUniforms
+                sets: vec![vec![pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                }]],
+                push_constants: &[],
+                vertex_buffers: vec![],
+                attributes: vec![],
+            },
+            options,
+        )
+    }
+
+    fn build(
+        device: &back::Device,
+        subpass: Subpass<back::Backend>,
+        parts: PipelineParts,
+        options: PipelineOptions,
     ) -> Result<Self, &'static str> {
-        use std::mem::size_of;
+        check_limits(
+            parts.push_constants,
+            &parts.vertex_buffers,
+            &parts.attributes,
+            &options.limits,
+        )?;
+
+        // Every fragment counts, so nothing is depth tested
+        // and each adds the same small amount to the target
+        let parts = if parts.overdraw {
+            PipelineParts {
+                frag: OVERDRAW_FRAG,
+                depth: None,
+                blend: Some(pso::BlendState::ADD),
+                logic_op: None,
+                ..parts
+            }
+        } else {
+            parts
+        };
+
+        check_parts(&parts, options.y_flip)?;
+        let dynamic_line_width = matches!(
+            parts.rasterizer.polygon_mode,
+            pso::PolygonMode::Line(pso::State::Dynamic)
+        );
 
         let (vert, frag) = {
             let mut compiler = Compiler::new().ok_or("Failed to create shader compiler")?;
-            let mut compile = |src, kind| compile_shader(src, &mut compiler, &device, kind);
-            let vert = compile("shaders/vert.glsl", ShaderKind::Vertex)?;
-            let frag = compile("shaders/frag.glsl", ShaderKind::Fragment)?;
+            let mut compile =
+                |src, kind| compile_shader(src, &mut compiler, &device, kind, options.dump_spirv);
+            let vert = compile(parts.vert, ShaderKind::Vertex)?;
+            // Shaders may be reloaded while editing,
+            // so don't leak the vertex module on errors
+            let frag = match compile(parts.frag, ShaderKind::Fragment) {
+                Ok(frag) => frag,
+                Err(e) => {
+                    unsafe { device.destroy_shader_module(vert) }
+                    return Err(e);
+                }
+            };
             (vert, frag)
         };
 
-        // This machinery is only used when graphics pipeline data
-        // comes from somewhere other than the vertex buffer.
-        // We still have to explicitly declare all these empty
-        // bits and bobs.
-        let descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout> =
-            vec![unsafe {
+        let descriptor_ranges = parts
+            .sets
+            .iter()
+            .map(|bindings| {
+                bindings
+                    .iter()
+                    .map(|binding| pso::DescriptorRangeDesc {
+                        ty: binding.ty,
+                        count: binding.count,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut descriptor_set_layouts: Vec<<back::Backend as Backend>::DescriptorSetLayout> =
+            Vec::with_capacity(parts.sets.len());
+        for bindings in parts.sets {
+            let layout = unsafe {
                 device.create_descriptor_set_layout(
-                    Vec::<pso::DescriptorSetLayoutBinding>::new(),
+                    bindings,
                     Vec::<<back::Backend as Backend>::Sampler>::new(),
                 )
+            };
+            match layout {
+                Ok(layout) => descriptor_set_layouts.push(layout),
+                Err(_) => {
+                    for layout in descriptor_set_layouts {
+                        unsafe { device.destroy_descriptor_set_layout(layout) }
+                    }
+                    unsafe {
+                        device.destroy_shader_module(vert);
+                        device.destroy_shader_module(frag);
+                    }
+                    return Err("Failed to create a descriptor set layout");
+                }
             }
-            .map_err(|_| "Failed to create a descriptor set layout")?];
-
-        let layout = unsafe {
-            device.create_pipeline_layout(
-                &descriptor_set_layouts,
-                Vec::<(pso::ShaderStageFlags, Range<u32>)>::new(),
-            )
         }
-        .map_err(|_| "Failed to create a pipeline layout")?;
 
+        let layout =
+            unsafe { device.create_pipeline_layout(&descriptor_set_layouts, parts.push_constants) }
+                .map_err(|_| "Failed to create a pipeline layout")?;
+
+        // Booleans are 32 bits in SPIR-V
+        let flip_y = (parts.flip_y as u32).to_ne_bytes();
         let handle = unsafe {
             device.create_graphics_pipeline(
                 &pso::GraphicsPipelineDesc {
@@ -54,8 +740,15 @@ impl PipelineInfo {
                         vertex: pso::EntryPoint {
                             entry: "main",
                             module: &vert,
-                            // Not sure what this is used for
-                            specialization: pso::Specialization::EMPTY,
+                            // Fills in constants the shader declared with
+                            // constant_id, before the driver compiles it
+                            specialization: pso::Specialization {
+                                constants: Cow::Borrowed(&[pso::SpecializationConstant {
+                                    id: 0,
+                                    range: 0..4,
+                                }]),
+                                data: Cow::Borrowed(&flip_y),
+                            },
                         },
                         domain: None,
                         geometry: None,
@@ -67,25 +760,8 @@ impl PipelineInfo {
                         }),
                     },
 
-                    vertex_buffers: vec![pso::VertexBufferDesc {
-                        // Not the location listed on the shader,
-                        // this is just a unique id for the buffer
-                        binding: 0,
-                        stride: (size_of::<f32>() * 2) as u32,
-                        rate: pso::VertexInputRate::Vertex,
-                    }],
-
-                    attributes: vec![pso::AttributeDesc {
-                        // This is the attribute location in the shader
-                        location: 0,
-                        // Matches vertex buffer description
-                        binding: 0,
-                        element: pso::Element {
-                            // Float vec2
-                            format: Format::Rg32Sfloat,
-                            offset: 0,
-                        },
-                    }],
+                    vertex_buffers: parts.vertex_buffers,
+                    attributes: parts.attributes,
 
                     input_assembler: pso::InputAssemblerDesc {
                         primitive: pso::Primitive::TriangleList,
@@ -94,31 +770,42 @@ impl PipelineInfo {
                     },
 
                     blender: pso::BlendDesc {
-                        logic_op: Some(pso::LogicOp::Copy),
+                        logic_op: parts.logic_op,
                         targets: vec![pso::ColorBlendDesc {
-                            mask: pso::ColorMask::ALL,
-                            blend: Some(pso::BlendState::ALPHA),
+                            mask: parts.color_mask,
+                            blend: parts.blend,
                         }],
                     },
 
                     depth_stencil: pso::DepthStencilDesc {
-                        depth: None,
+                        depth: parts.depth,
                         depth_bounds: false,
                         stencil: None,
                     },
 
-                    multisampling: None,
+                    multisampling: if parts.samples > 1 {
+                        Some(pso::Multisampling {
+                            rasterization_samples: parts.samples,
+                            sample_shading: parts
+                                .sample_shading
+                                .map(|fraction| fraction.max(0.0).min(1.0)),
+                            sample_mask: !0,
+                            alpha_coverage: false,
+                            alpha_to_one: false,
+                        })
+                    } else {
+                        None
+                    },
+                    // Viewport and scissor are left dynamic, set per
+                    // pass so the scene can draw into part of the target
                     baked_states: pso::BakedStates {
-                        viewport: Some(pso::Viewport {
-                            rect: content_size,
-                            depth: 0.0..1.0,
-                        }),
-                        scissor: Some(content_size),
+                        viewport: None,
+                        scissor: None,
                         blend_color: None,
                         depth_bounds: None,
                     },
 
-                    rasterizer: pso::Rasterizer::FILL,
+                    rasterizer: parts.rasterizer,
                     layout: &layout,
                     subpass: subpass,
                     flags: pso::PipelineCreationFlags::empty(),
@@ -136,12 +823,38 @@ impl PipelineInfo {
         }
 
         Ok(Self {
+            descriptor_ranges,
             descriptor_set_layouts,
             layout: ManuallyDrop::new(layout),
             handle: ManuallyDrop::new(handle),
+            dynamic_line_width,
         })
     }
 
+    pub fn allocate_set(
+        &self,
+        device: &back::Device,
+        descriptors: &mut DescriptorAllocator,
+        set: usize,
+    ) -> Result<DescriptorAllocation, &'static str> {
+        let layout = self
+            .descriptor_set_layouts
+            .get(set)
+            .ok_or("Pipeline has no descriptor set at that index")?;
+        descriptors.allocate(device, layout, &self.descriptor_ranges[set])
+    }
+
+    // Binding a set leaves the ones before it bound,
+    // so each can change without rebinding the others
+    pub unsafe fn bind_set(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        index: usize,
+        set: &<back::Backend as Backend>::DescriptorSet,
+    ) {
+        commands.bind_graphics_descriptor_sets(&self.layout, index, Some(set), &[]);
+    }
+
     pub fn free(&mut self, device: &back::Device) {
         use std::ptr::read;
 
@@ -156,11 +869,82 @@ impl PipelineInfo {
     }
 }
 
-fn compile_shader(
+fn check_limits(
+    push_constants: &[(pso::ShaderStageFlags, Range<u32>)],
+    vertex_buffers: &[pso::VertexBufferDesc],
+    attributes: &[pso::AttributeDesc],
+    limits: &PipelineLimits,
+) -> Result<(), &'static str> {
+    let push_constants_end = push_constants.iter().map(|(_, range)| range.end).max();
+    if push_constants_end.map_or(false, |end| end as usize > limits.push_constants_size) {
+        return Err("Push constant ranges exceed the device limit");
+    }
+    if vertex_buffers.len() > limits.vertex_bindings {
+        return Err("More vertex buffers than the device supports");
+    }
+    if attributes.len() > limits.vertex_attributes {
+        return Err("More vertex attributes than the device supports");
+    }
+    let stride = vertex_buffers.iter().map(|buffer| buffer.stride).max();
+    if stride.map_or(false, |stride| stride as usize > limits.vertex_stride) {
+        return Err("Vertex stride exceeds the device limit");
+    }
+    Ok(())
+}
+
+// Which way the meshes wind once drawn. A flip in the
+// shader that undoes the viewport's cancels out.
+// What can be caught before compiling anything
+fn check_parts(parts: &PipelineParts, y_flip: YFlip) -> Result<(), &'static str> {
+    // Every generated mesh would vanish, the classic
+    // mesh that disappears when culling is turned on
+    let winding = drawn_winding(y_flip, parts.flip_y);
+    if vertex::culls(&parts.rasterizer, winding) {
+        return Err("Culling would hide front faces, check the configured winding");
+    }
+
+    if parts.logic_op.is_some() && parts.blend.is_some() {
+        return Err("Logic ops need blending turned off");
+    }
+
+    // Reading past the end of a vertex would give garbage
+    for attribute in &parts.attributes {
+        let stride = parts
+            .vertex_buffers
+            .iter()
+            .find(|buffer| buffer.binding == attribute.binding)
+            .ok_or("Vertex attribute refers to a missing buffer")?
+            .stride;
+        let size = attribute.element.format.surface_desc().bits as u32 / 8;
+        if attribute.element.offset + size > stride {
+            return Err("Vertex attribute extends past the buffer stride");
+        }
+    }
+    Ok(())
+}
+
+fn drawn_winding(y_flip: YFlip, flip_in_shader: bool) -> pso::FrontFace {
+    if (y_flip == YFlip::Viewport) != flip_in_shader {
+        vertex::mirrored(vertex::WINDING)
+    } else {
+        vertex::WINDING
+    }
+}
+
+// Alpha blending, unless a logic op takes its place
+fn blend_unless(logic_op: &Option<pso::LogicOp>) -> Option<pso::BlendState> {
+    match logic_op {
+        Some(_) => None,
+        None => Some(pso::BlendState::ALPHA),
+    }
+}
+
+pub fn compile_shader(
     src_file: &str,
     compiler: &mut Compiler,
     device: &back::Device,
     kind: ShaderKind,
+    dump_spirv: bool,
 ) -> Result<<back::Backend as Backend>::ShaderModule, &'static str> {
     let src = std::fs::read_to_string(src_file).map_err(|_| "Could not read shader source file")?;
     let spirv = compiler
@@ -169,6 +953,305 @@ fn compile_shader(
             log::error!("{}", e);
             "Failed to compile fragment program"
         })?;
+    // The binary that gets used, for spirv-dis or spirv-cross
+    if dump_spirv {
+        let path = format!("{}.spv", src_file);
+        match std::fs::write(&path, spirv.as_binary_u8()) {
+            Ok(()) => log::info!("Wrote {}", path),
+            Err(_) => log::warn!("Could not write {}", path),
+        }
+    }
     unsafe { device.create_shader_module(spirv.as_binary()) }
         .map_err(|_| "Failed to create shader module")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        draw_batch,
+        drawing::{self, DrawCommand},
+        headless::{self, Headless},
+        utils::{QUAD_DATA, QUAD_INDICES},
+        Batch, BufferInfo, GfxConfig, InstanceTransforms, MeshDraw,
+    };
+    use gfx_hal::{buffer::Usage, Features};
+
+    fn check(limits: PipelineLimits) -> Result<(), &'static str> {
+        check_limits(
+            &[
+                (pso::ShaderStageFlags::VERTEX, VERTEX_PUSH_CONSTANTS),
+                (pso::ShaderStageFlags::FRAGMENT, FRAGMENT_PUSH_CONSTANTS),
+            ],
+            &[
+                Vertex::buffer_desc(0, pso::VertexInputRate::Vertex),
+                Sprite::buffer_desc(1, pso::VertexInputRate::Instance(1)),
+            ],
+            &[Vertex::attributes(0, 0), Sprite::attributes(1, 1)].concat(),
+            &limits,
+        )
+    }
+
+    #[test]
+    fn fits_the_guaranteed_limits() {
+        assert!(check(PipelineLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn low_limits_are_caught() {
+        let limits = PipelineLimits::default();
+        assert!(check(PipelineLimits {
+            push_constants_size: FRAGMENT_PUSH_CONSTANTS.end as usize - 1,
+            ..limits
+        })
+        .is_err());
+        assert!(check(PipelineLimits {
+            vertex_bindings: 1,
+            ..limits
+        })
+        .is_err());
+        assert!(check(PipelineLimits {
+            vertex_attributes: 1,
+            ..limits
+        })
+        .is_err());
+        assert!(check(PipelineLimits {
+            vertex_stride: 4,
+            ..limits
+        })
+        .is_err());
+    }
+
+    fn parts(
+        blend: Option<pso::BlendState>,
+        logic_op: Option<pso::LogicOp>,
+    ) -> PipelineParts<'static> {
+        PipelineParts {
+            vert: "shaders/blit_vert.glsl",
+            frag: "shaders/gradient_frag.glsl",
+            depth: None,
+            samples: 1,
+            sample_shading: None,
+            blend,
+            logic_op,
+            color_mask: pso::ColorMask::ALL,
+            rasterizer: pso::Rasterizer::FILL,
+            flip_y: false,
+            overdraw: false,
+            sets: vec![],
+            push_constants: &[],
+            vertex_buffers: vec![],
+            attributes: vec![],
+        }
+    }
+
+    #[test]
+    fn logic_ops_replace_blending() {
+        let xor = Some(pso::LogicOp::Xor);
+        assert_eq!(
+            check_parts(
+                &parts(Some(pso::BlendState::ALPHA), xor.clone()),
+                YFlip::Off
+            ),
+            Err("Logic ops need blending turned off")
+        );
+        assert!(check_parts(&parts(blend_unless(&xor), xor), YFlip::Off).is_ok());
+        assert!(check_parts(&parts(blend_unless(&None), None), YFlip::Off).is_ok());
+    }
+
+    #[test]
+    fn push_constant_ranges_are_disjoint_and_fit() {
+        let pairs = [
+            (VERTEX_PUSH_CONSTANTS, FRAGMENT_PUSH_CONSTANTS),
+            (NODE_VERTEX_PUSH_CONSTANTS, NODE_FRAGMENT_PUSH_CONSTANTS),
+            (NODE_VERTEX_PUSH_CONSTANTS, NODE_ID_PUSH_CONSTANTS),
+            (MODEL_VERTEX_PUSH_CONSTANTS, MODEL_FRAGMENT_PUSH_CONSTANTS),
+        ];
+        let limit = PipelineLimits::default().push_constants_size as u32;
+        for (vertex, fragment) in pairs.iter() {
+            assert!(vertex.end <= fragment.start, "{:?} {:?}", vertex, fragment);
+            assert!(fragment.end <= limit, "{:?}", fragment);
+            for range in &[vertex, fragment] {
+                assert_eq!(range.start % 4, 0);
+                assert_eq!(range.end % 4, 0);
+            }
+        }
+        // The resolution sits inside the fragment range, after the tint
+        assert!(FRAGMENT_PUSH_CONSTANTS.start + 16 <= RESOLUTION_OFFSET);
+        assert!(RESOLUTION_OFFSET + 8 <= FRAGMENT_PUSH_CONSTANTS.end);
+        assert!(SPRITE_PUSH_CONSTANTS.end <= limit);
+    }
+
+    fn options(y_flip: YFlip) -> PipelineOptions {
+        PipelineOptions {
+            y_flip,
+            ..PipelineOptions::default()
+        }
+    }
+
+    #[test]
+    fn both_flips_mirror_the_scene() {
+        for &y_flip in [YFlip::Viewport, YFlip::Shader].iter() {
+            let options = options(y_flip);
+            assert_eq!(
+                drawn_winding(y_flip, options.flips_in_shader()),
+                vertex::mirrored(vertex::WINDING)
+            );
+        }
+        let off = options(YFlip::Off);
+        assert_eq!(
+            drawn_winding(YFlip::Off, off.flips_in_shader()),
+            vertex::WINDING
+        );
+    }
+
+    #[test]
+    fn window_space_is_never_flipped() {
+        for &y_flip in [YFlip::Off, YFlip::Viewport, YFlip::Shader].iter() {
+            let options = options(y_flip);
+            assert_eq!(
+                drawn_winding(y_flip, options.undoes_viewport()),
+                vertex::WINDING
+            );
+        }
+    }
+
+    #[test]
+    #[ignore] // Needs a Vulkan device
+    fn overdraw_brightens_overlaps() {
+        let mut headless = Headless::new(32, 32, Features::empty());
+        let options = PipelineOptions {
+            overdraw: true,
+            ..PipelineOptions::default()
+        };
+        let mut pipeline = PipelineInfo::scene_nodes(
+            &headless.device,
+            Subpass {
+                index: 0,
+                main_pass: headless.render_pass(),
+            },
+            1,
+            None,
+            None,
+            pso::Rasterizer::FILL,
+            pso::Comparison::Less,
+            options,
+        )
+        .unwrap();
+
+        let device = &headless.device;
+        let adapter = &headless.adapter;
+        let mut vertices = BufferInfo::new(device, adapter, &QUAD_DATA, Usage::VERTEX).unwrap();
+        vertices.load_data(device, &QUAD_DATA).unwrap();
+        let mut indices = BufferInfo::new(device, adapter, &QUAD_INDICES, Usage::INDEX).unwrap();
+        indices.load_data(device, &QUAD_INDICES).unwrap();
+        // Side by side, overlapping across the middle quarter
+        let draws: Vec<MeshDraw> = [-0.25, 0.25]
+            .iter()
+            .map(|&x| MeshDraw {
+                pipeline: &pipeline,
+                mesh: DrawCommand::QUAD,
+                transform: Mat4::translation(Vec2 { x, y: 0.0 }),
+            })
+            .collect();
+        let (batches, transforms) = draw_batch::batch(&draws);
+        let mut instances = InstanceTransforms::default();
+        instances
+            .upload(device, adapter, 0, 1, &transforms)
+            .unwrap();
+
+        let area = headless.area();
+        let pixels = headless.draw(Vec4::new(0.0, 0.0, 0.0, 1.0), |commands| unsafe {
+            drawing::record_nodes(
+                commands,
+                &batches,
+                &vertices,
+                &indices,
+                &instances,
+                0,
+                Vec4::new(1.0, 1.0, 1.0, 1.0),
+                area,
+            )
+        });
+        let both = headless::pixel(&pixels, 32, 16, 16);
+        let one = headless::pixel(&pixels, 32, 6, 16);
+        let none = headless::pixel(&pixels, 32, 30, 16);
+        assert!(both[0] > one[0], "{:?} {:?}", both, one);
+        assert!(one[0] > none[0], "{:?} {:?}", one, none);
+
+        instances.free(&headless.device);
+        vertices.free(&headless.device);
+        indices.free(&headless.device);
+        pipeline.free(&headless.device);
+        headless.free();
+    }
+
+    #[test]
+    #[ignore] // Needs a Vulkan device with wideLines
+    fn wireframe_width_is_set_per_draw() {
+        let mut headless =
+            Headless::new(32, 32, Features::FILL_MODE_NON_SOLID | Features::WIDE_LINES);
+        let mut pipeline = PipelineInfo::node_wireframe(
+            &headless.device,
+            Subpass {
+                index: 0,
+                main_pass: headless.render_pass(),
+            },
+            1,
+            None,
+            None,
+            GfxConfig::default().wireframe_rasterizer(),
+            pso::Comparison::Less,
+            PipelineOptions::default(),
+        )
+        .unwrap();
+        assert!(pipeline.dynamic_line_width);
+
+        let device = &headless.device;
+        let adapter = &headless.adapter;
+        let mut vertices = BufferInfo::new(device, adapter, &QUAD_DATA, Usage::VERTEX).unwrap();
+        vertices.load_data(device, &QUAD_DATA).unwrap();
+        let mut indices = BufferInfo::new(device, adapter, &QUAD_INDICES, Usage::INDEX).unwrap();
+        indices.load_data(device, &QUAD_INDICES).unwrap();
+        let mut instances = InstanceTransforms::default();
+        instances
+            .upload(device, adapter, 0, 1, &[Mat4::IDENTITY])
+            .unwrap();
+
+        let area = headless.area();
+        let mut lit = |line_width: f32| {
+            let batches = [Batch {
+                pipeline: &pipeline,
+                mesh: DrawCommand {
+                    line_width,
+                    ..DrawCommand::QUAD
+                },
+                instances: 0..1,
+            }];
+            // Black fill, so the edges come out white
+            let pixels = headless.draw(Vec4::new(0.0, 0.0, 0.0, 1.0), |commands| unsafe {
+                drawing::record_nodes(
+                    commands,
+                    &batches,
+                    &vertices,
+                    &indices,
+                    &instances,
+                    0,
+                    Vec4::new(0.0, 0.0, 0.0, 1.0),
+                    area,
+                )
+            });
+            pixels.chunks(4).filter(|pixel| pixel[0] > 128).count()
+        };
+        let thin = lit(1.0);
+        let wide = lit(4.0);
+        assert!(thin > 0);
+        assert!(wide > thin * 2, "{} {}", wide, thin);
+
+        instances.free(&headless.device);
+        vertices.free(&headless.device);
+        indices.free(&headless.device);
+        pipeline.free(&headless.device);
+        headless.free();
+    }
+}
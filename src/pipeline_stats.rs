@@ -0,0 +1,173 @@
+use gfx_backend_vulkan as back;
+use gfx_hal::{command::CommandBuffer, device::Device, query, Backend};
+use std::{
+    fmt,
+    mem::{self, size_of, ManuallyDrop},
+    ptr,
+};
+
+// One 64 bit counter for each statistic queried
+const COUNTERS: usize = 3;
+const RESULT_SIZE: usize = COUNTERS * size_of::<u64>();
+
+// Counted across every draw in a frame
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PipelineStats {
+    pub primitives: u64,
+    pub vertex_invocations: u64,
+    pub fragment_invocations: u64,
+}
+
+impl PipelineStats {
+    // Reads one query's 64 bit counters
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < RESULT_SIZE {
+            return None;
+        }
+
+        const N: usize = size_of::<u64>();
+        let counter = |i: usize| {
+            let mut bytes = [0; N];
+            bytes.copy_from_slice(&data[i * N..(i + 1) * N]);
+            u64::from_ne_bytes(bytes)
+        };
+        Some(Self {
+            primitives: counter(0),
+            vertex_invocations: counter(1),
+            fragment_invocations: counter(2),
+        })
+    }
+}
+
+impl fmt::Display for PipelineStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Primitives: {}", self.primitives)?;
+        writeln!(f, "Vertex shader invocations: {}", self.vertex_invocations)?;
+        writeln!(
+            f,
+            "Fragment shader invocations: {}",
+            self.fragment_invocations
+        )
+    }
+}
+
+// A query per frame in flight, read back once
+// the frame's fence shows the GPU is done with it
+pub struct StatsQueries {
+    pub pool: ManuallyDrop<<back::Backend as Backend>::QueryPool>,
+    // Queries start out undefined until they are first reset
+    recorded: Vec<bool>,
+    // The most recent frame to finish
+    pub last: Option<PipelineStats>,
+}
+
+impl StatsQueries {
+    pub fn new(device: &back::Device, frames: usize) -> Result<Self, &'static str> {
+        // Results come back in bit order, so the fields
+        // of PipelineStats have to follow it
+        let statistics = query::PipelineStatistic::INPUT_ASSEMBLY_PRIMITIVES
+            | query::PipelineStatistic::VERTEX_SHADER_INVOCATIONS
+            | query::PipelineStatistic::FRAGMENT_SHADER_INVOCATIONS;
+        let pool = unsafe {
+            device.create_query_pool(query::Type::PipelineStatistics(statistics), frames as u32)
+        }
+        .map_err(|_| "Could not create pipeline statistics query pool")?;
+
+        Ok(Self {
+            pool: ManuallyDrop::new(pool),
+            recorded: vec![false; frames],
+            last: None,
+        })
+    }
+
+    // Call once the frame's fence has been waited on
+    pub fn read(&mut self, device: &back::Device, frame: usize) {
+        // Each result is only read once, so a frame that
+        // stops recording queries never repeats old numbers
+        if !mem::replace(&mut self.recorded[frame], false) {
+            return;
+        }
+
+        let mut data = [0u8; RESULT_SIZE];
+        let ready = unsafe {
+            device.get_query_pool_results(
+                &self.pool,
+                frame as u32..frame as u32 + 1,
+                &mut data,
+                RESULT_SIZE as _,
+                query::ResultFlags::BITS_64,
+            )
+        };
+        if let Ok(true) = ready {
+            self.last = PipelineStats::from_bytes(&data);
+        }
+    }
+
+    // Recorded at the top of the frame's command buffer. Covers only
+    // the frame's own query, since other frames may still be using theirs.
+    pub unsafe fn reset(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        frame: usize,
+    ) {
+        let id = frame as u32;
+        commands.reset_query_pool(&self.pool, id..id + 1);
+    }
+
+    // Must be recorded outside a render pass, after the reset
+    pub unsafe fn begin(
+        &mut self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        frame: usize,
+    ) {
+        commands.begin_query(
+            query::Query {
+                pool: &self.pool,
+                id: frame as u32,
+            },
+            query::ControlFlags::empty(),
+        );
+        self.recorded[frame] = true;
+    }
+
+    pub unsafe fn end(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        frame: usize,
+    ) {
+        commands.end_query(query::Query {
+            pool: &self.pool,
+            id: frame as u32,
+        });
+    }
+
+    pub fn free(&mut self, device: &back::Device) {
+        unsafe { device.destroy_query_pool(ManuallyDrop::into_inner(ptr::read(&self.pool))) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_counters_in_order() {
+        let data: Vec<u8> = [6u64, 4, 1234]
+            .iter()
+            .flat_map(|counter| counter.to_ne_bytes().to_vec())
+            .collect();
+        assert_eq!(
+            PipelineStats::from_bytes(&data),
+            Some(PipelineStats {
+                primitives: 6,
+                vertex_invocations: 4,
+                fragment_invocations: 1234,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_short_results() {
+        assert_eq!(PipelineStats::from_bytes(&[0; RESULT_SIZE - 1]), None);
+    }
+}
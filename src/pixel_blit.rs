@@ -0,0 +1,149 @@
+use crate::{
+    debug_names::set_debug_name,
+    descriptor::{DescriptorAllocation, DescriptorAllocator},
+    image_info::SamplerPreset,
+    swapchain::FORMAT,
+    BufferInfo, Framebuffer2D, ImageInfo, PipelineInfo, PipelineOptions,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter,
+    buffer,
+    command::CommandBuffer,
+    device::Device,
+    image,
+    pass::Subpass,
+    pso::{self, PipelineStage},
+    Backend,
+};
+
+// Shows a CPU framebuffer one texel per pixel over the scene,
+// uploading it every frame before the render pass.
+// Transparent pixels let the scene show through.
+pub struct PixelBlit {
+    pub framebuffer: Framebuffer2D,
+    pub image: ImageInfo,
+    // A region for each frame in flight, so the CPU can write
+    // one frame's pixels while another is still being copied
+    pub staging: BufferInfo,
+    // Draws the image in the swapchain's render pass
+    pub pipeline: PipelineInfo,
+    pub descriptor_set: DescriptorAllocation,
+}
+
+impl PixelBlit {
+    pub fn new(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        subpass: Subpass<back::Backend>,
+        samples: image::NumSamples,
+        framebuffer: Framebuffer2D,
+        frames: usize,
+        descriptors: &mut DescriptorAllocator,
+        options: PipelineOptions,
+    ) -> Result<Self, &'static str> {
+        let extent = image::Extent {
+            width: framebuffer.width,
+            height: framebuffer.height,
+            depth: 1,
+        };
+
+        // The swapchain format, so bytes come out as they went in
+        let mut image = ImageInfo::new(
+            device,
+            adapter,
+            extent,
+            FORMAT,
+            image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+        )?;
+        set_debug_name(device, &mut *image.image, "pixel_image");
+        // One texel per pixel, so filtering would only blur it
+        image.set_sampler(device, SamplerPreset::Nearest, 1.0)?;
+
+        let mut staging = BufferInfo::with_size(
+            device,
+            adapter,
+            (framebuffer.pixels.len() * frames) as u64,
+            buffer::Usage::TRANSFER_SRC,
+        )?;
+        staging.map_persistent(device)?;
+        set_debug_name(device, &mut *staging.buffer, "pixel_staging");
+
+        let pipeline = PipelineInfo::textured_quad(device, subpass, samples, options)?;
+        let descriptor_set = pipeline.allocate_set(device, descriptors, 0)?;
+
+        unsafe {
+            device.write_descriptor_sets(vec![pso::DescriptorSetWrite {
+                set: &descriptor_set.set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::CombinedImageSampler(
+                    &*image.image_view,
+                    image::Layout::ShaderReadOnlyOptimal,
+                    &*image.sampler,
+                )),
+            }]);
+        }
+
+        Ok(Self {
+            framebuffer,
+            image,
+            staging,
+            pipeline,
+            descriptor_set,
+        })
+    }
+
+    // Copies the frame's pixels into its region of the staging buffer,
+    // once the frame's fence says nothing is reading it
+    pub fn write(&self, device: &back::Device, frame: usize) -> Result<(), &'static str> {
+        self.staging
+            .write(device, self.offset(frame), &self.framebuffer.pixels)
+    }
+
+    // Copies the written pixels to the image.
+    // Must be recorded outside of a render pass.
+    pub unsafe fn record_upload(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        frame: usize,
+    ) {
+        // The previous frame may still be sampling the image
+        self.image.record_copy(
+            commands,
+            &self.staging.buffer,
+            self.offset(frame),
+            self.framebuffer.pixels.len() as u64,
+            1,
+            PipelineStage::FRAGMENT_SHADER,
+        );
+    }
+
+    // Draws into whichever render pass has been begun
+    pub unsafe fn record_blit(&self, commands: &mut <back::Backend as Backend>::CommandBuffer) {
+        commands.bind_graphics_pipeline(&self.pipeline.handle);
+        self.pipeline
+            .bind_set(commands, 0, &self.descriptor_set.set);
+        // Fullscreen triangle
+        commands.draw(0..3, 0..1);
+    }
+
+    fn offset(&self, frame: usize) -> u64 {
+        (self.framebuffer.pixels.len() * frame) as u64
+    }
+
+    // Hands back the pixels so they survive a resize
+    pub fn free(
+        mut self,
+        device: &back::Device,
+        descriptors: &mut DescriptorAllocator,
+    ) -> Framebuffer2D {
+        if let Err(e) = descriptors.free(self.descriptor_set) {
+            log::error!("{}", e);
+        }
+        self.pipeline.free(device);
+        self.staging.free(device);
+        self.image.free(device);
+        self.framebuffer
+    }
+}
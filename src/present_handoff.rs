@@ -0,0 +1,120 @@
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    command::{CommandBuffer, CommandBufferFlags, Level},
+    device::Device,
+    format::Aspects,
+    image::{Access, Layout, SubresourceRange},
+    memory::{Barrier, Dependencies},
+    pool::{CommandPool, CommandPoolCreateFlags},
+    pso::PipelineStage,
+    queue::{CommandQueue, QueueFamilyId, Submission},
+    Backend,
+};
+use std::{iter, mem::ManuallyDrop, ops::Range, ptr};
+
+// Moves each swapchain image from the graphics family to the present
+// family once it's drawn. The images belong to one family at a time,
+// and gfx-hal can't create them shared between families, so the graphics
+// queue releases the image and the present queue acquires it with a
+// matching barrier before presenting. Nothing brings them back, since
+// the graphics queue gets them with their contents discarded.
+pub struct PresentHandoff {
+    pub families: Range<QueueFamilyId>,
+    pool: ManuallyDrop<<back::Backend as Backend>::CommandPool>,
+    // The acquire for each swapchain image, recorded once
+    acquires: Vec<<back::Backend as Backend>::CommandBuffer>,
+}
+
+impl PresentHandoff {
+    pub fn new(
+        device: &back::Device,
+        families: Range<QueueFamilyId>,
+        images: &[<back::Backend as Backend>::Image],
+    ) -> Result<Self, &'static str> {
+        // Rerecorded in one go when the swapchain changes
+        let pool =
+            unsafe { device.create_command_pool(families.end, CommandPoolCreateFlags::empty()) }
+                .map_err(|_| "Could not create present command pool")?;
+        let mut handoff = Self {
+            families,
+            pool: ManuallyDrop::new(pool),
+            acquires: vec![],
+        };
+        handoff.record(images);
+        Ok(handoff)
+    }
+
+    // Only once nothing in flight uses the old buffers
+    pub fn record(&mut self, images: &[<back::Backend as Backend>::Image]) {
+        unsafe { self.pool.reset(false) };
+        unsafe { self.pool.free(self.acquires.drain(..)) };
+        for image in images {
+            let mut commands = unsafe { self.pool.allocate_one(Level::Primary) };
+            unsafe {
+                // Submitted again while earlier frames may still be pending
+                commands.begin_primary(CommandBufferFlags::SIMULTANEOUS_USE);
+                commands.pipeline_barrier(
+                    PipelineStage::TOP_OF_PIPE..PipelineStage::BOTTOM_OF_PIPE,
+                    Dependencies::empty(),
+                    iter::once(self.barrier(image)),
+                );
+                commands.finish();
+            }
+            self.acquires.push(commands);
+        }
+    }
+
+    // Goes after the render pass that leaves the image ready to present.
+    // The layout was already changed there, so this only hands it over.
+    pub unsafe fn record_release(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        image: &<back::Backend as Backend>::Image,
+    ) {
+        commands.pipeline_barrier(
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::BOTTOM_OF_PIPE,
+            Dependencies::empty(),
+            iter::once(self.barrier(image)),
+        );
+    }
+
+    // Waits for the render to finish, then acquires the image on the
+    // present queue and signals the semaphore presentation waits on
+    pub fn submit_acquire(
+        &self,
+        queue: &mut <back::Backend as Backend>::CommandQueue,
+        image_i: usize,
+        render_finished: &<back::Backend as Backend>::Semaphore,
+        handed_off: &<back::Backend as Backend>::Semaphore,
+    ) {
+        let submission = Submission {
+            command_buffers: Some(&self.acquires[image_i]),
+            wait_semaphores: Some((render_finished, PipelineStage::TOP_OF_PIPE)),
+            signal_semaphores: Some(handed_off),
+        };
+        unsafe { queue.submit(submission, None) };
+    }
+
+    pub fn free(self, device: &back::Device) {
+        // Destroying the pool frees its buffers too
+        unsafe { device.destroy_command_pool(ManuallyDrop::into_inner(ptr::read(&self.pool))) }
+    }
+
+    // The release and the acquire have to match exactly
+    fn barrier<'a>(
+        &self,
+        image: &'a <back::Backend as Backend>::Image,
+    ) -> Barrier<'a, back::Backend> {
+        Barrier::Image {
+            states: (Access::COLOR_ATTACHMENT_WRITE, Layout::Present)
+                ..(Access::empty(), Layout::Present),
+            target: image,
+            families: Some(self.families.clone()),
+            range: SubresourceRange {
+                aspects: Aspects::COLOR,
+                levels: 0..1,
+                layers: 0..1,
+            },
+        }
+    }
+}
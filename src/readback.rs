@@ -0,0 +1,295 @@
+use crate::{BufferInfo, ImageInfo};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter,
+    buffer,
+    command::{self, CommandBuffer, Level},
+    device::Device,
+    format,
+    image::{self, Layout},
+    memory::{Barrier, Dependencies},
+    pool::{CommandPool, CommandPoolCreateFlags},
+    pso::PipelineStage,
+    queue::{CommandQueue, QueueGroup},
+    Backend,
+};
+use std::{mem::ManuallyDrop, ptr};
+
+pub enum ReadbackStatus {
+    Pending,
+    // Tightly packed rows from the top
+    Ready(Vec<u8>),
+}
+
+// A copy from the GPU that's submitted right away and collected on
+// a later frame, so the render loop never waits on it. Has its own
+// pool and fence, so it can be freed without the frame's.
+pub struct ReadbackHandle {
+    pub extent: image::Extent,
+    pool: ManuallyDrop<<back::Backend as Backend>::CommandPool>,
+    fence: ManuallyDrop<<back::Backend as Backend>::Fence>,
+    buffer: BufferInfo,
+    size: usize,
+    // Set once the copy is on the queue
+    submitted: bool,
+    // Set once the data has been handed out
+    taken: bool,
+}
+
+impl ReadbackHandle {
+    // Copies the first mip level and layer of a color image.
+    // The image is left in the layout it was in, which is what
+    // it must be in by the time earlier submissions finish.
+    pub fn image(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        queue_group: &mut QueueGroup<back::Backend>,
+        source: &ImageInfo,
+        layout: Layout,
+    ) -> Result<Self, &'static str> {
+        let mut handle = Self::new(device, adapter, queue_group, source.format, source.extent)?;
+        unsafe {
+            let mut commands = handle.pool.allocate_one(Level::Primary);
+            commands.begin_primary(command::CommandBufferFlags::ONE_TIME_SUBMIT);
+            handle.record_copy(&mut commands, &source.image, layout);
+            commands.finish();
+
+            queue_group.queues[0].submit_without_semaphores(Some(&commands), Some(&*handle.fence));
+        }
+        handle.submitted = true;
+        Ok(handle)
+    }
+
+    // Copies a swapchain image, which only exists for the frame
+    // that acquires it, so the copy is recorded into that frame
+    // with record_frame_copy and submitted with submit_after.
+    pub fn swapchain(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        queue_group: &QueueGroup<back::Backend>,
+        format: format::Format,
+        extent: image::Extent,
+    ) -> Result<Self, &'static str> {
+        Self::new(device, adapter, queue_group, format, extent)
+    }
+
+    fn new(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        queue_group: &QueueGroup<back::Backend>,
+        format: format::Format,
+        extent: image::Extent,
+    ) -> Result<Self, &'static str> {
+        let desc = format.surface_desc();
+        if desc.dim != (1, 1) {
+            return Err("Can't read back block-compressed images");
+        }
+        let size = (extent.width * extent.height) as usize * desc.bits as usize / 8;
+
+        let mut buffer =
+            BufferInfo::with_size(device, adapter, size as u64, buffer::Usage::TRANSFER_DST)?;
+        if let Err(e) = buffer.map_persistent(device) {
+            buffer.free(device);
+            return Err(e);
+        }
+
+        let pool = match unsafe {
+            device.create_command_pool(queue_group.family, CommandPoolCreateFlags::TRANSIENT)
+        } {
+            Ok(pool) => pool,
+            Err(_) => {
+                buffer.free(device);
+                return Err("Could not create readback command pool");
+            }
+        };
+        let fence = match device.create_fence(false) {
+            Ok(fence) => fence,
+            Err(_) => {
+                unsafe { device.destroy_command_pool(pool) };
+                buffer.free(device);
+                return Err("Could not create readback fence");
+            }
+        };
+
+        Ok(Self {
+            extent,
+            pool: ManuallyDrop::new(pool),
+            fence: ManuallyDrop::new(fence),
+            buffer,
+            size,
+            submitted: false,
+            taken: false,
+        })
+    }
+
+    // Whether the copy still has to go into a frame
+    pub fn awaits_frame(&self) -> bool {
+        !self.submitted
+    }
+
+    // Goes after the frame's last pass, which leaves the image
+    // in the present layout, and before it's handed to another family
+    pub unsafe fn record_frame_copy(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        image: &<back::Backend as Backend>::Image,
+    ) {
+        self.record_copy(commands, image, Layout::Present);
+    }
+
+    // Right after the frame with the copy. A submission with nothing
+    // in it signals the fence once everything before it has finished.
+    pub fn submit_after(&mut self, queue: &mut <back::Backend as Backend>::CommandQueue) {
+        unsafe {
+            queue.submit_without_semaphores(
+                None::<&<back::Backend as Backend>::CommandBuffer>,
+                Some(&*self.fence),
+            )
+        };
+        self.submitted = true;
+    }
+
+    unsafe fn record_copy(
+        &self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        image: &<back::Backend as Backend>::Image,
+        layout: Layout,
+    ) {
+        let range = image::SubresourceRange {
+            aspects: format::Aspects::COLOR,
+            levels: 0..1,
+            layers: 0..1,
+        };
+        // Waits for anything earlier on the queue that drew into it
+        commands.pipeline_barrier(
+            (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::FRAGMENT_SHADER)
+                ..PipelineStage::TRANSFER,
+            Dependencies::empty(),
+            &[Barrier::Image {
+                states: (image::Access::COLOR_ATTACHMENT_WRITE, layout)
+                    ..(image::Access::TRANSFER_READ, Layout::TransferSrcOptimal),
+                target: image,
+                families: None,
+                range: range.clone(),
+            }],
+        );
+        commands.copy_image_to_buffer(
+            image,
+            Layout::TransferSrcOptimal,
+            &self.buffer.buffer,
+            &[command::BufferImageCopy {
+                buffer_offset: 0,
+                // Tightly packed
+                buffer_width: 0,
+                buffer_height: 0,
+                image_layers: image::SubresourceLayers {
+                    aspects: format::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: image::Offset { x: 0, y: 0, z: 0 },
+                image_extent: self.extent,
+            }],
+        );
+        // Back how it was for whatever draws into or samples it next
+        commands.pipeline_barrier(
+            PipelineStage::TRANSFER
+                ..(PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::FRAGMENT_SHADER),
+            Dependencies::empty(),
+            &[
+                Barrier::Image {
+                    states: (image::Access::TRANSFER_READ, Layout::TransferSrcOptimal)
+                        ..(image::Access::empty(), layout),
+                    target: image,
+                    families: None,
+                    range,
+                },
+                Barrier::Buffer {
+                    states: buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                    target: &*self.buffer.buffer,
+                    families: None,
+                    range: None..None,
+                },
+            ],
+        );
+    }
+
+    // Checks the fence without waiting on it. The data
+    // is handed out once, the first time it's ready.
+    pub fn poll(&mut self, device: &back::Device) -> Result<ReadbackStatus, &'static str> {
+        if self.taken {
+            return Err("Readback data was already taken");
+        }
+        if !self.submitted {
+            return Ok(ReadbackStatus::Pending);
+        }
+        let signaled = unsafe { device.get_fence_status(&self.fence) }
+            .map_err(|_| "Lost the device during readback")?;
+        if !signaled {
+            return Ok(ReadbackStatus::Pending);
+        }
+        let data = self.buffer.read_bytes(device, self.size)?;
+        self.taken = true;
+        Ok(ReadbackStatus::Ready(data))
+    }
+
+    // Waits for the copy first if it's still running
+    pub fn free(mut self, device: &back::Device) {
+        unsafe {
+            // Nothing would ever signal it otherwise
+            if self.submitted {
+                let _ = device.wait_for_fence(&self.fence, core::u64::MAX);
+            }
+            device.destroy_fence(ManuallyDrop::into_inner(ptr::read(&self.fence)));
+            // Frees the command buffer along with it
+            device.destroy_command_pool(ManuallyDrop::into_inner(ptr::read(&self.pool)));
+        }
+        self.buffer.free(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use gfx_hal::Features;
+
+    #[test]
+    #[ignore] // Needs a Vulkan device
+    fn status_goes_from_pending_to_ready() {
+        let mut headless = Headless::new(4, 4, Features::empty());
+        let extent = image::Extent {
+            width: 4,
+            height: 4,
+            depth: 1,
+        };
+        let mut handle = ReadbackHandle::swapchain(
+            &headless.device,
+            &headless.adapter,
+            &headless.queue_group,
+            format::Format::Rgba8Unorm,
+            extent,
+        )
+        .unwrap();
+
+        // Nothing's on the queue yet, so it can't be ready
+        assert!(handle.awaits_frame());
+        match handle.poll(&headless.device) {
+            Ok(ReadbackStatus::Pending) => {}
+            _ => panic!("Expected the readback to be pending"),
+        }
+
+        handle.submit_after(&mut headless.queue_group.queues[0]);
+        assert!(!handle.awaits_frame());
+        headless.device.wait_idle().unwrap();
+        match handle.poll(&headless.device) {
+            Ok(ReadbackStatus::Ready(data)) => assert_eq!(data.len(), 4 * 4 * 4),
+            _ => panic!("Expected the readback to be ready"),
+        }
+        // Handed out only once
+        assert!(handle.poll(&headless.device).is_err());
+
+        handle.free(&headless.device);
+        headless.free();
+    }
+}
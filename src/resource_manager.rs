@@ -0,0 +1,160 @@
+use crate::BufferInfo;
+use gfx_backend_vulkan as back;
+
+// Slot index plus the generation it was handed out in. Destroying
+// a resource bumps the slot's generation, so old handles to it
+// stop resolving even once the slot is reused.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(Key);
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+// Storage for one kind of resource, reusing freed slots
+struct Slots<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Default for Slots<T> {
+    fn default() -> Self {
+        Self {
+            slots: vec![],
+            free: vec![],
+        }
+    }
+}
+
+impl<T> Slots<T> {
+    fn insert(&mut self, value: T) -> Key {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+                Key {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                Key {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    fn get(&self, key: Key) -> Option<&T> {
+        self.slots
+            .get(key.index as usize)
+            .filter(|slot| slot.generation == key.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    fn remove(&mut self, key: Key) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(key.index as usize)
+            .filter(|slot| slot.generation == key.generation)?;
+        let value = slot.value.take()?;
+        // Wrapping could only revive a handle after
+        // four billion reuses of the same slot
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(key.index);
+        Some(value)
+    }
+
+    // Slots are kept rather than cleared, so that
+    // their generations still rule out old handles
+    fn take_all(&mut self) -> Vec<T> {
+        let mut values = vec![];
+        for index in 0..self.slots.len() as u32 {
+            let slot = &self.slots[index as usize];
+            let key = Key {
+                index,
+                generation: slot.generation,
+            };
+            values.extend(self.remove(key));
+        }
+        values
+    }
+}
+
+// Owns buffers so their lifetimes are managed in one
+// place. Everything else refers to them by handle, and a handle
+// to something destroyed resolves to None rather than a dangling
+// resource.
+#[derive(Default)]
+pub struct ResourceManager {
+    buffers: Slots<BufferInfo>,
+}
+
+impl ResourceManager {
+    pub fn create_buffer(&mut self, buffer: BufferInfo) -> BufferHandle {
+        BufferHandle(self.buffers.insert(buffer))
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> Option<&BufferInfo> {
+        self.buffers.get(handle.0)
+    }
+
+    // Every handle handed out so far is stale after this
+    pub fn free_all(&mut self, device: &back::Device) {
+        for mut buffer in self.buffers.take_all() {
+            buffer.free(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removing_bumps_the_generation() {
+        let mut slots = Slots::default();
+        let first = slots.insert('a');
+        assert_eq!(slots.remove(first), Some('a'));
+        let second = slots.insert('b');
+        // The slot is reused under a new generation
+        assert_eq!(second.index, first.index);
+        assert_eq!(second.generation, first.generation + 1);
+        assert_eq!(slots.get(second), Some(&'b'));
+    }
+
+    #[test]
+    fn stale_keys_are_rejected() {
+        let mut slots = Slots::default();
+        let stale = slots.insert('a');
+        slots.remove(stale);
+        slots.insert('b');
+        assert_eq!(slots.get(stale), None);
+        assert_eq!(slots.remove(stale), None);
+    }
+
+    #[test]
+    fn take_all_keeps_generations() {
+        let mut slots = Slots::default();
+        let a = slots.insert('a');
+        let b = slots.insert('b');
+        assert_eq!(slots.take_all(), vec!['a', 'b']);
+        assert_eq!(slots.get(a), None);
+        let c = slots.insert('c');
+        assert_ne!(c, a);
+        assert_ne!(c, b);
+        assert_eq!(slots.get(b), None);
+    }
+}
@@ -0,0 +1,78 @@
+use std::{collections::VecDeque, time::Duration};
+
+// Mean of the last few durations, so a single
+// slow frame doesn't swamp the reading
+pub struct RollingAverage {
+    samples: VecDeque<Duration>,
+    window: usize,
+    total: Duration,
+}
+
+impl RollingAverage {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            samples: VecDeque::with_capacity(window),
+            window,
+            total: Duration::default(),
+        }
+    }
+
+    // Drops the oldest sample once the window is full
+    pub fn push(&mut self, sample: Duration) {
+        if self.samples.len() == self.window {
+            if let Some(oldest) = self.samples.pop_front() {
+                self.total -= oldest;
+            }
+        }
+        self.samples.push_back(sample);
+        self.total += sample;
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.total / self.samples.len() as u32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn empty_has_no_average() {
+        assert_eq!(RollingAverage::new(4).average(), None);
+    }
+
+    #[test]
+    fn averages_what_it_has_so_far() {
+        let mut average = RollingAverage::new(4);
+        average.push(ms(10));
+        average.push(ms(20));
+        assert_eq!(average.average(), Some(ms(15)));
+    }
+
+    #[test]
+    fn oldest_samples_drop_out() {
+        let mut average = RollingAverage::new(2);
+        average.push(ms(100));
+        average.push(ms(10));
+        average.push(ms(20));
+        assert_eq!(average.average(), Some(ms(15)));
+    }
+
+    #[test]
+    fn zero_window_keeps_one_sample() {
+        let mut average = RollingAverage::new(0);
+        average.push(ms(10));
+        average.push(ms(30));
+        assert_eq!(average.average(), Some(ms(30)));
+    }
+}
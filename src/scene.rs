@@ -0,0 +1,95 @@
+use crate::transform::{Mat4, Transform};
+
+pub struct Node {
+    // Relative to the parent, or the world for roots
+    pub local: Transform,
+    pub children: Vec<usize>,
+}
+
+// A hierarchy of quads, where moving a node carries its children.
+// Nodes are referred to by index.
+#[derive(Default)]
+pub struct Scene {
+    pub nodes: Vec<Node>,
+    pub roots: Vec<usize>,
+}
+
+impl Scene {
+    // Panics if the parent doesn't exist
+    pub fn add_node(&mut self, parent: Option<usize>, local: Transform) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            local,
+            children: vec![],
+        });
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Indexed like the nodes. Each is the parent's world matrix
+    // times the node's local matrix, all the way down.
+    pub fn world_matrices(&self) -> Vec<Mat4> {
+        let mut world = vec![Mat4::IDENTITY; self.nodes.len()];
+        let mut stack: Vec<(usize, Mat4)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY))
+            .collect();
+
+        while let Some((id, parent)) = stack.pop() {
+            let node = &self.nodes[id];
+            world[id] = parent * node.local.matrix();
+            stack.extend(node.children.iter().map(|&child| (child, world[id])));
+        }
+
+        world
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{Vec2, Vec4};
+
+    fn moved(x: f32, scale: f32) -> Transform {
+        Transform {
+            translation: Vec2 { x, y: 0.0 },
+            rotation: 0.0,
+            scale: Vec2 { x: scale, y: scale },
+        }
+    }
+
+    // Where the node's local origin ends up
+    fn origin(world: Mat4) -> Vec4 {
+        world.transform(Vec4::new(0.0, 0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn children_follow_their_parents() {
+        let mut scene = Scene::default();
+        let root = scene.add_node(None, moved(1.0, 2.0));
+        let child = scene.add_node(Some(root), moved(1.0, 1.0));
+        let grandchild = scene.add_node(Some(child), moved(1.0, 1.0));
+        let other = scene.add_node(None, moved(-1.0, 1.0));
+
+        let world = scene.world_matrices();
+        assert_eq!(world.len(), 4);
+        assert_eq!(origin(world[root]), Vec4::new(1.0, 0.0, 0.0, 1.0));
+        // Offsets below the root are scaled by it
+        assert_eq!(origin(world[child]), Vec4::new(3.0, 0.0, 0.0, 1.0));
+        assert_eq!(origin(world[grandchild]), Vec4::new(5.0, 0.0, 0.0, 1.0));
+        assert_eq!(origin(world[other]), Vec4::new(-1.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn empty_scene_has_no_matrices() {
+        assert!(Scene::default().world_matrices().is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+use notify::{RawEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+use winit::event_loop::EventLoopProxy;
+
+// Sent to the event loop once the shader files settle
+#[derive(Copy, Clone, Debug)]
+pub struct ShadersChanged;
+
+// Editors often write a file several times per save,
+// so wait for a quiet period before reloading
+const QUIET_PERIOD: Duration = Duration::from_millis(200);
+
+// Collapses bursts of file events into a single reload
+pub struct Debouncer {
+    delay: Duration,
+    // When the most recent event arrived
+    pending: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            pending: None,
+        }
+    }
+
+    // Each event pushes the reload back
+    pub fn event(&mut self, now: Instant) {
+        self.pending = Some(now);
+    }
+
+    // Time until a pending reload is due, if there is one
+    pub fn time_left(&self, now: Instant) -> Option<Duration> {
+        self.pending
+            .map(|last| (last + self.delay).saturating_duration_since(now))
+    }
+
+    // True once per burst, after it has been quiet long enough
+    pub fn poll(&mut self, now: Instant) -> bool {
+        match self.time_left(now) {
+            Some(left) if left == Duration::from_secs(0) => {
+                self.pending = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_source(event: &RawEvent) -> bool {
+    event.path.as_ref().map_or(false, |path| {
+        path.extension().map_or(false, |ext| ext == "glsl")
+    })
+}
+
+// Watches the shader directory on a background thread.
+// The thread stops once the event loop is gone.
+pub fn watch(directory: &str, proxy: EventLoopProxy<ShadersChanged>) -> Result<(), &'static str> {
+    let (sender, receiver) = mpsc::channel::<RawEvent>();
+    let mut watcher: RecommendedWatcher =
+        notify::raw_watcher(sender).map_err(|_| "Could not create shader watcher")?;
+    watcher
+        .watch(directory, RecursiveMode::Recursive)
+        .map_err(|_| "Could not watch the shader directory")?;
+
+    thread::spawn(move || {
+        // Dropping the watcher would stop the events
+        let _watcher = watcher;
+        let mut debouncer = Debouncer::new(QUIET_PERIOD);
+
+        loop {
+            let received = match debouncer.time_left(Instant::now()) {
+                Some(left) => receiver.recv_timeout(left),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match received {
+                // Dumped SPIR-V lands in the same directory,
+                // and writing it mustn't set off another reload
+                Ok(event) if is_source(&event) => debouncer.event(Instant::now()),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    if debouncer.poll(Instant::now()) && proxy.send_event(ShadersChanged).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn nothing_pending_without_events() {
+        let mut debouncer = Debouncer::new(DELAY);
+        let now = Instant::now();
+        assert_eq!(debouncer.time_left(now), None);
+        assert!(!debouncer.poll(now + DELAY));
+    }
+
+    #[test]
+    fn fires_once_after_quiet_period() {
+        let mut debouncer = Debouncer::new(DELAY);
+        let start = Instant::now();
+        debouncer.event(start);
+        assert!(!debouncer.poll(start + DELAY / 2));
+        assert!(debouncer.poll(start + DELAY));
+        assert!(!debouncer.poll(start + DELAY * 2));
+    }
+
+    #[test]
+    fn events_push_the_reload_back() {
+        let mut debouncer = Debouncer::new(DELAY);
+        let start = Instant::now();
+        debouncer.event(start);
+        debouncer.event(start + DELAY / 2);
+        assert_eq!(debouncer.time_left(start + DELAY), Some(DELAY / 2));
+        assert!(!debouncer.poll(start + DELAY));
+        assert!(debouncer.poll(start + DELAY + DELAY / 2));
+    }
+}
@@ -0,0 +1,61 @@
+use crate::utils::Vec4;
+use std::time::Duration;
+
+// Time advanced by a single step while paused
+pub const STEP: Duration = Duration::from_micros(16_667);
+
+// Everything that changes over time, advanced once per rendered frame
+#[derive(Default, Debug)]
+pub struct Simulation {
+    pub frame: u64,
+    // Seconds of simulated time, which stops while paused
+    pub time: f32,
+}
+
+impl Simulation {
+    // Whether the tint moved, which is all that shows of the time
+    pub fn update(&mut self, dt: Duration) -> bool {
+        let tint = self.tint();
+        self.frame += 1;
+        self.time += dt.as_secs_f32();
+        self.tint() != tint
+    }
+
+    // A slow pulse, so it's obvious whether time is passing
+    pub fn tint(&self) -> Vec4 {
+        let brightness = 0.75 + 0.25 * (self.time * 2.0).sin();
+        Vec4::new(brightness, brightness, brightness, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_update_is_one_step() {
+        let mut simulation = Simulation::default();
+        simulation.update(STEP);
+        assert_eq!(simulation.frame, 1);
+        assert_eq!(simulation.time, STEP.as_secs_f32());
+        simulation.update(STEP);
+        assert_eq!(simulation.frame, 2);
+        assert!((simulation.time - 2.0 * STEP.as_secs_f32()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn only_moving_time_changes_the_tint() {
+        let mut simulation = Simulation::default();
+        assert!(simulation.update(STEP));
+        assert!(!simulation.update(Duration::from_millis(0)));
+    }
+
+    #[test]
+    fn frames_count_updates_not_time() {
+        let mut simulation = Simulation::default();
+        simulation.update(Duration::from_secs(1));
+        simulation.update(Duration::from_millis(0));
+        assert_eq!(simulation.frame, 2);
+        assert_eq!(simulation.time, 1.0);
+    }
+}
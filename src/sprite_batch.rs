@@ -0,0 +1,146 @@
+use crate::{
+    debug_names::set_debug_name,
+    utils::{Vec2, Vec4},
+    BufferInfo,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{adapter::Adapter, buffer::Usage, device::Device};
+use std::mem::size_of;
+
+// Per-instance data, laid out to match the sprite pipeline's attributes
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+pub struct Sprite {
+    // Top left corner in pixels
+    pub pos: Vec2,
+    pub size: Vec2,
+    pub color: Vec4,
+    // Offset in xy and size in zw, in texture coordinates
+    pub uv_rect: Vec4,
+}
+
+// Collects sprites over a frame so they can be
+// drawn with a single instanced call
+#[derive(Default)]
+pub struct SpriteBatch {
+    pub sprites: Vec<Sprite>,
+    // Created on first upload, with a region for each frame in flight
+    // so one frame's upload doesn't overwrite what another is drawing
+    pub instances: Option<BufferInfo>,
+    // Number of sprites each region can hold
+    pub capacity: usize,
+    // Number of regions in the instance buffer
+    pub frames: usize,
+}
+
+impl SpriteBatch {
+    pub fn push(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    // Byte offset of a frame's region in the instance buffer
+    pub fn offset(&self, frame: usize) -> u64 {
+        (frame * self.capacity * size_of::<Sprite>()) as u64
+    }
+
+    pub fn upload(
+        &mut self,
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        frame: usize,
+        frames: usize,
+    ) -> Result<(), &'static str> {
+        let capacity = capacity_for(self.capacity, self.sprites.len());
+        if capacity != self.capacity || frames != self.frames {
+            if let Some(mut old) = self.instances.take() {
+                // Frames in flight may still be reading the old buffer
+                let _ = device.wait_idle();
+                old.free(device);
+            }
+
+            let mut instances = BufferInfo::with_size(
+                device,
+                adapter,
+                (frames * capacity * size_of::<Sprite>()) as u64,
+                Usage::VERTEX,
+            )?;
+            // Rewritten every frame
+            instances.map_persistent(device)?;
+            set_debug_name(device, &mut *instances.buffer, "sprite_instances");
+            self.instances = Some(instances);
+            self.capacity = capacity;
+            self.frames = frames;
+        }
+
+        match &self.instances {
+            Some(instances) => instances.write(device, self.offset(frame), &self.sprites),
+            None => Ok(()),
+        }
+    }
+
+    pub fn free(&mut self, device: &back::Device) {
+        if let Some(mut instances) = self.instances.take() {
+            instances.free(device);
+        }
+        self.capacity = 0;
+        self.frames = 0;
+    }
+}
+
+// Sprites each region holds once `needed` are uploaded. Grows
+// geometrically so adding sprites one at a time doesn't reallocate
+// every frame, and never shrinks. At least one, so an empty
+// batch still gets a buffer.
+fn capacity_for(capacity: usize, needed: usize) -> usize {
+    if needed <= capacity && capacity > 0 {
+        capacity
+    } else {
+        needed.next_power_of_two().max(capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_hundred_sprites_grow_once() {
+        let capacity = capacity_for(0, 100);
+        assert_eq!(capacity, 128);
+        assert_eq!(capacity_for(capacity, 100), capacity);
+    }
+
+    #[test]
+    fn growing_one_at_a_time_doubles() {
+        let mut capacity = 0;
+        let mut reallocations = 0;
+        for needed in 1..=100 {
+            let grown = capacity_for(capacity, needed);
+            if grown != capacity {
+                reallocations += 1;
+            }
+            capacity = grown;
+            assert!(capacity >= needed);
+        }
+        // 1, 2, 4 and so on up to 128
+        assert_eq!(reallocations, 8);
+    }
+
+    #[test]
+    fn never_shrinks() {
+        assert_eq!(capacity_for(64, 3), 64);
+        assert_eq!(capacity_for(0, 0), 1);
+    }
+}
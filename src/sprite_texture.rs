@@ -0,0 +1,94 @@
+use crate::{
+    debug_names::set_debug_name,
+    descriptor::{DescriptorAllocation, DescriptorAllocator},
+    image_info::SamplerPreset,
+    ImageInfo, PipelineInfo,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{adapter::Adapter, device::Device, format::Format, image, pso, Backend};
+
+// The image every sprite samples, tinted by the sprite's color.
+// Plain white unless a file is given, so sprites keep their color.
+pub struct SpriteTexture {
+    pub image: ImageInfo,
+    pub descriptor_set: DescriptorAllocation,
+}
+
+impl SpriteTexture {
+    pub fn new(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        command_pool: &mut <back::Backend as Backend>::CommandPool,
+        queue: &mut <back::Backend as Backend>::CommandQueue,
+        pipeline: &PipelineInfo,
+        descriptors: &mut DescriptorAllocator,
+        path: Option<&str>,
+        // Checked against the device, see GfxConfig::anisotropy
+        anisotropy: f32,
+    ) -> Result<Self, &'static str> {
+        // DDS files upload their compressed blocks as they are,
+        // anything else the image crate reads is decoded to RGBA8
+        let mut image = match path {
+            Some(path) if path.to_lowercase().ends_with(".dds") => {
+                ImageInfo::from_dds(device, adapter, command_pool, queue, path)?
+            }
+            Some(path) => ImageInfo::from_file(device, adapter, command_pool, queue, path)?,
+            None => white(device, adapter, command_pool, queue)?,
+        };
+        set_debug_name(device, &mut *image.image, "sprite_texture");
+        if let Err(e) = image.set_sampler(device, SamplerPreset::filtered(anisotropy), anisotropy) {
+            image.free(device);
+            return Err(e);
+        }
+
+        // Compatible with the pipeline's layout however often it's rebuilt
+        let descriptor_set = pipeline.allocate_set(device, descriptors, 0)?;
+        unsafe {
+            device.write_descriptor_sets(vec![pso::DescriptorSetWrite {
+                set: &descriptor_set.set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::CombinedImageSampler(
+                    &*image.image_view,
+                    image::Layout::ShaderReadOnlyOptimal,
+                    &*image.sampler,
+                )),
+            }]);
+        }
+
+        Ok(Self {
+            image,
+            descriptor_set,
+        })
+    }
+
+    pub fn free(mut self, device: &back::Device, descriptors: &mut DescriptorAllocator) {
+        if let Err(e) = descriptors.free(self.descriptor_set) {
+            log::error!("{}", e);
+        }
+        self.image.free(device);
+    }
+}
+
+// A single texel, which leaves whatever it tints as it is
+pub fn white(
+    device: &back::Device,
+    adapter: &Adapter<back::Backend>,
+    command_pool: &mut <back::Backend as Backend>::CommandPool,
+    queue: &mut <back::Backend as Backend>::CommandQueue,
+) -> Result<ImageInfo, &'static str> {
+    let extent = image::Extent {
+        width: 1,
+        height: 1,
+        depth: 1,
+    };
+    let image = ImageInfo::new(
+        device,
+        adapter,
+        extent,
+        Format::Rgba8Srgb,
+        image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+    )?;
+    image.upload(device, adapter, command_pool, queue, &[255; 4], 1)?;
+    Ok(image)
+}
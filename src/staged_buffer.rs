@@ -0,0 +1,124 @@
+use crate::{
+    allocator::{self, Allocation},
+    BufferInfo,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter,
+    buffer::{self, Usage},
+    command::{BufferCopy, CommandBuffer},
+    device::Device,
+    memory::{Barrier, Dependencies, Properties},
+    pso::PipelineStage,
+    Backend,
+};
+use std::{
+    iter,
+    mem::{size_of, ManuallyDrop},
+    ptr,
+};
+
+// A device local buffer filled by a copy that's recorded
+// in the same command buffer as the draws that read it,
+// so the upload goes out with the frame's one submission
+pub struct StagedBuffer {
+    pub buffer: ManuallyDrop<<back::Backend as Backend>::Buffer>,
+    pub memory: ManuallyDrop<<back::Backend as Backend>::Memory>,
+    pub allocation: Allocation,
+    pub size: u64,
+    // How the draws read the buffer once it's filled
+    reads: buffer::Access,
+    // Kept until the buffer is freed, since the frame
+    // that copies from it may still be in flight
+    staging: BufferInfo,
+    // Set until the copy has been recorded
+    pending: bool,
+}
+
+impl StagedBuffer {
+    pub fn new<T>(
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        data: &[T],
+        usage: Usage,
+    ) -> Result<Self, &'static str> {
+        let staging = BufferInfo::new(device, adapter, data, Usage::TRANSFER_SRC)?;
+        staging.load_data(device, data)?;
+
+        let size = (data.len() * size_of::<T>()) as u64;
+        let mut buffer = unsafe { device.create_buffer(size, usage | Usage::TRANSFER_DST) }
+            .map_err(|_| "Failed to create a device local buffer")?;
+        let requirements = unsafe { device.get_buffer_requirements(&buffer) };
+        let (memory, allocation) = allocator::allocate(
+            device,
+            adapter,
+            &requirements,
+            Properties::DEVICE_LOCAL,
+            Properties::empty(),
+        )?;
+        unsafe { device.bind_buffer_memory(&memory, 0, &mut buffer) }
+            .map_err(|_| "Failed to bind the buffer memory")?;
+
+        let mut reads = buffer::Access::empty();
+        if usage.contains(Usage::VERTEX) {
+            reads |= buffer::Access::VERTEX_BUFFER_READ;
+        }
+        if usage.contains(Usage::INDEX) {
+            reads |= buffer::Access::INDEX_BUFFER_READ;
+        }
+
+        Ok(Self {
+            buffer: ManuallyDrop::new(buffer),
+            memory: ManuallyDrop::new(memory),
+            allocation,
+            size,
+            reads,
+            staging,
+            pending: true,
+        })
+    }
+
+    // Must be recorded outside of a render pass, before the draws.
+    // Only the first frame recorded copies anything.
+    pub unsafe fn record_upload(
+        &mut self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+    ) {
+        if !self.pending {
+            return;
+        }
+        self.pending = false;
+
+        commands.copy_buffer(
+            &self.staging.buffer,
+            &self.buffer,
+            iter::once(BufferCopy {
+                src: 0,
+                dst: 0,
+                size: self.size,
+            }),
+        );
+
+        // Later frames are ordered after this one on the queue,
+        // so the barrier covers their draws as well
+        commands.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::VERTEX_INPUT,
+            Dependencies::empty(),
+            &[Barrier::Buffer {
+                states: buffer::Access::TRANSFER_WRITE..self.reads,
+                target: &*self.buffer,
+                families: None,
+                range: None..None,
+            }],
+        );
+    }
+
+    pub fn free(&mut self, device: &back::Device) {
+        self.staging.free(device);
+        unsafe {
+            device.destroy_buffer(ManuallyDrop::into_inner(ptr::read(&self.buffer)));
+            let memory = ManuallyDrop::into_inner(ptr::read(&self.memory));
+            allocator::free(device, memory, &self.allocation);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    draw_batch::Batch, drawing, transform::Mat4, utils::Vec4, BufferInfo, InstanceTransforms,
+};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter,
+    command::{CommandBuffer, CommandBufferFlags, CommandBufferInheritanceInfo, Level},
+    device::Device,
+    pass,
+    pool::{CommandPool, CommandPoolCreateFlags},
+    pso,
+    queue::QueueFamilyId,
+    Backend,
+};
+use std::{mem::ManuallyDrop, ops::Range, ptr};
+
+// What the nodes were last recorded with. If any of it
+// changes, the recorded commands draw the wrong thing.
+#[derive(Clone, PartialEq)]
+struct Recorded {
+    transforms: Vec<Mat4>,
+    tint: Vec4,
+    viewport: pso::Rect,
+    depth: Range<f32>,
+    // In window pixels, for the projection
+    area: pso::Rect,
+    // GfxState::pass_generation, since a replaced render
+    // pass can end up at the same address as the old one
+    pass_generation: u64,
+}
+
+// One frame in flight's secondary buffers. The before and after
+// buffers are recorded every frame, around the nodes, since a
+// subpass that runs secondary buffers can't also draw inline.
+struct FrameCommands {
+    before: <back::Backend as Backend>::CommandBuffer,
+    nodes: <back::Backend as Backend>::CommandBuffer,
+    after: <back::Backend as Backend>::CommandBuffer,
+    recorded: Option<Recorded>,
+}
+
+// The scene nodes recorded into secondary command buffers and run
+// by every frame after, until the nodes or what draws them change.
+// Each frame in flight has its own recording and transforms, so one
+// can be recorded again once its fence signals without waiting on
+// the others. The viewport and scissor are set inside the recording,
+// since secondary buffers don't inherit dynamic state.
+pub struct StaticScene {
+    pool: ManuallyDrop<<back::Backend as Backend>::CommandPool>,
+    // Allocated as frames first use them
+    frames: Vec<FrameCommands>,
+    transforms: InstanceTransforms,
+    // Frames in flight the transforms were last uploaded for
+    frame_count: usize,
+}
+
+impl StaticScene {
+    pub fn new(device: &back::Device, family: QueueFamilyId) -> Result<Self, &'static str> {
+        // Each buffer is re-recorded on its own
+        let pool =
+            unsafe { device.create_command_pool(family, CommandPoolCreateFlags::RESET_INDIVIDUAL) }
+                .map_err(|_| "Could not create static scene command pool")?;
+        Ok(Self {
+            pool: ManuallyDrop::new(pool),
+            frames: vec![],
+            transforms: InstanceTransforms::default(),
+            frame_count: 0,
+        })
+    }
+
+    // For anything the recording depends on that the
+    // comparison can't see, like rebuilt pipelines
+    pub fn invalidate(&mut self) {
+        for frame in &mut self.frames {
+            frame.recorded = None;
+        }
+    }
+
+    // Records the frame's nodes again if anything they draw has changed.
+    // Only once the frame's fence shows its last recording is finished
+    // with, and before its primary buffer starts recording.
+    pub fn update(
+        &mut self,
+        device: &back::Device,
+        adapter: &Adapter<back::Backend>,
+        frame: usize,
+        frames: usize,
+        render_pass: &<back::Backend as Backend>::RenderPass,
+        pass_generation: u64,
+        batches: &[Batch],
+        transforms: &[Mat4],
+        vertices: &BufferInfo,
+        indices: &BufferInfo,
+        tint: Vec4,
+        viewport: pso::Rect,
+        depth: Range<f32>,
+        area: pso::Rect,
+    ) -> Result<(), &'static str> {
+        let wanted = Recorded {
+            transforms: transforms.to_vec(),
+            tint,
+            viewport,
+            depth: depth.clone(),
+            area,
+            pass_generation,
+        };
+        // The transforms start over with a new frame count,
+        // which every recording refers to
+        if frames != self.frame_count {
+            self.invalidate();
+            self.frame_count = frames;
+        }
+        self.allocate(frame);
+        if self.frames[frame].recorded.as_ref() == Some(&wanted) {
+            return Ok(());
+        }
+
+        self.frames[frame].recorded = None;
+        self.transforms
+            .upload(device, adapter, frame, frames, transforms)?;
+
+        let nodes = &mut self.frames[frame].nodes;
+        unsafe {
+            // Implicitly resets it, since the pool allows that
+            nodes.begin(
+                CommandBufferFlags::RENDER_PASS_CONTINUE,
+                inheritance(render_pass),
+            );
+            drawing::set_area(nodes, viewport, depth);
+            drawing::record_nodes(
+                nodes,
+                batches,
+                vertices,
+                indices,
+                &self.transforms,
+                frame,
+                tint,
+                area,
+            );
+            nodes.finish();
+        }
+        self.frames[frame].recorded = Some(wanted);
+        Ok(())
+    }
+
+    // Begins the frame's buffers for drawing before and after the
+    // nodes. Only once the frame's fence shows they're finished with.
+    pub unsafe fn begin_frame(
+        &mut self,
+        frame: usize,
+        render_pass: &<back::Backend as Backend>::RenderPass,
+    ) -> (
+        &mut <back::Backend as Backend>::CommandBuffer,
+        &mut <back::Backend as Backend>::CommandBuffer,
+    ) {
+        self.allocate(frame);
+        let FrameCommands { before, after, .. } = &mut self.frames[frame];
+        let flags = CommandBufferFlags::RENDER_PASS_CONTINUE | CommandBufferFlags::ONE_TIME_SUBMIT;
+        before.begin(flags, inheritance(render_pass));
+        after.begin(flags, inheritance(render_pass));
+        (before, after)
+    }
+
+    // Finishes the frame's buffers and runs all three in draw order,
+    // from a subpass begun for secondary command buffers
+    pub unsafe fn execute(
+        &mut self,
+        commands: &mut <back::Backend as Backend>::CommandBuffer,
+        frame: usize,
+    ) {
+        let FrameCommands {
+            before,
+            nodes,
+            after,
+            ..
+        } = &mut self.frames[frame];
+        before.finish();
+        after.finish();
+        commands.execute_commands([&*before, &*nodes, &*after].iter());
+    }
+
+    fn allocate(&mut self, frame: usize) {
+        while self.frames.len() <= frame {
+            let (before, nodes, after) = unsafe {
+                (
+                    self.pool.allocate_one(Level::Secondary),
+                    self.pool.allocate_one(Level::Secondary),
+                    self.pool.allocate_one(Level::Secondary),
+                )
+            };
+            self.frames.push(FrameCommands {
+                before,
+                nodes,
+                after,
+                recorded: None,
+            });
+        }
+    }
+
+    pub fn free(mut self, device: &back::Device) {
+        self.transforms.free(device);
+        // Frees the command buffers along with it
+        unsafe { device.destroy_command_pool(ManuallyDrop::into_inner(ptr::read(&self.pool))) }
+    }
+}
+
+// Without a framebuffer, so the same recording
+// works for every image the pass draws into
+fn inheritance(
+    render_pass: &<back::Backend as Backend>::RenderPass,
+) -> CommandBufferInheritanceInfo<back::Backend> {
+    CommandBufferInheritanceInfo {
+        subpass: Some(pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        }),
+        framebuffer: None,
+        ..CommandBufferInheritanceInfo::default()
+    }
+}
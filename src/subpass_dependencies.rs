@@ -0,0 +1,38 @@
+use gfx_hal::{
+    image::Access,
+    pass::{SubpassDependency, SubpassRef},
+    pso::PipelineStage,
+};
+use std::iter;
+
+// Waits for presentation to let go of the image before the first
+// subpass writes color, so the transition out of the initial layout
+// happens after the image is actually available. Also waits for the
+// last frame's writes, for targets shared between frames like the
+// offscreen depth buffer.
+pub fn external_to_first() -> SubpassDependency {
+    let stages = PipelineStage::COLOR_ATTACHMENT_OUTPUT
+        | PipelineStage::EARLY_FRAGMENT_TESTS
+        | PipelineStage::LATE_FRAGMENT_TESTS;
+    SubpassDependency {
+        passes: SubpassRef::External..SubpassRef::Pass(0),
+        stages: stages..stages,
+        accesses: (Access::COLOR_ATTACHMENT_WRITE | Access::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            ..(Access::COLOR_ATTACHMENT_READ
+                | Access::COLOR_ATTACHMENT_WRITE
+                | Access::DEPTH_STENCIL_ATTACHMENT_READ
+                | Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+    }
+}
+
+// The external dependency followed by one between each pair
+// of subpasses, where each reads the previous one's color output
+pub fn color_chain(subpass_count: usize) -> Vec<SubpassDependency> {
+    iter::once(external_to_first())
+        .chain((1..subpass_count).map(|i| SubpassDependency {
+            passes: SubpassRef::Pass(i - 1)..SubpassRef::Pass(i),
+            stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::FRAGMENT_SHADER,
+            accesses: Access::COLOR_ATTACHMENT_WRITE..Access::INPUT_ATTACHMENT_READ,
+        }))
+        .collect()
+}
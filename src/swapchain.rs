@@ -0,0 +1,591 @@
+use crate::{debug_names::set_debug_name, ImageCount, ImageInfo};
+use gfx_backend_vulkan as back;
+use gfx_hal::{
+    adapter::Adapter,
+    device::Device,
+    format::{self, Format},
+    image,
+    pso::Rect,
+    window::{self, CompositeAlpha, Extent2D, Surface, SurfaceCapabilities},
+    Backend,
+};
+use std::ops::RangeInclusive;
+
+pub const FORMAT: Format = Format::Rgba8Srgb;
+
+// Tried in order when HDR output is asked for. The color space
+// can't be chosen through gfx-hal yet and stays sRGB nonlinear,
+// so these give extra precision rather than true HDR.
+pub const HDR_FORMATS: [Format; 2] = [Format::Rgba16Sfloat, Format::A2b10g10r10Unorm];
+
+// Owns the presentable images along with everything
+// that has to be rebuilt when the window changes size
+pub struct Swapchain {
+    // The size the images were created with, after clamping to what
+    // the surface allows. Everything sized to the swapchain goes by
+    // this rather than the window, which it may not match.
+    pub extent: Extent2D,
+    // The same as a rect, for viewports and render areas
+    pub content_size: Rect,
+    pub format: Format,
+    pub image_count: usize,
+    pub samples: image::NumSamples,
+    // What was asked for, which may not be what the surface supports
+    pub composite_alpha: CompositeAlpha,
+    // Likewise, the number of images, with image_count being how many it got
+    pub requested_images: Option<ImageCount>,
+    // Likewise, the usage asked for on top of the color attachment
+    pub extra_usage: image::Usage,
+    // What the images were actually created with
+    pub usage: image::Usage,
+    // Drawn into and resolved to the swapchain image with MSAA,
+    // one per image like depth. Empty without MSAA.
+    pub msaa: Vec<ImageInfo>,
+    // One per image, since frames in flight draw into different images
+    pub depth: Vec<ImageInfo>,
+    pub framebuffers: Vec<<back::Backend as Backend>::Framebuffer>,
+    // Owned by the swapchain, kept for barriers on the images themselves
+    pub images: Vec<<back::Backend as Backend>::Image>,
+    pub image_views: Vec<<back::Backend as Backend>::ImageView>,
+    // Only missing after a failed recreation, which retires the old one
+    pub handle: Option<<back::Backend as Backend>::Swapchain>,
+}
+
+impl Swapchain {
+    pub fn new(
+        device: &back::Device,
+        surface: &mut <back::Backend as Backend>::Surface,
+        adapter: &Adapter<back::Backend>,
+        render_pass: &<back::Backend as Backend>::RenderPass,
+        extent: Extent2D,
+        format: Format,
+        samples: image::NumSamples,
+        composite_alpha: CompositeAlpha,
+        requested_images: Option<ImageCount>,
+        extra_usage: image::Usage,
+    ) -> Result<Self, &'static str> {
+        Self::create(
+            device,
+            surface,
+            adapter,
+            render_pass,
+            extent,
+            format,
+            samples,
+            composite_alpha,
+            requested_images,
+            extra_usage,
+            None,
+        )
+    }
+
+    // The old swapchain is handed over so that the driver can reuse
+    // its resources. It is retired whether or not creation succeeds.
+    fn create(
+        device: &back::Device,
+        surface: &mut <back::Backend as Backend>::Surface,
+        adapter: &Adapter<back::Backend>,
+        render_pass: &<back::Backend as Backend>::RenderPass,
+        extent: Extent2D,
+        format: Format,
+        samples: image::NumSamples,
+        composite_alpha: CompositeAlpha,
+        requested_images: Option<ImageCount>,
+        extra_usage: image::Usage,
+        old: Option<<back::Backend as Backend>::Swapchain>,
+    ) -> Result<Self, &'static str> {
+        let swapchain_config = swapchain_config(
+            &surface.capabilities(&adapter.physical_device),
+            format,
+            extent,
+            composite_alpha,
+            requested_images,
+            extra_usage,
+        );
+
+        // Clamped to what the surface allows, which everything
+        // sized to the swapchain goes by rather than the window
+        let extent = swapchain_config.extent;
+        let usage = swapchain_config.image_usage;
+
+        // Swapchain manages a collection of images
+        // Backbuffer contains handles to swapchain image memory
+        let (handle, backbuffer) =
+            unsafe { device.create_swapchain(surface, swapchain_config, old) }
+                .map_err(|_| "Could not create swapchain")?;
+
+        // Everything sized per image assumes there is at least one
+        if backbuffer.is_empty() {
+            unsafe { device.destroy_swapchain(handle) };
+            return Err("Swapchain was created without any images");
+        }
+
+        // Describe access to the underlying image memory,
+        // possibly a subregion
+        let image_views = backbuffer
+            .iter()
+            .map(|image| {
+                unsafe {
+                    device.create_image_view(
+                        &image,
+                        image::ViewKind::D2,
+                        format,
+                        format::Swizzle::NO,
+                        image::SubresourceRange {
+                            // Properties that further specify the image format,
+                            // especially if it is ambiguous
+                            aspects: format::Aspects::COLOR,
+                            // Mipmaps
+                            levels: 0..1,
+                            // Image array layers
+                            layers: 0..1,
+                        },
+                    )
+                }
+                .map_err(|_| "Could not create a backbuffer image view")
+            })
+            .collect::<Result<Vec<_>, &str>>()?;
+
+        let image_extent = image::Extent {
+            width: extent.width,
+            height: extent.height,
+            // Layers
+            depth: 1,
+        };
+
+        // Frames in flight draw at the same time, so
+        // sharing one image would race between them
+        let msaa = if samples > 1 {
+            image_views
+                .iter()
+                .map(|_| ImageInfo::multisampled(device, adapter, image_extent, format, samples))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![]
+        };
+
+        let depth = image_views
+            .iter()
+            .map(|_| ImageInfo::depth(device, adapter, image_extent, samples))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Where a render pass describes the types of image attachments,
+        // a framebuffer binds specific images to its attachements.
+        // The multisampled image comes first and the depth buffer
+        // last, matching the render pass.
+        let framebuffers = image_views
+            .iter()
+            .zip(&depth)
+            .enumerate()
+            .map(|(i, (view, depth))| -> Result<_, &'static str> {
+                let views = msaa
+                    .get(i)
+                    .map(|msaa| &*msaa.image_view)
+                    .into_iter()
+                    .chain(Some(view))
+                    .chain(Some(&*depth.image_view));
+                let mut framebuffer =
+                    unsafe { device.create_framebuffer(render_pass, views, image_extent) }
+                        .map_err(|_| "Could not create framebuffer")?;
+                set_debug_name(device, &mut framebuffer, "swapchain_framebuffer");
+                Ok(framebuffer)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            extent,
+            content_size: extent.to_extent().rect(),
+            format,
+            image_count: image_views.len(),
+            samples,
+            composite_alpha,
+            requested_images,
+            extra_usage,
+            usage,
+            msaa,
+            depth,
+            images: backbuffer,
+            handle: Some(handle),
+            framebuffers,
+            image_views,
+        })
+    }
+
+    // The caller must make sure the device is idle
+    // so that none of the old images are still in use
+    pub fn recreate(
+        &mut self,
+        device: &back::Device,
+        surface: &mut <back::Backend as Backend>::Surface,
+        adapter: &Adapter<back::Backend>,
+        render_pass: &<back::Backend as Backend>::RenderPass,
+        extent: Extent2D,
+    ) -> Result<(), &'static str> {
+        let result = Self::create(
+            device,
+            surface,
+            adapter,
+            render_pass,
+            extent,
+            self.format,
+            self.samples,
+            self.composite_alpha,
+            self.requested_images,
+            self.extra_usage,
+            self.handle.take(),
+        );
+
+        // The old images are gone either way,
+        // so nothing built on them can stay
+        self.free(device);
+        *self = result?;
+        Ok(())
+    }
+
+    pub fn free(&mut self, device: &back::Device) {
+        // Framebuffers reference the views, so they go first
+        for framebuffer in self.framebuffers.drain(..) {
+            unsafe { device.destroy_framebuffer(framebuffer) }
+        }
+
+        for view in self.image_views.drain(..) {
+            unsafe { device.destroy_image_view(view) }
+        }
+
+        for mut msaa in self.msaa.drain(..) {
+            msaa.free(device);
+        }
+
+        for mut depth in self.depth.drain(..) {
+            depth.free(device);
+        }
+
+        // Destroyed along with the swapchain
+        self.images.clear();
+
+        if let Some(handle) = self.handle.take() {
+            unsafe { device.destroy_swapchain(handle) }
+        }
+    }
+
+    // Frames can be copied out of the swapchain images
+    pub fn supports_readback(&self) -> bool {
+        self.usage.contains(image::Usage::TRANSFER_SRC)
+    }
+}
+
+// What's asked of the surface, with everything it doesn't support
+// clamped or dropped
+fn swapchain_config(
+    capabilities: &SurfaceCapabilities,
+    format: Format,
+    extent: Extent2D,
+    composite_alpha: CompositeAlpha,
+    requested_images: Option<ImageCount>,
+    extra_usage: image::Usage,
+) -> window::SwapchainConfig {
+    let extent = clamp_extent(capabilities, extent);
+    let mut config = window::SwapchainConfig::from_caps(capabilities, format, extent)
+        .with_present_mode(window::PresentMode::MAILBOX);
+    config.composite_alpha = pick_composite_alpha(
+        capabilities.composite_alpha,
+        composite_alpha,
+        config.composite_alpha,
+    );
+    config.image_usage = pick_usage(capabilities.usage, config.image_usage, extra_usage);
+    if let Some(requested) = requested_images {
+        config.image_count = pick_image_count(&capabilities.image_count, requested);
+    }
+    config
+}
+
+// Some platforms dictate the swapchain size through the current extent,
+// others leave it undefined and let us pick within the supported range
+fn clamp_extent(capabilities: &SurfaceCapabilities, requested: Extent2D) -> Extent2D {
+    // The backend usually maps Vulkan's 0xFFFFFFFF sentinel to None,
+    // but check for it too in case it slips through
+    let current = capabilities
+        .current_extent
+        .filter(|extent| extent.width != u32::max_value());
+
+    match current {
+        Some(extent) => extent,
+        None => {
+            let min = capabilities.extents.start();
+            let max = capabilities.extents.end();
+            Extent2D {
+                width: requested.width.max(min.width).min(max.width),
+                height: requested.height.max(min.height).min(max.height),
+            }
+        }
+    }
+}
+
+// Takes the requested mode if the surface supports it, otherwise opaque.
+// Some platforms don't offer opaque either, so the default
+// chosen from the capabilities is the last resort.
+fn pick_composite_alpha(
+    supported: CompositeAlpha,
+    requested: CompositeAlpha,
+    default: CompositeAlpha,
+) -> CompositeAlpha {
+    [requested, CompositeAlpha::OPAQUE]
+        .iter()
+        .cloned()
+        .find(|&mode| supported.contains(mode))
+        .unwrap_or(default)
+}
+
+// Vulkan reports no upper limit as zero, which the backend
+// should map to the largest count, but the minimum wins if
+// it slips through as an end below the start
+fn pick_image_count(supported: &RangeInclusive<u32>, requested: ImageCount) -> u32 {
+    let min = *supported.start();
+    let max = (*supported.end()).max(min);
+    let wanted = match requested {
+        ImageCount::Minimum => min,
+        ImageCount::MinimumPlusOne => min.saturating_add(1),
+    };
+    wanted.min(max)
+}
+
+// Adds whichever of the extra usages the surface supports.
+// The rest are reported and dropped rather than failing
+// swapchain creation, since none of them are essential.
+fn pick_usage(supported: image::Usage, default: image::Usage, extra: image::Usage) -> image::Usage {
+    let missing = extra - supported;
+    if !missing.is_empty() {
+        log::warn!("Swapchain images don't support {:?}", missing);
+    }
+    default | (extra & supported)
+}
+
+// Tried in turn when cycling formats on a surface that takes any.
+// The UNORM formats show how colors look without sRGB encoding.
+pub const CYCLE_FORMATS: [Format; 4] = [
+    FORMAT,
+    Format::Rgba8Unorm,
+    Format::Rgba16Sfloat,
+    Format::A2b10g10r10Unorm,
+];
+
+// The format after the current one, wrapping around
+pub fn next_format(supported: Option<&[Format]>, current: Format) -> Format {
+    let formats = supported.unwrap_or(&CYCLE_FORMATS);
+    match formats.iter().position(|&format| format == current) {
+        Some(i) => formats[(i + 1) % formats.len()],
+        None => formats.first().cloned().unwrap_or(current),
+    }
+}
+
+// Prefers the HDR formats when asked, falling back to sRGB.
+// No list of formats means the surface takes any of them.
+pub fn pick_format(supported: Option<&[Format]>, hdr: bool) -> Format {
+    let is_supported = |format: &Format| supported.map_or(true, |list| list.contains(format));
+    match HDR_FORMATS.iter().find(|format| is_supported(format)) {
+        Some(&format) if hdr => format,
+        _ => FORMAT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gfx_hal::window::PresentMode;
+
+    fn extent(width: u32, height: u32) -> Extent2D {
+        Extent2D { width, height }
+    }
+
+    fn capabilities(current_extent: Option<Extent2D>) -> SurfaceCapabilities {
+        SurfaceCapabilities {
+            image_count: 2..=8,
+            current_extent,
+            extents: extent(16, 16)..=extent(4096, 2048),
+            max_image_layers: 1,
+            usage: image::Usage::COLOR_ATTACHMENT,
+            present_modes: PresentMode::FIFO,
+            composite_alpha: CompositeAlpha::OPAQUE,
+        }
+    }
+
+    #[test]
+    fn current_extent_wins() {
+        let capabilities = capabilities(Some(extent(800, 600)));
+        assert_eq!(
+            clamp_extent(&capabilities, extent(1024, 768)),
+            extent(800, 600)
+        );
+    }
+
+    #[test]
+    fn undefined_extent_clamps_to_the_range() {
+        let capabilities = capabilities(None);
+        assert_eq!(
+            clamp_extent(&capabilities, extent(1024, 768)),
+            extent(1024, 768)
+        );
+        assert_eq!(
+            clamp_extent(&capabilities, extent(8, 8192)),
+            extent(16, 2048)
+        );
+    }
+
+    #[test]
+    fn sentinel_extent_counts_as_undefined() {
+        let sentinel = extent(u32::max_value(), u32::max_value());
+        let capabilities = capabilities(Some(sentinel));
+        assert_eq!(
+            clamp_extent(&capabilities, extent(10000, 10)),
+            extent(4096, 16)
+        );
+    }
+
+    #[test]
+    fn supported_composite_alpha_is_taken() {
+        let supported = CompositeAlpha::OPAQUE | CompositeAlpha::PREMULTIPLIED;
+        assert_eq!(
+            pick_composite_alpha(
+                supported,
+                CompositeAlpha::PREMULTIPLIED,
+                CompositeAlpha::INHERIT
+            ),
+            CompositeAlpha::PREMULTIPLIED
+        );
+    }
+
+    #[test]
+    fn unsupported_composite_alpha_falls_back_to_opaque() {
+        let supported = CompositeAlpha::OPAQUE | CompositeAlpha::INHERIT;
+        assert_eq!(
+            pick_composite_alpha(
+                supported,
+                CompositeAlpha::POSTMULTIPLIED,
+                CompositeAlpha::INHERIT
+            ),
+            CompositeAlpha::OPAQUE
+        );
+    }
+
+    #[test]
+    fn composite_alpha_default_is_the_last_resort() {
+        assert_eq!(
+            pick_composite_alpha(
+                CompositeAlpha::INHERIT,
+                CompositeAlpha::PREMULTIPLIED,
+                CompositeAlpha::INHERIT
+            ),
+            CompositeAlpha::INHERIT
+        );
+    }
+
+    #[test]
+    fn hdr_takes_the_first_supported_wide_format() {
+        let supported = [FORMAT, Format::A2b10g10r10Unorm];
+        assert_eq!(
+            pick_format(Some(&supported), true),
+            Format::A2b10g10r10Unorm
+        );
+        assert_eq!(pick_format(None, true), Format::Rgba16Sfloat);
+    }
+
+    #[test]
+    fn srgb_without_hdr_or_a_wide_format() {
+        let supported = [FORMAT, Format::Rgba16Sfloat];
+        assert_eq!(pick_format(Some(&supported), false), FORMAT);
+        assert_eq!(pick_format(Some(&[FORMAT]), true), FORMAT);
+    }
+
+    #[test]
+    fn next_format_wraps_around() {
+        let supported = [FORMAT, Format::Rgba8Unorm];
+        assert_eq!(next_format(Some(&supported), FORMAT), Format::Rgba8Unorm);
+        assert_eq!(next_format(Some(&supported), Format::Rgba8Unorm), FORMAT);
+    }
+
+    #[test]
+    fn next_format_cycles_when_any_is_taken() {
+        let last = CYCLE_FORMATS[CYCLE_FORMATS.len() - 1];
+        assert_eq!(next_format(None, FORMAT), CYCLE_FORMATS[1]);
+        assert_eq!(next_format(None, last), CYCLE_FORMATS[0]);
+    }
+
+    #[test]
+    fn next_format_starts_over_from_unknown() {
+        let supported = [Format::Rgba8Unorm, FORMAT];
+        assert_eq!(
+            next_format(Some(&supported), Format::Rgba16Sfloat),
+            Format::Rgba8Unorm
+        );
+        assert_eq!(next_format(Some(&[]), FORMAT), FORMAT);
+    }
+
+    #[test]
+    fn supported_extra_usage_is_added() {
+        let supported = image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSFER_SRC;
+        assert_eq!(
+            pick_usage(
+                supported,
+                image::Usage::COLOR_ATTACHMENT,
+                image::Usage::TRANSFER_SRC
+            ),
+            supported
+        );
+    }
+
+    #[test]
+    fn unsupported_extra_usage_is_dropped() {
+        assert_eq!(
+            pick_usage(
+                image::Usage::COLOR_ATTACHMENT,
+                image::Usage::COLOR_ATTACHMENT,
+                image::Usage::TRANSFER_SRC | image::Usage::STORAGE
+            ),
+            image::Usage::COLOR_ATTACHMENT
+        );
+    }
+
+    #[test]
+    fn extra_usage_is_split_by_support() {
+        let supported = image::Usage::COLOR_ATTACHMENT | image::Usage::STORAGE;
+        assert_eq!(
+            pick_usage(
+                supported,
+                image::Usage::COLOR_ATTACHMENT,
+                image::Usage::TRANSFER_SRC | image::Usage::STORAGE
+            ),
+            supported
+        );
+    }
+
+    #[test]
+    fn image_count_is_clamped_to_the_surface() {
+        assert_eq!(pick_image_count(&(2..=8), ImageCount::Minimum), 2);
+        assert_eq!(pick_image_count(&(2..=8), ImageCount::MinimumPlusOne), 3);
+        assert_eq!(pick_image_count(&(3..=3), ImageCount::MinimumPlusOne), 3);
+    }
+
+    #[test]
+    fn unbounded_image_count_is_taken() {
+        // The backend reports no maximum as the largest count
+        assert_eq!(
+            pick_image_count(&(2..=u32::max_value()), ImageCount::MinimumPlusOne),
+            3
+        );
+    }
+
+    #[test]
+    fn config_keeps_the_clamped_extent() {
+        let config = swapchain_config(
+            &capabilities(None),
+            FORMAT,
+            extent(8192, 8),
+            CompositeAlpha::OPAQUE,
+            None,
+            image::Usage::empty(),
+        );
+        assert_eq!(config.extent, extent(4096, 16));
+        // Nothing asked for past the color attachment
+        assert!(!config.image_usage.contains(image::Usage::TRANSFER_SRC));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::utils::{Vec2, Vec4};
+use std::ops::Mul;
+
+// Column major to match GLSL, so it can be pushed as is
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat4 {
+    pub columns: [Vec4; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Self = Self {
+        columns: [
+            Vec4::new(1.0, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 1.0, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        ],
+    };
+
+    pub fn translation(offset: Vec2) -> Self {
+        let mut m = Self::IDENTITY;
+        m.columns[3] = Vec4::new(offset.x, offset.y, 0.0, 1.0);
+        m
+    }
+
+    // Counterclockwise about the z axis, in radians
+    pub fn rotation(angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        let mut m = Self::IDENTITY;
+        m.columns[0] = Vec4::new(cos, sin, 0.0, 0.0);
+        m.columns[1] = Vec4::new(-sin, cos, 0.0, 0.0);
+        m
+    }
+
+    pub fn scale(scale: Vec2) -> Self {
+        let mut m = Self::IDENTITY;
+        m.columns[0].x = scale.x;
+        m.columns[1].y = scale.y;
+        m
+    }
+
+    pub fn transform(&self, v: Vec4) -> Vec4 {
+        self.columns[0] * v.x
+            + self.columns[1] * v.y
+            + self.columns[2] * v.z
+            + self.columns[3] * v.w
+    }
+
+    // Push constants are uploaded as words
+    pub fn to_bits(&self) -> [u32; 16] {
+        let mut bits = [0; 16];
+        for (i, column) in self.columns.iter().enumerate() {
+            bits[i * 4..i * 4 + 4].copy_from_slice(&column.to_bits());
+        }
+        bits
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+// Applies rhs first, then self
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self {
+            columns: [
+                self.transform(rhs.columns[0]),
+                self.transform(rhs.columns[1]),
+                self.transform(rhs.columns[2]),
+                self.transform(rhs.columns[3]),
+            ],
+        }
+    }
+}
+
+// A 2D placement, applied as scale, then rotation, then translation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec2,
+    // Radians, counterclockwise
+    pub rotation: f32,
+    pub scale: Vec2,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vec2::default(),
+            rotation: 0.0,
+            scale: Vec2 { x: 1.0, y: 1.0 },
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translation(self.translation)
+            * Mat4::rotation(self.rotation)
+            * Mat4::scale(self.scale)
+    }
+}
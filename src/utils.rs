@@ -1,25 +1,272 @@
-#[derive(Default, Copy, Clone)]
+use crate::vertex::{ColorVertex, Vertex};
+use std::ops::{Add, Mul, Sub};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
 pub struct Vec2 {
     pub x: f32,
     pub y: f32,
 }
 
+// Clip-space positions and RGBA colors
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct Vec4 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Vec4 {
+    pub const fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self { x, y, z, w }
+    }
+
+    // Colors picked in an image editor are sRGB encoded,
+    // but clear values and shader inputs are linear
+    pub fn from_srgb8(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self {
+            x: srgb_to_linear(r),
+            y: srgb_to_linear(g),
+            z: srgb_to_linear(b),
+            w: a as f32 / 255.0,
+        }
+    }
+
+    // For targets that store colors as they are, where the
+    // hardware won't encode them on write. Alpha stays linear.
+    pub fn linear_to_srgb(self) -> Self {
+        let encode = |c: f32| {
+            if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            }
+        };
+        Self {
+            x: encode(self.x),
+            y: encode(self.y),
+            z: encode(self.z),
+            w: self.w,
+        }
+    }
+
+    // Push constants are uploaded as words
+    pub fn to_bits(self) -> [u32; 4] {
+        [
+            self.x.to_bits(),
+            self.y.to_bits(),
+            self.z.to_bits(),
+            self.w.to_bits(),
+        ]
+    }
+}
+
+fn srgb_to_linear(channel: u8) -> f32 {
+    let c = channel as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl From<[f32; 4]> for Vec4 {
+    fn from([x, y, z, w]: [f32; 4]) -> Self {
+        Self { x, y, z, w }
+    }
+}
+
+impl From<Vec4> for [f32; 4] {
+    fn from(v: Vec4) -> Self {
+        [v.x, v.y, v.z, v.w]
+    }
+}
+
+impl Add for Vec4 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(
+            self.x + rhs.x,
+            self.y + rhs.y,
+            self.z + rhs.z,
+            self.w + rhs.w,
+        )
+    }
+}
+
+impl Sub for Vec4 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(
+            self.x - rhs.x,
+            self.y - rhs.y,
+            self.z - rhs.z,
+            self.w - rhs.w,
+        )
+    }
+}
+
+// Component-wise, as when tinting one color by another
+impl Mul for Vec4 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.x * rhs.x,
+            self.y * rhs.y,
+            self.z * rhs.z,
+            self.w * rhs.w,
+        )
+    }
+}
+
+impl Mul<f32> for Vec4 {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self::new(self.x * rhs, self.y * rhs, self.z * rhs, self.w * rhs)
+    }
+}
+
+// Window position as a fraction of the window size, y down
+pub fn window_to_unit(position: PhysicalPosition<f64>, size: PhysicalSize<u32>) -> Vec2 {
+    Vec2 {
+        x: position.x as f32 / size.width as f32,
+        y: position.y as f32 / size.height as f32,
+    }
+}
+
+// Window position in normalized device coordinates, -1 to 1.
+// Vulkan's y points down like winit's, so it isn't flipped.
+pub fn window_to_ndc(position: PhysicalPosition<f64>, size: PhysicalSize<u32>) -> Vec2 {
+    let unit = window_to_unit(position, size);
+    Vec2 {
+        x: unit.x * 2.0 - 1.0,
+        y: unit.y * 2.0 - 1.0,
+    }
+}
+
 pub const WINDOW_NAME: &str = "Learn Gfx";
 
-#[rustfmt::skip]
-pub const QUAD_DATA: [f32; 8] = [
-    -0.5, -0.5, 
-    -0.5,  0.5, 
-     0.5,  0.5, 
-     0.5, -0.5, 
+pub const QUAD_DATA: [Vertex; 4] = [
+    Vertex {
+        position: Vec2 { x: -0.5, y: -0.5 },
+    },
+    Vertex {
+        position: Vec2 { x: -0.5, y: 0.5 },
+    },
+    Vertex {
+        position: Vec2 { x: 0.5, y: 0.5 },
+    },
+    Vertex {
+        position: Vec2 { x: 0.5, y: -0.5 },
+    },
 ];
 
+// Two counter-clockwise triangles, see vertex::WINDING
 #[rustfmt::skip]
 pub const QUAD_INDICES: [u16; 6] = [
     0, 1, 2,
     0, 2, 3,
 ];
 
+// Red, green and blue corners around a centroid at the origin,
+// so the middle of the window gets an even mix of all three.
+// Counter-clockwise on screen, like the quad.
+pub const TRIANGLE_DATA: [ColorVertex; 3] = [
+    ColorVertex {
+        position: Vec2 { x: 0.0, y: -0.6 },
+        color: Vec4::new(1.0, 0.0, 0.0, 1.0),
+    },
+    ColorVertex {
+        position: Vec2 { x: -0.6, y: 0.3 },
+        color: Vec4::new(0.0, 0.0, 1.0, 1.0),
+    },
+    ColorVertex {
+        position: Vec2 { x: 0.6, y: 0.3 },
+        color: Vec4::new(0.0, 1.0, 0.0, 1.0),
+    },
+];
+
 // Matches mailbox presentation, which
 // uses three images for vsync
 pub const FRAMES_IN_FLIGHT: usize = 3;
+
+// Bounds for changing the count at runtime
+pub const MIN_FRAMES_IN_FLIGHT: usize = 1;
+pub const MAX_FRAMES_IN_FLIGHT: usize = 8;
+
+// Validation messages kept for inspection
+pub const VALIDATION_MESSAGES: usize = 64;
+
+// Written to the working directory, replacing the last one
+pub const SCREENSHOT_PATH: &str = "screenshot.png";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{} is not close to {}", a, b);
+    }
+
+    #[test]
+    fn srgb_endpoints_are_unchanged() {
+        let black = Vec4::from_srgb8(0, 0, 0, 0);
+        assert_eq!(black, Vec4::new(0.0, 0.0, 0.0, 0.0));
+        let white = Vec4::from_srgb8(255, 255, 255, 255);
+        assert_close(white.x, 1.0);
+        assert_close(white.w, 1.0);
+    }
+
+    #[test]
+    fn srgb_is_decoded_to_linear() {
+        let color = Vec4::from_srgb8(124, 10, 188, 128);
+        assert_close(color.x, 0.2016);
+        // Within the linear segment of the curve
+        assert_close(color.y, 10.0 / 255.0 / 12.92);
+        assert_close(color.z, 0.5029);
+        // Alpha isn't encoded
+        assert_close(color.w, 128.0 / 255.0);
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        let color = Vec4::from_srgb8(124, 10, 188, 128).linear_to_srgb();
+        assert_close(color.x, 124.0 / 255.0);
+        assert_close(color.y, 10.0 / 255.0);
+        assert_close(color.z, 188.0 / 255.0);
+        assert_close(color.w, 128.0 / 255.0);
+    }
+
+    #[test]
+    fn linear_to_srgb_encodes_both_segments() {
+        let color = Vec4::new(0.0, 0.002, 1.0, 0.5).linear_to_srgb();
+        assert_close(color.x, 0.0);
+        // Below the cutoff the curve is linear
+        assert_close(color.y, 0.002 * 12.92);
+        assert_close(color.z, 1.0);
+        assert_close(color.w, 0.5);
+        let mid = Vec4::new(0.5, 0.5, 0.5, 1.0).linear_to_srgb();
+        assert_close(mid.x, 0.7354);
+    }
+
+    #[test]
+    fn window_corners_map_to_ndc_corners() {
+        let size = PhysicalSize::new(200, 100);
+        let top_left = window_to_ndc(PhysicalPosition::new(0.0, 0.0), size);
+        assert_close(top_left.x, -1.0);
+        assert_close(top_left.y, -1.0);
+        let bottom_right = window_to_ndc(PhysicalPosition::new(200.0, 100.0), size);
+        assert_close(bottom_right.x, 1.0);
+        assert_close(bottom_right.y, 1.0);
+        let center = window_to_ndc(PhysicalPosition::new(100.0, 50.0), size);
+        assert_close(center.x, 0.0);
+        assert_close(center.y, 0.0);
+    }
+}
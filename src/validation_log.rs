@@ -0,0 +1,130 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+// Where the Vulkan backend logs from, including
+// the validation layers' debug messenger
+const BACKEND_TARGET: &str = "gfx_backend_vulkan";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationMessage {
+    pub level: log::Level,
+    pub text: String,
+}
+
+// The most recent validation messages, kept for an overlay or
+// to check an operation came through clean. The backend reports
+// them through the log crate, sometimes from driver threads,
+// so clones share one buffer behind a mutex.
+#[derive(Clone)]
+pub struct ValidationLog {
+    messages: Arc<Mutex<VecDeque<ValidationMessage>>>,
+    capacity: usize,
+}
+
+impl ValidationLog {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            messages: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    // Drops the oldest message once full
+    pub fn push(&self, level: log::Level, text: String) {
+        // A panic elsewhere while holding the lock
+        // shouldn't stop messages being recorded
+        let mut messages = match self.messages.lock() {
+            Ok(messages) => messages,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if messages.len() == self.capacity {
+            messages.pop_front();
+        }
+        messages.push_back(ValidationMessage { level, text });
+    }
+
+    // Oldest first
+    pub fn recent(&self) -> Vec<ValidationMessage> {
+        match self.messages.lock() {
+            Ok(messages) => messages.iter().cloned().collect(),
+            Err(poisoned) => poisoned.into_inner().iter().cloned().collect(),
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.recent()
+            .iter()
+            .any(|message| message.level == log::Level::Error)
+    }
+
+    pub fn clear(&self) {
+        match self.messages.lock() {
+            Ok(mut messages) => messages.clear(),
+            Err(poisoned) => poisoned.into_inner().clear(),
+        }
+    }
+
+    // Chained into the logger so warnings and errors from
+    // the backend are kept whatever the console shows
+    pub fn dispatch(&self) -> fern::Dispatch {
+        let log = self.clone();
+        fern::Dispatch::new()
+            .level(log::LevelFilter::Warn)
+            .filter(|metadata| metadata.target().starts_with(BACKEND_TARGET))
+            .chain(fern::Output::call(move |record| {
+                log.push(record.level(), record.args().to_string())
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(log: &ValidationLog) -> Vec<String> {
+        log.recent()
+            .into_iter()
+            .map(|message| message.text)
+            .collect()
+    }
+
+    #[test]
+    fn drops_oldest_when_full() {
+        let log = ValidationLog::new(2);
+        log.push(log::Level::Warn, "a".into());
+        log.push(log::Level::Warn, "b".into());
+        log.push(log::Level::Warn, "c".into());
+        assert_eq!(texts(&log), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn zero_capacity_keeps_one() {
+        let log = ValidationLog::new(0);
+        log.push(log::Level::Warn, "a".into());
+        log.push(log::Level::Warn, "b".into());
+        assert_eq!(texts(&log), vec!["b"]);
+    }
+
+    #[test]
+    fn has_errors_until_cleared() {
+        let log = ValidationLog::new(4);
+        log.push(log::Level::Warn, "a".into());
+        assert!(!log.has_errors());
+        log.push(log::Level::Error, "b".into());
+        assert!(log.has_errors());
+        log.clear();
+        assert!(!log.has_errors());
+        assert!(log.recent().is_empty());
+    }
+
+    #[test]
+    fn error_evicted_by_capacity() {
+        let log = ValidationLog::new(1);
+        log.push(log::Level::Error, "a".into());
+        log.push(log::Level::Warn, "b".into());
+        assert!(!log.has_errors());
+    }
+}
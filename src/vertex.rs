@@ -0,0 +1,294 @@
+use crate::{
+    transform::Mat4,
+    utils::{Vec2, Vec4},
+    Sprite,
+};
+use gfx_hal::{format::Format, pso};
+use std::mem::size_of;
+
+// Byte offset of a field, measured on a default instance
+macro_rules! offset_of {
+    ($ty:ty, $field:ident) => {{
+        let base = <$ty>::default();
+        (&base.$field as *const _ as usize - &base as *const $ty as usize) as u32
+    }};
+}
+
+// Describes how a pipeline reads a vertex type, so the stride
+// and attribute offsets all come from the struct itself
+pub trait VertexLayout: Sized {
+    // Attribute formats and offsets in location order
+    fn elements() -> Vec<pso::Element<Format>>;
+
+    fn buffer_desc(binding: u32, rate: pso::VertexInputRate) -> pso::VertexBufferDesc {
+        pso::VertexBufferDesc {
+            binding,
+            stride: size_of::<Self>() as u32,
+            rate,
+        }
+    }
+
+    fn attributes(binding: u32, first_location: u32) -> Vec<pso::AttributeDesc> {
+        Self::elements()
+            .into_iter()
+            .enumerate()
+            .map(|(i, element)| pso::AttributeDesc {
+                location: first_location + i as u32,
+                binding,
+                element,
+            })
+            .collect()
+    }
+}
+
+// Whether vertex attributes share one buffer or get one each
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VertexStreams {
+    // One buffer of whole vertices
+    Interleaved,
+    // A buffer per attribute, bound one after another
+    Separate,
+}
+
+// Every mesh generated here winds its front faces this way,
+// as seen on screen with clip space Y pointing down
+pub const WINDING: pso::FrontFace = pso::FrontFace::CounterClockwise;
+
+// Which way a triangle winds once it lands on screen,
+// or None if it has no area
+pub fn winding(a: Vec2, b: Vec2, c: Vec2) -> Option<pso::FrontFace> {
+    let cross = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+    // Y points down, which flips the usual sign
+    if cross < 0.0 {
+        Some(pso::FrontFace::CounterClockwise)
+    } else if cross > 0.0 {
+        Some(pso::FrontFace::Clockwise)
+    } else {
+        None
+    }
+}
+
+// Makes sure every indexed triangle follows WINDING
+pub fn check_winding(positions: &[Vec2], indices: &[u16]) -> Result<(), &'static str> {
+    if indices.len() % 3 != 0 {
+        return Err("Mesh index count isn't a multiple of three");
+    }
+
+    let corner = |i: u16| {
+        positions
+            .get(i as usize)
+            .cloned()
+            .ok_or("Mesh index out of range")
+    };
+    for triangle in indices.chunks(3) {
+        let facing = winding(
+            corner(triangle[0])?,
+            corner(triangle[1])?,
+            corner(triangle[2])?,
+        );
+        // Degenerate triangles draw nothing either way
+        if facing.map_or(false, |facing| facing != WINDING) {
+            return Err("Mesh triangle is wound the wrong way");
+        }
+    }
+    Ok(())
+}
+
+// Whether the rasterizer throws away triangles wound this way
+pub fn culls(rasterizer: &pso::Rasterizer, winding: pso::FrontFace) -> bool {
+    let face = if winding == rasterizer.front_face {
+        pso::Face::FRONT
+    } else {
+        pso::Face::BACK
+    };
+    rasterizer.cull_face.contains(face)
+}
+
+// Mirroring a triangle, as flipping Y does, reverses its winding
+pub fn mirrored(winding: pso::FrontFace) -> pso::FrontFace {
+    match winding {
+        pso::FrontFace::Clockwise => pso::FrontFace::CounterClockwise,
+        pso::FrontFace::CounterClockwise => pso::FrontFace::Clockwise,
+    }
+}
+
+// Bare vectors make single attribute streams
+impl VertexLayout for Vec2 {
+    fn elements() -> Vec<pso::Element<Format>> {
+        vec![pso::Element {
+            format: Format::Rg32Sfloat,
+            offset: 0,
+        }]
+    }
+}
+
+impl VertexLayout for Vec4 {
+    fn elements() -> Vec<pso::Element<Format>> {
+        vec![pso::Element {
+            format: Format::Rgba32Sfloat,
+            offset: 0,
+        }]
+    }
+}
+
+// Per-instance transforms, which take a location per column
+impl VertexLayout for Mat4 {
+    fn elements() -> Vec<pso::Element<Format>> {
+        (0..4)
+            .map(|column| pso::Element {
+                format: Format::Rgba32Sfloat,
+                offset: (column * size_of::<Vec4>()) as u32,
+            })
+            .collect()
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct Vertex {
+    pub position: Vec2,
+}
+
+// The quad shaders read a bare vec2 per vertex,
+// so this fails to compile if padding or fields sneak in
+const _: [(); 0] = [(); size_of::<Vertex>() - size_of::<Vec2>()];
+
+impl VertexLayout for Vertex {
+    fn elements() -> Vec<pso::Element<Format>> {
+        vec![pso::Element {
+            format: Format::Rg32Sfloat,
+            offset: offset_of!(Vertex, position),
+        }]
+    }
+}
+
+// Position plus a color to interpolate across the face
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct ColorVertex {
+    pub position: Vec2,
+    pub color: Vec4,
+}
+
+impl VertexLayout for ColorVertex {
+    fn elements() -> Vec<pso::Element<Format>> {
+        vec![
+            pso::Element {
+                format: Format::Rg32Sfloat,
+                offset: offset_of!(ColorVertex, position),
+            },
+            pso::Element {
+                format: Format::Rgba32Sfloat,
+                offset: offset_of!(ColorVertex, color),
+            },
+        ]
+    }
+}
+
+// For meshes loaded from files, which are three dimensional
+// and carry what lighting and texturing need
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: Vec2,
+}
+
+impl VertexLayout for MeshVertex {
+    fn elements() -> Vec<pso::Element<Format>> {
+        vec![
+            pso::Element {
+                format: Format::Rgb32Sfloat,
+                offset: offset_of!(MeshVertex, position),
+            },
+            pso::Element {
+                format: Format::Rgb32Sfloat,
+                offset: offset_of!(MeshVertex, normal),
+            },
+            pso::Element {
+                format: Format::Rg32Sfloat,
+                offset: offset_of!(MeshVertex, uv),
+            },
+        ]
+    }
+}
+
+impl VertexLayout for Sprite {
+    fn elements() -> Vec<pso::Element<Format>> {
+        vec![
+            pso::Element {
+                format: Format::Rg32Sfloat,
+                offset: offset_of!(Sprite, pos),
+            },
+            pso::Element {
+                format: Format::Rg32Sfloat,
+                offset: offset_of!(Sprite, size),
+            },
+            pso::Element {
+                format: Format::Rgba32Sfloat,
+                offset: offset_of!(Sprite, color),
+            },
+            pso::Element {
+                format: Format::Rgba32Sfloat,
+                offset: offset_of!(Sprite, uv_rect),
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{QUAD_DATA, QUAD_INDICES};
+
+    const TOP_LEFT: Vec2 = Vec2 { x: 0.0, y: 0.0 };
+    const BOTTOM_LEFT: Vec2 = Vec2 { x: 0.0, y: 1.0 };
+    const TOP_RIGHT: Vec2 = Vec2 { x: 1.0, y: 0.0 };
+
+    fn rasterizer(cull_face: pso::Face, front_face: pso::FrontFace) -> pso::Rasterizer {
+        pso::Rasterizer {
+            cull_face,
+            front_face,
+            ..pso::Rasterizer::FILL
+        }
+    }
+
+    #[test]
+    fn winding_follows_the_screen() {
+        // Down the left edge, then up to the right
+        assert_eq!(
+            winding(TOP_LEFT, BOTTOM_LEFT, TOP_RIGHT),
+            Some(pso::FrontFace::CounterClockwise)
+        );
+        assert_eq!(
+            winding(TOP_LEFT, TOP_RIGHT, BOTTOM_LEFT),
+            Some(pso::FrontFace::Clockwise)
+        );
+        assert_eq!(winding(TOP_LEFT, TOP_LEFT, TOP_RIGHT), None);
+    }
+
+    #[test]
+    fn quad_follows_the_winding() {
+        let positions: Vec<Vec2> = QUAD_DATA.iter().map(|vertex| vertex.position).collect();
+        assert!(check_winding(&positions, &QUAD_INDICES).is_ok());
+        assert!(check_winding(&positions, &[0, 2, 1]).is_err());
+        assert!(check_winding(&positions, &[0, 1, 4]).is_err());
+        assert!(check_winding(&positions, &[0, 1]).is_err());
+    }
+
+    #[test]
+    fn culls_by_face() {
+        let back = rasterizer(pso::Face::BACK, WINDING);
+        assert!(!culls(&back, WINDING));
+        assert!(culls(&back, mirrored(WINDING)));
+
+        let front = rasterizer(pso::Face::FRONT, WINDING);
+        assert!(culls(&front, WINDING));
+        assert!(!culls(&front, mirrored(WINDING)));
+
+        let none = rasterizer(pso::Face::empty(), WINDING);
+        assert!(!culls(&none, WINDING));
+        assert!(!culls(&none, mirrored(WINDING)));
+    }
+}